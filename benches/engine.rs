@@ -0,0 +1,85 @@
+use std::collections::{HashMap, HashSet};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use textadventure::{
+  adventure::{DependencyContext, Document, TimeOfDay},
+  generator::{generate, GeneratorConfig},
+  save::SaveGame,
+  validation::validate,
+};
+
+fn load(c: &mut Criterion) {
+  let yaml = generate(&GeneratorConfig::default());
+
+  let mut group = c.benchmark_group("load");
+  group.sample_size(10);
+  group.bench_function("parse 10k rooms", |b| {
+    b.iter(|| serde_yaml::from_str::<Document>(black_box(&yaml)).unwrap())
+  });
+  group.finish();
+}
+
+fn validation(c: &mut Criterion) {
+  let yaml = generate(&GeneratorConfig::default());
+  let document: Document = serde_yaml::from_str(&yaml).unwrap();
+
+  c.bench_function("validate 10k rooms", |b| {
+    b.iter(|| validate(black_box(&document.adventure)))
+  });
+}
+
+fn dependencies(c: &mut Criterion) {
+  let yaml = generate(&GeneratorConfig::default());
+  let document: Document = serde_yaml::from_str(&yaml).unwrap();
+
+  // Halfway through the item chain
+  let inventory: HashSet<String> = (0..500).map(|i| format!("item_{i}")).collect();
+  let failures = HashMap::new();
+  let flags = HashSet::new();
+  let reputation = HashMap::new();
+  let memory = HashMap::new();
+  let context = DependencyContext {
+    inventory: &inventory,
+    time_of_day: TimeOfDay::Day,
+    character: "",
+    failures: &failures,
+    ng_plus: false,
+    difficulty: "",
+    flags: &flags,
+    reputation: &reputation,
+    memory: &memory,
+  };
+
+  c.bench_function("evaluate all dependencies", |b| {
+    b.iter(|| {
+      document
+        .adventure
+        .rooms
+        .values()
+        .flat_map(|room| &room.actions)
+        .filter(|action| action.depends.is_met(black_box(&context)))
+        .count()
+    })
+  });
+}
+
+fn saves(c: &mut Criterion) {
+  let save = SaveGame {
+    current_text: "You are in room 9999 of 10000.".repeat(10),
+    inventory: (0..1_000).map(|i| format!("item_{i}")).collect(),
+    visited: (0..10_000).map(|i| format!("room_{i}")).collect(),
+    current_room: "room_9999".to_string(),
+    ..Default::default()
+  };
+  let serialized = serde_json::to_string(&save).unwrap();
+
+  c.bench_function("serialize save", |b| {
+    b.iter(|| serde_json::to_string(black_box(&save)).unwrap())
+  });
+  c.bench_function("deserialize save", |b| {
+    b.iter(|| serde_json::from_str::<SaveGame>(black_box(&serialized)).unwrap())
+  });
+}
+
+criterion_group!(benches, load, validation, dependencies, saves);
+criterion_main!(benches);
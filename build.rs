@@ -0,0 +1,90 @@
+//! Checks `adventure.yaml` while building, so a broken adventure fails the
+//! build instead of showing a blank page in the browser. The adventure is
+//! still downloaded at runtime, this only catches mistakes early.
+
+use std::fs;
+
+use anyhow::{anyhow, Context, Result};
+use serde_yaml::Value;
+
+// The modules only need serde, so they can be shared with the game
+#[allow(dead_code)]
+#[path = "src/adventure.rs"]
+mod adventure;
+#[allow(dead_code)]
+#[path = "src/includes.rs"]
+mod includes;
+#[allow(dead_code)]
+#[path = "src/localization.rs"]
+mod localization;
+#[allow(dead_code)]
+#[path = "src/migration.rs"]
+mod migration;
+#[allow(dead_code)]
+#[path = "src/validation.rs"]
+mod validation;
+
+const ADVENTURE: &str = "adventure.yaml";
+
+fn main() -> Result<()> {
+  for module in [
+    "adventure",
+    "includes",
+    "localization",
+    "migration",
+    "validation",
+  ] {
+    println!("cargo:rerun-if-changed=src/{module}.rs");
+  }
+  println!("cargo:rerun-if-changed={ADVENTURE}");
+
+  // Sites might only serve other adventures
+  if !fs::exists(ADVENTURE)? {
+    return Ok(());
+  }
+
+  let mut document: Value = serde_yaml::from_str(&fs::read_to_string(ADVENTURE)?)
+    .with_context(|| format!("unable to parse {ADVENTURE}"))?;
+  let sections = document
+    .get_mut("adventure")
+    .and_then(|a| a.as_mapping_mut())
+    .ok_or_else(|| anyhow!("{ADVENTURE} has no adventure section"))?;
+
+  let mut pending = includes::Pending::new(sections, ADVENTURE)?;
+  while let Some(file) = pending.next() {
+    println!("cargo:rerun-if-changed={file}");
+
+    // Json is yaml as well, toml is converted to it like the loader does
+    let raw = fs::read_to_string(&file).with_context(|| format!("unable to read {file}"))?;
+    let parsed = if file.ends_with(".toml") {
+      serde_yaml::to_value(toml::from_str::<toml::Value>(&raw)?)
+    } else {
+      serde_yaml::from_str(&raw)
+    };
+    let mut included = match parsed.with_context(|| format!("unable to parse {file}"))? {
+      Value::Mapping(m) => m,
+      Value::Null => continue,
+      _ => return Err(anyhow!("{file} has to contain sections of the adventure")),
+    };
+    pending.add(&mut included, &file)?;
+    includes::merge(sections, included, &file)?;
+  }
+
+  for warning in migration::migrate(&mut document)? {
+    println!("cargo:warning={warning}");
+  }
+  localization::localize(&mut document, None)?;
+  let document: adventure::Document =
+    serde_yaml::from_value(document).with_context(|| format!("unable to parse {ADVENTURE}"))?;
+
+  let problems = validation::validate(&document.adventure);
+  if !problems.is_empty() {
+    let report: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+    return Err(anyhow!(
+      "{ADVENTURE} has {} problems:\n{}",
+      problems.len(),
+      report.join("\n")
+    ));
+  }
+  Ok(())
+}
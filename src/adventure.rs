@@ -1,56 +1,2177 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use serde::Deserialize;
 
 #[derive(Deserialize, Clone)]
 pub struct Document {
+    /// The version of the file structure, older files are upgraded by
+    /// [`crate::migration`]
+    #[serde(default = "Document::default_version")]
+    pub version: u64,
     pub adventure: Adventure,
 }
 
+impl Document {
+    fn default_version() -> u64 {
+        1
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct Adventure {
+    /// Keeps the saves of this adventure apart from other adventures played
+    /// on the same site.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// A server that keeps a copy of the save, so players can continue on
+    /// another device. See [`crate::remote`].
+    #[serde(default)]
+    pub sync: Option<String>,
+    pub name: String,
+    /// The name in the adventure's default language, which keys the saves if
+    /// there is no `id`, so they don't depend on the language the player
+    /// picked. Filled in by the loader.
+    #[serde(skip)]
+    pub storage_name: Option<String>,
+    /// Shown on the title screen, along with the name
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub blurb: String,
+    /// Shows a title screen before the intro, which continues the saved game
+    /// or starts a new one.
+    #[serde(default)]
+    pub title_screen: bool,
+    pub start: String,
+    pub intro: String,
+    pub rooms: HashMap<String, Room>,
+    pub assets: AdventureAssets,
+    #[serde(default)]
+    pub fast_travel: FastTravel,
+    #[serde(default)]
+    pub ambience: Ambience,
+    /// Enables the day/night cycle if set.
+    #[serde(default)]
+    pub clock: Option<Clock>,
+    #[serde(default)]
+    pub bestiary: Bestiary,
+    #[serde(default)]
+    pub codex: Codex,
+    /// Secret codes the player can type anywhere in the game, keyed by the
+    /// code
+    #[serde(default)]
+    pub codes: HashMap<String, Code>,
+    /// Encounter tables keyed by room tag
+    #[serde(default)]
+    pub encounters: HashMap<String, EncounterTable>,
+    /// Survival meters like hunger or thirst, shown in alphabetical order
+    #[serde(default)]
+    pub meters: BTreeMap<String, Meter>,
+    #[serde(default)]
+    pub characters: Characters,
+    /// Reputation tracks, shown in alphabetical order
+    #[serde(default)]
+    pub factions: BTreeMap<String, Faction>,
+    /// Settings for individual items, keyed by the item
+    #[serde(default)]
+    pub items: HashMap<String, Item>,
+    #[serde(default)]
+    pub new_game_plus: NewGamePlus,
+    #[serde(default)]
+    pub action_rendering: ActionRendering,
+    /// What the status bar shows, in order. Without any fields, it is hidden.
+    #[serde(default)]
+    pub status: Vec<StatusField>,
+    /// Offered on the intro screen instead of the start action, in order
+    #[serde(default)]
+    pub difficulties: Vec<Difficulty>,
+    #[serde(default)]
+    pub use_items: UseItems,
+    #[serde(default)]
+    pub languages: Languages,
+    #[serde(default)]
+    pub strings: Strings,
+    #[serde(default)]
+    pub saves: Saves,
+    #[serde(default)]
+    pub undo: Undo,
+    #[serde(default)]
+    pub history: History,
+    /// Enables the typewriter effect if set.
+    #[serde(default)]
+    pub typewriter: Option<Typewriter>,
+    /// Keeps the texts of earlier turns on the page if set.
+    #[serde(default)]
+    pub scrollback: Option<Scrollback>,
+    /// Shows how many rooms the player discovered if set.
+    #[serde(default)]
+    pub progress: Option<Progress>,
+    /// Lets the player have the texts read aloud if set.
+    #[serde(default)]
+    pub narration: Option<Narration>,
+    /// Shows the html in the texts as written, instead of removing everything
+    /// that could change the page. Only for adventures from trusted sources.
+    #[serde(default)]
+    pub raw_html: bool,
+    /// The look of rooms that don't set their own
+    #[serde(default)]
+    pub theme: Theme,
+    /// How the game adapts to phones and other touch screens
+    #[serde(default)]
+    pub touch: Touch,
+    /// The files of the bundle the adventure was loaded from, by their path in
+    /// the bundle, as urls the page can load. Filled in by the loader.
+    #[serde(skip)]
+    pub files: HashMap<String, String>,
+}
+
+/// Dresses the page for a room. The class is added to the element with the
+/// `container` class, and the background color fades in behind it.
+#[derive(Deserialize, Clone, Default)]
+pub struct Theme {
+    #[serde(default)]
+    pub class: Option<String>,
+    /// Any css color, e.g. `#203040`
+    #[serde(default)]
+    pub background: Option<String>,
+}
+
+/// The layout for touch screens, with actions as large buttons and the
+/// inventory in a drawer. The game keeps above the on-screen keyboard while
+/// the player types.
+#[derive(Deserialize, Clone)]
+pub struct Touch {
+    #[serde(default)]
+    pub mode: TouchMode,
+    /// Shows the items the player carries in a drawer below the actions
+    #[serde(default = "Touch::default_inventory")]
+    pub inventory: bool,
+}
+
+impl Touch {
+    fn default_inventory() -> bool {
+        true
+    }
+}
+
+impl Default for Touch {
+    fn default() -> Self {
+        Self {
+            mode: TouchMode::default(),
+            inventory: Self::default_inventory(),
+        }
+    }
+}
+
+/// When the game uses the layout for touch screens.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TouchMode {
+    /// On devices whose main pointer is a finger
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Reveals the text of rooms and actions letter by letter. Clicking or
+/// pressing a key shows the rest at once.
+#[derive(Deserialize, Clone)]
+pub struct Typewriter {
+    /// Letters shown per second
+    #[serde(default = "Typewriter::default_speed")]
+    pub speed: f64,
+}
+
+impl Typewriter {
+    fn default_speed() -> f64 {
+        40.0
+    }
+}
+
+/// Shows every new text below the earlier ones, like a log, instead of
+/// replacing them.
+#[derive(Deserialize, Clone)]
+pub struct Scrollback {
+    /// How many texts are kept, the oldest ones are removed first
+    #[serde(default = "Scrollback::default_limit")]
+    pub limit: usize,
+}
+
+impl Scrollback {
+    fn default_limit() -> usize {
+        50
+    }
+}
+
+/// Counts the rooms the player visited, against all rooms there are to find.
+/// Players can hide it.
+#[derive(Deserialize, Clone, Default)]
+pub struct Progress {
+    /// Rooms that don't count, e.g. endings or rooms only some players see
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// The voices that read the texts aloud. Voices are picked from those the
+/// browser offers, by their name or by a language like `en-GB`.
+#[derive(Deserialize, Clone, Default)]
+pub struct Narration {
+    /// Reads everything but the lines of speakers without their own voice
+    #[serde(flatten)]
+    pub narrator: SpeakingVoice,
+    /// The voices of the speakers of dialogue lines, by their name
+    #[serde(default)]
+    pub speakers: HashMap<String, SpeakingVoice>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SpeakingVoice {
+    /// The browser's default voice if unset
+    #[serde(default)]
+    pub voice: Option<String>,
+    /// 1 is the normal speed, between 0.1 and 10
+    #[serde(default = "SpeakingVoice::default_rate")]
+    pub rate: f32,
+    /// 1 is the normal pitch, between 0 and 2
+    #[serde(default = "SpeakingVoice::default_pitch")]
+    pub pitch: f32,
+}
+
+impl SpeakingVoice {
+    fn default_rate() -> f32 {
+        1.0
+    }
+
+    fn default_pitch() -> f32 {
+        1.0
+    }
+}
+
+impl Default for SpeakingVoice {
+    fn default() -> Self {
+        Self {
+            voice: None,
+            rate: Self::default_rate(),
+            pitch: Self::default_pitch(),
+        }
+    }
+}
+
+/// Configures the save menu, which keeps games in numbered slots next to the
+/// automatic save. Placeholders in braces are replaced with the slot's data.
+#[derive(Deserialize, Clone)]
+pub struct Saves {
+    /// The name of the action that opens the save menu
+    #[serde(default = "Saves::default_name")]
+    pub name: String,
+    #[serde(default = "Saves::default_back")]
+    pub back: String,
+    /// The number of slots, 0 hides the menu
+    #[serde(default = "Saves::default_slots")]
+    pub slots: u32,
+    /// Seconds between automatic saves, 0 only saves after actions and when
+    /// the page is left
+    #[serde(default = "Saves::default_autosave")]
+    pub autosave: u32,
+    /// Describes a used slot, with `{slot}`, `{room}`, `{turn}` and `{time}`
+    #[serde(default = "Saves::default_summary")]
+    pub summary: String,
+    /// Describes a slot the player named, with `{name}` and the placeholders
+    /// of `summary`
+    #[serde(default = "Saves::default_named_summary")]
+    pub named_summary: String,
+    /// Shown above the field to name the slot, when saving to it
+    #[serde(default = "Saves::default_name_hint")]
+    pub name_hint: String,
+    #[serde(default = "Saves::default_empty")]
+    pub empty: String,
+    #[serde(default = "Saves::default_save")]
+    pub save: String,
+    #[serde(default = "Saves::default_load")]
+    pub load: String,
+    #[serde(default = "Saves::default_delete")]
+    pub delete: String,
+    #[serde(default = "Saves::default_export")]
+    pub export: String,
+    /// Shown above the exported save
+    #[serde(default = "Saves::default_export_hint")]
+    pub export_hint: String,
+    #[serde(default = "Saves::default_download")]
+    pub download: String,
+    #[serde(default = "Saves::default_import")]
+    pub import: String,
+    /// Shown above the field to paste a save into
+    #[serde(default = "Saves::default_import_hint")]
+    pub import_hint: String,
+    /// Shown if an imported save can't be read, with `{error}`
+    #[serde(default = "Saves::default_import_failed")]
+    pub import_failed: String,
+    #[serde(default = "Saves::default_close")]
+    pub close: String,
+    /// Creates a link that continues from the current state
+    #[serde(default = "Saves::default_share")]
+    pub share: String,
+    /// Shown above the created link
+    #[serde(default = "Saves::default_share_hint")]
+    pub share_hint: String,
+    /// Asks whether to continue from a shared link
+    #[serde(default = "Saves::default_shared")]
+    pub shared: String,
+}
+
+impl Saves {
+    fn default_name() -> String {
+        "Saves".to_string()
+    }
+
+    fn default_back() -> String {
+        "Back".to_string()
+    }
+
+    fn default_slots() -> u32 {
+        3
+    }
+
+    fn default_autosave() -> u32 {
+        60
+    }
+
+    fn default_summary() -> String {
+        "Slot {slot}: {room}, turn {turn}, saved {time}".to_string()
+    }
+
+    fn default_named_summary() -> String {
+        "{name}: {room}, turn {turn}, saved {time}".to_string()
+    }
+
+    fn default_name_hint() -> String {
+        "Name the save, or leave the name empty.".to_string()
+    }
+
+    fn default_empty() -> String {
+        "Slot {slot}: empty".to_string()
+    }
+
+    fn default_save() -> String {
+        "Save to slot {slot}".to_string()
+    }
+
+    fn default_load() -> String {
+        "Load slot {slot}".to_string()
+    }
+
+    fn default_delete() -> String {
+        "Delete slot {slot}".to_string()
+    }
+
+    fn default_export() -> String {
+        "Export save".to_string()
+    }
+
+    fn default_export_hint() -> String {
+        "Copy this text to continue in another browser, or download it as a file.".to_string()
+    }
+
+    fn default_download() -> String {
+        "Download".to_string()
+    }
+
+    fn default_import() -> String {
+        "Import save".to_string()
+    }
+
+    fn default_import_hint() -> String {
+        "Paste an exported save, or choose a downloaded one.".to_string()
+    }
+
+    fn default_import_failed() -> String {
+        "Unable to import the save: {error}".to_string()
+    }
+
+    fn default_close() -> String {
+        "Close".to_string()
+    }
+
+    fn default_share() -> String {
+        "Copy link".to_string()
+    }
+
+    fn default_share_hint() -> String {
+        "Whoever opens this link continues from here. It was copied to the clipboard.".to_string()
+    }
+
+    fn default_shared() -> String {
+        "Continue from the shared game? This replaces your progress.".to_string()
+    }
+}
+
+impl Default for Saves {
+    fn default() -> Self {
+        Self {
+            name: Self::default_name(),
+            back: Self::default_back(),
+            slots: Self::default_slots(),
+            autosave: Self::default_autosave(),
+            summary: Self::default_summary(),
+            named_summary: Self::default_named_summary(),
+            name_hint: Self::default_name_hint(),
+            empty: Self::default_empty(),
+            save: Self::default_save(),
+            load: Self::default_load(),
+            delete: Self::default_delete(),
+            export: Self::default_export(),
+            export_hint: Self::default_export_hint(),
+            download: Self::default_download(),
+            import: Self::default_import(),
+            import_hint: Self::default_import_hint(),
+            import_failed: Self::default_import_failed(),
+            close: Self::default_close(),
+            share: Self::default_share(),
+            share_hint: Self::default_share_hint(),
+            shared: Self::default_shared(),
+        }
+    }
+}
+
+/// Configures the global "Undo" action, which takes back the player's last
+/// actions.
+#[derive(Deserialize, Clone)]
+pub struct Undo {
+    #[serde(default = "Undo::default_name")]
+    pub name: String,
+    /// How many actions can be taken back, 0 disables undo
+    #[serde(default = "Undo::default_steps")]
+    pub steps: usize,
+}
+
+impl Undo {
+    fn default_name() -> String {
+        "Undo".to_string()
+    }
+
+    fn default_steps() -> usize {
+        10
+    }
+}
+
+impl Default for Undo {
+    fn default() -> Self {
+        Self {
+            name: Self::default_name(),
+            steps: Self::default_steps(),
+        }
+    }
+}
+
+/// Configures the history, which shows everything the player read and chose so
+/// far.
+#[derive(Deserialize, Clone)]
+pub struct History {
+    /// The name of the action that opens the history
+    #[serde(default = "History::default_name")]
+    pub name: String,
+    /// Downloads the history as a text file
+    #[serde(default = "History::default_download")]
+    pub download: String,
+    #[serde(default = "History::default_close")]
+    pub close: String,
+    /// How many actions are kept, the oldest ones are dropped first, so the
+    /// saves don't grow without end
+    #[serde(default = "History::default_limit")]
+    pub limit: usize,
+}
+
+impl History {
+    fn default_name() -> String {
+        "History".to_string()
+    }
+
+    fn default_limit() -> usize {
+        500
+    }
+
+    fn default_download() -> String {
+        "Download".to_string()
+    }
+
+    fn default_close() -> String {
+        "Close".to_string()
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            name: Self::default_name(),
+            download: Self::default_download(),
+            close: Self::default_close(),
+            limit: Self::default_limit(),
+        }
+    }
+}
+
+/// Configures the global "Use item" action, which lets the player use an item
+/// from their inventory on one of the `use_targets` of the room or of the
+/// other items they carry.
+#[derive(Deserialize, Clone)]
+pub struct UseItems {
+    #[serde(default = "UseItems::default_name")]
+    pub name: String,
+    #[serde(default = "UseItems::default_back")]
+    pub back: String,
+    /// Shown if the item can't be used on the target
+    #[serde(default = "UseItems::default_fails")]
+    pub fails: String,
+}
+
+impl UseItems {
+    fn default_name() -> String {
+        "Use item".to_string()
+    }
+
+    fn default_back() -> String {
+        "Back".to_string()
+    }
+
+    fn default_fails() -> String {
+        "That doesn't work.".to_string()
+    }
+}
+
+impl Default for UseItems {
+    fn default() -> Self {
+        Self {
+            name: Self::default_name(),
+            back: Self::default_back(),
+            fails: Self::default_fails(),
+        }
+    }
+}
+
+/// The languages the adventure is translated to. Any text can be given as a
+/// map from language code to translation instead of a plain string.
+#[derive(Deserialize, Clone)]
+pub struct Languages {
+    /// The name of the action that lets the player pick a language
+    #[serde(default = "Languages::default_name")]
+    pub name: String,
+    #[serde(default = "Languages::default_back")]
+    pub back: String,
+    /// Used for texts that are missing a translation
+    #[serde(default)]
+    pub default: String,
+    /// The name of every language, keyed by the language code
+    #[serde(default)]
+    pub available: BTreeMap<String, String>,
+}
+
+impl Languages {
+    fn default_name() -> String {
+        "Language".to_string()
+    }
+
+    fn default_back() -> String {
+        "Back".to_string()
+    }
+}
+
+impl Default for Languages {
+    fn default() -> Self {
+        Self {
+            name: Self::default_name(),
+            back: Self::default_back(),
+            default: String::new(),
+            available: BTreeMap::new(),
+        }
+    }
+}
+
+/// Texts shown by the engine itself, so they can be translated as well.
+/// Placeholders in braces are replaced with the actual values.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct Strings {
+    pub start: String,
+    pub turns_taken: String,
+    pub rooms_visited: String,
+    pub items_found: String,
+    pub endings_discovered: String,
+    pub score: String,
+    pub playtime: String,
+    /// Shown in the bestiary, with `{count}`
+    pub defeated: String,
+    /// The default name of shop offers, with `{item}`
+    pub buy: String,
+    /// Shown if the saves can't be accessed, with `{error}`
+    pub save_unavailable: String,
+    /// Shown if the save was damaged but could be restored, with `{error}`
+    pub save_restored: String,
+    /// Shown if the save couldn't be read and was kept aside, with `{error}`
+    /// and the `{key}` of the copy
+    pub save_set_aside: String,
+    /// Shown if the save couldn't be read and is lost, with `{error}`
+    pub save_lost: String,
+    /// Asks before the saves are sent to the server in `?sync=<url>`, with
+    /// `{url}`
+    pub confirm_sync: String,
+    /// Shown while a timed choice runs, with `{seconds}`
+    pub countdown: String,
+    /// Shown in the history when the countdown ran out
+    pub timeout: String,
+    /// The answers to the question of actions that need to be confirmed
+    pub confirm_yes: String,
+    pub confirm_no: String,
+    /// Asks before the game is reset, above what will be lost
+    pub reset_question: String,
+    /// Offers to export the save before resetting
+    pub reset_export: String,
+    /// The label of the music volume slider
+    pub volume: String,
+    pub mute: String,
+    pub unmute: String,
+    /// Shown if the browser blocked the audio until the player allows it
+    pub enable_sound: String,
+    /// Read out by screen readers for the list of actions and the reset link
+    pub actions: String,
+    pub reset: String,
+    /// The narration controls
+    pub narrate: String,
+    pub stop_narrating: String,
+    pub narration_rate: String,
+    pub narration_voice: String,
+    /// The voice picker's entry for the adventure's own voice
+    pub default_voice: String,
+    /// The labels of the status bar
+    pub turn: String,
+    pub time: String,
+    pub items: String,
+    /// The time in the status bar, with `{day}` and `{hour}`
+    pub clock: String,
+    /// The actions of the title screen
+    pub new_game: String,
+    pub continue_game: String,
+    /// Shown below the title, with `{author}` and `{version}`
+    pub author: String,
+    pub version: String,
+    /// The inventory drawer, with the number of items as `{count}`
+    pub inventory: String,
+    pub empty_inventory: String,
+    /// Closes the dialog of a modal action
+    pub close: String,
+    /// The progress indicator, with `{visited}` and `{total}`
+    pub progress: String,
+    pub show_progress: String,
+    pub hide_progress: String,
+}
+
+impl Default for Strings {
+    fn default() -> Self {
+        Self {
+            start: "Start".to_string(),
+            turns_taken: "Turns taken".to_string(),
+            rooms_visited: "Rooms visited".to_string(),
+            items_found: "Items found".to_string(),
+            endings_discovered: "Endings discovered".to_string(),
+            score: "Score".to_string(),
+            playtime: "Playtime".to_string(),
+            defeated: "Defeated {count} times".to_string(),
+            buy: "Buy {item}".to_string(),
+            save_unavailable: "Unable to access the saves: {error}".to_string(),
+            save_restored: "Your save was damaged ({error}) and has been restored from a backup."
+                .to_string(),
+            save_set_aside: "Your save couldn't be read ({error}). A copy was kept as {key}, \
+                             so starting over doesn't overwrite it."
+                .to_string(),
+            save_lost: "Your save was damaged ({error}) and could not be restored.".to_string(),
+            confirm_sync: "Keep a copy of your saves on {url}?".to_string(),
+            countdown: "{seconds} seconds left".to_string(),
+            timeout: "Timeout".to_string(),
+            confirm_yes: "Yes".to_string(),
+            confirm_no: "No".to_string(),
+            reset_question: "Start over from the beginning? This game will be lost:".to_string(),
+            reset_export: "Export first".to_string(),
+            volume: "Music volume".to_string(),
+            mute: "Mute".to_string(),
+            unmute: "Unmute".to_string(),
+            enable_sound: "Enable sound".to_string(),
+            actions: "Actions".to_string(),
+            reset: "Reset the game and start from the beginning".to_string(),
+            narrate: "Read aloud".to_string(),
+            stop_narrating: "Stop reading aloud".to_string(),
+            narration_rate: "Reading speed".to_string(),
+            narration_voice: "Voice".to_string(),
+            default_voice: "Default voice".to_string(),
+            turn: "Turn".to_string(),
+            time: "Time".to_string(),
+            items: "Items".to_string(),
+            clock: "Day {day}, {hour}:00".to_string(),
+            new_game: "New game".to_string(),
+            continue_game: "Continue".to_string(),
+            author: "by {author}".to_string(),
+            version: "Version {version}".to_string(),
+            inventory: "Inventory ({count})".to_string(),
+            empty_inventory: "You carry nothing.".to_string(),
+            close: "Close".to_string(),
+            progress: "{visited}/{total} locations discovered".to_string(),
+            show_progress: "Show progress".to_string(),
+            hide_progress: "Hide progress".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Difficulty {
+    /// Used by dependencies and saves
+    pub id: String,
+    pub name: String,
+    /// Items the player starts with
+    #[serde(default)]
+    pub items: Vec<String>,
+    /// Overrides the start value of meters
+    #[serde(default)]
+    pub meters: HashMap<String, i64>,
+    /// Multiplies the chance of every encounter
+    #[serde(default = "Difficulty::default_encounter_rate")]
+    pub encounter_rate: f64,
+}
+
+impl Difficulty {
+    fn default_encounter_rate() -> f64 {
+        1.0
+    }
+}
+
+/// Something the status bar shows.
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(try_from = "StatusFieldRepr")]
+pub enum StatusField {
+    /// The title of the current room
+    Room,
+    Turn,
+    Score,
+    /// The day and hour of the clock
+    Time,
+    /// How many items the player carries
+    Items,
+    /// A meter, by its key
+    Meter(String),
+    /// The rank with a faction, by its key
+    Reputation(String),
+}
+
+/// Either the name of a field, or a map naming the meter or faction.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StatusFieldRepr {
+    Name(String),
+    Meter { meter: String },
+    Reputation { reputation: String },
+}
+
+impl TryFrom<StatusFieldRepr> for StatusField {
+    type Error = String;
+
+    fn try_from(value: StatusFieldRepr) -> Result<Self, Self::Error> {
+        match value {
+            StatusFieldRepr::Name(name) => match name.as_str() {
+                "room" => Ok(Self::Room),
+                "turn" => Ok(Self::Turn),
+                "score" => Ok(Self::Score),
+                "time" => Ok(Self::Time),
+                "items" => Ok(Self::Items),
+                _ => Err(format!(
+                    "unknown status field {name}, expected room, turn, score, time or items"
+                )),
+            },
+            StatusFieldRepr::Meter { meter } => Ok(Self::Meter(meter)),
+            StatusFieldRepr::Reputation { reputation } => Ok(Self::Reputation(reputation)),
+        }
+    }
+}
+
+/// How the action links are updated after the player did something.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionRendering {
+    /// Keeps links that are still offered, so hover and focus survive
+    /// actions that only change the text
+    #[default]
+    Diff,
+    /// Recreates every link after each action
+    Rebuild,
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct Item {
+    /// Keeps the item when the player starts a New Game+
+    #[serde(default)]
+    pub carry_over: bool,
+    /// An image of the assets, shown while the player picks what to use the
+    /// item on
+    #[serde(default)]
+    pub image: Option<String>,
+    /// Things about the item the player can use other items on while they
+    /// carry it, in any room
+    #[serde(default)]
+    pub use_targets: Vec<UseTarget>,
+}
+
+/// Offered once the player reaches an ending. Restarts the adventure, but lets
+/// the player keep all items marked with `carry_over`.
+#[derive(Deserialize, Clone)]
+pub struct NewGamePlus {
+    /// The name of the action that starts the new game
+    #[serde(default = "NewGamePlus::default_name")]
+    pub name: String,
+}
+
+impl NewGamePlus {
+    fn default_name() -> String {
+        "New Game+".to_string()
+    }
+}
+
+impl Default for NewGamePlus {
+    fn default() -> Self {
+        Self {
+            name: Self::default_name(),
+        }
+    }
+}
+
+/// Protagonists the player can switch between. Each has their own inventory
+/// and location.
+#[derive(Deserialize, Clone)]
+pub struct Characters {
+    /// The name of the action that switches characters
+    #[serde(default = "Characters::default_name")]
+    pub name: String,
+    #[serde(default = "Characters::default_back")]
+    pub back: String,
+    /// The character the game starts with, defaults to the first one
+    #[serde(default)]
+    pub initial: Option<String>,
+    #[serde(default)]
+    pub playable: BTreeMap<String, Character>,
+}
+
+impl Characters {
+    fn default_name() -> String {
+        "Switch character".to_string()
+    }
+
+    fn default_back() -> String {
+        "Back".to_string()
+    }
+}
+
+impl Default for Characters {
+    fn default() -> Self {
+        Self {
+            name: Self::default_name(),
+            back: Self::default_back(),
+            initial: None,
+            playable: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Character {
+    pub name: String,
+    /// The room the character starts in, defaults to the adventure's start
+    #[serde(default)]
+    pub start: Option<String>,
+    /// The items the character starts with
+    #[serde(default)]
+    pub items: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct Code {
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub items: Vec<String>,
+    /// Flags to set
+    #[serde(default)]
+    pub flags: Vec<String>,
+    /// Moves the player to this room
+    #[serde(default)]
+    pub transition: Option<String>,
+}
+
+/// Lore entries the player unlocks while playing. Unlocked entries stay
+/// unlocked when the game is reset.
+#[derive(Deserialize, Clone)]
+pub struct Codex {
+    /// The name of the action that opens the codex
+    #[serde(default = "Codex::default_name")]
+    pub name: String,
+    #[serde(default = "Codex::default_close")]
+    pub close: String,
+    #[serde(default)]
+    pub entries: HashMap<String, CodexEntry>,
+}
+
+impl Codex {
+    fn default_name() -> String {
+        "Codex".to_string()
+    }
+
+    fn default_close() -> String {
+        "Close".to_string()
+    }
+}
+
+impl Default for Codex {
+    fn default() -> Self {
+        Self {
+            name: Self::default_name(),
+            close: Self::default_close(),
+            entries: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct CodexEntry {
+    pub title: String,
+    pub text: String,
+    pub unlock: CodexUnlock,
+}
+
+/// An entry is unlocked as soon as any of the conditions is met.
+#[derive(Deserialize, Clone, Default)]
+pub struct CodexUnlock {
+    /// Having any of these items
+    #[serde(default)]
+    pub items: Vec<String>,
+    /// Having any of these flags set
+    #[serde(default)]
+    pub flags: Vec<String>,
+    /// Having visited any of these rooms
+    #[serde(default)]
+    pub rooms: Vec<String>,
+}
+
+/// A value that depletes every turn and can be restored by actions.
+#[derive(Deserialize, Clone)]
+pub struct Meter {
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default = "Meter::default_max")]
+    pub max: i64,
+    #[serde(default = "Meter::default_max")]
+    pub start: i64,
+    /// How much the meter drops with every action that takes time, i.e. all
+    /// but those with `time: 0`
+    #[serde(default)]
+    pub decay: i64,
+    #[serde(default)]
+    pub thresholds: Vec<MeterThreshold>,
+}
+
+impl Meter {
+    fn default_max() -> i64 {
+        100
+    }
+}
+
+/// A group whose opinion of the player changes through actions.
+#[derive(Deserialize, Clone)]
+pub struct Faction {
+    pub name: String,
+    #[serde(default)]
+    pub start: i64,
+    /// Labels for ranges of reputation. The rank with the highest `at` that
+    /// the reputation reaches is shown.
+    #[serde(default)]
+    pub ranks: Vec<Rank>,
+}
+
+impl Faction {
+    pub fn rank(&self, reputation: i64) -> Option<&str> {
+        self.ranks
+            .iter()
+            .filter(|r| reputation >= r.at)
+            .max_by_key(|r| r.at)
+            .map(|r| r.label.as_str())
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Rank {
+    pub at: i64,
+    pub label: String,
+}
+
+/// Fires once when the meter drops below `below`.
+#[derive(Deserialize, Clone)]
+pub struct MeterThreshold {
+    pub below: i64,
+    #[serde(default)]
+    pub text: String,
+    /// Changes to other meters, as a penalty
+    #[serde(default)]
+    pub meters: HashMap<String, i64>,
+    /// Moves the player, e.g. to a game over room
+    #[serde(default)]
+    pub transition: Option<String>,
+}
+
+/// When transitioning into a room with a matching tag, the player might be
+/// diverted into one of the encounter rooms first.
+#[derive(Deserialize, Clone)]
+pub struct EncounterTable {
+    pub chance: f64,
+    /// How many turns have to pass before the table can trigger again
+    #[serde(default)]
+    pub cooldown: u64,
+    pub rooms: Vec<String>,
+    /// The name of the action that continues to the original destination
+    #[serde(default = "EncounterTable::default_continue_label", rename = "continue")]
+    pub continue_label: String,
+}
+
+impl EncounterTable {
+    fn default_continue_label() -> String {
+        "Continue on your way".to_string()
+    }
+}
+
+/// Enemies the player can defeat. Defeated enemies are listed in the
+/// bestiary, which is kept across games.
+#[derive(Deserialize, Clone)]
+pub struct Bestiary {
+    #[serde(default = "Bestiary::default_name")]
+    pub name: String,
+    #[serde(default = "Bestiary::default_back")]
+    pub back: String,
+    #[serde(default)]
+    pub enemies: HashMap<String, Enemy>,
+}
+
+impl Bestiary {
+    fn default_name() -> String {
+        "Bestiary".to_string()
+    }
+
+    fn default_back() -> String {
+        "Back".to_string()
+    }
+}
+
+impl Default for Bestiary {
+    fn default() -> Self {
+        Self {
+            name: Self::default_name(),
+            back: Self::default_back(),
+            enemies: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Enemy {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub stats: BTreeMap<String, serde_yaml::Value>,
+    /// How many times the enemy needs to be defeated before its stats are shown
+    #[serde(default = "Enemy::default_reveal_stats_after")]
+    pub reveal_stats_after: u32,
+}
+
+impl Enemy {
+    fn default_reveal_stats_after() -> u32 {
+        1
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Clock {
+    /// The hour at which the game starts
+    #[serde(default = "Clock::default_start")]
+    pub start: u64,
+    /// How many hours an action takes, unless it specifies its own `time`
+    #[serde(default = "Clock::default_hours_per_action")]
+    pub hours_per_action: u64,
+    #[serde(default = "Clock::default_day_start")]
+    pub day_start: u64,
+    #[serde(default = "Clock::default_night_start")]
+    pub night_start: u64,
+}
+
+impl Clock {
+    fn default_start() -> u64 {
+        8
+    }
+
+    fn default_hours_per_action() -> u64 {
+        1
+    }
+
+    fn default_day_start() -> u64 {
+        6
+    }
+
+    fn default_night_start() -> u64 {
+        20
+    }
+
+    pub fn time_of_day(&self, time: u64) -> TimeOfDay {
+        let hour = time % 24;
+        if hour >= self.day_start && hour < self.night_start {
+            TimeOfDay::Day
+        } else {
+            TimeOfDay::Night
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeOfDay {
+    Day,
+    Night,
+}
+
+/// Configures the global "Travel" action that lists visited rooms flagged
+/// with `fast_travel: true`.
+#[derive(Deserialize, Clone)]
+pub struct FastTravel {
+    #[serde(default = "FastTravel::default_name")]
+    pub name: String,
+    #[serde(default = "FastTravel::default_cancel")]
+    pub cancel: String,
+    /// Gates fast travel, e.g. behind a map item.
+    #[serde(default)]
+    pub depends: ActionDependencies,
+}
+
+impl FastTravel {
+    fn default_name() -> String {
+        "Travel".to_string()
+    }
+
+    fn default_cancel() -> String {
+        "Stay here".to_string()
+    }
+}
+
+impl Default for FastTravel {
+    fn default() -> Self {
+        Self {
+            name: Self::default_name(),
+            cancel: Self::default_cancel(),
+            depends: ActionDependencies::default(),
+        }
+    }
+}
+
+/// Flavor lines that are randomly appended to room descriptions.
+#[derive(Deserialize, Clone, Default)]
+pub struct Ambience {
+    /// The probability of appending a line whenever a room is described.
+    #[serde(default)]
+    pub chance: f64,
+    #[serde(default)]
+    pub lines: Vec<AmbienceLine>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct AmbienceLine {
+    pub text: String,
+    /// If not empty, the line is only used in rooms with at least one of these
+    /// tags.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub depends: ActionDependencies,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct AdventureAssets {
+    pub music: HashMap<String, Song>,
+    /// Instruments with their own sound, in addition to the built-in
+    /// waveforms
+    #[serde(default)]
+    pub instruments: HashMap<String, Instrument>,
+    /// Seconds the playing song fades out while the next one fades in
+    #[serde(default = "AdventureAssets::default_crossfade")]
+    pub crossfade: f64,
+    /// Short sounds that play once on top of the music
+    #[serde(default)]
+    pub sfx: HashMap<String, SoundEffect>,
+    /// Recorded music, played by name like the songs in `music`
+    #[serde(default)]
+    pub audio_files: HashMap<String, AudioFile>,
+    /// Pictures of rooms and items, shown above the text
+    #[serde(default)]
+    pub images: HashMap<String, Image>,
+}
+
+impl Default for AdventureAssets {
+    fn default() -> Self {
+        Self {
+            music: HashMap::new(),
+            instruments: HashMap::new(),
+            crossfade: Self::default_crossfade(),
+            sfx: HashMap::new(),
+            audio_files: HashMap::new(),
+            images: HashMap::new(),
+        }
+    }
+}
+
+impl AdventureAssets {
+    fn default_crossfade() -> f64 {
+        1.5
+    }
+
+    /// Looks up the sound of an instrument, which is either one of the
+    /// built-in waveforms, the drums or defined in `instruments`.
+    pub fn waveform(&self, instrument: &str) -> anyhow::Result<Waveform> {
+        Ok(match instrument {
+            "sine" => Waveform::Sine,
+            // Every voice used to be a square wave, whatever its instrument
+            "square" | "synth" => Waveform::Square,
+            "sawtooth" => Waveform::Sawtooth,
+            "triangle" => Waveform::Triangle,
+            "drums" => Waveform::Drums,
+            _ => match self.instruments.get(instrument) {
+                Some(i) if i.harmonics.is_empty() => {
+                    anyhow::bail!("the instrument {instrument} has no harmonics")
+                }
+                Some(i) => Waveform::Custom(i.harmonics.clone()),
+                None => anyhow::bail!(
+                    "unknown instrument {instrument}, expected sine, square, sawtooth, \
+                     triangle, drums or one of the instruments in the assets"
+                ),
+            },
+        })
+    }
+}
+
+/// An OGG or MP3 file, which is downloaded and decoded when the game starts.
+#[derive(Deserialize, Clone)]
+pub struct AudioFile {
+    /// Relative to the page, or a path in the bundle the adventure was loaded
+    /// from
+    pub url: String,
+    /// Scales the volume of the recording
+    #[serde(default = "Song::default_volume")]
+    pub volume: f32,
+    /// Starts the recording over once it ended
+    #[serde(default, rename = "loop")]
+    pub looping: bool,
+}
+
+/// A picture, which is downloaded when the game starts.
+#[derive(Deserialize, Clone)]
+pub struct Image {
+    /// Relative to the page, or a path in the bundle the adventure was loaded
+    /// from
+    pub url: String,
+    /// Describes the picture to players who can't see it
+    #[serde(default)]
+    pub alt: String,
+}
+
+/// An instrument made of harmonics, which are added up to form its waveform.
+#[derive(Deserialize, Clone)]
+pub struct Instrument {
+    /// The amplitude of the fundamental and of each overtone, in order
+    pub harmonics: Vec<f32>,
+}
+
+/// The shape of the wave a voice is played with.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Waveform {
+    Sine,
+    Square,
+    Sawtooth,
+    Triangle,
+    /// The amplitudes of the harmonics
+    Custom(Vec<f32>),
+    /// Kicks, snares and hi-hats instead of pitched notes
+    Drums,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Song {
+    /// The length of a whole note in seconds. Not needed for MIDI files,
+    /// which set their own tempo.
+    #[serde(default)]
+    pub unit_length: f64,
+    /// The voices of the song. For MIDI files, each voice sets the instrument,
+    /// envelope and volume of the track with notes at its position, and its
+    /// notes are left empty.
+    #[serde(default)]
+    pub voices: Vec<SongVoice>,
+    /// A MIDI file with the notes of the song, relative to the page or a path
+    /// in the bundle the adventure was loaded from
+    #[serde(default)]
+    pub midi: Option<String>,
+    /// The content of the MIDI file. Filled in by the loader.
+    #[serde(skip)]
+    pub midi_data: Option<Vec<u8>>,
+    /// Scales the volume of all voices
+    #[serde(default = "Song::default_volume")]
+    pub volume: f32,
+    /// Starts the song over once it ended, e.g. for background music
+    #[serde(default, rename = "loop")]
+    pub looping: bool,
+    /// Seconds the song fades in when it starts. Without it, the song only
+    /// fades in when it replaces another one, over the crossfade.
+    #[serde(default)]
+    pub fade_in: Option<f64>,
+    /// Seconds the song fades out when it is replaced or stopped, instead of
+    /// the crossfade
+    #[serde(default)]
+    pub fade_out: Option<f64>,
+    /// Effects the voices send part of their sound to, see
+    /// [`SongVoice::sends`]
+    #[serde(default)]
+    pub effects: SongEffects,
+    /// Renders the song into audio once when the game starts, and plays that
+    /// instead of scheduling every note on each playback
+    #[serde(default)]
+    pub prerender: bool,
+    /// Groups of voices that only play while the game state meets their
+    /// dependencies, keyed by the name voices use in [`SongVoice::layer`]
+    #[serde(default)]
+    pub layers: HashMap<String, SongLayer>,
+}
+
+/// Voices of a song that fade in and out with the game state, without
+/// restarting the song.
 #[derive(Deserialize, Clone)]
-pub struct Adventure {
-    pub name: String,
-    pub start: String,
-    pub intro: String,
-    pub rooms: HashMap<String, Room>,
-    pub assets: AdventureAssets,
+pub struct SongLayer {
+    #[serde(default)]
+    pub depends: ActionDependencies,
+    /// Seconds the layer fades in or out when the game state changed
+    #[serde(default = "SongLayer::default_fade")]
+    pub fade: f64,
+}
+
+impl SongLayer {
+    fn default_fade() -> f64 {
+        1.0
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct SongEffects {
+    #[serde(default)]
+    pub reverb: Option<Reverb>,
+    #[serde(default)]
+    pub delay: Option<Delay>,
 }
 
+/// A reverb made of noise that fades out over `seconds`, faster the larger
+/// `decay` is.
 #[derive(Deserialize, Clone)]
-pub struct AdventureAssets {
-    pub music: HashMap<String, Song>,
+pub struct Reverb {
+    #[serde(default = "Reverb::default_seconds")]
+    pub seconds: f64,
+    #[serde(default = "Reverb::default_decay")]
+    pub decay: f64,
 }
 
+impl Reverb {
+    fn default_seconds() -> f64 {
+        2.0
+    }
+
+    fn default_decay() -> f64 {
+        2.0
+    }
+}
+
+/// Echoes that repeat every `seconds`, each one `feedback` times as loud as
+/// the one before it.
 #[derive(Deserialize, Clone)]
-pub struct Song {
-    pub unit_length: f64,
-    pub voices: Vec<SongVoice>,
+pub struct Delay {
+    pub seconds: f64,
+    #[serde(default = "Delay::default_feedback")]
+    pub feedback: f32,
+}
+
+impl Delay {
+    fn default_feedback() -> f32 {
+        0.4
+    }
+}
+
+impl Song {
+    fn default_volume() -> f32 {
+        1.0
+    }
+}
+
+/// A sound effect, either a burst of noise or a few notes written like the
+/// voices of songs.
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum SoundEffect {
+    Noise {
+        /// Seconds until the noise faded out
+        noise: f64,
+        /// Removes frequencies above this many Hz, for a duller sound
+        #[serde(default)]
+        lowpass: Option<f32>,
+        #[serde(default = "SoundEffect::default_volume")]
+        volume: f32,
+    },
+    Notes {
+        #[serde(default = "SoundEffect::default_unit_length")]
+        unit_length: f64,
+        #[serde(flatten)]
+        voice: SongVoice,
+    },
+}
+
+impl SoundEffect {
+    fn default_volume() -> f32 {
+        1.0
+    }
+
+    fn default_unit_length() -> f64 {
+        1.0
+    }
 }
 
 #[derive(Deserialize, Clone)]
 pub struct SongVoice {
+    /// A built-in waveform or one of the instruments of the assets, see
+    /// [`AdventureAssets::waveform`]
     pub instrument: String,
+    #[serde(default)]
     pub notes: String,
+    #[serde(default)]
+    pub envelope: Envelope,
+    /// Scales the volume of the voice, to balance it against the others
+    #[serde(default = "SongVoice::default_volume")]
+    pub volume: f32,
+    /// Wobbles the pitch, by up to `depth` semitones
+    #[serde(default)]
+    pub vibrato: Option<Modulation>,
+    /// Wobbles the volume, by up to `depth` times the full volume
+    #[serde(default)]
+    pub tremolo: Option<Modulation>,
+    /// Seconds each note slides from the pitch of the note before it
+    #[serde(default)]
+    pub glide: f64,
+    /// Plays the notes of chords one after another instead of together, this
+    /// many per second, cycling through them until the chord ends
+    #[serde(default)]
+    pub arpeggio: Option<f64>,
+    /// How much of the voice goes to the effects of the song
+    #[serde(default)]
+    pub sends: Sends,
+    /// The layer of the song the voice belongs to, see [`Song::layers`]
+    #[serde(default)]
+    pub layer: Option<String>,
+    /// Semitones every note is moved by, e.g. -12 to play a melody as a bass
+    /// line
+    #[serde(default)]
+    pub transpose: i64,
+    /// The octave the first note is in before its octave marks, where middle c
+    /// starts octave 4, see [`crate::notation`]
+    #[serde(default = "SongVoice::default_start_octave")]
+    pub start_octave: i64,
 }
 
-#[derive(Deserialize, Clone)]
+/// How loud a voice is in each effect of its song, where 1 is as loud as the
+/// voice itself.
+#[derive(Deserialize, Clone, Default)]
+pub struct Sends {
+    #[serde(default)]
+    pub reverb: f32,
+    #[serde(default)]
+    pub delay: f32,
+}
+
+/// A sine wave without notes, with everything else at its default.
+impl Default for SongVoice {
+    fn default() -> Self {
+        Self {
+            instrument: "sine".to_string(),
+            notes: String::new(),
+            envelope: Envelope::default(),
+            volume: Self::default_volume(),
+            vibrato: None,
+            tremolo: None,
+            glide: 0.0,
+            arpeggio: None,
+            sends: Sends::default(),
+            layer: None,
+            transpose: 0,
+            start_octave: Self::default_start_octave(),
+        }
+    }
+}
+
+impl SongVoice {
+    fn default_volume() -> f32 {
+        1.0
+    }
+
+    fn default_start_octave() -> i64 {
+        4
+    }
+
+    /// Describes the first invalid vibrato, tremolo, glide or arpeggio, if
+    /// there is one.
+    pub fn check_modulation(&self) -> Result<(), String> {
+        for (name, modulation) in [("vibrato", &self.vibrato), ("tremolo", &self.tremolo)] {
+            let Some(modulation) = modulation else {
+                continue;
+            };
+            if !(modulation.rate > 0.0 && modulation.rate.is_finite()) {
+                return Err(format!(
+                    "the {name}'s rate has to be a positive number, got {}",
+                    modulation.rate
+                ));
+            }
+            if !(modulation.depth >= 0.0 && modulation.depth.is_finite()) {
+                return Err(format!(
+                    "the {name}'s depth can't be negative, got {}",
+                    modulation.depth
+                ));
+            }
+        }
+        if self.tremolo.as_ref().is_some_and(|t| t.depth > 1.0) {
+            return Err("the tremolo's depth can't be larger than 1".to_string());
+        }
+        if !(self.glide >= 0.0 && self.glide.is_finite()) {
+            return Err(format!("the glide can't be negative, got {}", self.glide));
+        }
+        if let Some(rate) = self.arpeggio {
+            if !(rate > 0.0 && rate.is_finite()) {
+                return Err(format!(
+                    "the arpeggio has to play a positive number of notes per second, got {rate}"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A periodic change of the pitch or volume of a voice.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Modulation {
+    /// How often it changes per second
+    pub rate: f32,
+    /// How far it changes, see [`SongVoice::vibrato`] and
+    /// [`SongVoice::tremolo`]
+    pub depth: f32,
+}
+
+/// How the volume of each note rises and falls, in seconds. A note rises to
+/// full volume during `attack`, falls to `sustain` (a fraction of the full
+/// volume) during `decay`, and fades out during the last `release` seconds.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Envelope {
+    #[serde(default = "Envelope::default_attack")]
+    pub attack: f64,
+    #[serde(default = "Envelope::default_decay")]
+    pub decay: f64,
+    #[serde(default = "Envelope::default_sustain")]
+    pub sustain: f64,
+    #[serde(default = "Envelope::default_release")]
+    pub release: f64,
+}
+
+impl Envelope {
+    fn default_attack() -> f64 {
+        0.01
+    }
+
+    fn default_decay() -> f64 {
+        0.05
+    }
+
+    fn default_sustain() -> f64 {
+        0.8
+    }
+
+    fn default_release() -> f64 {
+        0.03
+    }
+
+    /// Describes the first invalid value, if there is one.
+    pub fn check(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("attack", self.attack),
+            ("decay", self.decay),
+            ("release", self.release),
+        ] {
+            if !(value >= 0.0 && value.is_finite()) {
+                return Err(format!("the envelope's {name} can't be negative, got {value}"));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.sustain) {
+            return Err(format!(
+                "the envelope's sustain has to be between 0 and 1, got {}",
+                self.sustain
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self {
+            attack: Self::default_attack(),
+            decay: Self::default_decay(),
+            sustain: Self::default_sustain(),
+            release: Self::default_release(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
 pub struct Room {
+    /// A human readable name, used wherever the room is listed. Defaults to
+    /// the room's key.
+    #[serde(default)]
+    pub title: Option<String>,
     pub description: String,
+    /// ASCII art shown above the description, exactly as written
+    #[serde(default)]
+    pub art: String,
     pub actions: Vec<Action>,
+    #[serde(default)]
+    pub fast_travel: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Filters the music while the player is in the room.
+    #[serde(default)]
+    pub audio_profile: Option<AudioProfile>,
+    /// Plays while the player is in the room, unless it already plays. It
+    /// stops when the player enters a room without music.
+    #[serde(default)]
+    pub music: Option<String>,
+    /// An image of the assets, shown above the text while the player is in
+    /// the room
+    #[serde(default)]
+    pub image: Option<String>,
+    /// Replaces the adventure's theme while the player is in the room. Only
+    /// the parts it sets are replaced.
+    #[serde(default)]
+    pub theme: Option<Theme>,
+    /// Replaces parts of the room during the day, if the clock is enabled.
+    #[serde(default)]
+    pub day: Option<RoomVariant>,
+    /// Replaces parts of the room during the night, if the clock is enabled.
+    #[serde(default)]
+    pub night: Option<RoomVariant>,
+    /// Reaching an ending shows the player's statistics
+    #[serde(default)]
+    pub ending: bool,
+    /// Things in the room the player can use items on
+    #[serde(default)]
+    pub use_targets: Vec<UseTarget>,
+    /// Starts a countdown when the room is shown. If the player doesn't act
+    /// in time, `timeout` happens.
+    #[serde(default)]
+    pub time_limit_seconds: Option<f64>,
+    #[serde(default)]
+    pub timeout: Outcome,
+    /// How the room's actions are ordered, after their `order`
+    #[serde(default)]
+    pub sort_actions: ActionSorting,
+    /// Run when the player enters the room
+    #[serde(default)]
+    pub on_enter: Vec<RoomHook>,
+    /// Run when the player leaves the room
+    #[serde(default)]
+    pub on_exit: Vec<RoomHook>,
 }
 
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionSorting {
+    /// In the order they are written in
+    #[default]
+    AsAuthored,
+    /// Alphabetically by name
+    Alphabetical,
+    /// Actions that change the room go after all others
+    TransitionsLast,
+}
+
+/// Effects that happen automatically when a room is entered or left.
 #[derive(Deserialize, Clone, Default)]
-pub struct Action {
+pub struct RoomHook {
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub yields: Vec<String>,
+    /// Flags to set
+    #[serde(default)]
+    pub sets: Vec<String>,
+    /// Flags to clear
+    #[serde(default)]
+    pub clears: Vec<String>,
+    #[serde(default)]
+    pub music: Option<String>,
+    #[serde(default)]
+    pub depends: ActionDependencies,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct UseTarget {
     pub name: String,
     #[serde(default)]
+    pub depends: ActionDependencies,
+    /// What happens when an item is used on the target, keyed by the item
+    #[serde(default)]
+    pub outcomes: HashMap<String, UseOutcome>,
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct UseOutcome {
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
     pub yields: Vec<String>,
+    #[serde(default)]
+    pub consumes: Vec<String>,
+    #[serde(default)]
+    pub transition: Option<String>,
+    #[serde(default)]
+    pub score: i64,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RoomVariant {
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub image: Option<String>,
+    #[serde(default)]
+    pub actions: Option<Vec<Action>>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioProfile {
+    /// A short, dense reverb
+    Cave,
+    /// A long reverb
+    Hall,
+    /// A strong low-pass filter
+    Underwater,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(try_from = "RawAction")]
+pub struct Action {
+    /// Identifies the action when the actions are re-rendered, defaults to
+    /// the name
+    pub id: Option<String>,
+    /// Shown if none of the conditional names apply
+    pub name: String,
+    /// Names that depend on the game state. The first one whose dependencies
+    /// are met is shown.
+    pub conditional_names: Vec<ConditionalText>,
+    pub text: String,
+    /// ASCII art shown above the text, exactly as written
+    pub art: String,
+    pub depends: ActionDependencies,
+    pub music: Option<MusicTrigger>,
+    /// A sound effect of the assets to play
+    pub sfx: Option<String>,
+    /// How many hours the action takes, overriding the clock's default.
+    pub time: Option<u64>,
+    /// Counts the action as a failed attempt at the given check.
+    pub fails: Option<String>,
+    /// Records enemies as defeated in the bestiary.
+    pub defeats: Vec<String>,
+    /// Items that are removed from the inventory
+    pub consumes: Vec<String>,
+    /// Restores (or drains) meters by the given amounts
+    pub meters: HashMap<String, i64>,
+    /// Points added to the player's score
+    pub score: i64,
+    /// Changes the reputation with factions by the given amounts
+    pub reputation: HashMap<String, i64>,
+    /// Asks the player this question before running the action
+    pub confirm: Option<String>,
+    /// Flags to set
+    pub sets: Vec<String>,
+    /// Flags to clear
+    pub clears: Vec<String>,
+    /// Actions with a lower order are shown first
+    pub order: i64,
+    /// Starts a countdown while the action is offered. If the player doesn't
+    /// act in time, `timeout` happens.
+    pub time_limit_seconds: Option<f64>,
+    pub timeout: Outcome,
+    /// Shows the text in a dialog on top of the room, which keeps the room's
+    /// description in view
+    pub modal: bool,
+    /// Offered in the dialog of a modal action
+    pub choices: Vec<Action>,
+    pub kind: ActionKind,
+}
+
+impl Action {
+    pub fn id(&self) -> &str {
+        self.id.as_ref().unwrap_or(&self.name)
+    }
+
+    /// The name to show in the current state of the game.
+    pub fn label(&self, context: &DependencyContext) -> &str {
+        self.conditional_names
+            .iter()
+            .find(|n| n.depends.is_met(context))
+            .map(|n| &n.text)
+            .unwrap_or(&self.name)
+    }
+
+    /// The room the action is known to lead to, if any.
+    pub fn transition(&self) -> Option<&String> {
+        match &self.kind {
+            ActionKind::Navigate { transition } => Some(transition),
+            ActionKind::Custom { transition, .. } => transition.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// What an action does, selected with its `type` field. Actions without a type
+/// are `Custom` actions, which was the only kind of action in older
+/// adventures.
+#[derive(Clone)]
+pub enum ActionKind {
+    /// Moves the player to another room
+    Navigate { transition: String },
+    /// Gives the player items
+    GainItems { yields: Vec<String> },
+    /// Shows a conversation, line by line. Afterwards, `npc` remembers the
+    /// topics in `remembers`.
+    Dialogue {
+        lines: Vec<DialogueLine>,
+        npc: Option<String>,
+        remembers: Vec<String>,
+    },
+    /// Succeeds with the given chance, failures are counted per check
+    Check {
+        check: String,
+        chance: f64,
+        success: Outcome,
+        failure: Outcome,
+    },
+    /// Opens a menu that trades items for other items
+    Shop { offers: Vec<Offer>, leave: String },
+    /// Any combination of items and a transition
+    Custom {
+        yields: Vec<String>,
+        transition: Option<String>,
+    },
+}
+
+impl Default for ActionKind {
+    fn default() -> Self {
+        Self::Custom {
+            yields: Vec::new(),
+            transition: None,
+        }
+    }
+}
+
+/// One variant of a text that changes with the game state.
+#[derive(Deserialize, Clone)]
+pub struct ConditionalText {
+    pub text: String,
+    #[serde(default)]
+    pub depends: ActionDependencies,
+}
+
+/// Either a plain text, or a list of variants of which the first one whose
+/// dependencies are met is used. The last variant should have no dependencies.
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+enum TextRepr {
+    Plain(String),
+    Conditional(Vec<ConditionalText>),
+}
+
+#[derive(Deserialize, Clone)]
+pub struct DialogueLine {
+    #[serde(default)]
+    pub speaker: Option<String>,
     pub text: String,
+    /// Skips the line if the dependencies aren't met, e.g. to not repeat
+    /// something the npc remembers telling the player
     #[serde(default)]
     pub depends: ActionDependencies,
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct Outcome {
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub yields: Vec<String>,
     #[serde(default)]
     pub transition: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Offer {
+    pub item: String,
+    /// The name of the action buying the item, defaults to "Buy <item>"
     #[serde(default)]
-    pub music: Option<String>,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub text: String,
+    /// Items that are traded in for the offered one
+    #[serde(default)]
+    pub costs: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+enum ActionType {
+    Navigate,
+    GainItems,
+    Dialogue,
+    Check,
+    Shop,
+    Custom,
+}
+
+impl ActionType {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Navigate => "navigate",
+            Self::GainItems => "gain_items",
+            Self::Dialogue => "dialogue",
+            Self::Check => "check",
+            Self::Shop => "shop",
+            Self::Custom => "custom",
+        }
+    }
+
+    /// The type specific fields that make sense for this type of action
+    fn fields(&self) -> &'static [&'static str] {
+        match self {
+            Self::Navigate => &["transition"],
+            Self::GainItems => &["yields"],
+            Self::Dialogue => &["lines", "npc", "remembers"],
+            Self::Check => &["check", "chance", "success", "failure"],
+            Self::Shop => &["offers", "leave"],
+            Self::Custom => &["yields", "transition"],
+        }
+    }
+}
+
+/// The action as written in the adventure file, with the fields of all action
+/// types.
+#[derive(Deserialize)]
+struct RawAction {
+    #[serde(default)]
+    id: Option<String>,
+    name: TextRepr,
+    #[serde(default, rename = "type")]
+    action_type: Option<ActionType>,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    art: String,
+    #[serde(default)]
+    depends: ActionDependencies,
+    #[serde(default)]
+    music: Option<MusicTrigger>,
+    #[serde(default)]
+    sfx: Option<String>,
+    #[serde(default)]
+    time: Option<u64>,
+    #[serde(default)]
+    fails: Option<String>,
+    #[serde(default)]
+    defeats: Vec<String>,
+    #[serde(default)]
+    consumes: Vec<String>,
+    #[serde(default)]
+    meters: HashMap<String, i64>,
+    #[serde(default)]
+    score: i64,
+    #[serde(default)]
+    reputation: HashMap<String, i64>,
+    #[serde(default)]
+    confirm: Option<String>,
+    #[serde(default)]
+    sets: Vec<String>,
+    #[serde(default)]
+    clears: Vec<String>,
+    #[serde(default)]
+    order: i64,
+    #[serde(default)]
+    time_limit_seconds: Option<f64>,
+    #[serde(default)]
+    timeout: Outcome,
+    #[serde(default)]
+    modal: bool,
+    #[serde(default)]
+    choices: Vec<Action>,
+
+    #[serde(default)]
+    yields: Option<Vec<String>>,
+    #[serde(default)]
+    transition: Option<String>,
+    #[serde(default)]
+    lines: Option<Vec<DialogueLine>>,
+    #[serde(default)]
+    npc: Option<String>,
+    #[serde(default)]
+    remembers: Option<Vec<String>>,
+    #[serde(default)]
+    check: Option<String>,
+    #[serde(default)]
+    chance: Option<f64>,
+    #[serde(default)]
+    success: Option<Outcome>,
+    #[serde(default)]
+    failure: Option<Outcome>,
+    #[serde(default)]
+    offers: Option<Vec<Offer>>,
+    #[serde(default)]
+    leave: Option<String>,
+}
+
+impl TryFrom<RawAction> for Action {
+    type Error = String;
+
+    fn try_from(raw: RawAction) -> Result<Self, Self::Error> {
+        let action_type = raw.action_type.unwrap_or(ActionType::Custom);
+
+        // The last variant is the fallback, and identifies the action in errors
+        let (name, conditional_names) = match raw.name {
+            TextRepr::Plain(name) => (name, Vec::new()),
+            TextRepr::Conditional(mut names) => match names.pop() {
+                Some(last) => (last.text, names),
+                None => return Err("an action's name can't be empty".to_string()),
+            },
+        };
+
+        let present = [
+            ("yields", raw.yields.is_some()),
+            ("transition", raw.transition.is_some()),
+            ("lines", raw.lines.is_some()),
+            ("npc", raw.npc.is_some()),
+            ("remembers", raw.remembers.is_some()),
+            ("check", raw.check.is_some()),
+            ("chance", raw.chance.is_some()),
+            ("success", raw.success.is_some()),
+            ("failure", raw.failure.is_some()),
+            ("offers", raw.offers.is_some()),
+            ("leave", raw.leave.is_some()),
+        ];
+        if let Some((field, _)) = present
+            .iter()
+            .find(|(field, present)| *present && !action_type.fields().contains(field))
+        {
+            return Err(format!(
+                "{} action '{}' can't have a '{field}'",
+                action_type.name(),
+                name
+            ));
+        }
+
+        // The dialog would hide the new room, or the shop's offers
+        let replaces_room = matches!(action_type, ActionType::Navigate | ActionType::Shop)
+            || raw.transition.is_some();
+        if raw.modal && replaces_room {
+            return Err(format!(
+                "{} action '{}' can't be modal",
+                action_type.name(),
+                name
+            ));
+        }
+        if !raw.modal && !raw.choices.is_empty() {
+            return Err(format!(
+                "action '{name}' only shows its choices if it is modal"
+            ));
+        }
+
+        let missing = |field: &str| {
+            format!(
+                "{} action '{}' needs a '{field}'",
+                action_type.name(),
+                name
+            )
+        };
+
+        let kind = match action_type {
+            ActionType::Navigate => ActionKind::Navigate {
+                transition: raw.transition.ok_or_else(|| missing("transition"))?,
+            },
+            ActionType::GainItems => ActionKind::GainItems {
+                yields: raw.yields.ok_or_else(|| missing("yields"))?,
+            },
+            ActionType::Dialogue => ActionKind::Dialogue {
+                lines: raw.lines.ok_or_else(|| missing("lines"))?,
+                remembers: match (&raw.npc, raw.remembers) {
+                    (None, Some(_)) => return Err(missing("npc")),
+                    (_, remembers) => remembers.unwrap_or_default(),
+                },
+                npc: raw.npc,
+            },
+            ActionType::Check => ActionKind::Check {
+                check: raw.check.ok_or_else(|| missing("check"))?,
+                chance: raw.chance.ok_or_else(|| missing("chance"))?,
+                success: raw.success.unwrap_or_default(),
+                failure: raw.failure.unwrap_or_default(),
+            },
+            ActionType::Shop => ActionKind::Shop {
+                offers: raw.offers.ok_or_else(|| missing("offers"))?,
+                leave: raw.leave.unwrap_or_else(|| "Leave".to_string()),
+            },
+            ActionType::Custom => ActionKind::Custom {
+                yields: raw.yields.unwrap_or_default(),
+                transition: raw.transition,
+            },
+        };
+
+        Ok(Self {
+            id: raw.id,
+            name,
+            conditional_names,
+            text: raw.text,
+            art: raw.art,
+            depends: raw.depends,
+            music: raw.music,
+            sfx: raw.sfx,
+            time: raw.time,
+            fails: raw.fails,
+            defeats: raw.defeats,
+            consumes: raw.consumes,
+            meters: raw.meters,
+            score: raw.score,
+            reputation: raw.reputation,
+            confirm: raw.confirm,
+            sets: raw.sets,
+            clears: raw.clears,
+            order: raw.order,
+            time_limit_seconds: raw.time_limit_seconds,
+            timeout: raw.timeout,
+            modal: raw.modal,
+            choices: raw.choices,
+            kind,
+        })
+    }
 }
 
 #[derive(Deserialize, Clone, Default)]
@@ -59,4 +2180,208 @@ pub struct ActionDependencies {
     pub not: Vec<String>,
     #[serde(default)]
     pub on: Vec<String>,
+    #[serde(default)]
+    pub time: Option<TimeOfDay>,
+    /// Requires the player to be playing as this character
+    #[serde(default)]
+    pub character: Option<String>,
+    /// Compares the number of failed attempts per check, e.g. `lockpick: ">= 3"`
+    #[serde(default)]
+    pub failures: HashMap<String, Comparison>,
+    /// Requires the game to be, or not to be, a New Game+
+    #[serde(default)]
+    pub ng_plus: Option<bool>,
+    /// Requires the game to be played on this difficulty
+    #[serde(default)]
+    pub difficulty: Option<String>,
+    /// Flags that have to be set
+    #[serde(default)]
+    pub flags: Vec<String>,
+    /// Flags that must not be set
+    #[serde(default)]
+    pub not_flags: Vec<String>,
+    /// Compares the reputation with factions, e.g. `guards: ">= 10"`
+    #[serde(default)]
+    pub reputation: HashMap<String, Comparison>,
+    /// Topics npcs have to remember, keyed by the npc
+    #[serde(default)]
+    pub remembers: HashMap<String, Vec<String>>,
+    /// Topics npcs must not remember yet, keyed by the npc
+    #[serde(default)]
+    pub not_remembers: HashMap<String, Vec<String>>,
+}
+
+/// The parts of the game state that dependencies are checked against.
+pub struct DependencyContext<'a> {
+    pub inventory: &'a HashSet<String>,
+    pub time_of_day: TimeOfDay,
+    pub character: &'a str,
+    pub failures: &'a HashMap<String, i64>,
+    pub ng_plus: bool,
+    pub difficulty: &'a str,
+    pub flags: &'a HashSet<String>,
+    pub reputation: &'a HashMap<String, i64>,
+    /// The topics each npc remembers
+    pub memory: &'a HashMap<String, HashSet<String>>,
+}
+
+impl DependencyContext<'_> {
+    fn remembers(&self, npc: &str, topic: &str) -> bool {
+        self.memory.get(npc).is_some_and(|m| m.contains(topic))
+    }
+}
+
+impl ActionDependencies {
+    pub fn is_met(&self, context: &DependencyContext) -> bool {
+        self.on.iter().all(|item| context.inventory.contains(item))
+            && !self.not.iter().any(|item| context.inventory.contains(item))
+            && self.time.is_none_or(|t| t == context.time_of_day)
+            && self
+                .character
+                .as_ref()
+                .is_none_or(|c| c == context.character)
+            && self.failures.iter().all(|(check, comparison)| {
+                comparison.matches(context.failures.get(check).copied().unwrap_or_default())
+            })
+            && self.ng_plus.is_none_or(|n| n == context.ng_plus)
+            && self
+                .difficulty
+                .as_ref()
+                .is_none_or(|d| d == context.difficulty)
+            && self.flags.iter().all(|flag| context.flags.contains(flag))
+            && !self.not_flags.iter().any(|flag| context.flags.contains(flag))
+            && self.reputation.iter().all(|(faction, comparison)| {
+                comparison.matches(context.reputation.get(faction).copied().unwrap_or_default())
+            })
+            && self.remembers.iter().all(|(npc, topics)| {
+                topics.iter().all(|topic| context.remembers(npc, topic))
+            })
+            && !self.not_remembers.iter().any(|(npc, topics)| {
+                topics.iter().any(|topic| context.remembers(npc, topic))
+            })
+    }
+}
+
+/// A comparison against a number, written like `">= 3"`. A bare number means
+/// "at least".
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(try_from = "ComparisonRepr")]
+pub struct Comparison {
+    pub operator: Operator,
+    pub value: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    pub fn matches(&self, value: i64) -> bool {
+        match self.operator {
+            Operator::Less => value < self.value,
+            Operator::LessOrEqual => value <= self.value,
+            Operator::Equal => value == self.value,
+            Operator::NotEqual => value != self.value,
+            Operator::GreaterOrEqual => value >= self.value,
+            Operator::Greater => value > self.value,
+        }
+    }
+}
+
+/// The song an action plays, with fades that replace those of the songs.
+#[derive(Deserialize, Clone)]
+#[serde(from = "MusicTriggerRepr")]
+pub struct MusicTrigger {
+    pub song: String,
+    /// Seconds the song fades in
+    pub fade_in: Option<f64>,
+    /// Seconds the song that was playing fades out
+    pub fade_out: Option<f64>,
+}
+
+/// Either just the name of the song, or the song with its fades.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MusicTriggerRepr {
+    Song(String),
+    Fading {
+        song: String,
+        #[serde(default)]
+        fade_in: Option<f64>,
+        #[serde(default)]
+        fade_out: Option<f64>,
+    },
+}
+
+impl From<MusicTriggerRepr> for MusicTrigger {
+    fn from(value: MusicTriggerRepr) -> Self {
+        match value {
+            MusicTriggerRepr::Song(song) => Self {
+                song,
+                fade_in: None,
+                fade_out: None,
+            },
+            MusicTriggerRepr::Fading {
+                song,
+                fade_in,
+                fade_out,
+            } => Self {
+                song,
+                fade_in,
+                fade_out,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ComparisonRepr {
+    Number(i64),
+    Text(String),
+}
+
+impl TryFrom<ComparisonRepr> for Comparison {
+    type Error = String;
+
+    fn try_from(value: ComparisonRepr) -> Result<Self, Self::Error> {
+        let text = match value {
+            ComparisonRepr::Number(value) => {
+                return Ok(Self {
+                    operator: Operator::GreaterOrEqual,
+                    value,
+                })
+            }
+            ComparisonRepr::Text(text) => text,
+        };
+
+        let trimmed = text.trim();
+        // Longer operators need to be checked first
+        let operators = [
+            ("<=", Operator::LessOrEqual),
+            (">=", Operator::GreaterOrEqual),
+            ("==", Operator::Equal),
+            ("!=", Operator::NotEqual),
+            ("<", Operator::Less),
+            (">", Operator::Greater),
+            ("=", Operator::Equal),
+        ];
+        let (operator, rest) = operators
+            .iter()
+            .find_map(|(prefix, op)| trimmed.strip_prefix(prefix).map(|rest| (*op, rest)))
+            .unwrap_or((Operator::GreaterOrEqual, trimmed));
+
+        let value = rest
+            .trim()
+            .parse::<i64>()
+            .map_err(|_| format!("malformed comparison '{text}', expected e.g. '>= 3'"))?;
+
+        Ok(Self { operator, value })
+    }
 }
@@ -0,0 +1,297 @@
+//! Looks for parts of an adventure the player can never see: rooms that can't
+//! be reached, actions whose dependencies can never be met and rooms the
+//! player can't leave. Unlike [`crate::validation`], these are only warnings,
+//! as an adventure might be unfinished on purpose.
+
+use std::{
+  collections::{HashMap, HashSet, VecDeque},
+  fmt,
+};
+
+use crate::{
+  adventure::{Action, ActionKind, Adventure, Outcome, Room},
+  markdown,
+};
+
+pub enum Warning {
+  UnreachableRoom(String),
+  UnreachableEnding(String),
+  /// The room has no way out and isn't an ending
+  DeadEnd(String),
+  /// The action depends on an item or flag that is never given
+  Unsatisfiable {
+    room: String,
+    action: String,
+    requirement: String,
+  },
+  /// The description links to something that looks like an action id, but
+  /// the room has no such action
+  UnknownKeyword {
+    room: String,
+    target: String,
+  },
+}
+
+impl fmt::Display for Warning {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::UnreachableRoom(room) => write!(f, "room {room}: can't be reached"),
+      Self::UnreachableEnding(room) => write!(f, "room {room}: the ending can't be reached"),
+      Self::DeadEnd(room) => write!(f, "room {room}: has no exits and is not an ending"),
+      Self::Unsatisfiable {
+        room,
+        action,
+        requirement,
+      } => write!(
+        f,
+        "room {room}, action {action}: depends on {requirement}, which is never given"
+      ),
+      Self::UnknownKeyword { room, target } => write!(
+        f,
+        "room {room}: the description links to {target}, which is not one of its actions"
+      ),
+    }
+  }
+}
+
+/// Returns all warnings for the adventure, sorted by room.
+pub fn analyze(adventure: &Adventure) -> Vec<Warning> {
+  let mut rooms: Vec<(&String, &Room)> = adventure.rooms.iter().collect();
+  rooms.sort_by_key(|(id, _)| id.as_str());
+
+  let exits: HashMap<&str, Vec<&str>> = rooms
+    .iter()
+    .map(|(id, room)| (id.as_str(), exits(adventure, room)))
+    .collect();
+  let reachable = reachable(adventure, &exits);
+  let (items, flags) = given(adventure);
+
+  let mut warnings = Vec::new();
+  for (id, room) in rooms {
+    if !reachable.contains(id.as_str()) {
+      warnings.push(match room.ending {
+        true => Warning::UnreachableEnding(id.clone()),
+        false => Warning::UnreachableRoom(id.clone()),
+      });
+    }
+    if !room.ending && exits[id.as_str()].is_empty() {
+      warnings.push(Warning::DeadEnd(id.clone()));
+    }
+
+    for action in all_actions(room) {
+      let missing = action
+        .depends
+        .on
+        .iter()
+        .find(|item| !items.contains(item.as_str()))
+        .map(|item| format!("the item {item}"))
+        .or_else(|| {
+          action
+            .depends
+            .flags
+            .iter()
+            .find(|flag| !flags.contains(flag.as_str()))
+            .map(|flag| format!("the flag {flag}"))
+        });
+      if let Some(requirement) = missing {
+        warnings.push(Warning::Unsatisfiable {
+          room: id.clone(),
+          action: action.name.clone(),
+          requirement,
+        });
+      }
+    }
+
+    for target in unknown_keywords(room) {
+      warnings.push(Warning::UnknownKeyword {
+        room: id.clone(),
+        target,
+      });
+    }
+  }
+  warnings
+}
+
+/// The link targets in the descriptions of `room` that look like action ids
+/// rather than urls, but aren't the id of one of its actions. The descriptions
+/// are already rendered, where links to actions became keywords.
+fn unknown_keywords(room: &Room) -> Vec<String> {
+  let ids = markdown::action_ids(room);
+  let variants = [&room.day, &room.night]
+    .into_iter()
+    .flatten()
+    .filter_map(|v| v.description.as_ref());
+  let mut unknown: Vec<String> = Vec::new();
+  for description in std::iter::once(&room.description).chain(variants) {
+    for link in description.split("href=\"").skip(1) {
+      let Some((target, _)) = link.split_once('"') else {
+        continue;
+      };
+      let looks_like_id = !target.is_empty()
+        && target
+          .chars()
+          .all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+      let known = ids.iter().any(|id| id == target) || unknown.iter().any(|u| u == target);
+      if looks_like_id && !known {
+        unknown.push(target.to_string());
+      }
+    }
+  }
+  unknown
+}
+
+/// The actions of the room, including the ones only offered by day or night
+/// and the choices of modal actions.
+fn all_actions(room: &Room) -> Vec<&Action> {
+  let variants = [&room.day, &room.night]
+    .into_iter()
+    .flatten()
+    .filter_map(|v| v.actions.as_ref())
+    .flatten();
+  let mut actions: Vec<&Action> = room.actions.iter().chain(variants).collect();
+  let mut index = 0;
+  while let Some(action) = actions.get(index).copied() {
+    actions.extend(&action.choices);
+    index += 1;
+  }
+  actions
+}
+
+/// The rooms the room leads to, including encounters on the way.
+fn exits<'a>(adventure: &'a Adventure, room: &'a Room) -> Vec<&'a str> {
+  let mut outcomes: Vec<&Outcome> = vec![&room.timeout];
+  let mut exits: Vec<&str> = Vec::new();
+
+  for action in all_actions(room) {
+    exits.extend(action.transition().map(|t| t.as_str()));
+    outcomes.push(&action.timeout);
+    if let ActionKind::Check {
+      success, failure, ..
+    } = &action.kind
+    {
+      outcomes.push(success);
+      outcomes.push(failure);
+    }
+  }
+  for target in &room.use_targets {
+    exits.extend(
+      target
+        .outcomes
+        .values()
+        .filter_map(|o| o.transition.as_deref()),
+    );
+  }
+  exits.extend(outcomes.iter().filter_map(|o| o.transition.as_deref()));
+
+  // Encounters divert the player on the way into a tagged room
+  let encounters = exits
+    .iter()
+    .filter_map(|exit| adventure.rooms.get(*exit))
+    .flat_map(|destination| &destination.tags)
+    .filter_map(|tag| adventure.encounters.get(tag))
+    .flat_map(|table| table.rooms.iter().map(|r| r.as_str()))
+    .collect::<Vec<_>>();
+  exits.extend(encounters);
+  exits
+}
+
+fn reachable<'a>(
+  adventure: &'a Adventure,
+  exits: &HashMap<&'a str, Vec<&'a str>>,
+) -> HashSet<&'a str> {
+  // Codes, meter thresholds and the targets on items can move the player from
+  // anywhere
+  let mut queue: VecDeque<&str> = VecDeque::from([adventure.start.as_str()]);
+  queue.extend(
+    adventure
+      .characters
+      .playable
+      .values()
+      .filter_map(|c| c.start.as_deref()),
+  );
+  queue.extend(
+    adventure
+      .codes
+      .values()
+      .filter_map(|c| c.transition.as_deref()),
+  );
+  queue.extend(
+    adventure
+      .meters
+      .values()
+      .flat_map(|m| &m.thresholds)
+      .filter_map(|t| t.transition.as_deref()),
+  );
+  queue.extend(
+    adventure
+      .items
+      .values()
+      .flat_map(|i| &i.use_targets)
+      .flat_map(|t| t.outcomes.values())
+      .filter_map(|o| o.transition.as_deref()),
+  );
+
+  let mut reachable = HashSet::new();
+  while let Some(room) = queue.pop_front() {
+    if reachable.insert(room) {
+      queue.extend(exits.get(room).into_iter().flatten());
+    }
+  }
+  reachable
+}
+
+/// Every item and flag the player can get somewhere in the adventure.
+fn given(adventure: &Adventure) -> (HashSet<&str>, HashSet<&str>) {
+  let mut items: HashSet<&str> = HashSet::new();
+  let mut flags: HashSet<&str> = HashSet::new();
+
+  for code in adventure.codes.values() {
+    items.extend(code.items.iter().map(|i| i.as_str()));
+    flags.extend(code.flags.iter().map(|f| f.as_str()));
+  }
+  for difficulty in &adventure.difficulties {
+    items.extend(difficulty.items.iter().map(|i| i.as_str()));
+  }
+  for character in adventure.characters.playable.values() {
+    items.extend(character.items.iter().map(|i| i.as_str()));
+  }
+
+  for item in adventure.items.values() {
+    for outcome in item.use_targets.iter().flat_map(|t| t.outcomes.values()) {
+      items.extend(outcome.yields.iter().map(|i| i.as_str()));
+    }
+  }
+
+  for room in adventure.rooms.values() {
+    let mut outcomes: Vec<&Outcome> = vec![&room.timeout];
+    for hook in room.on_enter.iter().chain(&room.on_exit) {
+      items.extend(hook.yields.iter().map(|i| i.as_str()));
+      flags.extend(hook.sets.iter().map(|f| f.as_str()));
+    }
+    for outcome in room.use_targets.iter().flat_map(|t| t.outcomes.values()) {
+      items.extend(outcome.yields.iter().map(|i| i.as_str()));
+    }
+
+    for action in all_actions(room) {
+      flags.extend(action.sets.iter().map(|f| f.as_str()));
+      outcomes.push(&action.timeout);
+      match &action.kind {
+        ActionKind::GainItems { yields } | ActionKind::Custom { yields, .. } => {
+          items.extend(yields.iter().map(|i| i.as_str()))
+        }
+        ActionKind::Check {
+          success, failure, ..
+        } => {
+          outcomes.push(success);
+          outcomes.push(failure);
+        }
+        ActionKind::Shop { offers, .. } => items.extend(offers.iter().map(|o| o.item.as_str())),
+        _ => {}
+      }
+    }
+    for outcome in outcomes {
+      items.extend(outcome.yields.iter().map(|i| i.as_str()));
+    }
+  }
+  (items, flags)
+}
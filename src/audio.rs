@@ -1,228 +1,1828 @@
-use std::collections::HashMap;
+use std::{
+  cell::{Cell, RefCell},
+  collections::HashMap,
+  future::Future,
+  rc::Rc,
+};
 
 use anyhow::{anyhow, Context, Result};
-use web_sys::{AudioContext, OscillatorNode, OscillatorType};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::{closure::Closure, JsCast};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+  AudioBuffer, AudioBufferSourceNode, AudioContext, AudioContextState, AudioNode, AudioParam,
+  AudioScheduledSourceNode, BaseAudioContext, BiquadFilterType, GainNode, OfflineAudioContext,
+  OscillatorNode, OscillatorType, PeriodicWave,
+};
 
-use crate::adventure;
+use crate::{
+  adventure::{
+    self, ActionDependencies, AdventureAssets, AudioFile, AudioProfile, DependencyContext,
+    Envelope, Modulation, Sends, SongEffects, SoundEffect, Waveform,
+  },
+  js_to_anyhow, loader, midi, notation, sheet,
+};
+
+/// Where the volume the player picked is kept. It applies to every adventure,
+/// so it stays in local storage like the language.
+pub(crate) const VOLUME_KEY: &str = "textadventure_volume";
+
+/// The volume the player picked for the music.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Volume {
+  /// Between 0 and 1
+  pub level: f32,
+  pub muted: bool,
+}
+
+impl Default for Volume {
+  fn default() -> Self {
+    Self {
+      level: 1.0,
+      muted: false,
+    }
+  }
+}
+
+impl Volume {
+  /// The volume stored by [`Volume::store`], or the full volume.
+  pub fn stored() -> Self {
+    web_sys::window()
+      .and_then(|w| w.local_storage().ok().flatten())
+      .and_then(|s| s.get_item(VOLUME_KEY).ok().flatten())
+      .and_then(|raw| serde_json::from_str(&raw).ok())
+      .unwrap_or_default()
+  }
+
+  pub fn store(&self) -> Result<()> {
+    web_sys::window()
+      .ok_or_else(|| anyhow!("unable to get the window"))?
+      .local_storage()
+      .map_err(js_to_anyhow)?
+      .ok_or_else(|| anyhow!("local storage is not available"))?
+      .set_item(VOLUME_KEY, &serde_json::to_string(self)?)
+      .map_err(js_to_anyhow)
+  }
+}
+
+/// Plays all songs in one audio context, through a master gain that sets the
+/// volume of the music. Sound effects have their own gain, which is only
+/// silenced while the music is muted.
+pub struct Mixer {
+  context: AudioContext,
+  master: GainNode,
+  effects: GainNode,
+}
+
+impl Mixer {
+  pub fn new(volume: Volume) -> Result<Self> {
+    let context = AudioContext::new()
+      .map_err(js_to_anyhow)
+      .context("unable to get an audio context")?;
+
+    let master = context
+      .create_gain()
+      .map_err(js_to_anyhow)
+      .context("unable to create a gain node")?;
+    master
+      .connect_with_audio_node(&context.destination())
+      .map_err(js_to_anyhow)
+      .context("unable to connect the music to the final output")?;
+
+    let effects = context
+      .create_gain()
+      .map_err(js_to_anyhow)
+      .context("unable to create a gain node")?;
+    effects
+      .connect_with_audio_node(&context.destination())
+      .map_err(js_to_anyhow)
+      .context("unable to connect the sound effects to the final output")?;
+
+    let mixer = Self {
+      context,
+      master,
+      effects,
+    };
+    mixer.set_volume(volume);
+    Ok(mixer)
+  }
+
+  /// Whether the audio is suspended, which browsers do until the player
+  /// interacted with the page.
+  pub fn suspended(&self) -> bool {
+    self.context.state() == AudioContextState::Suspended
+  }
+
+  /// Asks the browser to start the audio, which it only allows after the
+  /// player interacted with the page. The returned future finishes once the
+  /// browser decided.
+  pub fn unlock(&self) -> impl Future<Output = Result<()>> {
+    let resumed = self.context.resume();
+    async move {
+      let promise = resumed.map_err(js_to_anyhow)?;
+      JsFuture::from(promise)
+        .await
+        .map_err(js_to_anyhow)
+        .context("the browser didn't allow the audio to start")?;
+      Ok(())
+    }
+  }
+
+  /// Halts the music and sound effects where they are. The time of the audio
+  /// context stands still while it is suspended, so scheduled notes play on
+  /// in time once it is resumed.
+  pub fn pause(&self) -> Result<()> {
+    self
+      .context
+      .suspend()
+      .map(|_| ())
+      .map_err(js_to_anyhow)
+      .context("unable to suspend the audio")
+  }
+
+  /// Continues what [`Mixer::pause`] halted.
+  pub fn resume(&self) -> Result<()> {
+    self
+      .context
+      .resume()
+      .map(|_| ())
+      .map_err(js_to_anyhow)
+      .context("unable to resume the audio")
+  }
+
+  pub fn set_volume(&self, volume: Volume) {
+    let level = if volume.muted { 0.0 } else { volume.level };
+    self.master.gain().set_value(level);
+    self
+      .effects
+      .gain()
+      .set_value(if volume.muted { 0.0 } else { 1.0 });
+  }
+}
+
+/// Seconds before its start that the next pass of a looping song is queued
+const LOOKAHEAD: f64 = 2.0;
+
+/// Milliseconds between checks whether a looping song needs its next pass
+const REPEAT_CHECK_MILLIS: i32 = 500;
 
 pub struct SongPlayer {
-  unit_length: f64,
-  voices: Vec<VoicePlayer>,
+  voices: Rc<Vec<Voice>>,
+  /// The recording played instead of voices, for songs from audio files and
+  /// pre-rendered songs
+  recording: Option<Rc<Recording>>,
+  /// The voices a pre-rendered song was rendered from, kept for its sheet
+  rendered: Vec<Voice>,
+  /// See [`adventure::Song::layers`]
+  layers: Vec<Layer>,
+  /// Starts the song over whenever it ended
+  looping: bool,
+  /// Seconds until the longest voice ended
+  length: f64,
+  /// The interval that schedules the next pass of a looping song
+  repeat: Repeat,
+  /// Counts how often the song was started or stopped, so a fade out doesn't
+  /// stop the song if it was started again in the meantime
+  generation: Rc<Cell<u32>>,
+  /// The volume of the song, which fades in and out
+  volume: f32,
+  /// See [`adventure::Song::fade_in`]
+  fade_in: Option<f64>,
+  /// See [`adventure::Song::fade_out`]
+  fade_out: Option<f64>,
   context: AudioContext,
+  /// All voices are mixed into this node, which is then routed through the
+  /// active audio profile.
+  output: GainNode,
+  /// The master gain of the [`Mixer`]
+  master: GainNode,
+  profile: Option<AudioProfile>,
 }
 
 impl SongPlayer {
-  pub fn play(&self) {
-    for voice in &self.voices {
-      voice.play();
+  /// Plays the song from the start, stopping it first if it is playing. It
+  /// fades in over `fade_in` seconds.
+  pub fn play(&self, fade_in: f64) -> Result<()> {
+    self.stop();
+
+    let start = self.context.current_time();
+    let gain = self.output.gain();
+    let _ = gain.cancel_scheduled_values(0.0);
+    if fade_in > 0.0 {
+      let faded = gain
+        .set_value_at_time(0.0, start)
+        .and_then(|g| g.linear_ramp_to_value_at_time(self.volume, start + fade_in));
+      if let Err(err) = faded {
+        log::warn!("Unable to fade in the song: {err:?}");
+        gain.set_value(self.volume);
+      }
+    } else {
+      gain.set_value(self.volume);
     }
+
+    if let Some(recording) = &self.recording {
+      recording.play()?;
+    }
+    for voice in self.voices.iter() {
+      voice.start()?;
+      voice.schedule(start);
+    }
+
+    if !self.looping || self.length <= 0.0 {
+      return Ok(());
+    }
+
+    // Passes are queued against the clock of the context, which stands still
+    // while the audio is paused, and a little ahead, so they start seamlessly
+    // even if the interval fires late
+    let length = self.length;
+    let voices = self.voices.clone();
+    let context = self.context.clone();
+    let mut next = start + length;
+    let mut queue = move || {
+      while next - context.current_time() < LOOKAHEAD {
+        for voice in voices.iter() {
+          voice.schedule(next);
+        }
+        next += length;
+      }
+    };
+    queue();
+    let callback = Closure::<dyn FnMut()>::new(queue);
+    let interval = web_sys::window()
+      .ok_or_else(|| anyhow!("unable to get the window"))?
+      .set_interval_with_callback_and_timeout_and_arguments_0(
+        callback.as_ref().unchecked_ref(),
+        REPEAT_CHECK_MILLIS,
+      )
+      .map_err(js_to_anyhow)
+      .context("unable to repeat the song")?;
+    *self.repeat.borrow_mut() = Some((interval, callback));
+    Ok(())
+  }
+
+  /// Silences the song, if it is playing.
+  pub fn stop(&self) {
+    self.generation.set(self.generation.get().wrapping_add(1));
+    silence(&self.voices, &self.repeat);
+    if let Some(recording) = &self.recording {
+      recording.stop();
+    }
+  }
+
+  /// Lowers the volume of the song to nothing over `seconds`, and stops it
+  /// then.
+  pub fn fade_out(&self, seconds: f64) {
+    if seconds <= 0.0 {
+      self.stop();
+      return;
+    }
+
+    let now = self.context.current_time();
+    let gain = self.output.gain();
+    let faded = gain
+      .cancel_scheduled_values(now)
+      .and_then(|g| g.set_value_at_time(g.value(), now))
+      .and_then(|g| g.linear_ramp_to_value_at_time(0.0, now + seconds));
+    if let Err(err) = faded {
+      log::warn!("Unable to fade out the song: {err:?}");
+      self.stop();
+      return;
+    }
+
+    let generation = self.generation.clone();
+    let expected = generation.get();
+    let voices = self.voices.clone();
+    let repeat = self.repeat.clone();
+    let recording = self.recording.clone();
+    after(seconds, move || {
+      if generation.get() == expected {
+        silence(&voices, &repeat);
+        if let Some(recording) = recording {
+          recording.stop();
+        }
+      }
+    });
+  }
+
+  /// The seconds the song fades in when it starts, if it sets them.
+  pub fn default_fade_in(&self) -> Option<f64> {
+    self.fade_in
+  }
+
+  /// The seconds the song fades out when it is replaced, if it sets them.
+  pub fn default_fade_out(&self) -> Option<f64> {
+    self.fade_out
+  }
+
+  /// Fades each layer in or out as the game state meets its dependencies or
+  /// not. `instant` sets every layer right away, for songs that just started.
+  pub fn update_layers(&self, context: &DependencyContext, instant: bool) {
+    let now = self.context.current_time();
+    for layer in &self.layers {
+      let enabled = layer.depends.is_met(context);
+      if layer.enabled.replace(enabled) == enabled && !instant {
+        continue;
+      }
+
+      let target = if enabled { 1.0 } else { 0.0 };
+      for gain in &layer.gains {
+        let gain = gain.gain();
+        let _ = gain.cancel_scheduled_values(now);
+        let faded = if instant || layer.fade <= 0.0 {
+          gain.set_value_at_time(target, now)
+        } else {
+          gain
+            .set_value_at_time(gain.value(), now)
+            .and_then(|g| g.linear_ramp_to_value_at_time(target, now + layer.fade))
+        };
+        if let Err(err) = faded {
+          log::warn!("Unable to fade a layer of the song: {err:?}");
+        }
+      }
+    }
+  }
+
+  /// Renders the song as staff notation in svg format.
+  pub fn sheet(&self) -> String {
+    let voices: Vec<&[Note]> = self
+      .voices
+      .iter()
+      .chain(&self.rendered)
+      .map(|v| v.notes())
+      .collect();
+    sheet::render(&voices)
+  }
+
+  /// Routes the song through the filters of `profile`, or straight to the
+  /// speakers if there is none.
+  pub fn set_profile(&mut self, profile: Option<AudioProfile>) -> Result<()> {
+    if self.profile == profile {
+      return Ok(());
+    }
+
+    self
+      .output
+      .disconnect()
+      .map_err(js_to_anyhow)
+      .context("unable to disconnect the song output")?;
+
+    match profile {
+      None => {
+        self
+          .output
+          .connect_with_audio_node(&self.master)
+          .map_err(js_to_anyhow)
+          .context("unable to connect the song to the final output")?;
+      }
+      Some(AudioProfile::Cave) => self.connect_reverb(1.5, 3.0, 0.6)?,
+      Some(AudioProfile::Hall) => self.connect_reverb(3.5, 2.0, 0.4)?,
+      Some(AudioProfile::Underwater) => {
+        let filter = self
+          .context
+          .create_biquad_filter()
+          .map_err(js_to_anyhow)
+          .context("unable to create a low-pass filter")?;
+        filter.set_type(BiquadFilterType::Lowpass);
+        filter.frequency().set_value(400.0);
+        filter.q().set_value(4.0);
+
+        self
+          .output
+          .connect_with_audio_node(&filter)
+          .map_err(js_to_anyhow)
+          .context("unable to connect the song to the low-pass filter")?;
+        filter
+          .connect_with_audio_node(&self.master)
+          .map_err(js_to_anyhow)
+          .context("unable to connect the low-pass filter to the final output")?;
+      }
+    }
+
+    self.profile = profile;
+    Ok(())
+  }
+
+  /// Mixes the dry signal with a reverb, using a generated impulse response of
+  /// exponentially decaying noise.
+  fn connect_reverb(&self, seconds: f64, decay: f64, wet: f32) -> Result<()> {
+    let impulse = impulse_response(&self.context, seconds, decay)?;
+
+    let convolver = self
+      .context
+      .create_convolver()
+      .map_err(js_to_anyhow)
+      .context("unable to create a convolver")?;
+    convolver.set_buffer(Some(&impulse));
+
+    let wet_gain = self
+      .context
+      .create_gain()
+      .map_err(js_to_anyhow)
+      .context("unable to create a gain node")?;
+    wet_gain.gain().set_value(wet);
+
+    self
+      .output
+      .connect_with_audio_node(&self.master)
+      .map_err(js_to_anyhow)
+      .context("unable to connect the song to the final output")?;
+    self
+      .output
+      .connect_with_audio_node(&convolver)
+      .map_err(js_to_anyhow)
+      .context("unable to connect the song to the reverb")?;
+    convolver
+      .connect_with_audio_node(&wet_gain)
+      .map_err(js_to_anyhow)
+      .context("unable to connect the reverb to its gain")?;
+    wet_gain
+      .connect_with_audio_node(&self.master)
+      .map_err(js_to_anyhow)
+      .context("unable to connect the reverb to the final output")?;
+
+    Ok(())
   }
 }
 
-impl TryFrom<adventure::Song> for SongPlayer {
-  type Error = anyhow::Error;
+impl SongPlayer {
+  /// Prepares a song, looking up its instruments in `assets`.
+  pub fn new(value: adventure::Song, assets: &AdventureAssets, mixer: &Mixer) -> Result<Self> {
+    let context = mixer.context.clone();
 
-  fn try_from(value: adventure::Song) -> Result<Self, Self::Error> {
-    let context = AudioContext::new().expect("unable to get an audio context");
+    let output = context
+      .create_gain()
+      .map_err(js_to_anyhow)
+      .context("unable to create a gain node")?;
+    output.gain().set_value(value.volume);
+    output
+      .connect_with_audio_node(&mixer.master)
+      .map_err(js_to_anyhow)
+      .context("unable to connect the song to the final output")?;
+
+    let mut scores = Vec::new();
+    if let Some(data) = &value.midi_data {
+      let tracks = midi::parse(data).context("unable to read the MIDI file")?;
+      for (index, track) in tracks.into_iter().enumerate() {
+        // Tracks without a voice are played like a plain voice
+        let voice = value.voices.get(index).cloned().unwrap_or_default();
+        let waveform = assets.waveform(&voice.instrument)?;
+        scores.push(Score::from_midi(voice, waveform, track));
+      }
+    } else {
+      if value.midi.is_some() {
+        return Err(anyhow!("the MIDI file of the song wasn't loaded"));
+      }
+      for voice in value.voices {
+        let waveform = assets.waveform(&voice.instrument)?;
+        scores.push(Score::parse(voice, waveform, value.unit_length)?);
+      }
+    }
+    let length = scores.iter().map(Score::length).fold(0.0, f64::max);
 
     let mut voices = Vec::new();
-    for voice in value.voices {
-      voices.push(VoicePlayer::new(voice, value.unit_length, &context)?);
+    let mut recording = None;
+    let mut rendered = Vec::new();
+    let mut layers = Vec::new();
+    if value.prerender {
+      // Looping songs have to end exactly where they start over
+      let seconds = if value.looping {
+        length
+      } else {
+        length + RENDER_TAIL
+      };
+      let (render_voices, buffer) = render(scores, &value.effects, seconds, context.sample_rate())?;
+      rendered = render_voices;
+      let song = Rc::new(Recording::new(&context, &output, value.looping));
+      song.load("the song".to_string(), buffer);
+      recording = Some(song);
+    } else {
+      let buses = EffectBuses::new(&value.effects, &context, &output)?;
+      let mut layer_gains: HashMap<String, Vec<GainNode>> = HashMap::new();
+      for score in scores {
+        let mut voice_output = buses.voice_output(&score.voice.sends, &context, &output)?;
+        if let Some(layer) = &score.voice.layer {
+          // Mutes the voice before its sends, so the effects fall silent, too
+          let gain = create_gain(&context)?;
+          gain
+            .connect_with_audio_node(&voice_output)
+            .map_err(js_to_anyhow)
+            .context("unable to connect the voice to its layer")?;
+          layer_gains
+            .entry(layer.clone())
+            .or_default()
+            .push(gain.clone());
+          voice_output = gain;
+        }
+        voices.push(Voice::new(score, &context, &voice_output)?);
+      }
+      for (name, layer) in value.layers {
+        layers.push(Layer {
+          gains: layer_gains.remove(&name).unwrap_or_default(),
+          depends: layer.depends,
+          fade: layer.fade,
+          enabled: Cell::new(true),
+        });
+      }
     }
 
     Ok(Self {
-      unit_length: value.unit_length,
-      voices,
+      voices: Rc::new(voices),
+      rendered,
+      layers,
+      // The buffer source loops pre-rendered songs by itself
+      looping: value.looping && recording.is_none(),
+      recording,
+      length,
+      repeat: Rc::new(RefCell::new(None)),
+      generation: Rc::new(Cell::new(0)),
+      volume: value.volume,
+      fade_in: value.fade_in,
+      fade_out: value.fade_out,
+      context,
+      output,
+      master: mixer.master.clone(),
+      profile: None,
+    })
+  }
+
+  /// Prepares a song that plays an audio file from `url`. The file is
+  /// downloaded and decoded in the background, and a song started before that
+  /// finished plays once it did.
+  pub fn from_file(file: AudioFile, url: &str, mixer: &Mixer) -> Result<Self> {
+    let context = mixer.context.clone();
+
+    let output = context
+      .create_gain()
+      .map_err(js_to_anyhow)
+      .context("unable to create a gain node")?;
+    output.gain().set_value(file.volume);
+    output
+      .connect_with_audio_node(&mixer.master)
+      .map_err(js_to_anyhow)
+      .context("unable to connect the song to the final output")?;
+
+    let recording = Rc::new(Recording::new(&context, &output, file.looping));
+    recording.load(
+      format!("the audio file {url}"),
+      decode(context.clone(), url.to_string()),
+    );
+
+    Ok(Self {
+      voices: Rc::new(Vec::new()),
+      rendered: Vec::new(),
+      layers: Vec::new(),
+      recording: Some(recording),
+      // The buffer source loops the recording by itself
+      looping: false,
+      length: 0.0,
+      repeat: Rc::new(RefCell::new(None)),
+      generation: Rc::new(Cell::new(0)),
+      volume: file.volume,
+      fade_in: None,
+      fade_out: None,
       context,
+      output,
+      master: mixer.master.clone(),
+      profile: None,
     })
   }
 }
 
-struct VoicePlayer {
-  unit_length: f64,
-  oscillator: OscillatorNode,
-  notes: Vec<Note>,
-  note_pos: usize,
+/// Voices of a song that play while the game state meets `depends`.
+struct Layer {
+  /// Mute or unmute each voice of the layer
+  gains: Vec<GainNode>,
+  depends: ActionDependencies,
+  /// See [`adventure::SongLayer::fade`]
+  fade: f64,
+  enabled: Cell<bool>,
 }
 
-impl VoicePlayer {
-  pub fn new(
-    voice: adventure::SongVoice,
-    unit_length: f64,
-    context: &AudioContext,
-  ) -> Result<Self> {
-    let raw_notes = voice.notes.split_whitespace();
+/// Seconds a pre-rendered song goes on after its last note, so releases,
+/// reverbs and echoes can ring out
+const RENDER_TAIL: f64 = 2.0;
 
-    let pitch_indices = note_indices();
-    let frequencies = frequencies();
+/// Renders the voices in an offline context, which runs as fast as it can.
+/// Returns the voices, which are done once the future finished with the
+/// rendered audio.
+fn render(
+  scores: Vec<Score>,
+  effects: &SongEffects,
+  seconds: f64,
+  rate: f32,
+) -> Result<(Vec<Voice>, impl Future<Output = Result<AudioBuffer>>)> {
+  let frames = ((seconds * rate as f64).ceil() as u32).max(1);
+  let context =
+    OfflineAudioContext::new_with_number_of_channels_and_length_and_sample_rate(2, frames, rate)
+      .map_err(js_to_anyhow)
+      .context("unable to create an offline audio context")?;
 
-    let rgx = regex::Regex::new("([a-z]+)(,|')?([1-9])?(\\.)?")
-      .with_context(|| "unable to parse the regex")?;
+  let output = create_gain(&context)?;
+  output
+    .connect_with_audio_node(&context.destination())
+    .map_err(js_to_anyhow)
+    .context("unable to connect the song to the rendered output")?;
 
-    let mut notes = Vec::new();
+  let buses = EffectBuses::new(effects, &context, &output)?;
+  let mut voices = Vec::new();
+  for score in scores {
+    let voice_output = buses.voice_output(&score.voice.sends, &context, &output)?;
+    let voice = Voice::new(score, &context, &voice_output)?;
+    voice.start()?;
+    voice.schedule(0.0);
+    voice.end_at(seconds);
+    voices.push(voice);
+  }
 
-    let octaves = [0.125, 0.25, 0.5, 1.0, 2.0];
-    let mut last_octave = 2;
-    // This might fail in some edge cases
-    let mut last_pitch_idx = 6;
+  let rendering = context
+    .start_rendering()
+    .map_err(js_to_anyhow)
+    .context("unable to render the song")?;
+  let buffer = async move {
+    JsFuture::from(rendering)
+      .await
+      .map_err(js_to_anyhow)
+      .context("unable to render the song")?
+      .dyn_into()
+      .map_err(|_| anyhow!("rendering didn't return an audio buffer"))
+  };
+  Ok((voices, buffer))
+}
 
-    let mut last_duration = unit_length / 4.0;
-    for raw_note in raw_notes {
-      let captures = rgx.captures(raw_note).expect("malformed note");
+/// Downloads and decodes an audio file.
+async fn decode(context: AudioContext, url: String) -> Result<AudioBuffer> {
+  let bytes = loader::fetch_bytes(&url).await?;
+  let data = js_sys::Uint8Array::from(bytes.as_slice()).buffer();
+  let promise = context.decode_audio_data(&data).map_err(js_to_anyhow)?;
+  JsFuture::from(promise)
+    .await
+    .map_err(js_to_anyhow)
+    .context("unable to decode the audio")?
+    .dyn_into()
+    .map_err(|_| anyhow!("decoding didn't return an audio buffer"))
+}
 
-      let note = captures
-        .get(1)
-        .with_context(|| format!("missing pitch in note {}", raw_note))?;
+/// Audio that was decoded from an [`AudioFile`] or rendered from a song, and
+/// the source playing it.
+struct Recording {
+  context: AudioContext,
+  output: GainNode,
+  looping: bool,
+  /// Empty until the audio was decoded or rendered
+  buffer: RefCell<Option<AudioBuffer>>,
+  source: RefCell<Option<AudioBufferSourceNode>>,
+  /// Whether the recording was started before it was ready
+  waiting: Cell<bool>,
+}
 
-      let frequency = if note.as_str() == "r" {
-        0.0
-      } else {
-        let pitch_idx = *pitch_indices.get(note.as_str()).with_context(|| {
-          format!(
-            "unable to parse note pitch {} of note {}",
-            note.as_str(),
-            raw_note
-          )
-        })?;
-
-        if (pitch_idx - last_pitch_idx).abs() > 6 {
-          if last_pitch_idx > 6 {
-            if last_octave + 1 < octaves.len() {
-              last_octave += 1;
-            }
-          } else if last_octave > 0 {
-            last_octave -= 1;
-          }
-        }
+impl Recording {
+  fn new(context: &AudioContext, output: &GainNode, looping: bool) -> Self {
+    Self {
+      context: context.clone(),
+      output: output.clone(),
+      looping,
+      buffer: RefCell::new(None),
+      source: RefCell::new(None),
+      waiting: Cell::new(false),
+    }
+  }
 
-        if let Some(octave_mod) = captures.get(2) {
-          if octave_mod.as_str() == "," {
-            if last_octave > 0 {
-              last_octave -= 1;
-            }
-          } else if last_octave + 1 < octaves.len() {
-            last_octave += 1;
-          }
+  /// Keeps the audio `loading` finishes with, and plays it if the recording
+  /// was started in the meantime. `what` names the audio in errors.
+  fn load(
+    self: &Rc<Self>,
+    what: String,
+    loading: impl Future<Output = Result<AudioBuffer>> + 'static,
+  ) {
+    let recording = self.clone();
+    wasm_bindgen_futures::spawn_local(async move {
+      let buffer = match loading.await {
+        Ok(b) => b,
+        Err(err) => {
+          log::error!("Unable to load {what}: {err:#}");
+          return;
         }
+      };
+      *recording.buffer.borrow_mut() = Some(buffer);
+      if recording.waiting.take() {
+        if let Err(err) = recording.play() {
+          log::error!("Unable to play {what}: {err:#}");
+        }
+      }
+    });
+  }
+
+  /// Plays the recording from the start, or once it is ready.
+  fn play(&self) -> Result<()> {
+    self.stop();
+    let buffer = self.buffer.borrow();
+    let Some(buffer) = buffer.as_ref() else {
+      self.waiting.set(true);
+      return Ok(());
+    };
+
+    let source = self
+      .context
+      .create_buffer_source()
+      .map_err(js_to_anyhow)
+      .context("unable to create a buffer source")?;
+    source.set_buffer(Some(buffer));
+    source.set_loop(self.looping);
+    source
+      .connect_with_audio_node(&self.output)
+      .map_err(js_to_anyhow)
+      .context("unable to connect the recording to the song output")?;
+    source
+      .start()
+      .map_err(js_to_anyhow)
+      .context("unable to start the recording")?;
+    *self.source.borrow_mut() = Some(source);
+    Ok(())
+  }
+
+  fn stop(&self) {
+    self.waiting.set(false);
+    if let Some(source) = self.source.take() {
+      let _ = source.stop();
+    }
+  }
+}
+
+/// Generates the impulse response of a reverb, which is noise that fades out
+/// over `seconds`, the faster the larger `decay` is.
+fn impulse_response(context: &BaseAudioContext, seconds: f64, decay: f64) -> Result<AudioBuffer> {
+  let rate = context.sample_rate();
+  let length = ((rate as f64 * seconds) as u32).max(1);
 
-        last_pitch_idx = pitch_idx;
+  let impulse = context
+    .create_buffer(2, length, rate)
+    .map_err(js_to_anyhow)
+    .context("unable to create the impulse response")?;
 
-        frequencies[pitch_idx as usize] * octaves[last_octave]
+  for channel in 0..2 {
+    let samples: Vec<f32> = (0..length)
+      .map(|i| {
+        let falloff = (1.0 - i as f64 / length as f64).powf(decay);
+        ((js_sys::Math::random() * 2.0 - 1.0) * falloff) as f32
+      })
+      .collect();
+    impulse
+      .copy_to_channel(&samples, channel)
+      .map_err(js_to_anyhow)
+      .context("unable to fill the impulse response")?;
+  }
+  Ok(impulse)
+}
+
+/// The reverb and delay of a song. Each takes its sound from a gain the
+/// voices send to, and plays into the song's output.
+struct EffectBuses {
+  reverb: Option<GainNode>,
+  delay: Option<GainNode>,
+}
+
+impl EffectBuses {
+  fn new(effects: &SongEffects, context: &BaseAudioContext, output: &GainNode) -> Result<Self> {
+    let mut reverb = None;
+    if let Some(settings) = &effects.reverb {
+      let input = create_gain(context)?;
+      let convolver = context
+        .create_convolver()
+        .map_err(js_to_anyhow)
+        .context("unable to create a convolver")?;
+      convolver.set_buffer(Some(&impulse_response(
+        context,
+        settings.seconds,
+        settings.decay,
+      )?));
+      input
+        .connect_with_audio_node(&convolver)
+        .map_err(js_to_anyhow)
+        .context("unable to connect the reverb")?;
+      convolver
+        .connect_with_audio_node(output)
+        .map_err(js_to_anyhow)
+        .context("unable to connect the reverb to the song output")?;
+      reverb = Some(input);
+    }
+
+    let mut delay = None;
+    if let Some(settings) = &effects.delay {
+      let input = create_gain(context)?;
+      let node = context
+        .create_delay_with_max_delay_time(settings.seconds)
+        .map_err(js_to_anyhow)
+        .context("unable to create a delay")?;
+      node.delay_time().set_value(settings.seconds as f32);
+      // Each echo goes through the delay again, a bit quieter
+      let feedback = create_gain(context)?;
+      feedback.gain().set_value(settings.feedback);
+
+      input
+        .connect_with_audio_node(&node)
+        .and_then(|_| node.connect_with_audio_node(&feedback))
+        .and_then(|_| feedback.connect_with_audio_node(&node))
+        .and_then(|_| node.connect_with_audio_node(output))
+        .map_err(js_to_anyhow)
+        .context("unable to connect the delay")?;
+      delay = Some(input);
+    }
+
+    Ok(Self { reverb, delay })
+  }
+
+  /// Creates the gain a voice plays into, which goes to the song's output
+  /// and, as much as the voice sends, to the effects.
+  fn voice_output(
+    &self,
+    sends: &Sends,
+    context: &BaseAudioContext,
+    output: &GainNode,
+  ) -> Result<GainNode> {
+    let voice_output = create_gain(context)?;
+    voice_output
+      .connect_with_audio_node(output)
+      .map_err(js_to_anyhow)
+      .context("unable to connect the voice to the song output")?;
+
+    for (bus, level) in [(&self.reverb, sends.reverb), (&self.delay, sends.delay)] {
+      let Some(bus) = bus else {
+        continue;
       };
+      if level <= 0.0 {
+        continue;
+      }
+      let send = create_gain(context)?;
+      send.gain().set_value(level);
+      voice_output
+        .connect_with_audio_node(&send)
+        .and_then(|_| send.connect_with_audio_node(bus))
+        .map_err(js_to_anyhow)
+        .context("unable to send the voice to the effects")?;
+    }
+    Ok(voice_output)
+  }
+}
+
+fn create_gain(context: &BaseAudioContext) -> Result<GainNode> {
+  context
+    .create_gain()
+    .map_err(js_to_anyhow)
+    .context("unable to create a gain node")
+}
+
+/// The volume of noise at its start, before it is scaled
+const NOISE_VOLUME: f32 = 0.05;
+
+/// Plays a sound effect of the assets. Playing it again while it still plays
+/// starts it over.
+pub struct EffectPlayer {
+  context: AudioContext,
+  output: GainNode,
+  sound: Sound,
+}
+
+enum Sound {
+  Noise {
+    /// White noise as long as the effect
+    buffer: AudioBuffer,
+    seconds: f64,
+    lowpass: Option<f32>,
+    volume: f32,
+  },
+  Notes(Box<Voice>),
+}
+
+impl EffectPlayer {
+  pub fn new(effect: SoundEffect, assets: &AdventureAssets, mixer: &Mixer) -> Result<Self> {
+    let context = mixer.context.clone();
+    let output = mixer.effects.clone();
+
+    let sound = match effect {
+      SoundEffect::Noise {
+        noise,
+        lowpass,
+        volume,
+      } => {
+        let rate = context.sample_rate();
+        let length = ((rate as f64 * noise) as u32).max(1);
+        let buffer = context
+          .create_buffer(1, length, rate)
+          .map_err(js_to_anyhow)
+          .context("unable to create the noise")?;
+        let samples: Vec<f32> = (0..length)
+          .map(|_| (js_sys::Math::random() * 2.0 - 1.0) as f32)
+          .collect();
+        buffer
+          .copy_to_channel(&samples, 0)
+          .map_err(js_to_anyhow)
+          .context("unable to fill the noise")?;
+        Sound::Noise {
+          buffer,
+          seconds: noise,
+          lowpass,
+          volume,
+        }
+      }
+      SoundEffect::Notes { unit_length, voice } => {
+        let waveform = assets.waveform(&voice.instrument)?;
+        let score = Score::parse(voice, waveform, unit_length)?;
+        Sound::Notes(Box::new(Voice::new(score, &context, &output)?))
+      }
+    };
+
+    Ok(Self {
+      context,
+      output,
+      sound,
+    })
+  }
+
+  pub fn play(&self) -> Result<()> {
+    let start = self.context.current_time();
+    match &self.sound {
+      Sound::Noise {
+        buffer,
+        seconds,
+        lowpass,
+        volume,
+      } => {
+        let source = self
+          .context
+          .create_buffer_source()
+          .map_err(js_to_anyhow)
+          .context("unable to create a buffer source")?;
+        source.set_buffer(Some(buffer));
 
-      if let Some(duration_capture) = captures.get(3) {
-        last_duration = unit_length
-          / duration_capture.as_str().parse::<u64>().with_context(|| {
-            format!(
-              "durations need to be unsigned integers, got {} in note {raw_note}",
-              duration_capture.as_str()
-            )
-          })? as f64;
-
-        // If there is a dot after the note, make it half as long again
-        if captures.get(4).is_some() {
-          last_duration *= 1.5;
+        let gain = self
+          .context
+          .create_gain()
+          .map_err(js_to_anyhow)
+          .context("unable to create a gain node")?;
+        gain
+          .gain()
+          .set_value_at_time(NOISE_VOLUME * volume, start)
+          .and_then(|g| g.linear_ramp_to_value_at_time(0.0, start + seconds))
+          .map_err(js_to_anyhow)
+          .context("unable to fade out the noise")?;
+
+        let mut last: AudioNode = source.clone().into();
+        if let Some(frequency) = lowpass {
+          let filter = self
+            .context
+            .create_biquad_filter()
+            .map_err(js_to_anyhow)
+            .context("unable to create a low-pass filter")?;
+          filter.set_type(BiquadFilterType::Lowpass);
+          filter.frequency().set_value(*frequency);
+          last
+            .connect_with_audio_node(&filter)
+            .map_err(js_to_anyhow)
+            .context("unable to connect the noise to the low-pass filter")?;
+          last = filter.into();
         }
+        last
+          .connect_with_audio_node(&gain)
+          .map_err(js_to_anyhow)
+          .context("unable to connect the noise to its gain")?;
+        gain
+          .connect_with_audio_node(&self.output)
+          .map_err(js_to_anyhow)
+          .context("unable to connect the noise to the output")?;
+
+        source
+          .start()
+          .map_err(js_to_anyhow)
+          .context("unable to start the noise")?;
+      }
+      Sound::Notes(voice) => {
+        voice.stop();
+        voice.start()?;
+        voice.schedule(start);
+        voice.end_at(start + voice.length());
+      }
+    }
+    Ok(())
+  }
+}
+
+/// The interval that repeats a looping song, and its callback, which is
+/// dropped once the interval is cleared.
+type Repeat = Rc<RefCell<Option<(i32, Closure<dyn FnMut()>)>>>;
+
+/// Stops every voice and the interval that repeats them.
+fn silence(voices: &[Voice], repeat: &Repeat) {
+  if let Some((interval, _callback)) = repeat.take() {
+    if let Some(window) = web_sys::window() {
+      window.clear_interval_with_handle(interval);
+    }
+  }
+  for voice in voices {
+    voice.stop();
+  }
+}
+
+/// Calls `callback` once `seconds` passed.
+fn after(seconds: f64, callback: impl FnOnce() + 'static) {
+  let callback = Closure::once_into_js(callback);
+  let scheduled = web_sys::window().map(|w| {
+    w.set_timeout_with_callback_and_timeout_and_arguments_0(
+      callback.unchecked_ref(),
+      (seconds * 1000.0) as i32,
+    )
+  });
+  if !matches!(scheduled, Some(Ok(_))) {
+    log::error!("Unable to schedule a timeout");
+  }
+}
+
+/// The volume of a voice at the peak of each note, before it is scaled
+const VOLUME: f32 = 0.02;
+
+/// A voice of a song or sound effect, played by either an oscillator or the
+/// drums.
+enum Voice {
+  Tone(VoicePlayer),
+  Drums(DrumPlayer),
+}
+
+/// The notes of a voice, before it has the audio nodes that play them.
+struct Score {
+  voice: adventure::SongVoice,
+  waveform: Waveform,
+  notes: Vec<Note>,
+  /// The drums of every note, for the drums
+  hits: Vec<Vec<Drum>>,
+}
+
+impl Score {
+  fn parse(voice: adventure::SongVoice, waveform: Waveform, unit_length: f64) -> Result<Self> {
+    let (notes, hits) = match waveform {
+      Waveform::Drums => drum_notes(&voice.notes, unit_length)?,
+      _ => (tone_notes(&voice, unit_length)?, Vec::new()),
+    };
+    Ok(Self {
+      voice,
+      waveform,
+      notes,
+      hits,
+    })
+  }
+
+  /// Plays a track of a MIDI file with the instrument, envelope and volume of
+  /// `voice`.
+  fn from_midi(voice: adventure::SongVoice, waveform: Waveform, track: midi::Track) -> Self {
+    let mut notes = Vec::new();
+    let mut hits = Vec::new();
+    for event in track.events {
+      if waveform == Waveform::Drums {
+        // Keys that aren't kicks, snares or hi-hats are left out
+        let drums: Vec<Drum> = event
+          .keys
+          .iter()
+          .filter_map(|k| Drum::from_key(*k))
+          .collect();
+        notes.push(Note {
+          frequencies: Vec::new(),
+          pitches: drums.iter().map(|d| d.pitch()).collect(),
+          value: event.value,
+          duration: event.duration,
+          legato: false,
+          volume: 1.0,
+          ramp_to: None,
+        });
+        hits.push(drums);
+      } else {
+        let pitches: Vec<i64> = event
+          .keys
+          .iter()
+          .map(|k| *k as i64 - 60 + voice.transpose)
+          .collect();
+        notes.push(Note {
+          frequencies: pitches.iter().map(|p| notation::frequency(*p)).collect(),
+          pitches,
+          value: event.value,
+          duration: event.duration,
+          legato: false,
+          volume: 1.0,
+          ramp_to: None,
+        });
       }
+    }
+    Self {
+      voice,
+      waveform,
+      notes,
+      hits,
+    }
+  }
+
+  /// The seconds until the last note ended.
+  fn length(&self) -> f64 {
+    self.notes.iter().map(|n| n.duration).sum()
+  }
+}
+
+impl Voice {
+  fn new(score: Score, context: &BaseAudioContext, output: &GainNode) -> Result<Self> {
+    Ok(match score.waveform {
+      Waveform::Drums => Self::Drums(DrumPlayer::new(
+        &score.voice,
+        score.notes,
+        score.hits,
+        context,
+        output,
+      )?),
+      waveform => Self::Tone(VoicePlayer::new(
+        &score.voice,
+        waveform,
+        score.notes,
+        context,
+        output,
+      )?),
+    })
+  }
+
+  fn start(&self) -> Result<()> {
+    match self {
+      Self::Tone(voice) => voice.start(),
+      // Every hit gets its own nodes once it is scheduled
+      Self::Drums(_) => Ok(()),
+    }
+  }
 
-      notes.push(Note {
-        frequency,
-        duration: last_duration,
+  fn schedule(&self, start: f64) {
+    match self {
+      Self::Tone(voice) => voice.schedule(start),
+      Self::Drums(drums) => drums.schedule(start),
+    }
+  }
+
+  fn stop(&self) {
+    match self {
+      Self::Tone(voice) => voice.stop(),
+      Self::Drums(drums) => drums.stop(),
+    }
+  }
+
+  fn end_at(&self, time: f64) {
+    if let Self::Tone(voice) = self {
+      voice.end_at(time);
+    }
+  }
+
+  fn notes(&self) -> &[Note] {
+    match self {
+      Self::Tone(voice) => &voice.notes,
+      Self::Drums(drums) => &drums.notes,
+    }
+  }
+
+  /// The seconds until the last note ended.
+  fn length(&self) -> f64 {
+    self.notes().iter().map(|n| n.duration).sum()
+  }
+}
+
+struct VoicePlayer {
+  context: BaseAudioContext,
+  waveform: Waveform,
+  /// The wave of custom instruments
+  periodic_wave: Option<PeriodicWave>,
+  /// Oscillators can only be started once, so every playback gets new ones.
+  /// There is one for each note of the largest chord.
+  oscillators: RefCell<Vec<OscillatorNode>>,
+  /// Shape the notes of each oscillator with the voice's envelope
+  gains: Vec<GainNode>,
+  envelope: Envelope,
+  /// The peak of each note
+  volume: f32,
+  notes: Vec<Note>,
+  /// The notes as they sound, with chords broken up by the arpeggio
+  played: Vec<Note>,
+  vibrato: Option<Modulation>,
+  /// The tremolo and the gain it changes, which all notes pass through
+  tremolo: Option<(Modulation, GainNode)>,
+  /// Follows the dynamics of the notes, after the tremolo
+  dynamics: GainNode,
+  /// See [`adventure::SongVoice::glide`]
+  glide: f64,
+  /// The oscillators driving the vibrato and tremolo, which are replaced
+  /// along with the others
+  lfos: RefCell<Vec<OscillatorNode>>,
+}
+
+/// Reads the notes of a voice with a pitched instrument.
+fn tone_notes(voice: &adventure::SongVoice, unit_length: f64) -> Result<Vec<Note>> {
+  let mut tones = Vec::new();
+  let mut relative = notation::Relative::new(voice.start_octave);
+
+  for event in notation::parse(&voice.notes, unit_length)? {
+    let pitches: Vec<i64> = relative
+      .resolve_all(&event.pitches)?
+      .into_iter()
+      .map(|p| p + voice.transpose)
+      .collect();
+
+    tones.push(Note {
+      frequencies: pitches.iter().map(|p| notation::frequency(*p)).collect(),
+      pitches,
+      value: event.value,
+      duration: event.duration,
+      legato: event.legato,
+      volume: event.volume,
+      ramp_to: event.ramp_to,
+    });
+  }
+  Ok(tones)
+}
+
+/// Breaks every chord into its notes, played one after another `rate` times
+/// per second and starting over until the chord ends.
+fn arpeggiate(notes: &[Note], rate: f64) -> Vec<Note> {
+  let mut played = Vec::new();
+  for note in notes {
+    if note.frequencies.len() < 2 {
+      played.push(note.clone());
+      continue;
+    }
+
+    let volume_at = |time: f64| match note.ramp_to {
+      Some(to) => note.volume + (to - note.volume) * (time / note.duration) as f32,
+      None => note.volume,
+    };
+    // Leaves out a last step that would only be rounding errors long
+    let steps = ((note.duration * rate - 1e-9).ceil() as usize).max(1);
+    for step in 0..steps {
+      let start = step as f64 / rate;
+      let duration = (note.duration - start).min(1.0 / rate);
+      let index = step % note.frequencies.len();
+      played.push(Note {
+        frequencies: vec![note.frequencies[index]],
+        pitches: vec![note.pitches[index]],
+        value: note.value * duration / note.duration,
+        duration,
+        // The notes of a chord flow into each other without a new attack
+        legato: step + 1 < steps || note.legato,
+        volume: volume_at(start),
+        ramp_to: note.ramp_to.map(|_| volume_at(start + duration)),
       });
     }
+  }
+  played
+}
 
-    let oscillator = context
-      .create_oscillator()
-      .expect("unable to create an oscillator");
+impl VoicePlayer {
+  fn new(
+    voice: &adventure::SongVoice,
+    waveform: Waveform,
+    notes: Vec<Note>,
+    context: &BaseAudioContext,
+    output: &GainNode,
+  ) -> Result<Self> {
+    let dynamics = create_gain(context)?;
+    dynamics
+      .connect_with_audio_node(output)
+      .map_err(js_to_anyhow)
+      .context("unable to connect the dynamics to the song output")?;
+    let output = &dynamics;
 
-    let gain = context
-      .create_gain()
-      .map_err(|e| anyhow!("{e:?}"))
-      .context("unable to create a gain node")?;
+    let periodic_wave = match &waveform {
+      Waveform::Custom(harmonics) => {
+        // The first coefficient is the constant offset, which stays silent
+        let mut imag = vec![0.0];
+        imag.extend(harmonics);
+        let mut real = vec![0.0; imag.len()];
+        let wave = context
+          .create_periodic_wave(&mut real, &mut imag)
+          .map_err(js_to_anyhow)
+          .context("unable to create the instrument's waveform")?;
+        Some(wave)
+      }
+      _ => None,
+    };
 
-    gain.gain().set_value(0.02);
-    gain
-      .connect_with_audio_node(&context.destination())
-      .map_err(|e| anyhow!("{e:?}"))
-      .context("unable to connect the gain to the final output")?;
+    let tremolo = match &voice.tremolo {
+      Some(tremolo) => {
+        let gain = context
+          .create_gain()
+          .map_err(js_to_anyhow)
+          .context("unable to create a gain node")?;
+        // The tremolo swings the volume around this
+        gain.gain().set_value(1.0 - tremolo.depth / 2.0);
+        gain
+          .connect_with_audio_node(output)
+          .map_err(js_to_anyhow)
+          .context("unable to connect the tremolo to the song output")?;
+        Some((tremolo.clone(), gain))
+      }
+      None => None,
+    };
+    let output = tremolo.as_ref().map_or(output, |(_, gain)| gain);
+
+    let played = match voice.arpeggio {
+      Some(rate) => arpeggiate(&notes, rate),
+      None => notes.clone(),
+    };
+    let chord_size = played.iter().map(|n| n.pitches.len()).max().unwrap_or(0);
+    let mut gains = Vec::new();
+    for _ in 0..chord_size.max(1) {
+      let gain = context
+        .create_gain()
+        .map_err(js_to_anyhow)
+        .context("unable to create a gain node")?;
 
-    oscillator
-      .connect_with_audio_node(&gain)
-      .map_err(|e| anyhow!("{e:?}"))
-      .context("unable to connect the oscillator to the gain")?;
+      gain.gain().set_value(0.0);
+      gain
+        .connect_with_audio_node(output)
+        .map_err(js_to_anyhow)
+        .context("unable to connect the gain to the song output")?;
+      gains.push(gain);
+    }
 
     Ok(Self {
-      unit_length,
-      oscillator,
+      context: context.clone(),
+      waveform,
+      periodic_wave,
+      oscillators: RefCell::new(Vec::new()),
+      gains,
+      envelope: voice.envelope.clone(),
+      volume: VOLUME * voice.volume,
       notes,
-      note_pos: 0,
+      played,
+      vibrato: voice.vibrato.clone(),
+      tremolo,
+      dynamics,
+      glide: voice.glide,
+      lfos: RefCell::new(Vec::new()),
     })
   }
 
-  fn play(&self) {
-  // TODO: This method only works once (probably cause the time has already advanced, after it was
-  // has played for a little bit)
-  self.oscillator.stop();
+  /// Starts new oscillators, which play the notes once they are scheduled.
+  fn start(&self) -> Result<()> {
+    let mut oscillators = Vec::new();
+    for gain in &self.gains {
+      let oscillator = self
+        .context
+        .create_oscillator()
+        .map_err(js_to_anyhow)
+        .context("unable to create an oscillator")?;
+      match (&self.waveform, &self.periodic_wave) {
+        (Waveform::Sine, _) => oscillator.set_type(OscillatorType::Sine),
+        (Waveform::Square, _) => oscillator.set_type(OscillatorType::Square),
+        (Waveform::Sawtooth, _) => oscillator.set_type(OscillatorType::Sawtooth),
+        (Waveform::Triangle, _) => oscillator.set_type(OscillatorType::Triangle),
+        (Waveform::Custom(_), Some(wave)) => oscillator.set_periodic_wave(wave),
+        (Waveform::Custom(_), None) => {
+          return Err(anyhow!("the instrument's waveform is missing"));
+        }
+        (Waveform::Drums, _) => return Err(anyhow!("drums are played by a DrumPlayer")),
+      }
+
+      oscillator
+        .connect_with_audio_node(gain)
+        .map_err(js_to_anyhow)
+        .context("unable to connect the oscillator to the gain")?;
+      oscillator
+        .start()
+        .map_err(js_to_anyhow)
+        .context("unable to start the oscillator")?;
+      oscillators.push(oscillator);
+    }
+
+    let mut lfos = Vec::new();
+    if let Some(vibrato) = &self.vibrato {
+      // Detune is measured in cents
+      let (lfo, depth) = self.lfo(vibrato.rate, vibrato.depth * 100.0)?;
+      for oscillator in &oscillators {
+        depth
+          .connect_with_audio_param(&oscillator.detune())
+          .map_err(js_to_anyhow)
+          .context("unable to connect the vibrato")?;
+      }
+      lfos.push(lfo);
+    }
+    if let Some((tremolo, gain)) = &self.tremolo {
+      let (lfo, depth) = self.lfo(tremolo.rate, tremolo.depth / 2.0)?;
+      depth
+        .connect_with_audio_param(&gain.gain())
+        .map_err(js_to_anyhow)
+        .context("unable to connect the tremolo")?;
+      lfos.push(lfo);
+    }
+
+    *self.oscillators.borrow_mut() = oscillators;
+    *self.lfos.borrow_mut() = lfos;
+    Ok(())
+  }
+
+  /// Starts a low frequency sine wave swinging between `-amount` and
+  /// `amount`, returning it and the gain that scales it.
+  fn lfo(&self, rate: f32, amount: f32) -> Result<(OscillatorNode, GainNode)> {
+    let lfo = self
+      .context
+      .create_oscillator()
+      .map_err(js_to_anyhow)
+      .context("unable to create an oscillator")?;
+    lfo.frequency().set_value(rate);
 
-  self.oscillator.frequency().set_value(0.0);
-  self.oscillator.set_type(OscillatorType::Square);
+    let depth = self
+      .context
+      .create_gain()
+      .map_err(js_to_anyhow)
+      .context("unable to create a gain node")?;
+    depth.gain().set_value(amount);
+    lfo
+      .connect_with_audio_node(&depth)
+      .map_err(js_to_anyhow)
+      .context("unable to connect the oscillator to its gain")?;
+    lfo
+      .start()
+      .map_err(js_to_anyhow)
+      .context("unable to start the oscillator")?;
+    Ok((lfo, depth))
+  }
 
-  let mut offset = 0.0;
-    for note in &self.notes {
-      self
-        .oscillator
-        .frequency()
-        .set_value_at_time(note.frequency, offset)
+  /// Stops the oscillators and drops the notes that were scheduled.
+  fn stop(&self) {
+    for oscillator in self
+      .oscillators
+      .borrow_mut()
+      .drain(..)
+      .chain(self.lfos.borrow_mut().drain(..))
+    {
+      let _ = oscillator.stop();
+      let _ = oscillator.disconnect();
+    }
+    for gain in &self.gains {
+      let gain = gain.gain();
+      let _ = gain.cancel_scheduled_values(0.0);
+      gain.set_value(0.0);
+    }
+    let dynamics = self.dynamics.gain();
+    let _ = dynamics.cancel_scheduled_values(0.0);
+    dynamics.set_value(1.0);
+  }
+
+  /// Stops the oscillators once `time` came, as nothing plays after it.
+  fn end_at(&self, time: f64) {
+    for oscillator in self
+      .oscillators
+      .borrow()
+      .iter()
+      .chain(self.lfos.borrow().iter())
+    {
+      let _ = oscillator.stop_with_when(time);
+    }
+  }
+
+  /// Schedules every note of the voice, starting at `start` in the time of
+  /// the audio context.
+  fn schedule(&self, start: f64) {
+    let oscillators = self.oscillators.borrow();
+    let mut offset = start;
+    // Whether the previous note of each oscillator flows into this one
+    let mut connected = vec![false; oscillators.len()];
+    // The frequency of the previous note of each oscillator, to glide from
+    let mut previous: Vec<Option<f32>> = vec![None; oscillators.len()];
+    let dynamics = self.dynamics.gain();
+    for note in &self.played {
+      match note.ramp_to {
+        Some(to) => dynamics
+          .set_value_at_time(note.volume, offset)
+          .and_then(|d| d.linear_ramp_to_value_at_time(to, offset + note.duration)),
+        None => dynamics.set_value_at_time(note.volume, offset),
+      }
+      .expect("uanble to schedule the dynamics");
+
+      // A chord is as loud as a single note
+      let volume = self.volume / note.frequencies.len().max(1) as f32;
+      for (i, (oscillator, gain)) in oscillators.iter().zip(&self.gains).enumerate() {
+        // Rests and smaller chords leave oscillators silent, as every note
+        // fades out before it ends
+        let Some(frequency) = note.frequencies.get(i) else {
+          connected[i] = false;
+          previous[i] = None;
+          continue;
+        };
+        let pitch = oscillator.frequency();
+        match previous[i] {
+          Some(from) if self.glide > 0.0 => pitch.set_value_at_time(from, offset).and_then(|p| {
+            p.exponential_ramp_to_value_at_time(*frequency, offset + self.glide.min(note.duration))
+          }),
+          _ => pitch.set_value_at_time(*frequency, offset),
+        }
         .expect("uanble to schedule the note");
-      self
-        .oscillator
-        .frequency()
-        .set_value_at_time(0.0, offset + note.duration - self.unit_length / 32.0)
+        previous[i] = Some(*frequency);
+        schedule_envelope(
+          &gain.gain(),
+          &self.envelope,
+          volume,
+          (offset, note.duration),
+          (!connected[i], !note.legato),
+        )
         .expect("uanble to schedule the note");
+        connected[i] = note.legato;
+      }
       offset += note.duration;
     }
-    self
-      .oscillator
-      .frequency()
-      .set_value_at_time(0.0, offset)
-      .expect("uanble to schedule the note");
+  }
+}
 
-    self
-      .oscillator
-      .start()
-      .expect("Unable to start the oscillator");
-  }
-}
-
-struct Note {
-  frequency: f32,
-  duration: f64,
-}
-
-fn note_indices() -> HashMap<String, i64> {
-  HashMap::from([
-    ("c".to_string(), 0),
-    ("cis".to_string(), 1),
-    ("des".to_string(), 1),
-    ("d".to_string(), 2),
-    ("dis".to_string(), 3),
-    ("es".to_string(), 3),
-    ("e".to_string(), 4),
-    ("f".to_string(), 5),
-    ("fis".to_string(), 6),
-    ("ges".to_string(), 6),
-    ("g".to_string(), 7),
-    ("gis".to_string(), 8),
-    ("as".to_string(), 8),
-    ("a".to_string(), 9),
-    ("ais".to_string(), 10),
-    ("bes".to_string(), 10),
-    ("b".to_string(), 11),
-  ])
-}
-
-fn frequencies() -> Vec<f32> {
-  vec![
-    261.0, 277.0, 293.0, 311.0, 329.0, 349.0, 369.0, 392.0, 415.0, 440.0, 466.0, 493.0,
-  ]
+#[derive(Clone, Copy)]
+enum Drum {
+  Kick,
+  Snare,
+  Hat,
+}
+
+impl Drum {
+  fn parse(name: &str) -> Option<Self> {
+    match name {
+      "k" | "kick" => Some(Self::Kick),
+      "s" | "snare" => Some(Self::Snare),
+      "h" | "hat" => Some(Self::Hat),
+      _ => None,
+    }
+  }
+
+  /// The drum of a key in the General MIDI percussion map.
+  fn from_key(key: u8) -> Option<Self> {
+    match key {
+      35 | 36 => Some(Self::Kick),
+      37..=40 => Some(Self::Snare),
+      42 | 44 | 46 => Some(Self::Hat),
+      _ => None,
+    }
+  }
+
+  /// Where the drum is written on the staff, as in most drum notation
+  fn pitch(self) -> i64 {
+    match self {
+      Self::Kick => 5,
+      Self::Snare => 12,
+      Self::Hat => 19,
+    }
+  }
+
+  /// The volume at the start of a hit
+  fn peak(self) -> f32 {
+    match self {
+      Self::Kick => 0.1,
+      Self::Snare => 0.05,
+      Self::Hat => 0.02,
+    }
+  }
+
+  /// Seconds until a hit faded out
+  fn decay(self) -> f64 {
+    match self {
+      Self::Kick => 0.2,
+      Self::Snare => 0.15,
+      Self::Hat => 0.05,
+    }
+  }
+}
+
+/// Plays a drum pattern. Kicks are a sine wave quickly falling in pitch,
+/// snares and hi-hats are filtered noise.
+struct DrumPlayer {
+  context: BaseAudioContext,
+  /// White noise, long enough for every hit
+  noise: AudioBuffer,
+  /// Sets the volume of the voice
+  gain: GainNode,
+  notes: Vec<Note>,
+  /// The drums of every note, none for rests
+  hits: Vec<Vec<Drum>>,
+  /// The scheduled nodes and when they end, so they can be stopped
+  sources: RefCell<Vec<(f64, AudioScheduledSourceNode)>>,
+}
+
+/// Reads the notes of a drums voice, returning the drums hit by each note.
+fn drum_notes(notes: &str, unit_length: f64) -> Result<(Vec<Note>, Vec<Vec<Drum>>)> {
+  let mut drum_notes = Vec::new();
+  let mut hits = Vec::new();
+  for event in notation::parse(notes, unit_length)? {
+    let drums = event
+      .pitches
+      .iter()
+      .map(|p| {
+        Drum::parse(&p.name)
+          .with_context(|| format!("unknown drum {}, expected k, s, h or r", p.name))
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    drum_notes.push(Note {
+      frequencies: Vec::new(),
+      pitches: drums.iter().map(|d| d.pitch()).collect(),
+      value: event.value,
+      duration: event.duration,
+      legato: false,
+      volume: event.volume,
+      ramp_to: event.ramp_to,
+    });
+    hits.push(drums);
+  }
+  Ok((drum_notes, hits))
+}
+
+impl DrumPlayer {
+  fn new(
+    voice: &adventure::SongVoice,
+    notes: Vec<Note>,
+    hits: Vec<Vec<Drum>>,
+    context: &BaseAudioContext,
+    output: &GainNode,
+  ) -> Result<Self> {
+    let rate = context.sample_rate();
+    let length = (rate as f64 * Drum::Snare.decay().max(Drum::Hat.decay())) as u32;
+    let noise = context
+      .create_buffer(1, length, rate)
+      .map_err(js_to_anyhow)
+      .context("unable to create the noise")?;
+    let samples: Vec<f32> = (0..length)
+      .map(|_| (js_sys::Math::random() * 2.0 - 1.0) as f32)
+      .collect();
+    noise
+      .copy_to_channel(&samples, 0)
+      .map_err(js_to_anyhow)
+      .context("unable to fill the noise")?;
+
+    let gain = context
+      .create_gain()
+      .map_err(js_to_anyhow)
+      .context("unable to create a gain node")?;
+    gain.gain().set_value(voice.volume);
+    gain
+      .connect_with_audio_node(output)
+      .map_err(js_to_anyhow)
+      .context("unable to connect the drums to the song output")?;
+
+    Ok(Self {
+      context: context.clone(),
+      noise,
+      gain,
+      notes,
+      hits,
+      sources: RefCell::new(Vec::new()),
+    })
+  }
+
+  fn schedule(&self, start: f64) {
+    // Hits that already ended don't need to be stopped anymore
+    let now = self.context.current_time();
+    self.sources.borrow_mut().retain(|(end, _)| *end > now);
+
+    let mut offset = start;
+    for (note, hits) in self.notes.iter().zip(&self.hits) {
+      for drum in hits {
+        if let Err(err) = self.hit(*drum, note.volume, offset) {
+          log::error!("Unable to schedule a drum hit: {err:#}");
+        }
+      }
+      offset += note.duration;
+    }
+  }
+
+  fn hit(&self, drum: Drum, volume: f32, time: f64) -> Result<()> {
+    let end = time + drum.decay();
+
+    let envelope = self
+      .context
+      .create_gain()
+      .map_err(js_to_anyhow)
+      .context("unable to create a gain node")?;
+    envelope
+      .gain()
+      .set_value_at_time(drum.peak() * volume, time)
+      .and_then(|g| g.exponential_ramp_to_value_at_time(0.001, end))
+      .map_err(js_to_anyhow)
+      .context("unable to shape the hit")?;
+    envelope
+      .connect_with_audio_node(&self.gain)
+      .map_err(js_to_anyhow)
+      .context("unable to connect the hit to the drums")?;
+
+    let source: AudioScheduledSourceNode = match drum {
+      Drum::Kick => {
+        let oscillator = self
+          .context
+          .create_oscillator()
+          .map_err(js_to_anyhow)
+          .context("unable to create an oscillator")?;
+        oscillator.set_type(OscillatorType::Sine);
+        oscillator
+          .frequency()
+          .set_value_at_time(150.0, time)
+          .and_then(|f| f.exponential_ramp_to_value_at_time(40.0, end))
+          .map_err(js_to_anyhow)
+          .context("unable to shape the kick")?;
+        oscillator
+          .connect_with_audio_node(&envelope)
+          .map_err(js_to_anyhow)
+          .context("unable to connect the kick")?;
+        oscillator.into()
+      }
+      Drum::Snare | Drum::Hat => {
+        let noise = self
+          .context
+          .create_buffer_source()
+          .map_err(js_to_anyhow)
+          .context("unable to create a buffer source")?;
+        noise.set_buffer(Some(&self.noise));
+
+        let filter = self
+          .context
+          .create_biquad_filter()
+          .map_err(js_to_anyhow)
+          .context("unable to create a high-pass filter")?;
+        filter.set_type(BiquadFilterType::Highpass);
+        filter
+          .frequency()
+          .set_value(if matches!(drum, Drum::Snare) {
+            1000.0
+          } else {
+            7000.0
+          });
+
+        noise
+          .connect_with_audio_node(&filter)
+          .map_err(js_to_anyhow)
+          .context("unable to connect the noise to the filter")?;
+        filter
+          .connect_with_audio_node(&envelope)
+          .map_err(js_to_anyhow)
+          .context("unable to connect the filter to the hit")?;
+        noise.into()
+      }
+    };
+
+    source
+      .start_with_when(time)
+      .and_then(|_| source.stop_with_when(end))
+      .map_err(js_to_anyhow)
+      .context("unable to schedule the hit")?;
+    self.sources.borrow_mut().push((end, source));
+    Ok(())
+  }
+
+  fn stop(&self) {
+    for (_, source) in self.sources.borrow_mut().drain(..) {
+      let _ = source.stop();
+    }
+  }
+}
+
+/// Fades a note with the given start and duration in and out. If the note is
+/// too short for the whole envelope, the release is kept and attack and decay
+/// are cut short. Tied and slurred notes skip the fade between them, which
+/// `fades` turns off for the fade in and out respectively.
+fn schedule_envelope(
+  gain: &AudioParam,
+  envelope: &Envelope,
+  volume: f32,
+  (start, duration): (f64, f64),
+  (fade_in, fade_out): (bool, bool),
+) -> Result<(), wasm_bindgen::JsValue> {
+  let sustain = volume * envelope.sustain as f32;
+  let end = start + duration;
+  let release = if fade_out {
+    end - envelope.release.min(duration)
+  } else {
+    end
+  };
+
+  if fade_in {
+    let attack = (start + envelope.attack).min(release);
+    let decay = (attack + envelope.decay).min(release);
+    gain.set_value_at_time(0.0, start)?;
+    gain.linear_ramp_to_value_at_time(volume, attack)?;
+    gain.linear_ramp_to_value_at_time(sustain, decay)?;
+  }
+  if fade_out {
+    gain.set_value_at_time(sustain, release)?;
+    gain.linear_ramp_to_value_at_time(0.0, end)?;
+  }
+  Ok(())
+}
+
+#[derive(Clone)]
+pub struct Note {
+  /// One for each note of a chord, none for rests and drums
+  pub frequencies: Vec<f32>,
+  /// Semitones relative to middle c, one for each note of a chord and none for
+  /// rests
+  pub pitches: Vec<i64>,
+  /// The fraction of a whole note, as written
+  pub value: f64,
+  /// Seconds, at the tempo where the note is
+  pub duration: f64,
+  /// Flows into the next note, see [`notation::Event::legato`]
+  pub legato: bool,
+  /// The loudness of the dynamics, see [`notation::Event::volume`]
+  pub volume: f32,
+  /// See [`notation::Event::ramp_to`]
+  pub ramp_to: Option<f32>,
 }
@@ -0,0 +1,98 @@
+//! Checks an adventure for problems without starting the game, e.g.
+//! `cargo run --bin check -- adventure.yaml`. Included files are merged in
+//! like the game does.
+
+use std::process::ExitCode;
+
+use anyhow::{anyhow, Context, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_yaml::Value;
+use textadventure::{
+  adventure::Document,
+  analysis::analyze,
+  includes,
+  loader::{self, Format},
+  validation::validate,
+};
+
+/// Prints the warnings of the loader, e.g. about deprecated or unsupported
+/// parts of the adventure, along with the problems.
+struct Printer;
+
+impl Log for Printer {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    metadata.level() <= Level::Warn
+  }
+
+  fn log(&self, record: &Record) {
+    if self.enabled(record.metadata()) {
+      let level = match record.level() {
+        Level::Error => "error",
+        _ => "warning",
+      };
+      println!("{level}: {}", record.args());
+    }
+  }
+
+  fn flush(&self) {}
+}
+
+static PRINTER: Printer = Printer;
+
+fn main() -> ExitCode {
+  if log::set_logger(&PRINTER).is_ok() {
+    log::set_max_level(LevelFilter::Warn);
+  }
+
+  let path = std::env::args()
+    .nth(1)
+    .unwrap_or_else(|| "adventure.yaml".to_string());
+  let document = match load(&path) {
+    Ok(d) => d,
+    Err(err) => {
+      eprintln!("Unable to load {path}: {err:#}");
+      return ExitCode::FAILURE;
+    }
+  };
+
+  let problems = validate(&document.adventure);
+  for problem in &problems {
+    println!("error: {problem}");
+  }
+  for warning in analyze(&document.adventure) {
+    println!("warning: {warning}");
+  }
+
+  match problems.is_empty() {
+    true => ExitCode::SUCCESS,
+    false => ExitCode::FAILURE,
+  }
+}
+
+/// Reads the adventure at `path` and merges in every file it includes.
+fn load(path: &str) -> Result<Document> {
+  let mut document = read(path)?;
+  let adventure = document
+    .get_mut("adventure")
+    .and_then(|a| a.as_mapping_mut())
+    .ok_or_else(|| anyhow!("{path} has no adventure section"))?;
+
+  let mut pending = includes::Pending::new(adventure, path)?;
+  while let Some(file) = pending.next() {
+    let mut included = match read(&file)? {
+      Value::Mapping(m) => m,
+      Value::Null => continue,
+      _ => return Err(anyhow!("{file} has to contain sections of the adventure")),
+    };
+    pending.add(&mut included, &file)?;
+    includes::merge(adventure, included, &file)?;
+  }
+
+  loader::finish(document, None)
+}
+
+fn read(path: &str) -> Result<Value> {
+  let raw = std::fs::read_to_string(path).with_context(|| format!("unable to read {path}"))?;
+  let format = Format::detect(path, &raw);
+  loader::read(&raw, format).with_context(|| format!("unable to parse {path} as {format:?}"))
+}
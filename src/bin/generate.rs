@@ -0,0 +1,20 @@
+//! Writes a synthetic adventure to stdout, e.g.
+//! `cargo run --bin generate -- 10000 1000 > big.yaml`
+
+use textadventure::generator::{generate, GeneratorConfig};
+
+fn main() {
+  let mut config = GeneratorConfig::default();
+
+  let mut args = std::env::args().skip(1);
+  if let Some(rooms) = args.next() {
+    config.rooms = rooms.parse().expect("the number of rooms must be a number");
+  }
+  if let Some(chain_length) = args.next() {
+    config.chain_length = chain_length
+      .parse()
+      .expect("the chain length must be a number");
+  }
+
+  print!("{}", generate(&config));
+}
@@ -0,0 +1,32 @@
+//! Writes the structure of an adventure as a Graphviz graph, e.g.
+//! `cargo run --bin graph -- adventure.yaml | dot -Tsvg > adventure.svg`.
+
+use std::process::ExitCode;
+
+use textadventure::{
+  graph::to_dot,
+  loader::{parse, Format},
+};
+
+fn main() -> ExitCode {
+  let path = std::env::args()
+    .nth(1)
+    .unwrap_or_else(|| "adventure.yaml".to_string());
+  let raw = match std::fs::read_to_string(&path) {
+    Ok(r) => r,
+    Err(err) => {
+      eprintln!("Unable to read {path}: {err}");
+      return ExitCode::FAILURE;
+    }
+  };
+  match parse(&raw, Format::detect(&path, &raw), None) {
+    Ok(document) => {
+      print!("{}", to_dot(&document.adventure));
+      ExitCode::SUCCESS
+    }
+    Err(err) => {
+      eprintln!("Unable to parse {path}: {err:#}");
+      ExitCode::FAILURE
+    }
+  }
+}
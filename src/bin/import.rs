@@ -0,0 +1,52 @@
+//! Converts stories written with other tools into adventures, e.g.
+//! `cargo run --bin import -- story.twee > adventure.yaml`. Twine stories in
+//! the Twee format and compiled Ink stories are supported. Everything that
+//! couldn't be converted is listed on stderr.
+
+use std::process::ExitCode;
+
+use textadventure::{ink, loader::Format, twee};
+
+fn main() -> ExitCode {
+  let Some(path) = std::env::args().nth(1) else {
+    eprintln!("Usage: import <story.twee|story.ink.json>");
+    return ExitCode::FAILURE;
+  };
+  let source = match std::fs::read_to_string(&path) {
+    Ok(s) => s,
+    Err(err) => {
+      eprintln!("Unable to read {path}: {err}");
+      return ExitCode::FAILURE;
+    }
+  };
+
+  let converted = match Format::detect(&path, &source) {
+    Format::Twee => twee::convert(&source),
+    Format::Ink => ink::convert(&source),
+    format => {
+      eprintln!("{path} is a {format:?} file, which can be used as it is");
+      return ExitCode::FAILURE;
+    }
+  };
+  let (document, warnings) = match converted {
+    Ok(c) => c,
+    Err(err) => {
+      eprintln!("Unable to convert {path}: {err:#}");
+      return ExitCode::FAILURE;
+    }
+  };
+  for warning in warnings {
+    eprintln!("warning: {warning}");
+  }
+
+  match serde_yaml::to_string(&document) {
+    Ok(yaml) => {
+      print!("{yaml}");
+      ExitCode::SUCCESS
+    }
+    Err(err) => {
+      eprintln!("Unable to write the adventure: {err}");
+      ExitCode::FAILURE
+    }
+  }
+}
@@ -0,0 +1,195 @@
+//! Builds adventures from Rust instead of yaml. Rooms and items have to be
+//! declared first, which returns typed handles. Transitions and dependencies
+//! can only be wired up with those handles, so generated adventures can't
+//! reference rooms or items that were never declared.
+
+use anyhow::{anyhow, Result};
+
+use crate::adventure::{Action, ActionKind, Adventure, Item, Room};
+
+/// A room declared with [`AdventureBuilder::room`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RoomRef(String);
+
+impl RoomRef {
+  pub fn key(&self) -> &str {
+    &self.0
+  }
+}
+
+/// An item declared with [`AdventureBuilder::item`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ItemRef(String);
+
+impl ItemRef {
+  pub fn key(&self) -> &str {
+    &self.0
+  }
+}
+
+pub struct AdventureBuilder {
+  adventure: Adventure,
+}
+
+impl AdventureBuilder {
+  pub fn new(name: &str, intro: &str) -> Self {
+    Self {
+      adventure: Adventure {
+        name: name.to_string(),
+        intro: intro.to_string(),
+        ..Default::default()
+      },
+    }
+  }
+
+  /// Declares a new room. Fails if a room with the same key already exists.
+  pub fn room(&mut self, key: &str, description: &str) -> Result<RoomRef> {
+    if self.adventure.rooms.contains_key(key) {
+      return Err(anyhow!("The room {key} was declared twice"));
+    }
+
+    self.adventure.rooms.insert(
+      key.to_string(),
+      Room {
+        description: description.to_string(),
+        ..Default::default()
+      },
+    );
+    Ok(RoomRef(key.to_string()))
+  }
+
+  /// Declares a new item. Fails if an item with the same key already exists.
+  pub fn item(&mut self, key: &str) -> Result<ItemRef> {
+    if self.adventure.items.contains_key(key) {
+      return Err(anyhow!("The item {key} was declared twice"));
+    }
+
+    self
+      .adventure
+      .items
+      .insert(key.to_string(), Item::default());
+    Ok(ItemRef(key.to_string()))
+  }
+
+  /// Gives access to the item's settings, like its image.
+  pub fn item_mut(&mut self, item: &ItemRef) -> &mut Item {
+    // Handles are only created for items that were inserted
+    self
+      .adventure
+      .items
+      .get_mut(&item.0)
+      .expect("item handles always refer to declared items")
+  }
+
+  /// Gives access to the room's remaining settings, like its title or tags.
+  pub fn room_mut(&mut self, room: &RoomRef) -> &mut Room {
+    // Handles are only created for rooms that were inserted
+    self
+      .adventure
+      .rooms
+      .get_mut(&room.0)
+      .expect("room handles always refer to declared rooms")
+  }
+
+  pub fn action(&mut self, room: &RoomRef, action: ActionBuilder) -> &mut Self {
+    self.room_mut(room).actions.push(action.action);
+    self
+  }
+
+  pub fn build(mut self, start: &RoomRef) -> Adventure {
+    self.adventure.start = start.0.clone();
+    self.adventure
+  }
+}
+
+/// Builds a custom action, the kind that can combine items and transitions.
+pub struct ActionBuilder {
+  action: Action,
+}
+
+impl ActionBuilder {
+  pub fn new(name: &str) -> Self {
+    Self {
+      action: Action {
+        name: name.to_string(),
+        ..Default::default()
+      },
+    }
+  }
+
+  pub fn text(mut self, text: &str) -> Self {
+    self.action.text = text.to_string();
+    self
+  }
+
+  pub fn transition(mut self, room: &RoomRef) -> Self {
+    if let ActionKind::Custom { transition, .. } = &mut self.action.kind {
+      *transition = Some(room.0.clone());
+    }
+    self
+  }
+
+  pub fn yields(mut self, item: &ItemRef) -> Self {
+    if let ActionKind::Custom { yields, .. } = &mut self.action.kind {
+      yields.push(item.0.clone());
+    }
+    self
+  }
+
+  pub fn consumes(mut self, item: &ItemRef) -> Self {
+    self.action.consumes.push(item.0.clone());
+    self
+  }
+
+  /// Only offers the action if the player has the item.
+  pub fn requires(mut self, item: &ItemRef) -> Self {
+    self.action.depends.on.push(item.0.clone());
+    self
+  }
+
+  /// Only offers the action if the player doesn't have the item.
+  pub fn excludes(mut self, item: &ItemRef) -> Self {
+    self.action.depends.not.push(item.0.clone());
+    self
+  }
+
+  pub fn build(self) -> Action {
+    self.action
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn wires_up_declared_rooms_and_items() {
+    let mut builder = AdventureBuilder::new("The Cave", "Welcome");
+    let cave = builder.room("cave", "A cave").unwrap();
+    let outside = builder.room("outside", "Daylight").unwrap();
+    let lantern = builder.item("lantern").unwrap();
+    builder.item_mut(&lantern).carry_over = true;
+    builder.action(
+      &cave,
+      ActionBuilder::new("Leave")
+        .requires(&lantern)
+        .transition(&outside),
+    );
+
+    let adventure = builder.build(&cave);
+    assert_eq!(adventure.start, "cave");
+    assert!(adventure.items["lantern"].carry_over);
+    let action = &adventure.rooms["cave"].actions[0];
+    assert_eq!(action.depends.on, ["lantern"]);
+    assert_eq!(action.transition().map(String::as_str), Some("outside"));
+  }
+
+  #[test]
+  fn rejects_keys_declared_twice() {
+    let mut builder = AdventureBuilder::new("The Cave", "Welcome");
+    builder.room("cave", "A cave").unwrap();
+    builder.item("lantern").unwrap();
+    assert!(builder.room("cave", "Another cave").is_err());
+    assert!(builder.item("lantern").is_err());
+  }
+}
@@ -0,0 +1,123 @@
+//! Packs an adventure and the files it uses into a single zip file, so the
+//! game only makes one request. The bundle's `bundle.yaml` names the
+//! adventure file, every other file is made available to the game.
+
+use std::{
+  collections::HashMap,
+  io::{Cursor, Read},
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+/// The manifest inside the bundle.
+pub const MANIFEST: &str = "bundle.yaml";
+
+/// The largest file a bundle may unpack into. A small zip file can claim or
+/// unpack into huge files, which would hang the page.
+const MAX_FILE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// The largest all files of a bundle may unpack into together.
+const MAX_BUNDLE_BYTES: u64 = 256 * 1024 * 1024;
+
+#[derive(Deserialize)]
+pub struct BundleManifest {
+  /// The path of the adventure inside the bundle
+  #[serde(default = "BundleManifest::default_adventure")]
+  pub adventure: String,
+}
+
+impl BundleManifest {
+  fn default_adventure() -> String {
+    "adventure.yaml".to_string()
+  }
+}
+
+pub struct Bundle {
+  pub manifest: BundleManifest,
+  files: HashMap<String, Vec<u8>>,
+}
+
+impl Bundle {
+  /// Whether the url points to a bundle instead of an adventure file.
+  pub fn is_bundle(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    path.to_ascii_lowercase().ends_with(".zip")
+  }
+
+  pub fn unpack(bytes: &[u8]) -> Result<Self> {
+    let mut archive =
+      zip::ZipArchive::new(Cursor::new(bytes)).context("the bundle is no zip file")?;
+
+    let mut files = HashMap::new();
+    let mut total = 0;
+    for i in 0..archive.len() {
+      let file = archive.by_index(i)?;
+      if file.is_dir() {
+        continue;
+      }
+      // The sizes in the zip file can't be trusted, so they are only checked
+      // while reading
+      let name = file.name().to_string();
+      let mut content = Vec::new();
+      file
+        .take(MAX_FILE_BYTES + 1)
+        .read_to_end(&mut content)
+        .with_context(|| format!("unable to unpack {name}"))?;
+      if content.len() as u64 > MAX_FILE_BYTES {
+        return Err(anyhow!("{name} in the bundle is too large"));
+      }
+      total += content.len() as u64;
+      if total > MAX_BUNDLE_BYTES {
+        return Err(anyhow!("The bundle is too large"));
+      }
+      files.insert(name, content);
+    }
+
+    let manifest = match files.get(MANIFEST) {
+      Some(raw) => {
+        serde_yaml::from_slice(raw).with_context(|| format!("unable to parse {MANIFEST}"))?
+      }
+      None => return Err(anyhow!("The bundle has no {MANIFEST}")),
+    };
+    Ok(Self { manifest, files })
+  }
+
+  /// Reads a text file of the bundle, like the adventure or its includes.
+  pub fn text(&self, path: &str) -> Result<String> {
+    let path = path.trim_start_matches("./");
+    let content = self
+      .files
+      .get(path)
+      .ok_or_else(|| anyhow!("The bundle has no file {path}"))?;
+    String::from_utf8(content.clone()).with_context(|| format!("{path} is not a text file"))
+  }
+
+  /// Every file of the bundle except the manifest, by its path.
+  pub fn files(&self) -> impl Iterator<Item = (&str, &[u8])> {
+    self
+      .files
+      .iter()
+      .filter(|(path, _)| *path != MANIFEST)
+      .map(|(path, content)| (path.as_str(), content.as_slice()))
+  }
+}
+
+/// The mime type of a file in the bundle, so the browser knows how to show it.
+pub fn mime_type(path: &str) -> &'static str {
+  let extension = path.rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase());
+  match extension.as_deref() {
+    Some("png") => "image/png",
+    Some("jpg") | Some("jpeg") => "image/jpeg",
+    Some("gif") => "image/gif",
+    Some("webp") => "image/webp",
+    Some("svg") => "image/svg+xml",
+    Some("ogg") => "audio/ogg",
+    Some("mp3") => "audio/mpeg",
+    Some("wav") => "audio/wav",
+    Some("mid") | Some("midi") => "audio/midi",
+    Some("yaml") | Some("yml") => "application/yaml",
+    Some("json") => "application/json",
+    _ => "application/octet-stream",
+  }
+}
@@ -0,0 +1,38 @@
+//! The `<text-adventure src="...">` element, which plays the adventure at
+//! `src` in its own shadow root. Several of them can share a page, and the
+//! page's styles and ids don't interfere with them.
+
+use wasm_bindgen::prelude::*;
+use web_sys::{HtmlElement, ShadowRoot};
+
+/// The tag of the element.
+pub const TAG: &str = "text-adventure";
+
+#[wasm_bindgen(inline_js = r#"
+export function define_element(tag, connect) {
+  if (customElements.get(tag)) {
+    return;
+  }
+  customElements.define(tag, class extends HTMLElement {
+    connectedCallback() {
+      // Moving the element around the page keeps its game
+      if (this.shadowRoot) {
+        return;
+      }
+      connect(this, this.attachShadow({ mode: "open" }));
+    }
+  });
+}
+"#)]
+extern "C" {
+  fn define_element(tag: &str, connect: &Closure<dyn FnMut(HtmlElement, ShadowRoot)>);
+}
+
+/// Defines the element. `connect` is called with every element that is put
+/// on the page, and the shadow root to play in, including the elements that
+/// were on the page already.
+pub fn define(connect: impl FnMut(HtmlElement, ShadowRoot) + 'static) {
+  let connect = Closure::<dyn FnMut(HtmlElement, ShadowRoot)>::new(connect);
+  define_element(TAG, &connect);
+  connect.forget();
+}
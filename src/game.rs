@@ -1,303 +1,4560 @@
 use std::{
-  collections::{HashMap, HashSet},
+  cell::Cell,
+  collections::{BTreeMap, HashMap, HashSet, VecDeque},
   rc::Rc,
-  sync::{Arc, Mutex},
+  sync::Mutex,
 };
 
 use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::{closure::Closure, JsCast, JsValue};
-use web_sys::HtmlElement;
+use web_sys::{Element, Event, EventInit, HtmlElement, HtmlImageElement, Node, ShadowRoot};
 
 use crate::{
-  adventure::{Action, Adventure, Room},
-  audio::SongPlayer,
+  adventure::{
+    Action, ActionDependencies, ActionKind, ActionRendering, ActionSorting, Adventure,
+    AdventureAssets, Ambience, Bestiary, Characters, Clock, Code, Codex, CodexEntry,
+    DependencyContext, Difficulty, EncounterTable, Enemy, Faction, FastTravel, History, Image,
+    Item, Languages, Meter, NewGamePlus, Offer, Outcome, Progress, Room, RoomHook, Saves,
+    Scrollback, StatusField, Strings, Theme, TimeOfDay, Touch, TouchMode, Typewriter, Undo,
+    UseItems, UseTarget,
+  },
+  analysis,
+  audio::{EffectPlayer, Mixer, SongPlayer, Volume},
+  js_to_anyhow,
+  layout::{self, Layout},
+  loader, markdown,
+  narration::Narrator,
+  remote,
+  rng::Rng,
+  save::{self, CharacterState, Detour, LoadWarning, SaveGame, TranscriptEntry},
+  storage, validation,
 };
 
+const SAVE_KEY: &str = "textadventure_save";
+const PROFILE_KEY: &str = "textadventure_profile";
+/// Starts the fragment of links to a shared game
+const SHARE_PREFIX: &str = "save=";
+/// Whether the player hid the progress indicator. Like the volume, it applies
+/// to every adventure.
+pub(crate) const PROGRESS_KEY: &str = "textadventure_progress";
+
+/// Keeps the storage of different adventures apart, by their id or else by
+/// their name in the default language. The hash tells apart names that only
+/// differ in punctuation.
+fn storage_key(base: &str, adventure: &Adventure) -> String {
+  match &adventure.id {
+    Some(id) => format!("{base}_{id}"),
+    None => {
+      let raw = adventure.storage_name.as_ref().unwrap_or(&adventure.name);
+      let name: String = raw
+        .chars()
+        .map(|c| match c.is_alphanumeric() {
+          true => c.to_ascii_lowercase(),
+          false => '_',
+        })
+        .collect();
+      let hash = save::checksum(raw) as u32;
+      format!("{base}_{name}_{hash:08x}")
+    }
+  }
+}
+
+/// Whether the player wants to see the progress indicator, which they do
+/// unless they hid it.
+fn progress_shown() -> bool {
+  web_sys::window()
+    .and_then(|w| w.local_storage().ok().flatten())
+    .and_then(|s| s.get_item(PROGRESS_KEY).ok().flatten())
+    .is_none_or(|shown| shown != "false")
+}
+
+fn store_progress_shown(shown: bool) -> Result<()> {
+  web_sys::window()
+    .ok_or_else(|| anyhow!("unable to get the window"))?
+    .local_storage()
+    .map_err(js_to_anyhow)?
+    .ok_or_else(|| anyhow!("local storage is not available"))?
+    .set_item(PROGRESS_KEY, &shown.to_string())
+    .map_err(js_to_anyhow)
+}
+
+/// Moves the saves from before adventures were told apart by their name. Only
+/// adventures without an id used these keys, and several of them might have
+/// shared them, so only saves of this adventure are moved.
+fn migrate_legacy_keys(adventure: &Adventure) {
+  if adventure.id.is_some() {
+    return;
+  }
+
+  let save_key = storage_key(SAVE_KEY, adventure);
+  let mut moves = vec![(SAVE_KEY.to_string(), save_key.clone())];
+  moves.extend((1..=adventure.saves.slots).map(|slot| {
+    (
+      format!("{SAVE_KEY}_slot_{slot}"),
+      format!("{save_key}_slot_{slot}"),
+    )
+  }));
+  let mut theirs = false;
+  for (from, to) in moves {
+    match save::read(&from).save {
+      Some(save) if !is_save_of(&save, adventure) => {
+        log::info!("Leaving the save {from} alone, it belongs to another adventure");
+        theirs |= from == SAVE_KEY;
+        continue;
+      }
+      _ => {}
+    }
+    if let Err(err) = save::rename(&from, &to) {
+      log::error!("Unable to move the save {from}: {err:#}");
+    }
+  }
+
+  // The profile belongs to the adventure of the game that was played last
+  if theirs {
+    return;
+  }
+  let profile_key = storage_key(PROFILE_KEY, adventure);
+  if let Err(err) = storage::rename(PROFILE_KEY, &profile_key) {
+    log::error!("Unable to move the profile: {err:#}");
+  }
+}
+
+/// Whether a save from before saves were told apart was made by `adventure`.
+/// These saves don't know their adventure, but the rooms it was in have to be
+/// rooms of the adventure.
+fn is_save_of(save: &SaveGame, adventure: &Adventure) -> bool {
+  std::iter::once(&save.current_room)
+    .chain(&save.visited)
+    .all(|room| adventure.rooms.contains_key(room))
+}
+
+/// Runs when a link is clicked.
+type LinkCallback = Rc<dyn Fn(&mut GameData, Rc<Mutex<GameData>>) -> Result<()>>;
+
+/// A link currently shown in the actions element.
+struct ActionLink {
+  /// Links with the same key are reused across renders
+  key: String,
+  /// Identifies the link in its click handler
+  serial: u64,
+  element: HtmlElement,
+  on_click: LinkCallback,
+  /// The click handler, which is dropped along with the link
+  listener: Closure<dyn FnMut()>,
+}
+
+/// What the title screen shows about the adventure.
+struct TitleScreen {
+  name: String,
+  author: Option<String>,
+  version: Option<String>,
+  blurb: String,
+}
+
+impl TitleScreen {
+  fn of(adventure: &Adventure) -> Option<Self> {
+    adventure.title_screen.then(|| Self {
+      name: adventure.name.clone(),
+      author: adventure.author.clone(),
+      version: adventure.version.clone(),
+      blurb: adventure.blurb.clone(),
+    })
+  }
+}
+
+/// A running countdown of a timed choice.
+struct Timer {
+  /// The interval that ticks the countdown, if it isn't paused
+  interval: Option<i32>,
+  /// Ticks the countdown, dropped once it is cancelled
+  callback: Closure<dyn FnMut()>,
+  element: HtmlElement,
+  /// The milliseconds left at the last tick
+  remaining: f64,
+  last_tick: f64,
+}
+
+impl Timer {
+  /// Continues the countdown, unless it is running already.
+  fn resume(&mut self) -> Result<()> {
+    if self.interval.is_some() {
+      return Ok(());
+    }
+    let window = web_sys::window().ok_or(anyhow!("unable to get the window"))?;
+    let interval = window
+      .set_interval_with_callback_and_timeout_and_arguments_0(
+        self.callback.as_ref().unchecked_ref(),
+        200,
+      )
+      .map_err(js_to_anyhow)?;
+    self.interval = Some(interval);
+    self.last_tick = js_sys::Date::now();
+    Ok(())
+  }
+
+  /// Stops the countdown until it is resumed.
+  fn pause(&mut self) {
+    let Some(interval) = self.interval.take() else {
+      return;
+    };
+    if let Some(window) = web_sys::window() {
+      window.clear_interval_with_handle(interval);
+    }
+    self.remaining -= js_sys::Date::now() - self.last_tick;
+  }
+}
+
+/// An open dialog.
+struct Modal {
+  element: Element,
+  /// The handlers of the dialog's links, dropped once it is closed
+  listeners: Vec<Closure<dyn FnMut()>>,
+}
+
+/// Text the typewriter reveals letter by letter.
+struct Typing {
+  interval: i32,
+  /// Reveals the next letters, dropped once the interval is cleared
+  callback: Closure<dyn FnMut()>,
+  /// Every text node of the text and all of its text, in order
+  nodes: Vec<(Node, String)>,
+}
+
+impl Typing {
+  /// Shows the first `count` letters, returning whether that is all of them.
+  fn reveal(&self, mut count: usize) -> bool {
+    let mut done = true;
+    for (node, text) in &self.nodes {
+      let shown: String = text.chars().take(count).collect();
+      let length = shown.chars().count();
+      done &= length == text.chars().count();
+      count -= length;
+      node.set_text_content(Some(&shown));
+    }
+    done
+  }
+}
+
 pub struct Game {
   data: Rc<Mutex<GameData>>,
 }
 
 struct GameData {
+  title_screen: Option<TitleScreen>,
   intro: String,
   start: String,
   rooms: HashMap<String, Room>,
   music: HashMap<String, SongPlayer>,
+  sfx: HashMap<String, EffectPlayer>,
+  /// The images of the assets, with urls the page can load
+  images: HashMap<String, Image>,
+  /// Plays the music at the volume the player picked
+  mixer: Mixer,
+  volume: Volume,
+  /// Reads the texts aloud, if the adventure has a narration and the browser
+  /// can speak
+  narrator: Option<Narrator>,
+  /// Trusts the html in texts, including those of saves
+  raw_html: bool,
+  /// The song that was played last
+  playing: Option<String>,
+  /// Seconds one song fades into the next
+  crossfade: f64,
 
   current_text: String,
   current_room: String,
 
   inventory: HashSet<String>,
+  visited: HashSet<String>,
+  flags: HashSet<String>,
+
+  fast_travel: FastTravel,
+  ambience: Ambience,
+  clock: Option<Clock>,
+  /// Hours since midnight of the first day
+  time: u64,
+  /// Failed attempts per check
+  failures: HashMap<String, i64>,
+
+  bestiary: Bestiary,
+  codex: Codex,
+  codes: HashMap<String, Code>,
+  /// The last keys the player typed, to detect codes
+  typed: String,
+  /// The codes the player entered, in order
+  entered_codes: Vec<String>,
+  profile: Profile,
+  save_key: String,
+  /// Where the save is uploaded to, if anywhere
+  sync: Option<String>,
+
+  encounters: HashMap<String, EncounterTable>,
+  rng: Rng,
+  /// The number of actions taken so far
+  turn: u64,
+  /// The turn of the last encounter per tag, for cooldowns
+  encounter_turns: HashMap<String, u64>,
+  /// Where the player was headed before an encounter interrupted them
+  detour: Option<Detour>,
+
+  meters: BTreeMap<String, Meter>,
+  meter_values: HashMap<String, i64>,
+
+  factions: BTreeMap<String, Faction>,
+  reputation: HashMap<String, i64>,
+  /// The topics each npc remembers talking about
+  memory: HashMap<String, HashSet<String>>,
+
+  score: i64,
+  /// Every item the player ever had, even if it was used up since
+  items_found: HashSet<String>,
+  /// Seconds played before the current session
+  playtime: f64,
+  /// When the current session started, in milliseconds since the epoch
+  session_start: f64,
+
+  characters: Characters,
+  active_character: String,
+  /// The state of all characters except the active one
+  character_states: HashMap<String, CharacterState>,
+
+  items: HashMap<String, Item>,
+  new_game_plus: NewGamePlus,
+  /// Whether this game was started from a finished one
+  ng_plus: bool,
+
+  difficulties: Vec<Difficulty>,
+  /// The id of the chosen difficulty, empty if the adventure has none
+  difficulty: String,
+
+  use_items: UseItems,
+  languages: Languages,
+  strings: Strings,
+  saves: Saves,
+  undo: Undo,
+  /// The states before the player's last actions, the most recent last
+  undo_stack: VecDeque<SaveGame>,
+  history: History,
+  /// Every action taken and the text it showed, in order
+  transcript: Vec<TranscriptEntry>,
+  /// The text of the last save, as menus replace the current text
+  saved_text: String,
+  /// Set once the save was removed for a reset, so leaving the page doesn't
+  /// write it again
+  resetting: bool,
+
+  action_rendering: ActionRendering,
+  links: Vec<ActionLink>,
+  /// The click handlers of removed links and other callbacks that might
+  /// still be running when they were removed, dropped on the next click
+  retired_listeners: Vec<Closure<dyn FnMut()>>,
+  next_link_serial: u64,
+  timer: Option<Timer>,
+  /// The interval that saves every few seconds, if the adventure wants it
+  autosave_interval: Option<(i32, Closure<dyn FnMut()>)>,
+  /// The open dialogs, the countdown waits while there are any
+  modals: Vec<Modal>,
+  typewriter: Option<Typewriter>,
+  scrollback: Option<Scrollback>,
+  /// The texts of earlier turns that are kept with a scrollback, oldest first
+  messages: VecDeque<HtmlElement>,
+  theme: Theme,
+  /// The text the typewriter is revealing, if it isn't done yet
+  typing: Option<Typing>,
+  /// Whether the player entered another room since the actions were last
+  /// finished, which moves the focus to the new actions
+  entered_room: bool,
+  /// How many of the links were rendered since the actions were last updated
+  rendered_links: usize,
 
   text_element: HtmlElement,
   actions_element: HtmlElement,
+  meters_element: Option<HtmlElement>,
+  /// The status bar is optional as well
+  status_element: Option<HtmlElement>,
+  status: Vec<StatusField>,
+  progress_element: Option<HtmlElement>,
+  progress: Option<Progress>,
+  /// Whether the player wants to see the progress
+  progress_shown: bool,
+  /// The drawer with the items, only shown in the layout for touch screens
+  inventory_element: Option<HtmlElement>,
+  touch: Touch,
+  /// Shows the images of rooms and items, if the page has it
+  scene_element: Option<HtmlElement>,
+  /// Wraps the game, and carries the theme of the room
+  container_element: HtmlElement,
 }
 
 impl Game {
   pub fn start(&mut self) -> Result<()> {
     let mut data = self.data.lock().unwrap();
 
-    data.current_text = data.intro.clone();
-    data.text_element.set_inner_html(&data.current_text);
-
-    let actions = vec![Action {
-      name: "Start".to_string(),
-      transition: Some(data.start.clone()),
-      ..Default::default()
-    }];
-
-    Self::update_actions(&mut data, self.data.clone(), &actions)?;
+    Self::show_start(&mut data, self.data.clone())?;
+    Self::start_autosave(&mut data, self.data.clone())?;
 
-    Self::load(&mut data, self.data.clone());
+    // A reload can add codes or the typewriter, so these always listen
+    Self::listen_for_codes(self.data.clone(), &data.container_element)?;
+    Self::listen_for_skips(self.data.clone(), &data.container_element)?;
+    Self::listen_for_keywords(self.data.clone(), &data.text_element)?;
 
     Ok(())
   }
 
-  fn goto_room(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>, room: &str) {
-    data.current_room = room.to_string();
+  /// Shows the title screen, or the intro and the saved game, if there is
+  /// one.
+  fn show_start(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    if data.title_screen.is_some() {
+      Self::show_title_screen(data, data_ptr)?;
+    } else {
+      Self::show_intro(data, data_ptr.clone())?;
+      Self::load(data, data_ptr);
+    }
+    Self::finish_actions(data);
+    Ok(())
+  }
 
-    let room = match data.rooms.get(room) {
-      Some(r) => r.clone(),
-      None => {
-        log::error!("Tried to go to nonexistant room {room}");
-        return;
-      }
+  /// Shows the name of the adventure and who wrote it. The player continues
+  /// the saved game, if there is one, or starts a new one with the intro.
+  fn show_title_screen(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    let Some(title) = &data.title_screen else {
+      return Self::show_intro(data, data_ptr);
     };
 
-    data.current_text += &room.description;
-    data.text_element.set_inner_html(&data.current_text);
+    let mut text = format!("<h1 class=\"title\">{}</h1>", markdown::escape(&title.name));
+    let byline: Vec<String> = [
+      title
+        .author
+        .as_ref()
+        .map(|a| data.strings.author.replace("{author}", a)),
+      title
+        .version
+        .as_ref()
+        .map(|v| data.strings.version.replace("{version}", v)),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|line| markdown::escape(&line))
+    .collect();
+    if !byline.is_empty() {
+      text += &format!("<p class=\"byline\">{}</p>", byline.join(" · "));
+    }
+    if !title.blurb.is_empty() {
+      text += &format!("<p class=\"blurb\">{}</p>", title.blurb);
+    }
+    data.current_text = text;
+    Self::rewrite_text(data);
+    Self::apply_theme(data, None);
 
-    // Item states might have changed
-    if let Err(err) = Self::update_actions(data, data_ptr.clone(), &room.actions) {
-      log::error!("Unable to update the actions: {err:#}");
+    Self::update_actions(data, data_ptr.clone(), &[])?;
+    if save::read(&data.save_key).save.is_some() {
+      let name = data.strings.continue_game.clone();
+      let on_click: LinkCallback = Rc::new(|data, data_ptr| {
+        Self::load(data, data_ptr);
+        Ok(())
+      });
+      Self::add_link(
+        data,
+        data_ptr.clone(),
+        "continue",
+        &name,
+        "location_change",
+        on_click,
+      )?;
     }
+    let name = data.strings.new_game.clone();
+    let on_click: LinkCallback = Rc::new(Self::show_intro);
+    Self::add_link(
+      data,
+      data_ptr,
+      "new_game",
+      &name,
+      "location_change",
+      on_click,
+    )?;
+    Ok(())
   }
 
-  fn update_actions(
-    data: &mut GameData,
-    data_ptr: Rc<Mutex<GameData>>,
-    actions: &[Action],
-  ) -> Result<()> {
-    data.actions_element.set_inner_html("");
+  /// Shows the intro, with the action that starts the game, or one per
+  /// difficulty.
+  fn show_intro(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    data.current_text = data.intro.clone();
+    Self::rewrite_text(data);
+    Self::apply_theme(data, None);
 
-    let document = web_sys::window().unwrap().document().unwrap();
+    if data.difficulties.is_empty() {
+      let actions = vec![Self::start_action(data)];
+      return Self::update_actions(data, data_ptr, &actions);
+    }
 
-    for action in actions {
-      // Check if the action's requirements are met
-      let skip = (|| {
-        for item in &action.depends.on {
-          log::info!("Checking if we have {item}");
-          if !data.inventory.contains(item) {
-            return true;
-          }
+    Self::update_actions(data, data_ptr.clone(), &[])?;
+    for difficulty in data.difficulties.clone() {
+      let key = format!("difficulty:{}", difficulty.id);
+      let name = difficulty.name.clone();
+      let on_click: LinkCallback = Rc::new(move |data, data_ptr| {
+        Self::choose_difficulty(data, data_ptr, &difficulty);
+        Ok(())
+      });
+      Self::add_link(
+        data,
+        data_ptr.clone(),
+        &key,
+        &name,
+        "location_change",
+        on_click,
+      )?;
+    }
+    Ok(())
+  }
+
+  /// Watches what the player types in the game, and applies a code once it
+  /// was typed. `longest` is the length of the longest code.
+  fn listen_for_codes(data_ptr: Rc<Mutex<GameData>>, container: &HtmlElement) -> Result<()> {
+    let callback =
+      Closure::<dyn FnMut(web_sys::KeyboardEvent)>::new(move |event: web_sys::KeyboardEvent| {
+        let key = event.key();
+        if key.chars().count() != 1 {
+          return;
         }
 
-        for item in &action.depends.not {
-          log::info!("Checking if we don't have {item}");
-          if data.inventory.contains(item) {
-            return true;
-          }
+        // Typing into a field, like when importing a save, doesn't count
+        let editing = event
+          .target()
+          .and_then(|t| t.dyn_into::<Element>().ok())
+          .is_some_and(|e| matches!(e.tag_name().as_str(), "INPUT" | "TEXTAREA"));
+        if editing {
+          return;
         }
-        false
-      })();
-      if skip {
-        continue;
-      }
 
-      // Create a new link element
-      let link: HtmlElement = document
-        .create_element("a")
-        .map_err(js_to_anyhow)?
-        .dyn_into()
-        .map_err(|_| anyhow!("Expected a link"))?;
+        let mut data = data_ptr.lock().unwrap();
+        // Codes only work once the game started
+        if data.current_room.is_empty() {
+          return;
+        }
+        let Some(longest) = data.codes.keys().map(|c| c.chars().count()).max() else {
+          return;
+        };
 
-      // Set the text, and add a pseudo target to make the link clickable
-      link.set_inner_text(&action.name);
-      link.set_attribute("href", "#").map_err(js_to_anyhow)?;
+        data.typed += &key;
+        let skip = data.typed.chars().count().saturating_sub(longest);
+        data.typed = data.typed.chars().skip(skip).collect();
 
-      // Actions that change locations look different
-      if action.transition.is_some() {
-        link.set_class_name("location_change");
+        let code = data
+          .codes
+          .keys()
+          .find(|code| data.typed.ends_with(code.as_str()))
+          .cloned();
+        if let Some(code) = code {
+          data.typed.clear();
+          Self::enter_code(&mut data, data_ptr.clone(), &code);
+        }
+      });
+
+    Self::make_focusable(container)?;
+    container
+      .add_event_listener_with_callback("keydown", callback.as_ref().unchecked_ref())
+      .map_err(js_to_anyhow)?;
+    callback.forget();
+    Ok(())
+  }
+
+  /// Lets clicks anywhere in the game focus it, so the keys the player
+  /// presses afterwards reach it.
+  fn make_focusable(container: &HtmlElement) -> Result<()> {
+    if !container.has_attribute("tabindex") {
+      container
+        .set_attribute("tabindex", "-1")
+        .map_err(js_to_anyhow)?;
+    }
+    Ok(())
+  }
+
+  /// Shows the current text, letter by letter if the adventure has a
+  /// typewriter.
+  fn show_text(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) {
+    Self::cancel_typing(data);
+    let element = Self::write_text(data);
+    // Read before the typewriter empties the text
+    if let Some(narrator) = &data.narrator {
+      if let Err(err) = narrator.read(&element) {
+        log::error!("Unable to read the text aloud: {err:#}");
       }
+    }
 
-      // Setup the callback that will be run if the action is selected.
-      let callback_data = data_ptr.clone();
-      let callback_action = action.clone();
-      let callback = Closure::<dyn FnMut()>::new(move || {
-        let mut data = callback_data.lock().unwrap();
+    let Some(speed) = data.typewriter.as_ref().map(|t| t.speed) else {
+      return;
+    };
+    if let Err(err) = Self::start_typing(data, data_ptr, &element, speed) {
+      log::error!("Unable to reveal the text: {err:#}");
+      Self::finish_typing(data);
+    }
+  }
 
-        // Give the player all items the action yields
-        for item in &callback_action.yields {
-          data.inventory.insert(item.clone());
-        }
+  /// Shows the current text, and returns the element it is shown in. With a
+  /// scrollback, the text is added below those of earlier turns, which are
+  /// put back if a menu replaced them.
+  fn write_text(data: &mut GameData) -> HtmlElement {
+    let Some(limit) = data.scrollback.as_ref().map(|s| s.limit) else {
+      data.text_element.set_inner_html(&data.current_text);
+      return data.text_element.clone();
+    };
 
-        // Play music if requested
-        if let Some(music) = &callback_action.music {
-          if let Some(player) = data.music.get(music) {
-            player.play();
-          }
-        }
+    if data.messages.back().is_some_and(|m| !m.is_connected()) {
+      data.text_element.set_inner_html("");
+      for message in &data.messages {
+        let _ = data.text_element.append_child(message);
+      }
+    }
+    for message in &data.messages {
+      let _ = message.class_list().add_1("earlier");
+    }
 
-        data.current_text = callback_action.text.clone();
-        if !data.current_text.is_empty() {
-          data.current_text += "<br/><br/>";
-        }
+    let message = match Self::create_message(&data.current_text) {
+      Ok(m) => m,
+      Err(err) => {
+        log::error!("Unable to add the text: {err:#}");
+        return data.text_element.clone();
+      }
+    };
+    if let Err(err) = data.text_element.append_child(&message) {
+      log::error!("Unable to add the text: {}", js_to_anyhow(err));
+    }
+    data.messages.push_back(message.clone());
+    while data.messages.len() > limit.max(1) {
+      if let Some(oldest) = data.messages.pop_front() {
+        oldest.remove();
+      }
+    }
+    message.scroll_into_view_with_bool(true);
+    message
+  }
 
-        if let Some(destination) = &callback_action.transition {
-          Self::goto_room(&mut data, callback_data.clone(), destination);
-        } else {
-          let room = match data.rooms.get(&data.current_room) {
-            Some(r) => r.clone(),
-            None => {
-              log::error!(
-                "Current room is set to nonexistant room {}, can't reload the actions",
-                &data.current_room
-              );
-              return;
-            }
-          };
-          data.current_text += &room.description;
+  fn create_message(html: &str) -> Result<HtmlElement> {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let message: HtmlElement = document
+      .create_element("div")
+      .map_err(js_to_anyhow)?
+      .dyn_into()
+      .map_err(|_| anyhow!("Expected a div"))?;
+    message.set_class_name("message");
+    message.set_inner_html(html);
+    Ok(message)
+  }
+
+  /// Shows the current text without the earlier ones, e.g. when a game is
+  /// loaded.
+  fn rewrite_text(data: &mut GameData) {
+    for message in data.messages.drain(..) {
+      message.remove();
+    }
+    data.text_element.set_inner_html("");
+    Self::write_text(data);
+  }
+
+  fn start_typing(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    element: &HtmlElement,
+    speed: f64,
+  ) -> Result<()> {
+    let window = web_sys::window().ok_or(anyhow!("unable to get the window"))?;
 
-          data.text_element.set_inner_html(&data.current_text);
+    let mut nodes = Vec::new();
+    text_nodes(element, &mut nodes);
+    for (node, _) in &nodes {
+      node.set_text_content(Some(""));
+    }
+    // Screen readers wait for the whole text instead of reading every letter
+    data
+      .text_element
+      .set_attribute("aria-busy", "true")
+      .map_err(js_to_anyhow)?;
 
-          // Item states might have changed
-          if let Err(err) = Self::update_actions(&mut data, callback_data.clone(), &room.actions) {
-            log::error!("Unable to update the actions: {err:#}");
-          }
+    let start = js_sys::Date::now();
+    let callback = Closure::<dyn FnMut()>::new(move || {
+      let element = {
+        let mut data = data_ptr.lock().unwrap();
+        let shown = ((js_sys::Date::now() - start) / 1000.0 * speed) as usize;
+        if !data.typing.as_ref().is_some_and(|t| t.reveal(shown)) {
+          return;
         }
+        Self::finish_typing(&mut data);
+        data.text_element.clone()
+      };
+      announce_typed(&element);
+    });
+    let interval = window
+      .set_interval_with_callback_and_timeout_and_arguments_0(callback.as_ref().unchecked_ref(), 30)
+      .map_err(js_to_anyhow)?;
 
-        // Save the new state
-        Self::save(&mut data);
-      });
+    data.typing = Some(Typing {
+      interval,
+      callback,
+      nodes,
+    });
+    Ok(())
+  }
 
-      link.set_onclick(Some(callback.as_ref().unchecked_ref()));
-      callback.forget();
+  /// Shows the whole text the typewriter is revealing. Returns whether there
+  /// was one, so the caller can announce it once the data is unlocked.
+  fn finish_typing(data: &mut GameData) -> bool {
+    let Some(typing) = data.typing.take() else {
+      return false;
+    };
+    if let Some(window) = web_sys::window() {
+      window.clear_interval_with_handle(typing.interval);
+    }
+    typing.reveal(usize::MAX);
+    let _ = data.text_element.remove_attribute("aria-busy");
+    // The interval might be what finished the text
+    data.retired_listeners.push(typing.callback);
+    true
+  }
 
-      data
-        .actions_element
-        .append_child(&link)
+  /// Stops the typewriter, as its text is replaced.
+  fn cancel_typing(data: &mut GameData) {
+    if let Some(typing) = data.typing.take() {
+      if let Some(window) = web_sys::window() {
+        window.clear_interval_with_handle(typing.interval);
+      }
+      let _ = data.text_element.remove_attribute("aria-busy");
+      data.retired_listeners.push(typing.callback);
+    }
+  }
+
+  /// Lets the player skip the typewriter by clicking or pressing a key.
+  fn listen_for_skips(data_ptr: Rc<Mutex<GameData>>, container: &HtmlElement) -> Result<()> {
+    let callback = Closure::<dyn FnMut()>::new(move || {
+      let element = {
+        let mut data = data_ptr.lock().unwrap();
+        Self::finish_typing(&mut data).then(|| data.text_element.clone())
+      };
+      if let Some(element) = element {
+        announce_typed(&element);
+      }
+    });
+    Self::make_focusable(container)?;
+    for event in ["pointerdown", "keydown"] {
+      container
+        .add_event_listener_with_callback(event, callback.as_ref().unchecked_ref())
         .map_err(js_to_anyhow)?;
     }
+    callback.forget();
+    Ok(())
+  }
+
+  /// Lets the keywords in the texts run their action, like the action's own
+  /// link would. Keywords of actions that aren't offered do nothing.
+  fn listen_for_keywords(data_ptr: Rc<Mutex<GameData>>, text: &HtmlElement) -> Result<()> {
+    let callback = Closure::<dyn FnMut(Event)>::new(move |event: Event| {
+      let keyword = event
+        .target()
+        .and_then(|t| t.dyn_into::<Element>().ok())
+        .and_then(|e| e.closest("a[data-action]").ok().flatten());
+      let Some(keyword) = keyword else {
+        return;
+      };
+      event.prevent_default();
 
+      let id = keyword.get_attribute("data-action").unwrap_or_default();
+      let link = {
+        let data = data_ptr.lock().unwrap();
+        Self::offered_link(&data, &id)
+      };
+      // The link's own handler locks the data again
+      if let Some(link) = link {
+        link.click();
+      }
+    });
+    text
+      .add_event_listener_with_callback("click", callback.as_ref().unchecked_ref())
+      .map_err(js_to_anyhow)?;
+    callback.forget();
     Ok(())
   }
 
-  fn save(data: &mut GameData) {
-    let save = SaveGame {
-      current_text: data.current_text.clone(),
-      inventory: data.inventory.clone().into_iter().collect(),
-      current_room: data.current_room.clone(),
+  /// The link of the action with `id`, if it is offered right now.
+  fn offered_link(data: &GameData, id: &str) -> Option<HtmlElement> {
+    let key = format!("action:{id}");
+    data
+      .links
+      .iter()
+      .find(|l| l.key == key)
+      .map(|l| l.element.clone())
+  }
+
+  /// Marks the keywords in the texts whose action isn't offered right now.
+  fn mark_keywords(data: &GameData) {
+    let Ok(keywords) = data.text_element.query_selector_all("a[data-action]") else {
+      return;
     };
+    for index in 0..keywords.length() {
+      let Some(keyword) = keywords
+        .get(index)
+        .and_then(|k| k.dyn_into::<Element>().ok())
+      else {
+        continue;
+      };
+      let id = keyword.get_attribute("data-action").unwrap_or_default();
+      let offered = Self::offered_link(data, &id).is_some();
+      let _ = keyword
+        .class_list()
+        .toggle_with_force("unavailable", !offered);
+      let _ = keyword.set_attribute("aria-disabled", &(!offered).to_string());
+    }
+  }
 
-    let serialized = serde_json::to_string(&save).unwrap();
+  fn enter_code(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>, code: &str) {
+    let effect = data.codes[code].clone();
+    data.entered_codes.push(code.to_string());
 
-    let window = web_sys::window().unwrap();
-    window
-      .local_storage()
-      .unwrap()
-      .unwrap()
-      .set("textadventure_save", &serialized)
-      .unwrap();
+    let action = Action {
+      name: code.to_string(),
+      text: effect.text,
+      sets: effect.flags,
+      time: Some(0),
+      kind: ActionKind::Custom {
+        yields: effect.items,
+        transition: effect.transition,
+      },
+      ..Default::default()
+    };
+    Self::execute_action(data, data_ptr, &action);
+    Self::save(data);
+    Self::finish_actions(data);
   }
 
-  fn load(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) {
-    let window = web_sys::window().unwrap();
-    if let Some(save) = window
-      .local_storage()
-      .unwrap()
-      .unwrap()
-      .get_item("textadventure_save")
-      .unwrap()
-    {
-      let parsed = serde_json::from_str::<SaveGame>(&save);
-      if let Ok(save) = parsed {
-        data.inventory = save.inventory.into_iter().collect();
+  fn start_action(data: &GameData) -> Action {
+    Action {
+      name: data.strings.start.clone(),
+      time: Some(0),
+      kind: ActionKind::Navigate {
+        transition: data.start.clone(),
+      },
+      ..Default::default()
+    }
+  }
+
+  /// Applies the difficulty's starting conditions and starts the game.
+  fn choose_difficulty(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    difficulty: &Difficulty,
+  ) {
+    data.difficulty = difficulty.id.clone();
+    Self::gain_items(data, &difficulty.items);
+    data
+      .meter_values
+      .extend(difficulty.meters.iter().map(|(k, v)| (k.clone(), *v)));
+    Self::render_meters(data);
 
-        Self::goto_room(data, data_ptr.clone(), &save.current_room);
+    let action = Self::start_action(data);
+    Self::execute_action(data, data_ptr, &action);
+    Self::save(data);
+  }
 
-        data.current_text = save.current_text;
-        data.text_element.set_inner_html(&data.current_text);
-      } else {
-        log::warn!("Found malformed save data: {save}");
+  /// Continues from the save on the sync server if it is newer than the local
+  /// one, and uploads the local one otherwise. If the server can't be
+  /// reached, the local save is used.
+  pub async fn sync(&self) {
+    let (endpoint, key) = {
+      let data = self.data.lock().unwrap();
+      match &data.sync {
+        Some(endpoint) => (endpoint.clone(), data.save_key.clone()),
+        None => return,
+      }
+    };
+
+    let remote = match remote::pull(&endpoint, &key).await {
+      Ok(r) => r,
+      Err(err) => {
+        log::warn!("Unable to download the save, continuing offline: {err:#}");
+        return;
+      }
+    };
+
+    // The newest save wins
+    let local = save::read(&key).save;
+    match (remote, local) {
+      (Some(remote), Some(local)) if remote.saved_at == local.saved_at => {}
+      (Some(remote), Some(local)) if local.saved_at > remote.saved_at => {
+        remote::push(&endpoint, &key, &local)
+      }
+      (Some(remote), _) => {
+        if let Err(err) = save::write(&key, &remote) {
+          log::error!("Unable to store the downloaded save: {err:#}");
+        }
       }
+      (None, Some(local)) => remote::push(&endpoint, &key, &local),
+      (None, None) => {}
     }
   }
 
-  fn reset() {
-    let window = web_sys::window().unwrap();
-    window
-      .local_storage()
-      .unwrap()
-      .unwrap()
-      .remove_item("textadventure_save")
-      .unwrap();
+  /// Exposes `getSnapshot()` and `loadSnapshot(snapshot)` as the
+  /// `textadventure` property of `target`, so external tools can read and
+  /// replace the game's state, and `pauseMusic()` and `resumeMusic()` to halt
+  /// the audio. The target is the window, or the `<text-adventure>` element.
+  pub fn expose_api(&self, target: &JsValue) -> Result<()> {
+    let api = js_sys::Object::new();
 
-    window.location().set_href("/").unwrap();
-  }
-}
+    let snapshot_data = self.data.clone();
+    let get_snapshot = Closure::<dyn FnMut() -> Result<JsValue, JsValue>>::new(move || {
+      let data = snapshot_data.lock().unwrap();
+      Self::get_snapshot(&data).map_err(|err| JsValue::from_str(&format!("{err:#}")))
+    });
+    js_sys::Reflect::set(&api, &"getSnapshot".into(), get_snapshot.as_ref())
+      .map_err(js_to_anyhow)?;
+    get_snapshot.forget();
 
-impl TryFrom<Adventure> for Game {
-  type Error = anyhow::Error;
+    let load_data = self.data.clone();
+    let load_snapshot =
+      Closure::<dyn FnMut(JsValue) -> Result<(), JsValue>>::new(move |snapshot| {
+        let mut data = load_data.lock().unwrap();
+        Self::load_snapshot(&mut data, load_data.clone(), snapshot)
+          .map_err(|err| JsValue::from_str(&format!("{err:#}")))
+      });
+    js_sys::Reflect::set(&api, &"loadSnapshot".into(), load_snapshot.as_ref())
+      .map_err(js_to_anyhow)?;
+    load_snapshot.forget();
 
-  fn try_from(value: Adventure) -> Result<Self, Self::Error> {
-    // Load the music
-    let mut music = HashMap::new();
-    for (key, val) in value.assets.music {
-      music.insert(key, SongPlayer::try_from(val)?);
+    for (name, pause) in [("pauseMusic", true), ("resumeMusic", false)] {
+      let audio_data = self.data.clone();
+      let callback = Closure::<dyn FnMut() -> Result<(), JsValue>>::new(move || {
+        let data = audio_data.lock().unwrap();
+        let result = if pause {
+          data.mixer.pause()
+        } else {
+          data.mixer.resume()
+        };
+        result.map_err(|err| JsValue::from_str(&format!("{err:#}")))
+      });
+      js_sys::Reflect::set(&api, &name.into(), callback.as_ref()).map_err(js_to_anyhow)?;
+      callback.forget();
     }
 
-    let window = web_sys::window().ok_or(anyhow!("unable to get the window"))?;
-    let document = window
-      .document()
-      .ok_or(anyhow!("unable to get the document"))?;
+    js_sys::Reflect::set(target, &"textadventure".into(), &api).map_err(js_to_anyhow)?;
 
-    let text_element: HtmlElement = document
-      .get_element_by_id("maintext")
-      .ok_or(anyhow!("Missing a #maintext element in the dom"))?
-      .dyn_into()
-      .map_err(|_| anyhow!("maintext ist not an html element"))?;
+    Ok(())
+  }
 
-    let actions_element: HtmlElement = document
-      .get_element_by_id("actions")
-      .ok_or(anyhow!("Missing an #actions element in the dom"))?
-      .dyn_into()
-      .map_err(|_| anyhow!("maintext ist not an html element"))?;
+  /// Shows the staff notation of a song instead of starting the game, which
+  /// lets authors verify how the notes were interpreted.
+  pub fn show_sheet(&mut self, song: &str) -> Result<()> {
+    let mut data = self.data.lock().unwrap();
 
-    let reset_element: HtmlElement = document
-      .get_element_by_id("reset")
-      .ok_or(anyhow!("Missing a #reset element in the dom"))?
-      .dyn_into()
-      .map_err(|_| anyhow!("reset ist not an html element"))?;
+    let sheet = data
+      .music
+      .get(song)
+      .ok_or_else(|| anyhow!("There is no song called {song}"))?
+      .sheet();
+    data.current_text = sheet;
+    data.text_element.set_inner_html(&data.current_text);
+    Self::remove_links(&mut data, 0);
 
-    let reset_callback = Closure::<dyn FnMut()>::new(Self::reset);
-    reset_element.set_onclick(Some(reset_callback.as_ref().unchecked_ref()));
-    reset_callback.forget();
+    let link = Self::create_link("Play")?;
+    let callback_data = self.data.clone();
+    let callback_song = song.to_string();
+    let callback = Closure::<dyn FnMut()>::new(move || {
+      Self::play_music(
+        &mut callback_data.lock().unwrap(),
+        &callback_song,
+        None,
+        None,
+      );
+    });
+    link.set_onclick(Some(callback.as_ref().unchecked_ref()));
+    callback.forget();
 
-    let data = GameData {
-      intro: value.intro,
-      rooms: value.rooms,
-      start: value.start,
-      inventory: HashSet::new(),
-      current_text: String::default(),
-      current_room: String::default(),
-      music,
-      text_element,
-      actions_element,
-    };
+    data
+      .actions_element
+      .append_child(&link)
+      .map_err(js_to_anyhow)?;
 
-    Ok(Self {
-      data: Rc::new(Mutex::new(data)),
-    })
+    Ok(())
   }
-}
 
-fn js_to_anyhow(val: JsValue) -> anyhow::Error {
-  anyhow!("{val:?}")
-}
+  /// Moves the player to `room`. The rooms' `on_exit` and `on_enter` hooks
+  /// only run if `run_hooks` is set, so restoring a game doesn't repeat them.
+  fn goto_room(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>, room: &str, run_hooks: bool) {
+    let previous = std::mem::replace(&mut data.current_room, room.to_string());
+    data.visited.insert(room.to_string());
+    data.entered_room = previous != room;
+    let previous_music = data.rooms.get(&previous).and_then(|r| r.music.clone());
 
-#[derive(Serialize, Deserialize)]
-struct SaveGame {
-  current_text: String,
-  inventory: Vec<String>,
-  current_room: String,
+    let run_hooks = run_hooks && previous != room;
+    if run_hooks {
+      let hooks = data
+        .rooms
+        .get(&previous)
+        .map(|r| r.on_exit.clone())
+        .unwrap_or_default();
+      let text = Self::run_hooks(data, &hooks);
+      if !text.is_empty() {
+        data.current_text += &format!("{text}<br/><br/>");
+      }
+    }
+
+    let room = match data.rooms.get(room) {
+      Some(r) => Self::resolve_room(data, r),
+      None => {
+        log::error!("Tried to go to nonexistant room {room}");
+        return;
+      }
+    };
+
+    for player in data.music.values_mut() {
+      if let Err(err) = player.set_profile(room.audio_profile) {
+        log::error!("Unable to apply the audio profile: {err:#}");
+      }
+    }
+    if previous != data.current_room {
+      Self::room_music(data, previous_music.as_deref(), room.music.as_deref());
+    }
+
+    let description = Self::describe_room(data, &room);
+    data.current_text += &description;
+
+    if run_hooks {
+      let text = Self::run_hooks(data, &room.on_enter);
+      if !text.is_empty() {
+        data.current_text += &format!("<br/><br/>{text}");
+      }
+    }
+
+    if room.ending {
+      if data.profile.endings.insert(data.current_room.clone()) {
+        data.profile.save();
+      }
+      let statistics = Self::statistics(data);
+      data.current_text += &statistics;
+    }
+
+    Self::show_text(data, data_ptr.clone());
+    Self::show_image(data, room.image.as_deref());
+    Self::apply_theme(data, room.theme.as_ref());
+
+    // Item states might have changed
+    if let Err(err) = Self::update_room_actions(data, data_ptr.clone(), &room) {
+      log::error!("Unable to update the actions: {err:#}");
+    }
+  }
+
+  /// Starts the music of the room the player entered, unless it already
+  /// plays. Without music, the music of the room the player left fades out.
+  fn room_music(data: &mut GameData, previous: Option<&str>, music: Option<&str>) {
+    match music {
+      Some(song) if data.playing.as_deref() != Some(song) => {
+        Self::play_music(data, song, None, None)
+      }
+      Some(_) => {}
+      None => {
+        if previous.is_some() && data.playing.as_deref() == previous {
+          Self::stop_music(data, None);
+        }
+      }
+    }
+  }
+
+  /// Plays `song` from the start, fading out whatever song was playing. The
+  /// given fades win over those of the songs, and songs without them
+  /// crossfade.
+  fn play_music(data: &mut GameData, song: &str, fade_in: Option<f64>, fade_out: Option<f64>) {
+    let replaces = data.playing.is_some();
+    Self::stop_music(data, fade_out);
+    let Some(player) = data.music.get(song) else {
+      log::error!("Tried to play nonexistant song {song}");
+      return;
+    };
+    let fade =
+      fade_in
+        .or(player.default_fade_in())
+        .unwrap_or(if replaces { data.crossfade } else { 0.0 });
+    match player.play(fade) {
+      Ok(()) => {
+        player.update_layers(&Self::dependency_context(data), true);
+        data.playing = Some(song.to_string());
+      }
+      Err(err) => log::error!("Unable to play {song}: {err:#}"),
+    }
+  }
+
+  /// Fades the layers of the playing song in or out, as the action might
+  /// have changed what they depend on.
+  fn update_layers(data: &GameData) {
+    if let Some(player) = data.playing.as_ref().and_then(|p| data.music.get(p)) {
+      player.update_layers(&Self::dependency_context(data), false);
+    }
+  }
+
+  /// Fades out the playing song over `fade_out` seconds, or else its own fade
+  /// out or the crossfade.
+  fn stop_music(data: &mut GameData, fade_out: Option<f64>) {
+    if let Some(player) = data.playing.take().and_then(|p| data.music.get(&p)) {
+      player.fade_out(
+        fade_out
+          .or(player.default_fade_out())
+          .unwrap_or(data.crossfade),
+      );
+    }
+  }
+
+  /// Applies the effects of all hooks whose dependencies are met, and returns
+  /// their text.
+  fn run_hooks(data: &mut GameData, hooks: &[RoomHook]) -> String {
+    let mut texts = Vec::new();
+    for hook in hooks {
+      if !Self::dependencies_met(data, &hook.depends) {
+        continue;
+      }
+
+      Self::gain_items(data, &hook.yields);
+      data.flags.extend(hook.sets.iter().cloned());
+      for flag in &hook.clears {
+        data.flags.remove(flag);
+      }
+
+      if let Some(music) = &hook.music {
+        Self::play_music(data, music, None, None);
+      }
+
+      if !hook.text.is_empty() {
+        texts.push(hook.text.clone());
+      }
+    }
+    texts.join("<br/><br/>")
+  }
+
+  /// Applies the room's day or night variant, depending on the current time.
+  fn resolve_room(data: &GameData, room: &Room) -> Room {
+    let mut resolved = room.clone();
+
+    let variant = match Self::time_of_day(data) {
+      TimeOfDay::Day => &room.day,
+      TimeOfDay::Night => &room.night,
+    };
+
+    if let Some(variant) = variant {
+      if let Some(description) = &variant.description {
+        resolved.description = description.clone();
+      }
+      if let Some(actions) = &variant.actions {
+        resolved.actions = actions.clone();
+      }
+      if let Some(image) = &variant.image {
+        resolved.image = Some(image.clone());
+      }
+    }
+
+    resolved
+  }
+
+  /// Without a clock it is always day.
+  fn time_of_day(data: &GameData) -> TimeOfDay {
+    match &data.clock {
+      Some(clock) => clock.time_of_day(data.time),
+      None => TimeOfDay::Day,
+    }
+  }
+
+  /// Returns the description of `room`, occasionally followed by a random
+  /// ambience line that fits the room.
+  fn describe_room(data: &mut GameData, room: &Room) -> String {
+    let mut description = room.art.clone() + &room.description;
+
+    if data.ambience.lines.is_empty() || data.rng.next_f64() >= data.ambience.chance {
+      return description;
+    }
+
+    let candidates: Vec<String> = data
+      .ambience
+      .lines
+      .iter()
+      .filter(|line| line.tags.is_empty() || line.tags.iter().any(|t| room.tags.contains(t)))
+      .filter(|line| Self::dependencies_met(data, &line.depends))
+      .map(|line| line.text.clone())
+      .collect();
+
+    if !candidates.is_empty() {
+      let line = &candidates[data.rng.next_index(candidates.len())];
+      description += &format!("<br/><br/><span class=\"ambience\">{line}</span>");
+    }
+
+    description
+  }
+
+  /// Renders the actions of `room`, followed by any global actions that are
+  /// currently available.
+  fn update_room_actions(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    room: &Room,
+  ) -> Result<()> {
+    let mut actions = room.actions.clone();
+    // All sorts are stable, so equal actions keep the order they were written in
+    match room.sort_actions {
+      ActionSorting::AsAuthored => actions.sort_by_key(|a| a.order),
+      ActionSorting::Alphabetical => {
+        actions.sort_by(|a, b| (a.order, &a.name).cmp(&(b.order, &b.name)))
+      }
+      ActionSorting::TransitionsLast => {
+        actions.sort_by_key(|a| (a.order, a.transition().is_some()))
+      }
+    }
+
+    if let Some(detour) = &data.detour {
+      actions.push(Action {
+        name: detour.label.clone(),
+        kind: ActionKind::Navigate {
+          transition: detour.destination.clone(),
+        },
+        ..Default::default()
+      });
+    }
+    Self::update_actions(data, data_ptr.clone(), &actions)?;
+    Self::start_timer(data, data_ptr.clone(), room, &actions)?;
+
+    Self::unlock_codex(data);
+
+    if !Self::travel_destinations(data).is_empty() {
+      let name = data.fast_travel.name.clone();
+      let link = Self::add_menu_link(data, data_ptr.clone(), &name, Self::show_travel_menu)?;
+      link.set_class_name("location_change");
+    }
+
+    if !data.profile.defeated.is_empty() {
+      let name = data.bestiary.name.clone();
+      Self::add_menu_link(data, data_ptr.clone(), &name, Self::show_bestiary)?;
+    }
+
+    if !data.profile.codex.is_empty() {
+      let name = data.codex.name.clone();
+      Self::add_menu_link(data, data_ptr.clone(), &name, Self::show_codex)?;
+    }
+
+    if data.characters.playable.len() > 1 {
+      let name = data.characters.name.clone();
+      Self::add_menu_link(data, data_ptr.clone(), &name, Self::show_character_menu)?;
+    }
+
+    if !data.inventory.is_empty() && !Self::use_targets(data, room, None).is_empty() {
+      let name = data.use_items.name.clone();
+      Self::add_menu_link(data, data_ptr.clone(), &name, Self::show_use_menu)?;
+    }
+
+    if data.languages.available.len() > 1 {
+      let name = data.languages.name.clone();
+      Self::add_menu_link(data, data_ptr.clone(), &name, Self::show_language_menu)?;
+    }
+
+    if !data.transcript.is_empty() {
+      let name = data.history.name.clone();
+      Self::add_menu_link(data, data_ptr.clone(), &name, Self::show_history)?;
+    }
+
+    if !data.undo_stack.is_empty() {
+      let name = data.undo.name.clone();
+      Self::add_menu_link(data, data_ptr.clone(), &name, Self::undo)?;
+    }
+
+    if data.saves.slots > 0 {
+      let name = data.saves.name.clone();
+      Self::add_menu_link(data, data_ptr.clone(), &name, Self::show_saves)?;
+    }
+
+    if room.ending {
+      let name = data.new_game_plus.name.clone();
+      Self::add_menu_link(data, data_ptr.clone(), &name, |data, _| {
+        Self::start_new_game_plus(data);
+        Ok(())
+      })?;
+    }
+
+    Ok(())
+  }
+
+  fn show_character_menu(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    Self::update_actions(data, data_ptr.clone(), &[])?;
+
+    let others: Vec<(String, String)> = data
+      .characters
+      .playable
+      .iter()
+      .filter(|(key, _)| **key != data.active_character)
+      .map(|(key, character)| (key.clone(), character.name.clone()))
+      .collect();
+
+    for (key, name) in others {
+      Self::add_menu_link(data, data_ptr.clone(), &name, move |data, data_ptr| {
+        Self::switch_character(data, data_ptr, &key);
+        Ok(())
+      })?;
+    }
+
+    let back = data.characters.back.clone();
+    Self::add_menu_link(data, data_ptr, &back, Self::show_room)?;
+    Ok(())
+  }
+
+  /// Stores the inventory and location of the active character, and continues
+  /// where `character` was left off.
+  fn switch_character(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>, character: &str) {
+    let state = match data.character_states.remove(character) {
+      Some(s) => s,
+      None => {
+        log::error!("Tried to switch to unknown character {character}");
+        return;
+      }
+    };
+
+    let previous = CharacterState {
+      room: data.current_room.clone(),
+      inventory: std::mem::take(&mut data.inventory).into_iter().collect(),
+    };
+    let previous_key = std::mem::replace(&mut data.active_character, character.to_string());
+    data.character_states.insert(previous_key, previous);
+
+    data.inventory = state.inventory.into_iter().collect();
+
+    let name = &data.characters.playable[character].name;
+    data.current_text = format!("<i>{}</i><br/><br/>", markdown::escape(name));
+    Self::goto_room(data, data_ptr, &state.room, false);
+
+    Self::save(data);
+  }
+
+  /// The targets in `room` and on the carried items the player can currently
+  /// use items on. The targets of `used` are left out, as it can't be used on
+  /// itself.
+  fn use_targets(data: &GameData, room: &Room, used: Option<&str>) -> Vec<UseTarget> {
+    let mut carried: Vec<&String> = data
+      .inventory
+      .iter()
+      .filter(|item| Some(item.as_str()) != used)
+      .collect();
+    carried.sort();
+    let item_targets = carried
+      .into_iter()
+      .filter_map(|item| data.items.get(item))
+      .flat_map(|item| &item.use_targets);
+
+    room
+      .use_targets
+      .iter()
+      .chain(item_targets)
+      .filter(|target| Self::dependencies_met(data, &target.depends))
+      .cloned()
+      .collect()
+  }
+
+  /// Lets the player pick the item to use.
+  fn show_use_menu(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    Self::update_actions(data, data_ptr.clone(), &[])?;
+
+    let mut items: Vec<String> = data.inventory.iter().cloned().collect();
+    items.sort();
+    for item in items {
+      let callback_item = item.clone();
+      Self::add_menu_link(data, data_ptr.clone(), &item, move |data, data_ptr| {
+        Self::show_use_targets(data, data_ptr, &callback_item)
+      })?;
+    }
+
+    let back = data.use_items.back.clone();
+    Self::add_menu_link(data, data_ptr, &back, Self::show_room)?;
+    Ok(())
+  }
+
+  /// Lets the player pick what to use `item` on.
+  fn show_use_targets(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    item: &str,
+  ) -> Result<()> {
+    let image = data.items.get(item).and_then(|i| i.image.clone());
+    Self::show_image(data, image.as_deref());
+
+    let room = data
+      .rooms
+      .get(&data.current_room)
+      .map(|r| Self::resolve_room(data, r))
+      .ok_or_else(|| anyhow!("The current room {} doesn't exist", data.current_room))?;
+
+    Self::update_actions(data, data_ptr.clone(), &[])?;
+    for target in Self::use_targets(data, &room, Some(item)) {
+      let Some(outcome) = target.outcomes.get(item).cloned() else {
+        // Trying doesn't cost a turn or time, so it doesn't run as an action
+        let fails = data.use_items.fails.clone();
+        let on_click: LinkCallback = Rc::new(move |data, _| {
+          data.text_element.set_inner_html(&markdown::escape(&fails));
+          Ok(())
+        });
+        let key = format!("action:use:{}", target.name);
+        Self::add_link(data, data_ptr.clone(), &key, &target.name, "", on_click)?;
+        continue;
+      };
+
+      let action = Action {
+        id: Some(format!("use:{}", target.name)),
+        name: target.name,
+        text: outcome.text,
+        consumes: outcome.consumes,
+        score: outcome.score,
+        kind: ActionKind::Custom {
+          yields: outcome.yields,
+          transition: outcome.transition,
+        },
+        ..Default::default()
+      };
+      Self::add_action_link(data, data_ptr.clone(), &action)?;
+    }
+
+    let back = data.use_items.back.clone();
+    Self::add_menu_link(data, data_ptr, &back, Self::show_use_menu)?;
+    Ok(())
+  }
+
+  /// Lists all languages. Picking one reloads the game, as the adventure is
+  /// translated while it is loaded.
+  fn show_language_menu(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    Self::update_actions(data, data_ptr.clone(), &[])?;
+
+    for (code, name) in data.languages.available.clone() {
+      Self::add_menu_link(data, data_ptr.clone(), &name, move |_, _| {
+        storage::store_language(&code)?;
+        wasm_bindgen_futures::spawn_local(async {
+          storage::flush().await;
+          if let Some(window) = web_sys::window() {
+            let _ = window.location().reload();
+          }
+        });
+        Ok(())
+      })?;
+    }
+
+    let back = data.languages.back.clone();
+    Self::add_menu_link(data, data_ptr, &back, Self::show_room)?;
+    Ok(())
+  }
+
+  fn show_saves(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    // The menu replaces the room's text, which is what the slots should keep
+    let room_text = Rc::new(data.current_text.clone());
+    Self::render_saves(data, data_ptr, room_text)
+  }
+
+  fn render_saves(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    room_text: Rc<String>,
+  ) -> Result<()> {
+    Self::update_actions(data, data_ptr.clone(), &[])?;
+
+    let mut text = format!("<h2>{}</h2>", markdown::escape(&data.saves.name));
+    for slot in 1..=data.saves.slots {
+      let key = format!("{}_slot_{slot}", data.save_key);
+      let number = slot.to_string();
+      let saved = save::read(&key).save;
+
+      let summary = match &saved {
+        Some(save) => {
+          let room = data
+            .rooms
+            .get(&save.current_room)
+            .and_then(|r| r.title.clone())
+            .unwrap_or_else(|| save.current_room.clone());
+          let summary = match save.slot_name.is_empty() {
+            true => &data.saves.summary,
+            false => &data.saves.named_summary,
+          };
+          summary
+            .replace("{name}", &save.slot_name)
+            .replace("{room}", &room)
+            .replace("{turn}", &save.turn.to_string())
+            .replace("{time}", &format_time(save.saved_at))
+        }
+        None => data.saves.empty.clone(),
+      };
+      let summary = summary.replace("{slot}", &number);
+      text += &format!("<p>{}</p>", markdown::escape(&summary));
+
+      let name = data.saves.save.replace("{slot}", &number);
+      let slot_name = saved.as_ref().map(|s| s.slot_name.clone());
+      let slot = (key.clone(), name.clone());
+      let save_text = room_text.clone();
+      Self::add_menu_link(data, data_ptr.clone(), &name, move |data, data_ptr| {
+        let slot = slot.clone();
+        let slot_name = slot_name.clone().unwrap_or_default();
+        Self::show_slot_name(data, data_ptr, slot, &slot_name, save_text.clone())
+      })?;
+
+      if saved.is_none() {
+        continue;
+      }
+
+      let name = data.saves.load.replace("{slot}", &number);
+      let load_key = key.clone();
+      Self::add_menu_link(data, data_ptr.clone(), &name, move |data, data_ptr| {
+        let save = save::read(&load_key)
+          .save
+          .ok_or_else(|| anyhow!("The slot {load_key} is empty"))?;
+        Self::restore(data, data_ptr, save);
+        Self::save(data);
+        Ok(())
+      })?;
+
+      let name = data.saves.delete.replace("{slot}", &number);
+      let delete_text = room_text.clone();
+      Self::add_menu_link(data, data_ptr.clone(), &name, move |data, data_ptr| {
+        save::remove(&key);
+        Self::render_saves(data, data_ptr, delete_text.clone())
+      })?;
+    }
+
+    data.current_text = text;
+    data.text_element.set_inner_html(&data.current_text);
+
+    let export = data.saves.export.clone();
+    let export_text = room_text.clone();
+    Self::add_menu_link(data, data_ptr.clone(), &export, move |data, data_ptr| {
+      let save = SaveGame {
+        current_text: export_text.to_string(),
+        ..Self::snapshot(data)
+      };
+      Self::show_export(data, data_ptr, &save)
+    })?;
+
+    let import = data.saves.import.clone();
+    Self::add_menu_link(data, data_ptr.clone(), &import, Self::show_import)?;
+
+    let share = data.saves.share.clone();
+    Self::add_menu_link(data, data_ptr.clone(), &share, move |data, data_ptr| {
+      let save = SaveGame {
+        current_text: room_text.to_string(),
+        ..Self::snapshot(data)
+      };
+      Self::show_share(data, data_ptr, save)
+    })?;
+
+    let back = data.saves.back.clone();
+    Self::add_menu_link(data, data_ptr, &back, Self::show_room)?;
+    Ok(())
+  }
+
+  /// Shows the save as text to copy, and offers it as a JSON file.
+  fn show_export(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    save: &SaveGame,
+  ) -> Result<()> {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let saves = data.saves.clone();
+    let (modal, dialog) = Self::create_modal(data)?;
+
+    let hint = document.create_element("p").map_err(js_to_anyhow)?;
+    hint.set_text_content(Some(&saves.export_hint));
+    dialog.append_child(&hint).map_err(js_to_anyhow)?;
+
+    let field = document.create_element("textarea").map_err(js_to_anyhow)?;
+    field.set_attribute("readonly", "").map_err(js_to_anyhow)?;
+    field.set_text_content(Some(&save::export(save)?));
+    dialog.append_child(&field).map_err(js_to_anyhow)?;
+
+    let json = serde_json::to_string_pretty(save)?;
+    let download = Self::create_link(&saves.download)?;
+    let url = loader::object_url(json.as_bytes(), "application/json")?;
+    download.set_attribute("href", &url).map_err(js_to_anyhow)?;
+    download
+      .set_attribute("download", "save.json")
+      .map_err(js_to_anyhow)?;
+
+    let close = Self::create_close_link(data, data_ptr, &modal, &saves.close)?;
+
+    let answers = document.create_element("p").map_err(js_to_anyhow)?;
+    answers.set_class_name("answers");
+    answers.append_child(&download).map_err(js_to_anyhow)?;
+    answers.append_child(&close).map_err(js_to_anyhow)?;
+    dialog.append_child(&answers).map_err(js_to_anyhow)?;
+    Ok(())
+  }
+
+  /// Shows a link that continues from `save`, and copies it to the clipboard.
+  fn show_share(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>, save: SaveGame) -> Result<()> {
+    // The transcript would make the link far too long
+    let save = SaveGame {
+      transcript: Vec::new(),
+      ..save
+    };
+
+    let window = web_sys::window().ok_or(anyhow!("unable to get the window"))?;
+    let location = window.location();
+    let link = format!(
+      "{}{}{}#{SHARE_PREFIX}{}",
+      location.origin().map_err(js_to_anyhow)?,
+      location.pathname().map_err(js_to_anyhow)?,
+      location.search().map_err(js_to_anyhow)?,
+      save::to_link(&save)?
+    );
+    copy_to_clipboard(&link);
+
+    let document = window
+      .document()
+      .ok_or(anyhow!("unable to get the document"))?;
+    let (modal, dialog) = Self::create_modal(data)?;
+
+    let hint = document.create_element("p").map_err(js_to_anyhow)?;
+    hint.set_text_content(Some(&data.saves.share_hint));
+    dialog.append_child(&hint).map_err(js_to_anyhow)?;
+
+    let field = document.create_element("textarea").map_err(js_to_anyhow)?;
+    field.set_attribute("readonly", "").map_err(js_to_anyhow)?;
+    field.set_text_content(Some(&link));
+    dialog.append_child(&field).map_err(js_to_anyhow)?;
+
+    let close_name = data.saves.close.clone();
+    let close = Self::create_close_link(data, data_ptr, &modal, &close_name)?;
+    dialog.append_child(&close).map_err(js_to_anyhow)?;
+    Ok(())
+  }
+
+  /// Offers to continue from the game shared in the page's link, if any.
+  pub fn offer_shared_save(&self) -> Result<()> {
+    let window = web_sys::window().ok_or(anyhow!("unable to get the window"))?;
+    let location = window.location();
+    let hash = location.hash().map_err(js_to_anyhow)?;
+    let Some(encoded) = hash.strip_prefix(&format!("#{SHARE_PREFIX}")) else {
+      return Ok(());
+    };
+    let save = save::from_link(encoded).context("Unable to read the shared game")?;
+
+    // Reloading the page shouldn't ask again
+    let url = format!(
+      "{}{}",
+      location.pathname().map_err(js_to_anyhow)?,
+      location.search().map_err(js_to_anyhow)?
+    );
+    window
+      .history()
+      .and_then(|h| h.replace_state_with_url(&JsValue::NULL, "", Some(&url)))
+      .map_err(js_to_anyhow)?;
+
+    let mut data = self.data.lock().unwrap();
+    let document = window
+      .document()
+      .ok_or(anyhow!("unable to get the document"))?;
+    let (modal, dialog) = Self::create_modal(&mut data)?;
+
+    let text = document.create_element("p").map_err(js_to_anyhow)?;
+    text.set_text_content(Some(&data.saves.shared));
+    dialog.append_child(&text).map_err(js_to_anyhow)?;
+
+    let yes = Self::create_link(&data.strings.confirm_yes)?;
+    let yes_modal = modal.clone();
+    let data_ptr = self.data.clone();
+    let mut save = Some(save);
+    let yes_callback = Closure::<dyn FnMut()>::new(move || {
+      let mut data = data_ptr.lock().unwrap();
+      Self::close_modal(&mut data, &yes_modal);
+      let Some(save) = save.take() else {
+        return;
+      };
+
+      Self::restore(&mut data, data_ptr.clone(), save);
+      Self::finish_actions(&mut data);
+      Self::save(&mut data);
+    });
+    yes.set_onclick(Some(yes_callback.as_ref().unchecked_ref()));
+    Self::keep_listener(&mut data, &modal, yes_callback);
+
+    let no_name = data.strings.confirm_no.clone();
+    let no = Self::create_close_link(&mut data, self.data.clone(), &modal, &no_name)?;
+
+    let answers = document.create_element("p").map_err(js_to_anyhow)?;
+    answers.set_class_name("answers");
+    answers.append_child(&yes).map_err(js_to_anyhow)?;
+    answers.append_child(&no).map_err(js_to_anyhow)?;
+    dialog.append_child(&answers).map_err(js_to_anyhow)?;
+    Ok(())
+  }
+
+  /// Asks for the name of a slot, starting with its current one, and saves
+  /// the game into it. `slot` is the key of the slot and the name of the
+  /// action that saves into it.
+  fn show_slot_name(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    slot: (String, String),
+    slot_name: &str,
+    room_text: Rc<String>,
+  ) -> Result<()> {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let (modal, dialog) = Self::create_modal(data)?;
+    let (key, name) = slot;
+
+    let hint = document.create_element("p").map_err(js_to_anyhow)?;
+    hint.set_text_content(Some(&data.saves.name_hint));
+    dialog.append_child(&hint).map_err(js_to_anyhow)?;
+
+    let field: web_sys::HtmlInputElement = document
+      .create_element("input")
+      .map_err(js_to_anyhow)?
+      .dyn_into()
+      .map_err(|_| anyhow!("Expected an input"))?;
+    field.set_type("text");
+    field.set_value(slot_name);
+    dialog.append_child(&field).map_err(js_to_anyhow)?;
+
+    let save = Self::create_link(&name)?;
+    let save_modal = modal.clone();
+    let save_data = data_ptr.clone();
+    let save_callback = Closure::<dyn FnMut()>::new(move || {
+      let mut data = save_data.lock().unwrap();
+      Self::close_modal(&mut data, &save_modal);
+      let save = SaveGame {
+        current_text: room_text.to_string(),
+        slot_name: field.value().trim().to_string(),
+        ..Self::snapshot(&data)
+      };
+      if let Err(err) = save::write(&key, &save) {
+        log::error!("Unable to save the game: {err:#}");
+      }
+      if let Err(err) = Self::render_saves(&mut data, save_data.clone(), room_text.clone()) {
+        log::error!("Unable to show the saves: {err:#}");
+      }
+      Self::finish_actions(&mut data);
+    });
+    save.set_onclick(Some(save_callback.as_ref().unchecked_ref()));
+    Self::keep_listener(data, &modal, save_callback);
+
+    let close_name = data.saves.close.clone();
+    let close = Self::create_close_link(data, data_ptr, &modal, &close_name)?;
+
+    let answers = document.create_element("p").map_err(js_to_anyhow)?;
+    answers.set_class_name("answers");
+    answers.append_child(&save).map_err(js_to_anyhow)?;
+    answers.append_child(&close).map_err(js_to_anyhow)?;
+    dialog.append_child(&answers).map_err(js_to_anyhow)?;
+    Ok(())
+  }
+
+  /// Asks for an exported save, pasted or as a file, and continues from it.
+  fn show_import(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let (modal, dialog) = Self::create_modal(data)?;
+
+    let hint = document.create_element("p").map_err(js_to_anyhow)?;
+    hint.set_text_content(Some(&data.saves.import_hint));
+    dialog.append_child(&hint).map_err(js_to_anyhow)?;
+
+    let field: web_sys::HtmlTextAreaElement = document
+      .create_element("textarea")
+      .map_err(js_to_anyhow)?
+      .dyn_into()
+      .map_err(|_| anyhow!("Expected a textarea"))?;
+    dialog.append_child(&field).map_err(js_to_anyhow)?;
+
+    // A chosen file is read into the field, so it can be checked like a
+    // pasted save
+    let upload: web_sys::HtmlInputElement = document
+      .create_element("input")
+      .map_err(js_to_anyhow)?
+      .dyn_into()
+      .map_err(|_| anyhow!("Expected an input"))?;
+    upload.set_type("file");
+    upload.set_accept(".json,.txt,application/json,text/plain");
+    let upload_input = upload.clone();
+    let upload_field = field.clone();
+    let upload_callback = Closure::<dyn FnMut()>::new(move || {
+      let Some(file) = upload_input.files().and_then(|f| f.get(0)) else {
+        return;
+      };
+      let field = upload_field.clone();
+      wasm_bindgen_futures::spawn_local(async move {
+        match wasm_bindgen_futures::JsFuture::from(file.text()).await {
+          Ok(text) => field.set_value(&text.as_string().unwrap_or_default()),
+          Err(err) => log::error!("Unable to read the save: {err:?}"),
+        }
+      });
+    });
+    upload.set_onchange(Some(upload_callback.as_ref().unchecked_ref()));
+    Self::keep_listener(data, &modal, upload_callback);
+    dialog.append_child(&upload).map_err(js_to_anyhow)?;
+
+    let error = document.create_element("p").map_err(js_to_anyhow)?;
+    error.set_class_name("warning");
+    dialog.append_child(&error).map_err(js_to_anyhow)?;
+
+    let import = Self::create_link(&data.saves.import)?;
+    let import_modal = modal.clone();
+    let import_data = data_ptr.clone();
+    let import_callback = Closure::<dyn FnMut()>::new(move || {
+      let mut data = import_data.lock().unwrap();
+      let save = match save::import(&field.value()) {
+        Ok(s) => s,
+        Err(err) => {
+          let message = data
+            .saves
+            .import_failed
+            .replace("{error}", &format!("{err:#}"));
+          error.set_text_content(Some(&message));
+          return;
+        }
+      };
+      Self::close_modal(&mut data, &import_modal);
+
+      Self::restore(&mut data, import_data.clone(), save);
+      Self::finish_actions(&mut data);
+      Self::save(&mut data);
+    });
+    import.set_onclick(Some(import_callback.as_ref().unchecked_ref()));
+    Self::keep_listener(data, &modal, import_callback);
+
+    let close_name = data.saves.close.clone();
+    let close = Self::create_close_link(data, data_ptr, &modal, &close_name)?;
+
+    let answers = document.create_element("p").map_err(js_to_anyhow)?;
+    answers.set_class_name("answers");
+    answers.append_child(&import).map_err(js_to_anyhow)?;
+    answers.append_child(&close).map_err(js_to_anyhow)?;
+    dialog.append_child(&answers).map_err(js_to_anyhow)?;
+    Ok(())
+  }
+
+  /// Remembers the items that carry over in the profile and restarts the
+  /// game. The items are handed out again when the new game is created.
+  fn start_new_game_plus(data: &mut GameData) {
+    let mut carried: Vec<String> = data
+      .inventory
+      .iter()
+      .filter(|item| data.items.get(*item).is_some_and(|i| i.carry_over))
+      .cloned()
+      .collect();
+    carried.sort();
+
+    data.profile.carried_items = Some(carried);
+    data.profile.save();
+
+    Self::reset(data);
+  }
+
+  /// Decides whether the player runs into an encounter on the way to
+  /// `destination`, based on the destination's tags. Returns the room the
+  /// player actually ends up in.
+  fn roll_encounter(data: &mut GameData, destination: &str) -> String {
+    let mut tags = match data.rooms.get(destination) {
+      Some(room) => room.tags.clone(),
+      None => return destination.to_string(),
+    };
+    // Keep the order of the rolls stable, so saves replay the same way
+    tags.sort();
+
+    for tag in tags {
+      let table = match data.encounters.get(&tag) {
+        Some(t) if !t.rooms.is_empty() => t.clone(),
+        _ => continue,
+      };
+
+      if let Some(last) = data.encounter_turns.get(&tag) {
+        if data.turn < last + table.cooldown {
+          continue;
+        }
+      }
+
+      if data.rng.next_f64() >= table.chance * Self::encounter_rate(data) {
+        continue;
+      }
+
+      let encounter = table.rooms[data.rng.next_index(table.rooms.len())].clone();
+      data.encounter_turns.insert(tag, data.turn);
+      data.detour = Some(Detour {
+        destination: destination.to_string(),
+        label: table.continue_label,
+      });
+      return encounter;
+    }
+
+    destination.to_string()
+  }
+
+  fn encounter_rate(data: &GameData) -> f64 {
+    data
+      .difficulties
+      .iter()
+      .find(|d| d.id == data.difficulty)
+      .map(|d| d.encounter_rate)
+      .unwrap_or(1.0)
+  }
+
+  /// Appends a link to the actions that opens a menu, rather than running an
+  /// action.
+  fn add_menu_link(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    name: &str,
+    show: impl Fn(&mut GameData, Rc<Mutex<GameData>>) -> Result<()> + 'static,
+  ) -> Result<HtmlElement> {
+    let menu = name.to_string();
+    let on_click: LinkCallback = Rc::new(move |data, data_ptr| {
+      show(data, data_ptr).with_context(|| format!("Unable to show the {menu} menu"))
+    });
+    Self::add_link(data, data_ptr, &format!("menu:{name}"), name, "", on_click)
+  }
+
+  /// Appends a link to the actions. Links that were rendered before are kept
+  /// if their key matches, only their callback is replaced.
+  fn add_link(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    key: &str,
+    name: &str,
+    class: &str,
+    on_click: LinkCallback,
+  ) -> Result<HtmlElement> {
+    let index = data.rendered_links;
+    data.rendered_links += 1;
+
+    // Links in between that are no longer offered are dropped, so hiding an
+    // action doesn't rebuild all the ones after it
+    let start = index.min(data.links.len());
+    if let Some(offset) = data.links[start..].iter().position(|l| l.key == key) {
+      let dropped: Vec<_> = data.links.drain(start..start + offset).collect();
+      Self::retire_links(data, dropped);
+
+      let link = &mut data.links[index];
+      if link.element.inner_text() != name {
+        link.element.set_inner_text(name);
+      }
+      link.element.set_class_name(class);
+      link.on_click = on_click;
+      return Ok(link.element.clone());
+    }
+
+    let element = Self::create_link(name)?;
+    element.set_class_name(class);
+
+    // The callback is looked up when clicked, so reused links run the current
+    // one
+    let serial = data.next_link_serial;
+    data.next_link_serial += 1;
+    let callback = Closure::<dyn FnMut()>::new(move || {
+      let mut data = data_ptr.lock().unwrap();
+      // None of them is running anymore
+      data.retired_listeners.clear();
+      let on_click = data
+        .links
+        .iter()
+        .find(|l| l.serial == serial)
+        .map(|l| l.on_click.clone());
+      if let Some(on_click) = on_click {
+        if let Err(err) = on_click(&mut data, data_ptr.clone()) {
+          log::error!("{err:#}");
+        }
+      }
+      Self::finish_actions(&mut data);
+    });
+    element.set_onclick(Some(callback.as_ref().unchecked_ref()));
+
+    // New links go before the ones that weren't rendered again yet
+    let next = data.links.get(index).map(|l| l.element.clone());
+    data
+      .actions_element
+      .insert_before(&element, next.as_ref().map(|e| e.as_ref()))
+      .map_err(js_to_anyhow)?;
+
+    data.links.insert(
+      index,
+      ActionLink {
+        key: key.to_string(),
+        serial,
+        element: element.clone(),
+        on_click,
+        listener: callback,
+      },
+    );
+
+    Ok(element)
+  }
+
+  /// Starts the countdown of the room, or of the offered action with the
+  /// shortest time limit, whichever runs out first.
+  fn start_timer(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    room: &Room,
+    actions: &[Action],
+  ) -> Result<()> {
+    let room_limit = room.time_limit_seconds.map(|s| (s, &room.timeout));
+    let action_limits = actions
+      .iter()
+      .filter(|a| Self::dependencies_met(data, &a.depends))
+      .filter_map(|a| a.time_limit_seconds.map(|s| (s, &a.timeout)));
+    let (seconds, timeout) = match room_limit
+      .into_iter()
+      .chain(action_limits)
+      .min_by(|a, b| a.0.total_cmp(&b.0))
+    {
+      Some((seconds, timeout)) => (seconds, timeout.clone()),
+      None => return Ok(()),
+    };
+
+    let window = web_sys::window().ok_or(anyhow!("unable to get the window"))?;
+    let document = window
+      .document()
+      .ok_or(anyhow!("unable to get the document"))?;
+    let element: HtmlElement = document
+      .create_element("div")
+      .map_err(js_to_anyhow)?
+      .dyn_into()
+      .map_err(|_| anyhow!("Expected an html element"))?;
+    element.set_class_name("countdown");
+    element.set_inner_text(
+      &data
+        .strings
+        .countdown
+        .replace("{seconds}", &seconds.ceil().to_string()),
+    );
+
+    // The countdown goes above the actions
+    data
+      .actions_element
+      .insert_before(&element, data.actions_element.first_child().as_ref())
+      .map_err(js_to_anyhow)?;
+
+    let callback = Closure::<dyn FnMut()>::new(move || {
+      let mut data = data_ptr.lock().unwrap();
+      let countdown = data.strings.countdown.clone();
+      let Some(timer) = data.timer.as_mut() else {
+        return;
+      };
+
+      let now = js_sys::Date::now();
+      timer.remaining -= now - timer.last_tick;
+      timer.last_tick = now;
+
+      let remaining = (timer.remaining / 1000.0).ceil();
+      if remaining > 0.0 {
+        let text = countdown.replace("{seconds}", &remaining.to_string());
+        timer.element.set_inner_text(&text);
+        return;
+      }
+
+      Self::cancel_timer(&mut data);
+      Self::run_timeout(&mut data, data_ptr.clone(), &timeout);
+    });
+
+    let mut timer = Timer {
+      interval: None,
+      callback,
+      element,
+      remaining: seconds * 1000.0,
+      last_tick: js_sys::Date::now(),
+    };
+    // The countdown waits while a dialog covers the game
+    if data.modals.is_empty() {
+      timer.resume()?;
+    }
+    data.timer = Some(timer);
+    Ok(())
+  }
+
+  fn cancel_timer(data: &mut GameData) {
+    if let Some(mut timer) = data.timer.take() {
+      timer.pause();
+      timer.element.remove();
+      // The countdown might be what ran out
+      data.retired_listeners.push(timer.callback);
+    }
+  }
+
+  fn run_timeout(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>, timeout: &Outcome) {
+    let action = Action {
+      name: data.strings.timeout.clone(),
+      text: timeout.text.clone(),
+      kind: ActionKind::Custom {
+        yields: timeout.yields.clone(),
+        transition: timeout.transition.clone(),
+      },
+      ..Default::default()
+    };
+    Self::execute_action(data, data_ptr, &action);
+    Self::save(data);
+    Self::finish_actions(data);
+  }
+
+  /// Removes all links starting at `from`.
+  fn remove_links(data: &mut GameData, from: usize) {
+    let dropped: Vec<_> = data.links.drain(from.min(data.links.len())..).collect();
+    Self::retire_links(data, dropped);
+    data.rendered_links = data.rendered_links.min(from);
+  }
+
+  /// Removes the links from the page. Their click handlers are kept until the
+  /// next click, as the handler that removed them might still be running.
+  fn retire_links(data: &mut GameData, links: Vec<ActionLink>) {
+    for link in links {
+      link.element.remove();
+      link.element.set_onclick(None);
+      data.retired_listeners.push(link.listener);
+    }
+  }
+
+  /// Removes the links that were not rendered again since the actions were
+  /// last updated.
+  fn finish_actions(data: &mut GameData) {
+    let rendered = data.rendered_links;
+    Self::remove_links(data, rendered);
+    Self::restore_focus(data);
+    Self::render_status(data);
+    Self::render_progress(data);
+    Self::render_inventory(data);
+    Self::mark_keywords(data);
+  }
+
+  /// Moves the keyboard focus to the first action after the player entered a
+  /// room, or when the focused link was removed. Otherwise keyboard and screen
+  /// reader users would have to find their way back from the top of the page.
+  fn restore_focus(data: &mut GameData) {
+    let entered = std::mem::take(&mut data.entered_room);
+    let Some(document) = web_sys::window().and_then(|w| w.document()) else {
+      return;
+    };
+    // In a shadow root, the document only knows that the host has the focus
+    let root = data.container_element.get_root_node();
+    let active = match root.dyn_ref::<ShadowRoot>() {
+      Some(shadow) => shadow.active_element(),
+      None => document.active_element(),
+    };
+    let lost = match active {
+      Some(element) => !element.is_connected() || document.body().is_some_and(|b| *b == element),
+      None => true,
+    };
+    if !entered && !lost {
+      return;
+    }
+    if let Some(link) = data.links.first() {
+      if let Err(err) = link.element.focus() {
+        log::error!("Unable to focus the actions: {}", js_to_anyhow(err));
+      }
+    }
+  }
+
+  /// Returns the keys of all visited fast travel rooms the player can currently
+  /// travel to, sorted by their title.
+  fn travel_destinations(data: &GameData) -> Vec<(String, String)> {
+    if !Self::dependencies_met(data, &data.fast_travel.depends) {
+      return Vec::new();
+    }
+
+    let mut destinations: Vec<(String, String)> = data
+      .visited
+      .iter()
+      .filter(|key| **key != data.current_room)
+      .filter_map(|key| {
+        let room = data.rooms.get(key)?;
+        if !room.fast_travel {
+          return None;
+        }
+        let title = room.title.clone().unwrap_or_else(|| key.clone());
+        Some((key.clone(), title))
+      })
+      .collect();
+    destinations.sort_by(|a, b| a.1.cmp(&b.1));
+    destinations
+  }
+
+  fn show_travel_menu(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    let actions: Vec<Action> = Self::travel_destinations(data)
+      .into_iter()
+      .map(|(key, title)| Action {
+        name: title,
+        kind: ActionKind::Navigate { transition: key },
+        ..Default::default()
+      })
+      .collect();
+
+    Self::update_actions(data, data_ptr.clone(), &actions)?;
+
+    let cancel = data.fast_travel.cancel.clone();
+    Self::add_menu_link(data, data_ptr, &cancel, Self::show_room)?;
+    Ok(())
+  }
+
+  /// Lists all enemies the player has defeated so far. Stats are only revealed
+  /// after enough victories.
+  fn show_bestiary(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    let mut enemies: Vec<(&String, &Enemy)> = data
+      .bestiary
+      .enemies
+      .iter()
+      .filter(|(key, _)| data.profile.defeated.contains_key(*key))
+      .collect();
+    enemies.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+
+    let mut text = format!("<h2>{}</h2>", markdown::escape(&data.bestiary.name));
+    for (key, enemy) in enemies {
+      let defeated = data.profile.defeated[key];
+      text += &format!(
+        "<h3>{}</h3><p>{}</p><p>{}</p>",
+        markdown::escape(&enemy.name),
+        enemy.description,
+        data
+          .strings
+          .defeated
+          .replace("{count}", &defeated.to_string())
+      );
+
+      if defeated >= enemy.reveal_stats_after && !enemy.stats.is_empty() {
+        text += "<table class=\"stats\">";
+        for (stat, value) in &enemy.stats {
+          let value = match value {
+            serde_yaml::Value::String(s) => s.clone(),
+            serde_yaml::Value::Number(n) => n.to_string(),
+            serde_yaml::Value::Bool(b) => b.to_string(),
+            other => serde_yaml::to_string(other)?.trim().to_string(),
+          };
+          text += &format!(
+            "<tr><td>{}</td><td>{}</td></tr>",
+            markdown::escape(stat),
+            markdown::escape(&value)
+          );
+        }
+        text += "</table>";
+      }
+    }
+
+    data.current_text = text;
+    data.text_element.set_inner_html(&data.current_text);
+
+    Self::update_actions(data, data_ptr.clone(), &[])?;
+
+    let back = data.bestiary.back.clone();
+    Self::add_menu_link(data, data_ptr, &back, Self::show_room)?;
+    Ok(())
+  }
+
+  fn dependency_context(data: &GameData) -> DependencyContext<'_> {
+    DependencyContext {
+      inventory: &data.inventory,
+      time_of_day: Self::time_of_day(data),
+      character: &data.active_character,
+      failures: &data.failures,
+      ng_plus: data.ng_plus,
+      difficulty: &data.difficulty,
+      flags: &data.flags,
+      reputation: &data.reputation,
+      memory: &data.memory,
+    }
+  }
+
+  fn dependencies_met(data: &GameData, depends: &ActionDependencies) -> bool {
+    depends.is_met(&Self::dependency_context(data))
+  }
+
+  fn create_link(text: &str) -> Result<HtmlElement> {
+    let document = web_sys::window().unwrap().document().unwrap();
+
+    let link: HtmlElement = document
+      .create_element("a")
+      .map_err(js_to_anyhow)?
+      .dyn_into()
+      .map_err(|_| anyhow!("Expected a link"))?;
+
+    // Set the text, and add a pseudo target to make the link clickable
+    link.set_inner_text(text);
+    link.set_attribute("href", "#").map_err(js_to_anyhow)?;
+    // It does something on the page instead of leading somewhere
+    link.set_attribute("role", "button").map_err(js_to_anyhow)?;
+
+    Ok(link)
+  }
+
+  /// Lets space press the links that act as buttons, like it presses real
+  /// buttons. One listener on the container serves all links, so links don't
+  /// need their own.
+  fn listen_for_button_keys(container: &HtmlElement) -> Result<()> {
+    let callback =
+      Closure::<dyn FnMut(web_sys::KeyboardEvent)>::new(move |event: web_sys::KeyboardEvent| {
+        if event.key() != " " {
+          return;
+        }
+        let link = event
+          .target()
+          .and_then(|t| t.dyn_into::<HtmlElement>().ok())
+          .filter(|e| e.tag_name() == "A" && e.get_attribute("role").as_deref() == Some("button"));
+        if let Some(link) = link {
+          event.prevent_default();
+          link.click();
+        }
+      });
+    container
+      .add_event_listener_with_callback("keydown", callback.as_ref().unchecked_ref())
+      .map_err(js_to_anyhow)?;
+    callback.forget();
+    Ok(())
+  }
+
+  fn update_actions(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    actions: &[Action],
+  ) -> Result<()> {
+    // Whatever the player did, they made it in time
+    Self::cancel_timer(data);
+
+    if data.action_rendering == ActionRendering::Rebuild {
+      Self::remove_links(data, 0);
+    }
+    data.rendered_links = 0;
+
+    for action in actions {
+      // Check if the action's requirements are met
+      if Self::dependencies_met(data, &action.depends) {
+        Self::add_action_link(data, data_ptr.clone(), action)?;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Appends a link that runs `action` to the actions.
+  fn add_action_link(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    action: &Action,
+  ) -> Result<()> {
+    // Actions that change locations look different
+    let class = if action.transition().is_some() {
+      "location_change"
+    } else {
+      ""
+    };
+
+    // Setup the callback that will be run if the action is selected.
+    let callback_action = action.clone();
+    let on_click: LinkCallback = Rc::new(move |data, data_ptr| {
+      if let Some(question) = &callback_action.confirm {
+        return Self::show_confirmation(data, data_ptr, question, &callback_action);
+      }
+
+      Self::remember_undo(data);
+      Self::execute_action(data, data_ptr, &callback_action);
+
+      // Save the new state
+      Self::save(data);
+      Ok(())
+    });
+
+    let label = action.label(&Self::dependency_context(data)).to_string();
+    Self::add_link(
+      data,
+      data_ptr,
+      &format!("action:{}", action.id()),
+      &label,
+      class,
+      on_click,
+    )?;
+    Ok(())
+  }
+
+  /// Asks the player whether they really want to run `action`, in a dialog
+  /// on top of the game.
+  fn show_confirmation(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    question: &str,
+    action: &Action,
+  ) -> Result<()> {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let (modal, dialog) = Self::create_modal(data)?;
+
+    let text = document.create_element("p").map_err(js_to_anyhow)?;
+    text.set_text_content(Some(question));
+    dialog.append_child(&text).map_err(js_to_anyhow)?;
+
+    let yes = Self::create_link(&data.strings.confirm_yes)?;
+    let yes_modal = modal.clone();
+    let yes_action = action.clone();
+    let yes_data = data_ptr.clone();
+    let yes_callback = Closure::<dyn FnMut()>::new(move || {
+      let mut data = yes_data.lock().unwrap();
+      Self::close_modal(&mut data, &yes_modal);
+
+      Self::remember_undo(&mut data);
+      Self::execute_action(&mut data, yes_data.clone(), &yes_action);
+      Self::save(&mut data);
+      Self::finish_actions(&mut data);
+    });
+    yes.set_onclick(Some(yes_callback.as_ref().unchecked_ref()));
+    Self::keep_listener(data, &modal, yes_callback);
+
+    let no_name = data.strings.confirm_no.clone();
+    let no = Self::create_close_link(data, data_ptr, &modal, &no_name)?;
+
+    let answers = document.create_element("p").map_err(js_to_anyhow)?;
+    answers.set_class_name("answers");
+    answers.append_child(&yes).map_err(js_to_anyhow)?;
+    answers.append_child(&no).map_err(js_to_anyhow)?;
+    dialog.append_child(&answers).map_err(js_to_anyhow)?;
+    Ok(())
+  }
+
+  /// Shows the text of a modal action in a dialog on top of the room, with
+  /// the action's choices and a link that closes it. Choices run like the
+  /// room's actions once the dialog is closed.
+  fn show_modal_action(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    text: &str,
+    choices: &[Action],
+  ) -> Result<()> {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let (modal, dialog) = Self::create_modal(data)?;
+
+    let body = document.create_element("div").map_err(js_to_anyhow)?;
+    body.set_class_name("text");
+    body.set_inner_html(text);
+    dialog.append_child(&body).map_err(js_to_anyhow)?;
+    if let Some(narrator) = &data.narrator {
+      if let Err(err) = narrator.read(&body) {
+        log::error!("Unable to read the text aloud: {err:#}");
+      }
+    }
+
+    let answers = document.create_element("p").map_err(js_to_anyhow)?;
+    answers.set_class_name("answers");
+    for choice in choices {
+      if !Self::dependencies_met(data, &choice.depends) {
+        continue;
+      }
+      let link = Self::create_link(choice.label(&Self::dependency_context(data)))?;
+      if choice.transition().is_some() {
+        link.set_class_name("location_change");
+      }
+
+      let choice_modal = modal.clone();
+      let choice_action = choice.clone();
+      let choice_data = data_ptr.clone();
+      let callback = Closure::<dyn FnMut()>::new(move || {
+        let mut data = choice_data.lock().unwrap();
+        Self::close_modal(&mut data, &choice_modal);
+
+        if let Some(question) = &choice_action.confirm {
+          if let Err(err) =
+            Self::show_confirmation(&mut data, choice_data.clone(), question, &choice_action)
+          {
+            log::error!("Unable to ask for the confirmation: {err:#}");
+          }
+          return;
+        }
+        Self::remember_undo(&mut data);
+        Self::execute_action(&mut data, choice_data.clone(), &choice_action);
+        Self::save(&mut data);
+        Self::finish_actions(&mut data);
+      });
+      link.set_onclick(Some(callback.as_ref().unchecked_ref()));
+      Self::keep_listener(data, &modal, callback);
+      answers.append_child(&link).map_err(js_to_anyhow)?;
+    }
+
+    let close_name = data.strings.close.clone();
+    let close = Self::create_close_link(data, data_ptr, &modal, &close_name)?;
+    answers.append_child(&close).map_err(js_to_anyhow)?;
+    dialog.append_child(&answers).map_err(js_to_anyhow)?;
+
+    // Keyboard users continue in the dialog
+    if let Some(first) = answers.first_element_child() {
+      if let Some(first) = first.dyn_ref::<HtmlElement>() {
+        let _ = first.focus();
+      }
+    }
+    Ok(())
+  }
+
+  /// Shows an empty dialog on top of the game, and pauses the countdown until
+  /// it is closed. Returns the modal, which `close_modal` removes, and the
+  /// dialog to fill. The modal is part of the container, so it gets the
+  /// game's style even in a shadow root.
+  fn create_modal(data: &mut GameData) -> Result<(Element, Element)> {
+    let document = web_sys::window().unwrap().document().unwrap();
+
+    let modal = document.create_element("div").map_err(js_to_anyhow)?;
+    modal.set_class_name("modal");
+    let dialog = document.create_element("div").map_err(js_to_anyhow)?;
+    modal.append_child(&dialog).map_err(js_to_anyhow)?;
+    data
+      .container_element
+      .append_child(&modal)
+      .map_err(js_to_anyhow)?;
+
+    data.modals.push(Modal {
+      element: modal.clone(),
+      listeners: Vec::new(),
+    });
+    if let Some(timer) = &mut data.timer {
+      timer.pause();
+    }
+    Ok((modal, dialog))
+  }
+
+  /// Removes a dialog of `create_modal`, and continues the countdown once no
+  /// dialog is left.
+  fn close_modal(data: &mut GameData, modal: &Element) {
+    modal.remove();
+    if let Some(index) = data.modals.iter().position(|m| m.element == *modal) {
+      let closed = data.modals.remove(index);
+      // One of them is what closes the dialog
+      data.retired_listeners.extend(closed.listeners);
+    }
+    if !data.modals.is_empty() {
+      return;
+    }
+    if let Some(timer) = &mut data.timer {
+      if let Err(err) = timer.resume() {
+        log::error!("Unable to continue the countdown: {err:#}");
+      }
+    }
+  }
+
+  /// Keeps `listener` of a link in `modal` until the modal is closed.
+  fn keep_listener(data: &mut GameData, modal: &Element, listener: Closure<dyn FnMut()>) {
+    match data.modals.iter_mut().find(|m| m.element == *modal) {
+      Some(open) => open.listeners.push(listener),
+      None => data.retired_listeners.push(listener),
+    }
+  }
+
+  /// Creates a link that closes `modal`.
+  fn create_close_link(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    modal: &Element,
+    name: &str,
+  ) -> Result<HtmlElement> {
+    let link = Self::create_link(name)?;
+    let callback_modal = modal.clone();
+    let callback = Closure::<dyn FnMut()>::new(move || {
+      Self::close_modal(&mut data_ptr.lock().unwrap(), &callback_modal)
+    });
+    link.set_onclick(Some(callback.as_ref().unchecked_ref()));
+    Self::keep_listener(data, modal, callback);
+    Ok(link)
+  }
+
+  /// Unlocks all codex entries whose conditions are met now. Unlocks are kept
+  /// in the profile, so they survive resets.
+  fn unlock_codex(data: &mut GameData) {
+    let unlocked: Vec<String> = data
+      .codex
+      .entries
+      .iter()
+      .filter(|(key, _)| !data.profile.codex.contains(*key))
+      .filter(|(_, entry)| {
+        let unlock = &entry.unlock;
+        unlock.items.iter().any(|i| data.inventory.contains(i))
+          || unlock.flags.iter().any(|f| data.flags.contains(f))
+          || unlock.rooms.iter().any(|r| data.visited.contains(r))
+      })
+      .map(|(key, _)| key.clone())
+      .collect();
+
+    if !unlocked.is_empty() {
+      data.profile.codex.extend(unlocked);
+      data.profile.save();
+    }
+  }
+
+  /// Shows all unlocked codex entries in a dialog.
+  fn show_codex(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let (modal, dialog) = Self::create_modal(data)?;
+    dialog.set_class_name("codex");
+
+    let mut entries: Vec<&CodexEntry> = data
+      .codex
+      .entries
+      .iter()
+      .filter(|(key, _)| data.profile.codex.contains(*key))
+      .map(|(_, entry)| entry)
+      .collect();
+    entries.sort_by(|a, b| a.title.cmp(&b.title));
+
+    let mut html = format!("<h2>{}</h2>", markdown::escape(&data.codex.name));
+    for entry in entries {
+      html += &format!(
+        "<h3>{}</h3><p>{}</p>",
+        markdown::escape(&entry.title),
+        entry.text
+      );
+    }
+    let content = document.create_element("div").map_err(js_to_anyhow)?;
+    content.set_inner_html(&html);
+    dialog.append_child(&content).map_err(js_to_anyhow)?;
+
+    let close_name = data.codex.close.clone();
+    let close = Self::create_close_link(data, data_ptr, &modal, &close_name)?;
+    dialog.append_child(&close).map_err(js_to_anyhow)?;
+    Ok(())
+  }
+
+  fn execute_action(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>, action: &Action) {
+    data.turn += 1;
+    data.score += action.score;
+
+    for (faction, change) in &action.reputation {
+      *data.reputation.entry(faction.clone()).or_default() += change;
+    }
+
+    for item in &action.consumes {
+      data.inventory.remove(item);
+    }
+
+    if !action.defeats.is_empty() {
+      for enemy in &action.defeats {
+        *data.profile.defeated.entry(enemy.clone()).or_default() += 1;
+      }
+      data.profile.save();
+    }
+
+    if let Some(check) = &action.fails {
+      Self::record_failure(data, check);
+    }
+
+    data.flags.extend(action.sets.iter().cloned());
+    for flag in &action.clears {
+      data.flags.remove(flag);
+    }
+
+    // Let time pass
+    if let Some(clock) = &data.clock {
+      data.time += action.time.unwrap_or(clock.hours_per_action);
+    }
+
+    // Play music if requested
+    if let Some(music) = &action.music {
+      Self::play_music(data, &music.song, music.fade_in, music.fade_out);
+    }
+    if let Some(sfx) = &action.sfx {
+      match data.sfx.get(sfx).map(|e| e.play()) {
+        Some(Ok(())) => {}
+        Some(Err(err)) => log::error!("Unable to play {sfx}: {err:#}"),
+        None => log::error!("Tried to play nonexistant sound effect {sfx}"),
+      }
+    }
+
+    let room_text = std::mem::take(&mut data.current_text);
+    data.current_text = action.art.clone() + &action.text;
+    if !data.current_text.is_empty() {
+      data.current_text += "<br/><br/>";
+    }
+
+    let mut transition = match &action.kind {
+      ActionKind::Navigate { transition } => Some(transition.clone()),
+      ActionKind::GainItems { yields } => {
+        Self::gain_items(data, yields);
+        None
+      }
+      ActionKind::Dialogue {
+        lines,
+        npc,
+        remembers,
+      } => {
+        // Lines are chosen before the npc remembers the new topics
+        let lines: Vec<_> = lines
+          .iter()
+          .filter(|line| Self::dependencies_met(data, &line.depends))
+          .collect();
+        if let Some(npc) = npc {
+          data
+            .memory
+            .entry(npc.clone())
+            .or_default()
+            .extend(remembers.iter().cloned());
+        }
+
+        for line in lines {
+          data.current_text += &match &line.speaker {
+            // The narration reads the line in the speaker's voice
+            Some(speaker) => {
+              let speaker = markdown::escape(speaker);
+              format!(
+                "<b>{speaker}:</b> <span data-speaker=\"{speaker}\">{}</span><br/><br/>",
+                line.text
+              )
+            }
+            None => format!("{}<br/><br/>", line.text),
+          };
+        }
+        None
+      }
+      ActionKind::Check {
+        check,
+        chance,
+        success,
+        failure,
+      } => {
+        let outcome = if data.rng.next_f64() < *chance {
+          success
+        } else {
+          Self::record_failure(data, check);
+          failure
+        };
+
+        Self::gain_items(data, &outcome.yields);
+        if !outcome.text.is_empty() {
+          data.current_text += &outcome.text;
+          data.current_text += "<br/><br/>";
+        }
+        outcome.transition.clone()
+      }
+      ActionKind::Shop { offers, leave } => {
+        // The shop replaces the room's actions until the player leaves it
+        Self::show_text(data, data_ptr.clone());
+        Self::record(data, action);
+        if let Err(err) = Self::show_shop(data, data_ptr, offers, leave) {
+          log::error!("Unable to show the shop: {err:#}");
+        }
+        return;
+      }
+      ActionKind::Custom { yields, transition } => {
+        Self::gain_items(data, yields);
+        transition.clone()
+      }
+    };
+
+    // Meters tick every turn that takes time, and crossing a threshold might
+    // force the player somewhere else
+    let passes_time = action.time != Some(0);
+    if let Some(destination) = Self::update_meters(data, &action.meters, passes_time) {
+      transition = Some(destination);
+    }
+    Self::render_meters(data);
+
+    // Modal actions leave the room's text in view, unless a threshold forces
+    // the player somewhere else
+    if action.modal && transition.is_none() {
+      Self::record(data, action);
+      let text = std::mem::replace(&mut data.current_text, room_text);
+      if let Err(err) = Self::show_modal_action(data, data_ptr.clone(), &text, &action.choices) {
+        log::error!("Unable to show the action: {err:#}");
+      }
+      let room = data
+        .rooms
+        .get(&data.current_room)
+        .map(|r| Self::resolve_room(data, r));
+      if let Some(room) = room {
+        if let Err(err) = Self::update_room_actions(data, data_ptr, &room) {
+          log::error!("Unable to update the actions: {err:#}");
+        }
+      }
+      Self::update_layers(data);
+      return;
+    }
+
+    if let Some(destination) = &transition {
+      // Continuing after an encounter must not trigger another one
+      let destination = if data.detour.take().map(|d| d.destination).as_ref() == Some(destination) {
+        destination.clone()
+      } else {
+        Self::roll_encounter(data, destination)
+      };
+      Self::goto_room(data, data_ptr, &destination, true);
+    } else if let Err(err) = Self::render_current_room(data, data_ptr) {
+      log::error!("Unable to render the room: {err:#}");
+    }
+    Self::update_layers(data);
+    Self::record(data, action);
+  }
+
+  /// Adds the action and the text it showed to the transcript.
+  fn record(data: &mut GameData, action: &Action) {
+    let entry = TranscriptEntry {
+      action: action.name.clone(),
+      text: data.current_text.clone(),
+    };
+    data.transcript.push(entry);
+    Self::trim_transcript(data);
+  }
+
+  /// Drops the oldest entries of the transcript beyond the history's limit.
+  fn trim_transcript(data: &mut GameData) {
+    let excess = data.transcript.len().saturating_sub(data.history.limit);
+    data.transcript.drain(..excess);
+  }
+
+  /// Shows the transcript in a dialog, and offers it as a text file.
+  fn show_history(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let (modal, dialog) = Self::create_modal(data)?;
+    dialog.set_class_name("history");
+
+    let mut html = format!("<h2>{}</h2>", markdown::escape(&data.history.name));
+    for entry in &data.transcript {
+      html += &format!(
+        "<p class=\"choice\">&gt; {}</p><p>{}</p>",
+        markdown::escape(&entry.action),
+        entry.text
+      );
+    }
+    let content = document.create_element("div").map_err(js_to_anyhow)?;
+    content.set_inner_html(&html);
+    dialog.append_child(&content).map_err(js_to_anyhow)?;
+
+    let text: Vec<String> = data
+      .transcript
+      .iter()
+      .map(|entry| format!("> {}\n\n{}\n", entry.action, plain_text(&entry.text)))
+      .collect();
+    let download = Self::create_link(&data.history.download)?;
+    let url = loader::object_url(text.join("\n").as_bytes(), "text/plain")?;
+    download.set_attribute("href", &url).map_err(js_to_anyhow)?;
+    download
+      .set_attribute("download", "transcript.txt")
+      .map_err(js_to_anyhow)?;
+
+    let close_name = data.history.close.clone();
+    let close = Self::create_close_link(data, data_ptr, &modal, &close_name)?;
+
+    let answers = document.create_element("p").map_err(js_to_anyhow)?;
+    answers.set_class_name("answers");
+    answers.append_child(&download).map_err(js_to_anyhow)?;
+    answers.append_child(&close).map_err(js_to_anyhow)?;
+    dialog.append_child(&answers).map_err(js_to_anyhow)?;
+
+    // The latest text is the most interesting
+    content.set_scroll_top(content.scroll_height());
+    Ok(())
+  }
+
+  /// Appends the description of the current room to the text and renders its
+  /// actions.
+  fn render_current_room(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    let room = match data.rooms.get(&data.current_room) {
+      Some(r) => Self::resolve_room(data, r),
+      None => {
+        return Err(anyhow!(
+          "Current room is set to nonexistant room {}, can't reload the actions",
+          &data.current_room
+        ))
+      }
+    };
+    let description = Self::describe_room(data, &room);
+    data.current_text += &description;
+
+    Self::show_text(data, data_ptr.clone());
+    Self::show_image(data, room.image.as_deref());
+    Self::apply_theme(data, room.theme.as_ref());
+
+    // Item states might have changed
+    Self::update_room_actions(data, data_ptr, &room)
+  }
+
+  /// Returns from a menu to the current room, without running an action.
+  fn show_room(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    data.current_text.clear();
+    Self::render_current_room(data, data_ptr)
+  }
+
+  /// Lets all meters decay by one turn and applies `changes`. Returns the room
+  /// a crossed threshold transitions to, if any.
+  fn update_meters(
+    data: &mut GameData,
+    changes: &HashMap<String, i64>,
+    passes_time: bool,
+  ) -> Option<String> {
+    let meters = data.meters.clone();
+    let mut before = HashMap::new();
+    for (key, meter) in &meters {
+      let old = data.meter_values.get(key).copied().unwrap_or(meter.start);
+      let decay = if passes_time { meter.decay } else { 0 };
+      let change = changes.get(key).copied().unwrap_or_default() - decay;
+      data
+        .meter_values
+        .insert(key.clone(), (old + change).clamp(0, meter.max));
+      before.insert(key, old);
+    }
+
+    // Thresholds only fire when the meter drops below them. Their penalties
+    // might push other meters below theirs, so this repeats until no more
+    // thresholds are crossed, firing each at most once.
+    let mut transition = None;
+    let mut fired = HashSet::new();
+    loop {
+      let mut crossed = Vec::new();
+      for (key, meter) in &meters {
+        let old = before[key];
+        let new = data.meter_values[key];
+        for (index, threshold) in meter.thresholds.iter().enumerate() {
+          if old >= threshold.below && new < threshold.below && fired.insert((key, index)) {
+            crossed.push(threshold);
+          }
+        }
+      }
+      if crossed.is_empty() {
+        break;
+      }
+
+      for threshold in crossed {
+        if !threshold.text.is_empty() {
+          data.current_text += &format!(
+            "<span class=\"warning\">{}</span><br/><br/>",
+            threshold.text
+          );
+        }
+
+        for (other, penalty) in &threshold.meters {
+          if let Some(other_meter) = meters.get(other) {
+            let value = data
+              .meter_values
+              .entry(other.clone())
+              .or_insert(other_meter.start);
+            *value = (*value + penalty).clamp(0, other_meter.max);
+          }
+        }
+
+        if threshold.transition.is_some() {
+          transition = threshold.transition.clone();
+        }
+      }
+    }
+
+    transition
+  }
+
+  /// Shows the fields of the status bar with their current values.
+  fn render_status(data: &GameData) {
+    let Some(element) = &data.status_element else {
+      return;
+    };
+
+    let mut html = String::new();
+    for field in &data.status {
+      let (label, value) = match field {
+        StatusField::Room => {
+          let title = data
+            .rooms
+            .get(&data.current_room)
+            .map(|r| r.title.clone().unwrap_or_else(|| data.current_room.clone()));
+          match title {
+            Some(title) => (None, title),
+            None => continue,
+          }
+        }
+        StatusField::Turn => (Some(&data.strings.turn), data.turn.to_string()),
+        StatusField::Score => (Some(&data.strings.score), data.score.to_string()),
+        StatusField::Time => {
+          if data.clock.is_none() {
+            continue;
+          }
+          let time = data
+            .strings
+            .clock
+            .replace("{day}", &(data.time / 24 + 1).to_string())
+            .replace("{hour}", &(data.time % 24).to_string());
+          (Some(&data.strings.time), time)
+        }
+        StatusField::Items => (Some(&data.strings.items), data.inventory.len().to_string()),
+        StatusField::Meter(key) => {
+          let Some(meter) = data.meters.get(key) else {
+            continue;
+          };
+          let value = data.meter_values.get(key).copied().unwrap_or(meter.start);
+          (
+            Some(meter.label.as_ref().unwrap_or(key)),
+            format!("{value}/{}", meter.max),
+          )
+        }
+        StatusField::Reputation(key) => {
+          let Some(faction) = data.factions.get(key) else {
+            continue;
+          };
+          let value = data.reputation.get(key).copied().unwrap_or(faction.start);
+          let rank = faction.rank(value).map(str::to_string);
+          (
+            Some(&faction.name),
+            rank.unwrap_or_else(|| value.to_string()),
+          )
+        }
+      };
+
+      html += "<span class=\"field\">";
+      if let Some(label) = label {
+        html += &format!("<span class=\"label\">{}</span>", markdown::escape(label));
+      }
+      html += &format!(
+        "<span class=\"value\">{}</span></span>",
+        markdown::escape(&value)
+      );
+    }
+    element.set_inner_html(&html);
+  }
+
+  /// Shows how many of the rooms that count the player visited, or only the
+  /// link to show that again.
+  fn render_progress(data: &GameData) {
+    let Some(element) = &data.progress_element else {
+      return;
+    };
+    // Nothing was discovered on the intro
+    let progress = data
+      .progress
+      .as_ref()
+      .filter(|_| !data.current_room.is_empty());
+    let Some(progress) = progress else {
+      element.set_hidden(true);
+      return;
+    };
+    element.set_hidden(false);
+
+    let counted: Vec<&String> = data
+      .rooms
+      .keys()
+      .filter(|room| !progress.exclude.contains(room))
+      .collect();
+    let visited = counted
+      .iter()
+      .filter(|room| data.visited.contains(room.as_str()))
+      .count();
+    let text = data
+      .strings
+      .progress
+      .replace("{visited}", &visited.to_string())
+      .replace("{total}", &counted.len().to_string());
+
+    if let Some(count) = element.query_selector(".count").ok().flatten() {
+      count.set_text_content(Some(&text));
+      let _ = count.toggle_attribute_with_force("hidden", !data.progress_shown);
+    }
+    if let Some(toggle) = element.query_selector("a").ok().flatten() {
+      toggle.set_text_content(Some(if data.progress_shown {
+        &data.strings.hide_progress
+      } else {
+        &data.strings.show_progress
+      }));
+    }
+  }
+
+  /// Fills `element` with the progress indicator, and a link that hides or
+  /// shows it. The texts are filled in by [`Game::render_progress`].
+  fn create_progress_controls(data_ptr: Rc<Mutex<GameData>>, element: &HtmlElement) -> Result<()> {
+    // Until the game started
+    element.set_hidden(true);
+
+    let document = web_sys::window().unwrap().document().unwrap();
+    let count = document.create_element("span").map_err(js_to_anyhow)?;
+    count.set_class_name("count");
+    element.append_child(&count).map_err(js_to_anyhow)?;
+
+    let toggle = Self::create_link("")?;
+    element.append_child(&toggle).map_err(js_to_anyhow)?;
+    let callback = Closure::<dyn FnMut()>::new(move || {
+      let mut data = data_ptr.lock().unwrap();
+      data.progress_shown = !data.progress_shown;
+      if let Err(err) = store_progress_shown(data.progress_shown) {
+        log::error!("Unable to store whether the progress is shown: {err:#}");
+      }
+      Self::render_progress(&data);
+    });
+    toggle.set_onclick(Some(callback.as_ref().unchecked_ref()));
+    callback.forget();
+    Ok(())
+  }
+
+  /// Lists the items the player carries in the drawer. Whether it is open is
+  /// left to the player.
+  fn render_inventory(data: &GameData) {
+    let Some(element) = &data.inventory_element else {
+      return;
+    };
+    if element.hidden() {
+      return;
+    }
+
+    let mut items: Vec<&String> = data.inventory.iter().collect();
+    items.sort();
+    let summary = data
+      .strings
+      .inventory
+      .replace("{count}", &items.len().to_string());
+    let mut html = format!("<summary>{}</summary>", markdown::escape(&summary));
+    if items.is_empty() {
+      html += &format!("<p>{}</p>", markdown::escape(&data.strings.empty_inventory));
+    } else {
+      html += "<ul>";
+      for item in items {
+        html += &format!("<li>{}</li>", markdown::escape(item));
+      }
+      html += "</ul>";
+    }
+    element.set_inner_html(&html);
+  }
+
+  /// Switches the container to the layout for touch screens, or back, and
+  /// shows the inventory drawer with it.
+  fn apply_touch(data: &GameData) {
+    let active = match data.touch.mode {
+      TouchMode::Always => true,
+      TouchMode::Never => false,
+      TouchMode::Auto => web_sys::window()
+        .and_then(|w| w.match_media("(pointer: coarse)").ok().flatten())
+        .is_some_and(|query| query.matches()),
+    };
+    let classes = data.container_element.class_list();
+    if let Err(err) = classes.toggle_with_force("touch", active) {
+      log::error!("Unable to apply the touch layout: {err:?}");
+    }
+    if let Some(inventory) = &data.inventory_element {
+      inventory.set_hidden(!active || !data.touch.inventory);
+    }
+  }
+
+  /// Keeps the game above the on-screen keyboard. The container shrinks to
+  /// the part of the page that is still visible, and the focused text field
+  /// scrolls back into view.
+  fn listen_for_viewport(container: &HtmlElement) -> Result<()> {
+    let window = web_sys::window().ok_or(anyhow!("unable to get the window"))?;
+    let viewport = js_sys::Reflect::get(&window, &"visualViewport".into()).map_err(js_to_anyhow)?;
+    let Ok(viewport) = viewport.dyn_into::<web_sys::EventTarget>() else {
+      return Ok(());
+    };
+    // A <text-adventure> element keeps the size the page gives it
+    if container.get_root_node().dyn_ref::<ShadowRoot>().is_some() {
+      return Ok(());
+    }
+
+    let callback_viewport = viewport.clone();
+    let container = container.clone();
+    let callback = Closure::<dyn FnMut()>::new(move || {
+      let height = js_sys::Reflect::get(&callback_viewport, &"height".into())
+        .ok()
+        .and_then(|h| h.as_f64())
+        .filter(|h| *h > 0.0);
+      if let Some(height) = height {
+        let style = container.style();
+        if let Err(err) = style.set_property("--viewport-height", &format!("{height}px")) {
+          log::error!("Unable to follow the viewport: {err:?}");
+        }
+      }
+      let focused = container.owner_document().and_then(|d| d.active_element());
+      let field = focused.filter(|e| matches!(e.tag_name().as_str(), "INPUT" | "TEXTAREA"));
+      if let Some(field) = field {
+        field.scroll_into_view_with_bool(false);
+      }
+    });
+    viewport
+      .add_event_listener_with_callback("resize", callback.as_ref().unchecked_ref())
+      .map_err(js_to_anyhow)?;
+    callback.forget();
+    Ok(())
+  }
+
+  fn render_meters(data: &GameData) {
+    let element = match &data.meters_element {
+      Some(e) => e,
+      None => return,
+    };
+
+    let mut html = String::new();
+    for (key, meter) in &data.meters {
+      let value = data.meter_values.get(key).copied().unwrap_or(meter.start);
+      let percent = if meter.max > 0 {
+        value as f64 / meter.max as f64 * 100.0
+      } else {
+        0.0
+      };
+      let low = meter.thresholds.iter().any(|t| value < t.below);
+
+      html += &format!(
+        "<div class=\"meter{}\" title=\"{value}/{}\"><span>{}</span>\
+         <div class=\"bar\"><div class=\"fill\" style=\"width: {percent}%\"></div></div></div>",
+        if low { " low" } else { "" },
+        meter.max,
+        meter.label.as_ref().unwrap_or(key),
+      );
+    }
+
+    for (key, faction) in &data.factions {
+      let value = data.reputation.get(key).copied().unwrap_or(faction.start);
+      let rank = match faction.rank(value) {
+        Some(rank) => format!("{rank} ({value})"),
+        None => value.to_string(),
+      };
+      html += &format!(
+        "<div class=\"faction\"><span>{}</span><div class=\"rank\">{rank}</div></div>",
+        faction.name
+      );
+    }
+    element.set_inner_html(&html);
+  }
+
+  fn gain_items(data: &mut GameData, items: &[String]) {
+    data.inventory.extend(items.iter().cloned());
+    data.items_found.extend(items.iter().cloned());
+  }
+
+  /// Seconds played, including previous sessions
+  fn playtime(data: &GameData) -> f64 {
+    data.playtime + (js_sys::Date::now() - data.session_start) / 1000.0
+  }
+
+  /// Renders the statistics shown when the player reaches an ending.
+  fn statistics(data: &GameData) -> String {
+    let total_endings = data.rooms.values().filter(|r| r.ending).count();
+    let endings_found = data
+      .profile
+      .endings
+      .iter()
+      .filter(|e| data.rooms.get(*e).is_some_and(|r| r.ending))
+      .count();
+
+    let seconds = Self::playtime(data) as u64;
+    let playtime = format!(
+      "{}:{:02}:{:02}",
+      seconds / 3600,
+      (seconds / 60) % 60,
+      seconds % 60
+    );
+
+    let rows = [
+      (&data.strings.turns_taken, data.turn.to_string()),
+      (
+        &data.strings.rooms_visited,
+        format!("{}/{}", data.visited.len(), data.rooms.len()),
+      ),
+      (
+        &data.strings.items_found,
+        data.items_found.len().to_string(),
+      ),
+      (
+        &data.strings.endings_discovered,
+        format!("{endings_found}/{total_endings}"),
+      ),
+      (&data.strings.score, data.score.to_string()),
+      (&data.strings.playtime, playtime),
+    ];
+
+    let mut html = "<table class=\"stats\">".to_string();
+    for (label, value) in rows {
+      html += &format!(
+        "<tr><td>{}</td><td>{value}</td></tr>",
+        markdown::escape(label)
+      );
+    }
+    html += "</table>";
+    html
+  }
+
+  /// Counts a failed attempt at `check`. The counters are part of the save,
+  /// so the downloaded save shows them while testing an adventure.
+  fn record_failure(data: &mut GameData, check: &str) {
+    *data.failures.entry(check.to_string()).or_default() += 1;
+  }
+
+  /// Lists the offers the player can afford, each trading the offer's costs
+  /// for its item.
+  fn show_shop(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    offers: &[Offer],
+    leave: &str,
+  ) -> Result<()> {
+    let actions: Vec<Action> = offers
+      .iter()
+      .map(|offer| Action {
+        name: offer
+          .name
+          .clone()
+          .unwrap_or_else(|| data.strings.buy.replace("{item}", &offer.item)),
+        text: offer.text.clone(),
+        depends: ActionDependencies {
+          on: offer.costs.clone(),
+          not: vec![offer.item.clone()],
+          ..Default::default()
+        },
+        consumes: offer.costs.clone(),
+        time: Some(0),
+        kind: ActionKind::GainItems {
+          yields: vec![offer.item.clone()],
+        },
+        ..Default::default()
+      })
+      .collect();
+    Self::update_actions(data, data_ptr.clone(), &actions)?;
+
+    Self::add_menu_link(data, data_ptr, leave, Self::show_room)?;
+    Ok(())
+  }
+
+  /// The complete state of the game, as it is saved.
+  fn snapshot(data: &GameData) -> SaveGame {
+    SaveGame {
+      version: save::VERSION,
+      current_text: data.current_text.clone(),
+      inventory: data.inventory.clone().into_iter().collect(),
+      visited: data.visited.clone().into_iter().collect(),
+      current_room: data.current_room.clone(),
+      time: Some(data.time),
+      failures: data.failures.clone(),
+      rng: Some(data.rng),
+      turn: data.turn,
+      encounter_turns: data.encounter_turns.clone(),
+      detour: data.detour.clone(),
+      meters: data.meter_values.clone(),
+      active_character: data.active_character.clone(),
+      character_states: data.character_states.clone(),
+      score: data.score,
+      items_found: data.items_found.iter().cloned().collect(),
+      playtime: Self::playtime(data),
+      ng_plus: data.ng_plus,
+      difficulty: data.difficulty.clone(),
+      flags: data.flags.iter().cloned().collect(),
+      reputation: data.reputation.clone(),
+      memory: data
+        .memory
+        .iter()
+        .map(|(npc, topics)| (npc.clone(), topics.iter().cloned().collect()))
+        .collect(),
+      codes: data.entered_codes.clone(),
+      saved_at: js_sys::Date::now(),
+      slot_name: String::new(),
+      transcript: data.transcript.clone(),
+    }
+  }
+
+  fn save(data: &mut GameData) {
+    let save = Self::snapshot(data);
+    data.saved_text = save.current_text.clone();
+
+    // The carried over items are part of the save from now on
+    if data.profile.carried_items.take().is_some() {
+      data.profile.save();
+    }
+
+    Self::write_save(data, &save);
+  }
+
+  /// Saves state that changed without an action, like the playtime. Menus
+  /// replace the text, so the text of the last save is kept.
+  fn autosave(data: &mut GameData) {
+    if data.resetting || data.current_room.is_empty() || data.saved_text.is_empty() {
+      return;
+    }
+    let save = SaveGame {
+      current_text: data.saved_text.clone(),
+      ..Self::snapshot(data)
+    };
+    Self::write_save(data, &save);
+  }
+
+  fn write_save(data: &GameData, save: &SaveGame) {
+    if let Err(err) = save::write(&data.save_key, save) {
+      log::error!("Unable to save the game: {err:#}");
+    }
+    if let Some(endpoint) = &data.sync {
+      remote::push(endpoint, &data.save_key, save);
+    }
+  }
+
+  /// Saves when the player leaves or hides the page, and every few seconds
+  /// if the adventure wants it.
+  fn start_autosave(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    let window = web_sys::window().ok_or(anyhow!("unable to get the window"))?;
+    let document = window
+      .document()
+      .ok_or(anyhow!("unable to get the document"))?;
+
+    let unload_data = data_ptr.clone();
+    let on_unload = Closure::<dyn FnMut()>::new(move || {
+      Self::autosave(&mut unload_data.lock().unwrap());
+    });
+    window
+      .add_event_listener_with_callback("beforeunload", on_unload.as_ref().unchecked_ref())
+      .map_err(js_to_anyhow)?;
+    on_unload.forget();
+
+    let hidden_data = data_ptr.clone();
+    let hidden_document = document.clone();
+    let on_hidden = Closure::<dyn FnMut()>::new(move || {
+      if hidden_document.hidden() {
+        Self::autosave(&mut hidden_data.lock().unwrap());
+      }
+    });
+    document
+      .add_event_listener_with_callback("visibilitychange", on_hidden.as_ref().unchecked_ref())
+      .map_err(js_to_anyhow)?;
+    on_hidden.forget();
+
+    Self::schedule_autosave(data, data_ptr)
+  }
+
+  /// Replaces the interval that saves every few seconds, as the adventure
+  /// wants it now.
+  fn schedule_autosave(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    let window = web_sys::window().ok_or(anyhow!("unable to get the window"))?;
+    if let Some((handle, _)) = data.autosave_interval.take() {
+      window.clear_interval_with_handle(handle);
+    }
+
+    let seconds = data.saves.autosave;
+    if seconds > 0 {
+      let on_interval = Closure::<dyn FnMut()>::new(move || {
+        Self::autosave(&mut data_ptr.lock().unwrap());
+      });
+      let handle = window
+        .set_interval_with_callback_and_timeout_and_arguments_0(
+          on_interval.as_ref().unchecked_ref(),
+          (seconds * 1000) as i32,
+        )
+        .map_err(js_to_anyhow)?;
+      data.autosave_interval = Some((handle, on_interval));
+    }
+    Ok(())
+  }
+
+  /// Keeps the state before an action of the player, so it can be undone.
+  fn remember_undo(data: &mut GameData) {
+    if data.undo.steps == 0 {
+      return;
+    }
+    if data.undo_stack.len() >= data.undo.steps {
+      data.undo_stack.pop_front();
+    }
+    let snapshot = Self::snapshot(data);
+    data.undo_stack.push_back(snapshot);
+  }
+
+  /// Returns to the state before the player's last action.
+  fn undo(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    let mut save = data
+      .undo_stack
+      .pop_back()
+      .ok_or_else(|| anyhow!("There is nothing to undo"))?;
+    // The time spent still counts
+    save.playtime = Self::playtime(data);
+    // Restoring forgets the steps, but the earlier ones can still be undone
+    let steps = std::mem::take(&mut data.undo_stack);
+    Self::restore(data, data_ptr, save);
+    data.undo_stack = steps;
+    Self::save(data);
+    Ok(())
+  }
+
+  /// Continues the game from a saved state. Everything that is part of the
+  /// save is overwritten, and the actions before can't be undone anymore.
+  fn restore(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>, save: SaveGame) {
+    data.undo_stack.clear();
+    data.inventory = save.inventory.into_iter().collect();
+    data.visited = save.visited.into_iter().collect();
+    data.flags = save.flags.into_iter().collect();
+    data.entered_codes = save.codes;
+    data.transcript = save.transcript;
+    // Saves can be imported from anywhere, so their texts are sanitized like
+    // the adventure's
+    if !data.raw_html {
+      for entry in &mut data.transcript {
+        entry.text = markdown::sanitize(&entry.text);
+      }
+    }
+    Self::trim_transcript(data);
+    data.memory = save
+      .memory
+      .into_iter()
+      .map(|(npc, topics)| (npc, topics.into_iter().collect()))
+      .collect();
+    if let Some(time) = save.time {
+      data.time = time;
+    }
+    data.failures = save.failures;
+    if let Some(rng) = save.rng {
+      data.rng = rng;
+    }
+    data.turn = save.turn;
+    data.encounter_turns = save.encounter_turns;
+    data.detour = save.detour;
+    data.meter_values = data
+      .meters
+      .iter()
+      .map(|(key, meter)| (key.clone(), meter.start))
+      .chain(save.meters)
+      .collect();
+    data.score = save.score;
+    data.reputation = data
+      .factions
+      .iter()
+      .map(|(key, faction)| (key.clone(), faction.start))
+      .chain(save.reputation)
+      .collect();
+    data.items_found = save.items_found.into_iter().collect();
+    data.playtime = save.playtime;
+    data.session_start = js_sys::Date::now();
+    data.ng_plus = save.ng_plus;
+    data.difficulty = save.difficulty;
+    if !save.character_states.is_empty() {
+      data.active_character = save.active_character;
+      data.character_states = save.character_states;
+    }
+    data.current_text = match data.raw_html {
+      true => save.current_text,
+      false => markdown::sanitize(&save.current_text),
+    };
+    data.saved_text = data.current_text.clone();
+    Self::render_meters(data);
+
+    if data.rooms.contains_key(&save.current_room) {
+      data.current_room = save.current_room;
+      Self::resume_room(data, data_ptr);
+    } else {
+      // The adventure changed since the game was saved
+      log::warn!(
+        "The saved room {} doesn't exist anymore, continuing from the start",
+        save.current_room
+      );
+      let start = data.start.clone();
+      data.current_text.clear();
+      Self::goto_room(data, data_ptr, &start, false);
+    }
+  }
+
+  /// Shows the current room as the player left it, without any of the
+  /// effects of entering it.
+  fn resume_room(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) {
+    let Some(room) = data
+      .rooms
+      .get(&data.current_room)
+      .map(|r| Self::resolve_room(data, r))
+    else {
+      return;
+    };
+
+    for player in data.music.values_mut() {
+      if let Err(err) = player.set_profile(room.audio_profile) {
+        log::error!("Unable to apply the audio profile: {err:#}");
+      }
+    }
+    // Whatever played before belongs to another room
+    let playing = data.playing.clone();
+    Self::room_music(data, playing.as_deref(), room.music.as_deref());
+
+    Self::cancel_typing(data);
+    Self::rewrite_text(data);
+    Self::show_image(data, room.image.as_deref());
+    Self::apply_theme(data, room.theme.as_ref());
+    if let Err(err) = Self::update_room_actions(data, data_ptr, &room) {
+      log::error!("Unable to update the actions: {err:#}");
+    }
+  }
+
+  fn load(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) {
+    let report = save::read(&data.save_key);
+    if let Some(save) = report.save {
+      Self::restore(data, data_ptr, save);
+    }
+
+    // Let the player know if their progress could not be fully restored
+    if let Some(warning) = report.warning {
+      let warning = match warning {
+        LoadWarning::Unavailable(err) => data.strings.save_unavailable.replace("{error}", &err),
+        LoadWarning::Restored(err) => data.strings.save_restored.replace("{error}", &err),
+        LoadWarning::SetAside(err, key) => data
+          .strings
+          .save_set_aside
+          .replace("{error}", &err)
+          .replace("{key}", &key),
+        LoadWarning::Lost(err) => data.strings.save_lost.replace("{error}", &err),
+      };
+      data.current_text = format!(
+        "<span class=\"warning\">{warning}</span><br/><br/>{}",
+        data.current_text
+      );
+    }
+    Self::rewrite_text(data);
+  }
+
+  /// Returns the state of the game as a plain JS object.
+  fn get_snapshot(data: &GameData) -> Result<JsValue> {
+    let serialized = serde_json::to_string(&Self::snapshot(data))?;
+    js_sys::JSON::parse(&serialized).map_err(js_to_anyhow)
+  }
+
+  /// Continues from a snapshot, given either as an object or as a JSON string.
+  fn load_snapshot(
+    data: &mut GameData,
+    data_ptr: Rc<Mutex<GameData>>,
+    snapshot: JsValue,
+  ) -> Result<()> {
+    let serialized = match snapshot.as_string() {
+      Some(s) => s,
+      None => js_sys::JSON::stringify(&snapshot)
+        .map_err(js_to_anyhow)?
+        .into(),
+    };
+    let save = save::parse(&serialized).context("Malformed snapshot")?;
+
+    Self::restore(data, data_ptr, save);
+    Self::finish_actions(data);
+    Self::save(data);
+    Ok(())
+  }
+
+  /// Fails if the adventure has problems, and logs its warnings.
+  fn check(adventure: &Adventure) -> Result<()> {
+    let problems = validation::validate(adventure);
+    if !problems.is_empty() {
+      let report: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+      return Err(anyhow!(
+        "The adventure has {} problems:\n{}",
+        problems.len(),
+        report.join("\n")
+      ));
+    }
+    for warning in analysis::analyze(adventure) {
+      log::warn!("{warning}");
+    }
+    Ok(())
+  }
+
+  /// Resolves the images of the assets, and starts downloading them so
+  /// rooms don't wait for their image.
+  fn load_images(
+    assets: &AdventureAssets,
+    files: &HashMap<String, String>,
+  ) -> HashMap<String, Image> {
+    let mut images = HashMap::new();
+    for (key, image) in &assets.images {
+      let mut image = image.clone();
+      if let Some(url) = files.get(&image.url) {
+        image.url = url.clone();
+      }
+      match HtmlImageElement::new() {
+        Ok(preload) => preload.set_src(&image.url),
+        Err(err) => log::warn!("Unable to preload the image {key}: {err:?}"),
+      }
+      images.insert(key.clone(), image);
+    }
+    images
+  }
+
+  /// Shows the image in the scene, or hides the scene without one.
+  fn show_image(data: &GameData, image: Option<&str>) {
+    let Some(scene) = &data.scene_element else {
+      return;
+    };
+    let Some((name, image)) = image.and_then(|name| match data.images.get(name) {
+      Some(image) => Some((name, image)),
+      None => {
+        log::error!("Tried to show nonexistant image {name}");
+        None
+      }
+    }) else {
+      scene.set_inner_html("");
+      let _ = scene.remove_attribute("data-image");
+      scene.set_hidden(true);
+      return;
+    };
+
+    // The same image stays, instead of fading in again
+    if scene.get_attribute("data-image").as_deref() == Some(name) {
+      return;
+    }
+    if let Err(err) = Self::render_image(scene, name, image) {
+      log::error!("Unable to show the image {name}: {err:#}");
+    }
+  }
+
+  /// Dresses the page in the adventure's theme, with whatever `room` replaces
+  /// of it.
+  fn apply_theme(data: &GameData, room: Option<&Theme>) {
+    let container = &data.container_element;
+    let class = room
+      .and_then(|t| t.class.as_deref())
+      .or(data.theme.class.as_deref());
+    let background = room
+      .and_then(|t| t.background.as_deref())
+      .or(data.theme.background.as_deref());
+
+    // The class of the previous room is remembered, so the page's own
+    // classes are left alone
+    let classes = container.class_list();
+    if let Some(previous) = container.get_attribute("data-theme-class") {
+      let _ = classes.remove_1(&previous);
+    }
+    let applied = match class {
+      Some(class) => classes
+        .add_1(class)
+        .and_then(|_| container.set_attribute("data-theme-class", class)),
+      None => container.remove_attribute("data-theme-class"),
+    };
+    if let Err(err) = applied {
+      log::error!("Unable to apply the theme: {err:?}");
+    }
+
+    let style = container.style();
+    let applied = match background {
+      Some(color) => style.set_property("background-color", color),
+      None => style.remove_property("background-color").map(|_| ()),
+    };
+    if let Err(err) = applied {
+      log::error!("Unable to apply the background of the theme: {err:?}");
+    }
+  }
+
+  fn render_image(scene: &HtmlElement, name: &str, image: &Image) -> Result<()> {
+    let element = HtmlImageElement::new().map_err(js_to_anyhow)?;
+    element.set_alt(&image.alt);
+    // Fades in once it is loaded, see the default style
+    element.set_class_name("loading");
+    let loaded = element.clone();
+    let on_load = Closure::<dyn FnMut()>::new(move || loaded.set_class_name(""));
+    element.set_onload(Some(on_load.as_ref().unchecked_ref()));
+    on_load.forget();
+    element.set_src(&image.url);
+
+    scene.set_inner_html("");
+    scene.append_child(&element).map_err(js_to_anyhow)?;
+    scene
+      .set_attribute("data-image", name)
+      .map_err(js_to_anyhow)?;
+    scene.set_hidden(false);
+    Ok(())
+  }
+
+  /// Prepares the songs and audio files of the assets. Audio files in the
+  /// bundle the adventure came from are loaded from there.
+  fn load_music(
+    assets: &AdventureAssets,
+    files: &HashMap<String, String>,
+    mixer: &Mixer,
+  ) -> Result<HashMap<String, SongPlayer>> {
+    let mut music = HashMap::new();
+    for (key, song) in &assets.music {
+      let player = SongPlayer::new(song.clone(), assets, mixer)
+        .with_context(|| format!("Unable to load {key}"))?;
+      music.insert(key.clone(), player);
+    }
+    for (key, file) in &assets.audio_files {
+      let url = files.get(&file.url).unwrap_or(&file.url);
+      let player = SongPlayer::from_file(file.clone(), url, mixer)
+        .with_context(|| format!("Unable to load the audio file {key}"))?;
+      music.insert(key.clone(), player);
+    }
+    Ok(music)
+  }
+
+  fn load_sfx(assets: &AdventureAssets, mixer: &Mixer) -> Result<HashMap<String, EffectPlayer>> {
+    let mut sfx = HashMap::new();
+    for (key, effect) in &assets.sfx {
+      let player = EffectPlayer::new(effect.clone(), assets, mixer)
+        .with_context(|| format!("Unable to load the sound effect {key}"))?;
+      sfx.insert(key.clone(), player);
+    }
+    Ok(sfx)
+  }
+
+  /// Swaps in a changed version of the adventure while it is played, keeping
+  /// the player's progress, and shows the current room again.
+  pub fn reload(&mut self, adventure: Adventure) -> Result<()> {
+    Self::check(&adventure)?;
+
+    let mut data = self.data.lock().unwrap();
+    let music = Self::load_music(&adventure.assets, &adventure.files, &data.mixer)?;
+    let sfx = Self::load_sfx(&adventure.assets, &data.mixer)?;
+    if !data.current_room.is_empty() && !adventure.rooms.contains_key(&data.current_room) {
+      return Err(anyhow!(
+        "The current room {} was removed, reset the game to continue",
+        data.current_room
+      ));
+    }
+
+    // Meters and factions that were added start out at their start values
+    for (key, meter) in &adventure.meters {
+      data.meter_values.entry(key.clone()).or_insert(meter.start);
+    }
+    for (key, faction) in &adventure.factions {
+      data.reputation.entry(key.clone()).or_insert(faction.start);
+    }
+
+    data.title_screen = TitleScreen::of(&adventure);
+    data.intro = adventure.intro;
+    data.rooms = adventure.rooms;
+    // The songs might have changed, so the old ones are stopped, including
+    // those that are still fading out
+    data.playing = None;
+    for player in data.music.values() {
+      player.stop();
+    }
+    data.music = music;
+    data.sfx = sfx;
+    data.images = Self::load_images(&adventure.assets, &adventure.files);
+    data.crossfade = adventure.assets.crossfade;
+    data.fast_travel = adventure.fast_travel;
+    data.ambience = adventure.ambience;
+    data.clock = adventure.clock;
+    data.bestiary = adventure.bestiary;
+    data.codex = adventure.codex;
+    data.codes = adventure.codes;
+    data.encounters = adventure.encounters;
+    data.meters = adventure.meters;
+    data.factions = adventure.factions;
+    data.characters = adventure.characters;
+    data.items = adventure.items;
+    data.new_game_plus = adventure.new_game_plus;
+    data.difficulties = adventure.difficulties;
+    data.use_items = adventure.use_items;
+    data.languages = adventure.languages;
+    data.strings = adventure.strings;
+    data.saves = adventure.saves;
+    data.undo = adventure.undo;
+    data.history = adventure.history;
+    data.typewriter = adventure.typewriter;
+    data.status = adventure.status;
+    data.scrollback = adventure.scrollback;
+    data.raw_html = adventure.raw_html;
+    if let (Some(narrator), Some(narration)) = (&mut data.narrator, adventure.narration) {
+      narrator.set_narration(narration);
+    }
+    data.theme = adventure.theme;
+    data.action_rendering = adventure.action_rendering;
+    data.touch = adventure.touch;
+    Self::apply_touch(&data);
+    data.progress = adventure.progress;
+    Self::render_progress(&data);
+    Self::schedule_autosave(&mut data, self.data.clone())?;
+
+    // Still on the intro, which might have changed as well
+    if data.current_room.is_empty() {
+      return Self::show_start(&mut data, self.data.clone());
+    }
+    Self::show_room(&mut data, self.data.clone())?;
+    Self::finish_actions(&mut data);
+    Ok(())
+  }
+
+  /// Fills `element` with a slider for the music volume and a link that mutes
+  /// the music. Adventures without music don't show them.
+  fn create_volume_controls(data_ptr: Rc<Mutex<GameData>>, element: &HtmlElement) -> Result<()> {
+    let data = data_ptr.lock().unwrap();
+    if data.music.is_empty() {
+      element.set_hidden(true);
+      return Self::unlock_audio(data_ptr.clone(), None);
+    }
+
+    let document = web_sys::window().unwrap().document().unwrap();
+    let slider: web_sys::HtmlInputElement = document
+      .create_element("input")
+      .map_err(js_to_anyhow)?
+      .dyn_into()
+      .map_err(|_| anyhow!("Expected an input"))?;
+    slider.set_type("range");
+    slider.set_min("0");
+    slider.set_max("100");
+    slider.set_value(&((data.volume.level * 100.0).round() as u32).to_string());
+    slider.set_title(&data.strings.volume);
+    slider
+      .set_attribute("aria-label", &data.strings.volume)
+      .map_err(js_to_anyhow)?;
+    element.append_child(&slider).map_err(js_to_anyhow)?;
+
+    let mute = Self::create_link(if data.volume.muted {
+      &data.strings.unmute
+    } else {
+      &data.strings.mute
+    })?;
+    mute
+      .set_attribute("aria-pressed", &data.volume.muted.to_string())
+      .map_err(js_to_anyhow)?;
+    element.append_child(&mute).map_err(js_to_anyhow)?;
+
+    let slider_data = data_ptr.clone();
+    let slider_input = slider.clone();
+    let slider_callback = Closure::<dyn FnMut()>::new(move || {
+      let mut data = slider_data.lock().unwrap();
+      data.volume.level = (slider_input.value_as_number() / 100.0).clamp(0.0, 1.0) as f32;
+      Self::apply_volume(&data);
+    });
+    slider.set_oninput(Some(slider_callback.as_ref().unchecked_ref()));
+    slider_callback.forget();
+
+    let mute_data = data_ptr.clone();
+    let mute_link = mute.clone();
+    let mute_callback = Closure::<dyn FnMut()>::new(move || {
+      let mut data = mute_data.lock().unwrap();
+      data.volume.muted = !data.volume.muted;
+      mute_link.set_inner_text(if data.volume.muted {
+        &data.strings.unmute
+      } else {
+        &data.strings.mute
+      });
+      let _ = mute_link.set_attribute("aria-pressed", &data.volume.muted.to_string());
+      Self::apply_volume(&data);
+    });
+    mute.set_onclick(Some(mute_callback.as_ref().unchecked_ref()));
+    mute_callback.forget();
+
+    let enable = Self::create_link(&data.strings.enable_sound)?;
+    enable.set_hidden(true);
+    element.append_child(&enable).map_err(js_to_anyhow)?;
+    let enable_data = data_ptr.clone();
+    let enable_link = enable.clone();
+    let enable_callback = Closure::<dyn FnMut()>::new(move || {
+      Self::start_audio(enable_data.clone(), Some(enable_link.clone()));
+    });
+    enable.set_onclick(Some(enable_callback.as_ref().unchecked_ref()));
+    enable_callback.forget();
+
+    Self::unlock_audio(data_ptr.clone(), Some(enable))
+  }
+
+  /// Fills `element` with a link that turns the narration on and off, a
+  /// slider for its speed and a picker for the narrator's voice. Adventures
+  /// without narration don't show them.
+  fn create_narration_controls(data_ptr: Rc<Mutex<GameData>>, element: &HtmlElement) -> Result<()> {
+    let data = data_ptr.lock().unwrap();
+    let Some(narrator) = &data.narrator else {
+      element.set_hidden(true);
+      return Ok(());
+    };
+    let listening = narrator.listening.clone();
+
+    let toggle = Self::create_link(if listening.enabled {
+      &data.strings.stop_narrating
+    } else {
+      &data.strings.narrate
+    })?;
+    toggle
+      .set_attribute("aria-pressed", &listening.enabled.to_string())
+      .map_err(js_to_anyhow)?;
+    element.append_child(&toggle).map_err(js_to_anyhow)?;
+
+    let document = web_sys::window().unwrap().document().unwrap();
+    let slider: web_sys::HtmlInputElement = document
+      .create_element("input")
+      .map_err(js_to_anyhow)?
+      .dyn_into()
+      .map_err(|_| anyhow!("Expected an input"))?;
+    slider.set_type("range");
+    slider.set_min("50");
+    slider.set_max("200");
+    slider.set_value(&((listening.rate * 100.0).round() as u32).to_string());
+    slider.set_title(&data.strings.narration_rate);
+    slider
+      .set_attribute("aria-label", &data.strings.narration_rate)
+      .map_err(js_to_anyhow)?;
+    element.append_child(&slider).map_err(js_to_anyhow)?;
+
+    let picker: web_sys::HtmlSelectElement = document
+      .create_element("select")
+      .map_err(js_to_anyhow)?
+      .dyn_into()
+      .map_err(|_| anyhow!("Expected a select"))?;
+    picker.set_title(&data.strings.narration_voice);
+    picker
+      .set_attribute("aria-label", &data.strings.narration_voice)
+      .map_err(js_to_anyhow)?;
+    Self::list_voices(&data, &picker)?;
+    element.append_child(&picker).map_err(js_to_anyhow)?;
+
+    let toggle_data = data_ptr.clone();
+    let toggle_link = toggle.clone();
+    let toggle_callback = Closure::<dyn FnMut()>::new(move || {
+      let mut data = toggle_data.lock().unwrap();
+      let data = &mut *data;
+      let Some(narrator) = &mut data.narrator else {
+        return;
+      };
+      narrator.listening.enabled = !narrator.listening.enabled;
+      let enabled = narrator.listening.enabled;
+      toggle_link.set_inner_text(if enabled {
+        &data.strings.stop_narrating
+      } else {
+        &data.strings.narrate
+      });
+      let _ = toggle_link.set_attribute("aria-pressed", &enabled.to_string());
+
+      // Turning it on reads what is on the screen
+      if let Err(err) = narrator.read(&data.text_element) {
+        log::error!("Unable to read the text aloud: {err:#}");
+      }
+      if let Err(err) = narrator.listening.store() {
+        log::error!("Unable to store the narration: {err:#}");
+      }
+    });
+    toggle.set_onclick(Some(toggle_callback.as_ref().unchecked_ref()));
+    toggle_callback.forget();
+
+    let slider_data = data_ptr.clone();
+    let slider_input = slider.clone();
+    let slider_callback = Closure::<dyn FnMut()>::new(move || {
+      let mut data = slider_data.lock().unwrap();
+      let Some(narrator) = &mut data.narrator else {
+        return;
+      };
+      narrator.listening.rate = (slider_input.value_as_number() / 100.0).clamp(0.5, 2.0) as f32;
+      if let Err(err) = narrator.listening.store() {
+        log::error!("Unable to store the narration: {err:#}");
+      }
+    });
+    slider.set_onchange(Some(slider_callback.as_ref().unchecked_ref()));
+    slider_callback.forget();
+
+    let picker_data = data_ptr.clone();
+    let picker_select = picker.clone();
+    let picker_callback = Closure::<dyn FnMut()>::new(move || {
+      let mut data = picker_data.lock().unwrap();
+      let Some(narrator) = &mut data.narrator else {
+        return;
+      };
+      let voice = picker_select.value();
+      narrator.listening.voice = Some(voice).filter(|v| !v.is_empty());
+      if let Err(err) = narrator.listening.store() {
+        log::error!("Unable to store the narration: {err:#}");
+      }
+    });
+    picker.set_onchange(Some(picker_callback.as_ref().unchecked_ref()));
+    picker_callback.forget();
+
+    // Browsers load their voices in the background
+    let voices_data = data_ptr.clone();
+    let voices_callback = Closure::<dyn FnMut()>::new(move || {
+      let data = voices_data.lock().unwrap();
+      if let Err(err) = Self::list_voices(&data, &picker) {
+        log::error!("Unable to list the voices: {err:#}");
+      }
+    });
+    narrator
+      .synthesis()
+      .set_onvoiceschanged(Some(voices_callback.as_ref().unchecked_ref()));
+    voices_callback.forget();
+
+    Ok(())
+  }
+
+  /// Offers the voices of the browser in `picker`, after the adventure's own.
+  fn list_voices(data: &GameData, picker: &web_sys::HtmlSelectElement) -> Result<()> {
+    let Some(narrator) = &data.narrator else {
+      return Ok(());
+    };
+    let chosen = narrator.listening.voice.clone().unwrap_or_default();
+
+    picker.set_length(0);
+    let default =
+      web_sys::HtmlOptionElement::new_with_text_and_value(&data.strings.default_voice, "")
+        .map_err(js_to_anyhow)?;
+    picker
+      .add_with_html_option_element(&default)
+      .map_err(js_to_anyhow)?;
+    for voice in narrator.voices() {
+      let name = voice.name();
+      let option = web_sys::HtmlOptionElement::new_with_text_and_value(
+        &format!("{name} ({})", voice.lang()),
+        &name,
+      )
+      .map_err(js_to_anyhow)?;
+      picker
+        .add_with_html_option_element(&option)
+        .map_err(js_to_anyhow)?;
+    }
+    picker.set_value(&chosen);
+    Ok(())
+  }
+
+  /// Starts the audio on the first click or key press, as browsers keep it
+  /// suspended until then. If that fails, `enable` is shown to try again.
+  fn unlock_audio(data_ptr: Rc<Mutex<GameData>>, enable: Option<HtmlElement>) -> Result<()> {
+    let document = web_sys::window()
+      .and_then(|w| w.document())
+      .ok_or(anyhow!("unable to get the document"))?;
+
+    let unlocked = Rc::new(Cell::new(false));
+    let callback = Closure::<dyn FnMut()>::new(move || {
+      if !unlocked.replace(true) {
+        Self::start_audio(data_ptr.clone(), enable.clone());
+      }
+    });
+    for event in ["pointerdown", "keydown"] {
+      document
+        .add_event_listener_with_callback(event, callback.as_ref().unchecked_ref())
+        .map_err(js_to_anyhow)?;
+    }
+    callback.forget();
+    Ok(())
+  }
+
+  /// Asks the browser to start the audio, and shows `enable` if it refused.
+  fn start_audio(data_ptr: Rc<Mutex<GameData>>, enable: Option<HtmlElement>) {
+    let unlock = data_ptr.lock().unwrap().mixer.unlock();
+    wasm_bindgen_futures::spawn_local(async move {
+      let result = unlock.await;
+      if let Err(err) = &result {
+        log::warn!("Unable to start the audio: {err:#}");
+      }
+      let blocked = result.is_err() || data_ptr.lock().unwrap().mixer.suspended();
+      if let Some(enable) = enable {
+        enable.set_hidden(!blocked);
+      }
+    });
+  }
+
+  /// Plays the music at the volume the player picked, and keeps it for later
+  /// visits.
+  fn apply_volume(data: &GameData) {
+    data.mixer.set_volume(data.volume);
+    if let Err(err) = data.volume.store() {
+      log::error!("Unable to store the volume: {err:#}");
+    }
+  }
+
+  /// Shows what a reset would lose, and resets the game once the player
+  /// confirmed it.
+  fn confirm_reset(data: &mut GameData, data_ptr: Rc<Mutex<GameData>>) -> Result<()> {
+    // Before the game started, there is nothing to lose
+    if data.current_room.is_empty() {
+      Self::reset(data);
+      return Ok(());
+    }
+
+    let document = web_sys::window().unwrap().document().unwrap();
+    let (modal, dialog) = Self::create_modal(data)?;
+
+    let question = document.create_element("p").map_err(js_to_anyhow)?;
+    question.set_text_content(Some(&data.strings.reset_question));
+    dialog.append_child(&question).map_err(js_to_anyhow)?;
+
+    let summary = document.create_element("div").map_err(js_to_anyhow)?;
+    summary.set_inner_html(&Self::statistics(data));
+    dialog.append_child(&summary).map_err(js_to_anyhow)?;
+
+    let yes = Self::create_link(&data.strings.confirm_yes)?;
+    let yes_data = data_ptr.clone();
+    let yes_callback =
+      Closure::<dyn FnMut()>::new(move || Self::reset(&mut yes_data.lock().unwrap()));
+    yes.set_onclick(Some(yes_callback.as_ref().unchecked_ref()));
+    Self::keep_listener(data, &modal, yes_callback);
+
+    // The export opens on top, so the player can still decide afterwards
+    let export = Self::create_link(&data.strings.reset_export)?;
+    let save = Rc::new(Self::snapshot(data));
+    let export_data = data_ptr.clone();
+    let export_callback = Closure::<dyn FnMut()>::new(move || {
+      let mut data = export_data.lock().unwrap();
+      if let Err(err) = Self::show_export(&mut data, export_data.clone(), &save) {
+        log::error!("Unable to export the save: {err:#}");
+      }
+    });
+    export.set_onclick(Some(export_callback.as_ref().unchecked_ref()));
+    Self::keep_listener(data, &modal, export_callback);
+
+    let no_name = data.strings.confirm_no.clone();
+    let no = Self::create_close_link(data, data_ptr, &modal, &no_name)?;
+
+    let answers = document.create_element("p").map_err(js_to_anyhow)?;
+    answers.set_class_name("answers");
+    answers.append_child(&yes).map_err(js_to_anyhow)?;
+    answers.append_child(&export).map_err(js_to_anyhow)?;
+    answers.append_child(&no).map_err(js_to_anyhow)?;
+    dialog.append_child(&answers).map_err(js_to_anyhow)?;
+    Ok(())
+  }
+
+  fn reset(data: &mut GameData) {
+    data.resetting = true;
+    save::remove(&data.save_key);
+
+    // The save has to be gone before the game starts again
+    wasm_bindgen_futures::spawn_local(async {
+      storage::flush().await;
+      let window = web_sys::window().unwrap();
+      window.location().reload().unwrap();
+    });
+  }
+}
+
+impl TryFrom<Adventure> for Game {
+  type Error = anyhow::Error;
+
+  /// Plays the adventure on the page, see [`layout::prepare`].
+  fn try_from(value: Adventure) -> Result<Self, Self::Error> {
+    let document = web_sys::window()
+      .and_then(|w| w.document())
+      .ok_or(anyhow!("unable to get the document"))?;
+    Self::new(value, layout::prepare(&document)?)
+  }
+}
+
+impl Game {
+  /// Plays the adventure in `layout`, e.g. one from [`layout::mount`].
+  pub fn new(value: Adventure, layout: Layout) -> Result<Self> {
+    Self::check(&value)?;
+    migrate_legacy_keys(&value);
+    let save_key = storage_key(SAVE_KEY, &value);
+    let profile_key = storage_key(PROFILE_KEY, &value);
+    let volume = Volume::stored();
+    let mixer = Mixer::new(volume)?;
+    let title_screen = TitleScreen::of(&value);
+    let language = storage::stored_language()
+      .or_else(|| Some(value.languages.default.clone()).filter(|l| !l.is_empty()));
+    let narrator =
+      value
+        .narration
+        .clone()
+        .and_then(|narration| match Narrator::new(narration, language) {
+          Ok(narrator) => Some(narrator),
+          Err(err) => {
+            log::warn!("The texts can't be read aloud: {err:#}");
+            None
+          }
+        });
+    let music = Self::load_music(&value.assets, &value.files, &mixer)?;
+    let images = Self::load_images(&value.assets, &value.files);
+    let sfx = Self::load_sfx(&value.assets, &mixer)?;
+
+    let text_element = layout.required("maintext")?;
+    let actions_element = layout.required("actions")?;
+
+    // Meters are optional, as not every adventure uses them
+    let status_element = layout.element("status");
+    let inventory_element = layout.element("inventory");
+    let progress_element = layout.element("progress");
+    let meters_element = layout.element("meters");
+
+    // So is the scene, which starts out empty
+    let scene_element = layout.element("scene");
+    if let Some(scene) = &scene_element {
+      scene.set_hidden(true);
+    }
+
+    // The volume controls are optional as well
+    let volume_element = layout.element("volume");
+    let narration_element = layout.element("narration");
+
+    let reset_element = layout.required("reset")?;
+
+    // Screen readers announce new texts, and know what the controls do
+    let strings = &value.strings;
+    for (element, name, content) in [
+      (&text_element, "aria-live", "polite"),
+      (&actions_element, "role", "group"),
+      (&actions_element, "aria-label", strings.actions.as_str()),
+      (&reset_element, "aria-label", strings.reset.as_str()),
+    ] {
+      element.set_attribute(name, content).map_err(js_to_anyhow)?;
+    }
+    // Pages may bring a real button
+    if reset_element.tag_name() == "A" {
+      reset_element
+        .set_attribute("role", "button")
+        .map_err(js_to_anyhow)?;
+    }
+    Self::listen_for_button_keys(&layout.container)?;
+    Self::listen_for_viewport(&layout.container)?;
+
+    // Every character starts in their own room, with their own items
+    let mut character_states: HashMap<String, CharacterState> = value
+      .characters
+      .playable
+      .iter()
+      .map(|(key, character)| {
+        let state = CharacterState {
+          room: character
+            .start
+            .clone()
+            .unwrap_or_else(|| value.start.clone()),
+          inventory: character.items.clone(),
+        };
+        (key.clone(), state)
+      })
+      .collect();
+    let active_character = value
+      .characters
+      .initial
+      .clone()
+      .or_else(|| value.characters.playable.keys().next().cloned())
+      .unwrap_or_default();
+    let (start, mut inventory): (String, HashSet<String>) =
+      match character_states.remove(&active_character) {
+        Some(state) => (state.room, state.inventory.into_iter().collect()),
+        None => (value.start, HashSet::new()),
+      };
+
+    // A New Game+ was started from a finished game
+    let profile = Profile::load(profile_key);
+    let ng_plus = profile.carried_items.is_some();
+    inventory.extend(profile.carried_items.iter().flatten().cloned());
+
+    let data = GameData {
+      title_screen,
+      intro: value.intro,
+      rooms: value.rooms,
+      start,
+      inventory: inventory.clone(),
+      visited: HashSet::new(),
+      flags: HashSet::new(),
+      fast_travel: value.fast_travel,
+      ambience: value.ambience,
+      time: value.clock.as_ref().map(|c| c.start).unwrap_or_default(),
+      clock: value.clock,
+      failures: HashMap::new(),
+      bestiary: value.bestiary,
+      codex: value.codex,
+      codes: value.codes,
+      typed: String::new(),
+      entered_codes: Vec::new(),
+      profile,
+      save_key,
+      sync: value.sync,
+      encounters: value.encounters,
+      rng: Rng::seeded(),
+      turn: 0,
+      encounter_turns: HashMap::new(),
+      detour: None,
+      meter_values: value
+        .meters
+        .iter()
+        .map(|(key, meter)| (key.clone(), meter.start))
+        .collect(),
+      meters: value.meters,
+      reputation: value
+        .factions
+        .iter()
+        .map(|(key, faction)| (key.clone(), faction.start))
+        .collect(),
+      factions: value.factions,
+      memory: HashMap::new(),
+      meters_element,
+      status_element,
+      status: value.status,
+      inventory_element,
+      touch: value.touch,
+      progress_element: progress_element.clone(),
+      progress: value.progress,
+      progress_shown: progress_shown(),
+      scene_element,
+      container_element: layout.container,
+      score: 0,
+      items_found: inventory.clone(),
+      playtime: 0.0,
+      session_start: js_sys::Date::now(),
+      characters: value.characters,
+      active_character,
+      character_states,
+      items: value.items,
+      new_game_plus: value.new_game_plus,
+      ng_plus,
+      difficulties: value.difficulties,
+      difficulty: String::new(),
+      use_items: value.use_items,
+      languages: value.languages,
+      strings: value.strings,
+      saves: value.saves,
+      undo: value.undo,
+      undo_stack: VecDeque::new(),
+      history: value.history,
+      transcript: Vec::new(),
+      saved_text: String::new(),
+      resetting: false,
+      action_rendering: value.action_rendering,
+      links: Vec::new(),
+      retired_listeners: Vec::new(),
+      autosave_interval: None,
+      next_link_serial: 0,
+      timer: None,
+      modals: Vec::new(),
+      typewriter: value.typewriter,
+      scrollback: value.scrollback,
+      messages: VecDeque::new(),
+      theme: value.theme,
+      typing: None,
+      entered_room: false,
+      rendered_links: 0,
+      current_text: String::default(),
+      current_room: String::default(),
+      music,
+      sfx,
+      images,
+      mixer,
+      volume,
+      narrator,
+      raw_html: value.raw_html,
+      playing: None,
+      crossfade: value.assets.crossfade,
+      text_element,
+      actions_element,
+    };
+
+    Self::apply_touch(&data);
+
+    let data = Rc::new(Mutex::new(data));
+    let reset_data = data.clone();
+    let reset_callback = Closure::<dyn FnMut()>::new(move || {
+      let mut data = reset_data.lock().unwrap();
+      if let Err(err) = Self::confirm_reset(&mut data, reset_data.clone()) {
+        log::error!("Unable to ask for the reset: {err:#}");
+      }
+    });
+    reset_element.set_onclick(Some(reset_callback.as_ref().unchecked_ref()));
+    reset_callback.forget();
+
+    if let Some(element) = volume_element {
+      Self::create_volume_controls(data.clone(), &element)?;
+    }
+    if let Some(element) = progress_element {
+      Self::create_progress_controls(data.clone(), &element)?;
+    }
+    if let Some(element) = narration_element {
+      Self::create_narration_controls(data.clone(), &element)?;
+    }
+
+    Ok(Self { data })
+  }
+}
+
+/// Collects the text nodes below `node` and their text, in the order they are
+/// read.
+fn text_nodes(node: &Node, nodes: &mut Vec<(Node, String)>) {
+  let children = node.child_nodes();
+  for index in 0..children.length() {
+    let Some(child) = children.item(index) else {
+      continue;
+    };
+    if child.node_type() == Node::TEXT_NODE {
+      let text = child.text_content().unwrap_or_default();
+      nodes.push((child, text));
+    } else {
+      text_nodes(&child, nodes);
+    }
+  }
+}
+
+/// Fires `typewriterdone` on the text once the typewriter showed all of it,
+/// so the page can chain further effects. It bubbles up to the document.
+fn announce_typed(element: &HtmlElement) {
+  let mut init = EventInit::new();
+  init.bubbles(true);
+  let dispatched = Event::new_with_event_init_dict("typewriterdone", &init)
+    .and_then(|event| element.dispatch_event(&event));
+  if let Err(err) = dispatched {
+    log::warn!("Unable to announce the revealed text: {err:?}");
+  }
+}
+
+/// Copies the text to the clipboard, if the browser allows it. The clipboard
+/// api isn't part of the stable web-sys bindings, so it is called by name.
+fn copy_to_clipboard(text: &str) {
+  let written = web_sys::window()
+    .and_then(|w| js_sys::Reflect::get(&w, &"navigator".into()).ok())
+    .and_then(|n| js_sys::Reflect::get(&n, &"clipboard".into()).ok())
+    .filter(|c| !c.is_undefined())
+    .and_then(|clipboard| {
+      let write_text = js_sys::Reflect::get(&clipboard, &"writeText".into()).ok()?;
+      let write_text: js_sys::Function = write_text.dyn_into().ok()?;
+      write_text.call1(&clipboard, &text.into()).ok()
+    });
+  if written.is_none() {
+    log::warn!("Unable to copy to the clipboard");
+  }
+}
+
+/// Turns the html of a text into plain text, keeping its line breaks.
+fn plain_text(html: &str) -> String {
+  let breaks = regex::Regex::new(r"(?i)<br\s*/?>|</p>").expect("the break pattern is valid");
+  let tags = regex::Regex::new(r"<[^>]*>").expect("the tag pattern is valid");
+  let text = breaks.replace_all(html, "\n");
+  tags
+    .replace_all(&text, "")
+    .replace("&lt;", "<")
+    .replace("&gt;", ">")
+    .replace("&quot;", "\"")
+    .replace("&#39;", "'")
+    .replace("&amp;", "&")
+    .trim()
+    .to_string()
+}
+
+/// Formats a time in milliseconds since the epoch in the player's timezone.
+fn format_time(milliseconds: f64) -> String {
+  let date = js_sys::Date::new(&milliseconds.into());
+  format!(
+    "{}-{:02}-{:02} {:02}:{:02}",
+    date.get_full_year(),
+    date.get_month() + 1,
+    date.get_date(),
+    date.get_hours(),
+    date.get_minutes()
+  )
+}
+
+/// Progress that is kept across games, even when the game is reset.
+#[derive(Serialize, Deserialize, Default)]
+struct Profile {
+  /// How often each enemy was defeated
+  #[serde(default)]
+  defeated: HashMap<String, u32>,
+  /// All ending rooms reached in any game
+  #[serde(default)]
+  endings: HashSet<String>,
+  /// All unlocked codex entries
+  #[serde(default)]
+  codex: HashSet<String>,
+  /// The items kept for a New Game+ that hasn't been saved yet
+  #[serde(default)]
+  carried_items: Option<Vec<String>>,
+  /// Where the profile is stored
+  #[serde(skip)]
+  key: String,
+}
+
+impl Profile {
+  fn load(key: String) -> Self {
+    let profile = match storage::get(&key) {
+      Ok(Some(raw)) => serde_json::from_str(&raw).unwrap_or_else(|err| {
+        log::warn!("Found malformed profile data: {err}");
+        Self::default()
+      }),
+      Ok(None) => Self::default(),
+      Err(err) => {
+        log::error!("Unable to load the profile: {err:#}");
+        Self::default()
+      }
+    };
+    Self { key, ..profile }
+  }
+
+  fn save(&self) {
+    let serialized = serde_json::to_string(self).unwrap();
+    if let Err(err) = storage::set(&self.key, &serialized) {
+      log::error!("Unable to save the profile: {err:#}");
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::loader::{self, Format};
+
+  const TRANSLATED: &str = "
+adventure:
+  name: {en: The Cave, de: Die Höhle}
+  start: cave
+  intro: {en: Welcome, de: Willkommen}
+  languages:
+    default: en
+    available: {en: English, de: Deutsch}
+  rooms:
+    cave:
+      description: {en: A cave, de: Eine Höhle}
+      actions: []
+  assets:
+    music: {}
+";
+
+  #[test]
+  fn storage_key_ignores_the_language() {
+    let english = loader::parse(TRANSLATED, Format::Yaml, Some("en")).unwrap();
+    let german = loader::parse(TRANSLATED, Format::Yaml, Some("de")).unwrap();
+    assert_eq!(german.adventure.name, "Die Höhle");
+    assert_eq!(
+      storage_key(SAVE_KEY, &english.adventure),
+      storage_key(SAVE_KEY, &german.adventure)
+    );
+    assert!(storage_key(SAVE_KEY, &german.adventure).starts_with("textadventure_save_the_cave_"));
+  }
 }
@@ -0,0 +1,89 @@
+//! Generates synthetic adventures of arbitrary size, to measure how the engine
+//! scales with large adventures.
+
+use std::fmt::Write;
+
+use crate::migration::CURRENT_VERSION;
+
+pub struct GeneratorConfig {
+  pub rooms: usize,
+  /// Actions per room in addition to the ones moving between rooms
+  pub actions_per_room: usize,
+  /// The length of the chain of items, where every item requires the previous
+  /// one to be picked up.
+  pub chain_length: usize,
+}
+
+impl Default for GeneratorConfig {
+  fn default() -> Self {
+    Self {
+      rooms: 10_000,
+      actions_per_room: 4,
+      chain_length: 1_000,
+    }
+  }
+}
+
+/// Generates the yaml source of an adventure. The rooms form a ring, and the
+/// first `chain_length` rooms each hold an item that depends on the item of
+/// the room before.
+pub fn generate(config: &GeneratorConfig) -> String {
+  let rooms = config.rooms.max(1);
+
+  let mut yaml = String::new();
+  let _ = writeln!(yaml, "version: {CURRENT_VERSION}");
+  let _ = writeln!(yaml, "adventure:");
+  let _ = writeln!(yaml, "  name: Synthetic adventure with {rooms} rooms");
+  let _ = writeln!(yaml, "  start: room_0");
+  let _ = writeln!(yaml, "  intro: A generated adventure.");
+  let _ = writeln!(yaml, "  rooms:");
+
+  for room in 0..rooms {
+    let _ = writeln!(yaml, "    room_{room}:");
+    let _ = writeln!(yaml, "      title: Room {room}");
+    let _ = writeln!(
+      yaml,
+      "      description: You are in room {room} of {rooms}."
+    );
+    let _ = writeln!(yaml, "      tags: [tag_{}]", room % 10);
+    let _ = writeln!(yaml, "      actions:");
+
+    let _ = writeln!(yaml, "        - name: Go forward");
+    let _ = writeln!(yaml, "          type: navigate");
+    let _ = writeln!(yaml, "          transition: room_{}", (room + 1) % rooms);
+    let _ = writeln!(yaml, "        - name: Go back");
+    let _ = writeln!(yaml, "          type: navigate");
+    let _ = writeln!(
+      yaml,
+      "          transition: room_{}",
+      (room + rooms - 1) % rooms
+    );
+
+    if room < config.chain_length {
+      let _ = writeln!(yaml, "        - name: Take item {room}");
+      let _ = writeln!(yaml, "          type: gain_items");
+      let _ = writeln!(yaml, "          yields: [item_{room}]");
+      let _ = writeln!(yaml, "          depends:");
+      let _ = writeln!(yaml, "            not: [item_{room}]");
+      if room > 0 {
+        let _ = writeln!(yaml, "            on: [item_{}]", room - 1);
+      }
+    }
+
+    for action in 0..config.actions_per_room {
+      let _ = writeln!(yaml, "        - name: Look around {action}");
+      let _ = writeln!(yaml, "          text: Nothing of interest.");
+      let _ = writeln!(yaml, "          depends:");
+      let _ = writeln!(
+        yaml,
+        "            not: [item_{}]",
+        (room + action) % config.chain_length.max(1)
+      );
+    }
+  }
+
+  let _ = writeln!(yaml, "  assets:");
+  let _ = writeln!(yaml, "    music: {{}}");
+
+  yaml
+}
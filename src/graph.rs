@@ -0,0 +1,164 @@
+//! Exports the structure of an adventure as a Graphviz graph. Rooms are
+//! nodes, and every way from one room to another is an edge, labeled with
+//! the action and the items it needs. Render it with e.g.
+//! `dot -Tsvg adventure.dot > adventure.svg`.
+
+use std::fmt::Write;
+
+use crate::adventure::{Action, ActionKind, Adventure, Room};
+
+/// Writes the adventure as a graph in the DOT language.
+pub fn to_dot(adventure: &Adventure) -> String {
+  let mut rooms: Vec<(&String, &Room)> = adventure.rooms.iter().collect();
+  rooms.sort_by_key(|(id, _)| id.as_str());
+
+  let mut dot = String::new();
+  let _ = writeln!(dot, "digraph {} {{", quote(&adventure.name));
+  let _ = writeln!(dot, "  node [shape=box];");
+
+  for (id, room) in &rooms {
+    let label = room.title.as_deref().unwrap_or(id);
+    let mut attributes = vec![format!("label={}", quote(label))];
+    if room.ending {
+      attributes.push("peripheries=2".to_string());
+    }
+    if **id == adventure.start {
+      attributes.push("style=bold".to_string());
+    }
+    let _ = writeln!(dot, "  {} [{}];", quote(id), attributes.join(", "));
+  }
+
+  for (id, room) in &rooms {
+    for (action, variant) in actions(room) {
+      for (target, outcome) in targets(action) {
+        let mut label = action.name.clone();
+        if let Some(outcome) = outcome {
+          label += &format!(" ({outcome})");
+        }
+        if let Some(variant) = variant {
+          label += &format!(" [{variant}]");
+        }
+        if !action.depends.on.is_empty() {
+          label += &format!("\nneeds {}", action.depends.on.join(", "));
+        }
+        let _ = writeln!(
+          dot,
+          "  {} -> {} [label={}];",
+          quote(id),
+          quote(target),
+          quote(&label)
+        );
+      }
+    }
+
+    for target in &room.use_targets {
+      let mut outcomes: Vec<_> = target.outcomes.iter().collect();
+      outcomes.sort_by_key(|(item, _)| item.as_str());
+      for (item, outcome) in outcomes {
+        if let Some(transition) = &outcome.transition {
+          let label = format!("use {item} on {}", target.name);
+          let _ = writeln!(
+            dot,
+            "  {} -> {} [label={}];",
+            quote(id),
+            quote(transition),
+            quote(&label)
+          );
+        }
+      }
+    }
+  }
+
+  let _ = writeln!(dot, "}}");
+  dot
+}
+
+/// The actions of the room, with the time of day they are limited to.
+fn actions(room: &Room) -> Vec<(&Action, Option<&str>)> {
+  let mut actions: Vec<_> = room.actions.iter().map(|a| (a, None)).collect();
+  for (variant, name) in [(&room.day, "day"), (&room.night, "night")] {
+    if let Some(variant_actions) = variant.as_ref().and_then(|v| v.actions.as_ref()) {
+      actions.extend(variant_actions.iter().map(|a| (a, Some(name))));
+    }
+  }
+  actions
+}
+
+/// The rooms an action can lead to, with the outcome leading there.
+fn targets(action: &Action) -> Vec<(&str, Option<&str>)> {
+  let mut targets: Vec<(&str, Option<&str>)> = Vec::new();
+  targets.extend(action.transition().map(|t| (t.as_str(), None)));
+  if let ActionKind::Check {
+    success, failure, ..
+  } = &action.kind
+  {
+    targets.extend(success.transition.as_deref().map(|t| (t, Some("success"))));
+    targets.extend(failure.transition.as_deref().map(|t| (t, Some("failure"))));
+  }
+  targets.extend(
+    action
+      .timeout
+      .transition
+      .as_deref()
+      .map(|t| (t, Some("timeout"))),
+  );
+  targets
+}
+
+/// Quotes an id or label, so any room name can be used.
+fn quote(text: &str) -> String {
+  format!(
+    "\"{}\"",
+    text
+      .replace('\\', "\\\\")
+      .replace('"', "\\\"")
+      .replace('\n', "\\n")
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::loader::{parse, Format};
+
+  const ADVENTURE: &str = r#"
+adventure:
+  name: The "Cave"
+  start: cave
+  intro: Welcome
+  rooms:
+    cave:
+      description: A cave
+      actions:
+        - {name: Leave, type: navigate, transition: outside, depends: {on: [lantern]}}
+        - {name: Wait, type: custom}
+    outside:
+      description: Daylight
+      ending: true
+      actions: []
+  assets:
+    music: {}
+"#;
+
+  #[test]
+  fn draws_rooms_and_the_ways_between_them() {
+    let document = parse(ADVENTURE, Format::Yaml, None).unwrap();
+    let dot = to_dot(&document.adventure);
+    assert_eq!(
+      dot,
+      "digraph \"The \\\"Cave\\\"\" {
+  node [shape=box];
+  \"cave\" [label=\"cave\", style=bold];
+  \"outside\" [label=\"outside\", peripheries=2];
+  \"cave\" -> \"outside\" [label=\"Leave\\nneeds lantern\"];
+}
+"
+    );
+  }
+
+  #[test]
+  fn leaves_out_actions_that_lead_nowhere() {
+    let document = parse(ADVENTURE, Format::Yaml, None).unwrap();
+    assert!(!to_dot(&document.adventure).contains("Wait"));
+  }
+}
@@ -0,0 +1,160 @@
+//! Splits adventures across files. A file lists the files it includes, whose
+//! sections are merged into the adventure. Reading the files is left to the
+//! caller, so this works for downloaded files as well as files on disk.
+
+use std::collections::{HashSet, VecDeque};
+
+use anyhow::{anyhow, Context, Result};
+use serde_yaml::{Mapping, Value};
+
+/// The files that are still to be included. Files are included in the order
+/// they are listed, before the files they include in turn, and only once,
+/// even if several files list them.
+pub struct Pending {
+  queue: VecDeque<String>,
+  loaded: HashSet<String>,
+}
+
+impl Pending {
+  /// Starts with the includes of the adventure in `file`.
+  pub fn new(sections: &mut Mapping, file: &str) -> Result<Self> {
+    let mut pending = Self {
+      queue: VecDeque::new(),
+      loaded: HashSet::from([file.to_string()]),
+    };
+    pending.add(sections, file)?;
+    Ok(pending)
+  }
+
+  /// Takes the includes of a file that was just read, which are included
+  /// after the ones that are already pending.
+  pub fn add(&mut self, sections: &mut Mapping, file: &str) -> Result<()> {
+    self.queue.extend(take_includes(sections, file)?);
+    Ok(())
+  }
+
+}
+
+impl Iterator for Pending {
+  type Item = String;
+
+  /// The next file to include.
+  fn next(&mut self) -> Option<String> {
+    while let Some(file) = self.queue.pop_front() {
+      // Files that include the adventure back don't merge it a second time
+      if self.loaded.insert(file.clone()) {
+        return Some(file);
+      }
+    }
+    None
+  }
+}
+
+/// Removes the `include` entry of a file, returning the urls of the included
+/// files.
+pub fn take_includes(sections: &mut Mapping, file: &str) -> Result<Vec<String>> {
+  let includes: Vec<String> = match sections.remove("include") {
+    None => return Ok(Vec::new()),
+    Some(Value::String(s)) => vec![s],
+    Some(value) => serde_yaml::from_value(value)
+      .with_context(|| format!("the includes of {file} have to be a path or a list of paths"))?,
+  };
+  Ok(includes.iter().map(|i| resolve(file, i)).collect())
+}
+
+/// Resolves `path` relative to the file that includes it.
+fn resolve(file: &str, path: &str) -> String {
+  if path.starts_with('/') || path.contains("://") {
+    return path.to_string();
+  }
+  match file.rsplit_once('/') {
+    Some((dir, _)) => format!("{dir}/{path}"),
+    None => path.to_string(),
+  }
+}
+
+/// Merges the sections of an included file into the adventure. Maps are
+/// merged entry by entry and lists are appended, anything defined twice is an
+/// error.
+pub fn merge(adventure: &mut Mapping, included: Mapping, file: &str) -> Result<()> {
+  for (key, value) in included {
+    let name = key.as_str().unwrap_or("?").to_string();
+    match (adventure.get_mut(&key), value) {
+      (None, value) => {
+        adventure.insert(key, value);
+      }
+      (Some(Value::Mapping(existing)), Value::Mapping(entries)) => {
+        for (entry, value) in entries {
+          if existing.contains_key(&entry) {
+            return Err(anyhow!(
+              "{file} defines {name}.{}, which is already defined",
+              entry.as_str().unwrap_or("?")
+            ));
+          }
+          existing.insert(entry, value);
+        }
+      }
+      (Some(Value::Sequence(existing)), Value::Sequence(entries)) => existing.extend(entries),
+      _ => return Err(anyhow!("{file} defines {name}, which is already defined")),
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::*;
+
+  fn sections(raw: &str) -> Mapping {
+    serde_yaml::from_str(raw).unwrap()
+  }
+
+  #[test]
+  fn merges_files_in_the_order_they_are_listed() {
+    let files = HashMap::from([
+      ("/parts/a.yaml", "include: [c.yaml, d.yaml]\norder: [a]"),
+      ("/parts/b.yaml", "include: c.yaml\norder: [b]"),
+      ("/parts/c.yaml", "order: [c]"),
+      ("/parts/d.yaml", "include: /adventure.yaml\norder: [d]"),
+    ]);
+    let mut adventure = sections("include: [parts/a.yaml, parts/b.yaml]\norder: [main]");
+
+    // c is only merged once, and the adventure isn't merged into itself
+    let mut pending = Pending::new(&mut adventure, "/adventure.yaml").unwrap();
+    while let Some(file) = pending.next() {
+      let mut included = sections(files[file.as_str()]);
+      pending.add(&mut included, &file).unwrap();
+      merge(&mut adventure, included, &file).unwrap();
+    }
+
+    let order: Vec<String> = serde_yaml::from_value(adventure["order"].clone()).unwrap();
+    assert_eq!(order, ["main", "a", "b", "c", "d"]);
+  }
+
+  #[test]
+  fn rejects_sections_defined_twice() {
+    let mut adventure = sections("start: cave\nrooms: {cave: {}}\ntags: [a]");
+
+    let err = merge(&mut adventure, sections("rooms: {cave: {}}"), "b.yaml").unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "b.yaml defines rooms.cave, which is already defined"
+    );
+    let err = merge(&mut adventure, sections("start: hall"), "b.yaml").unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "b.yaml defines start, which is already defined"
+    );
+
+    merge(
+      &mut adventure,
+      sections("rooms: {hall: {}}\ntags: [b]"),
+      "b.yaml",
+    )
+    .unwrap();
+    assert_eq!(adventure["rooms"].as_mapping().unwrap().len(), 2);
+    assert_eq!(adventure["tags"].as_sequence().unwrap().len(), 2);
+  }
+}
@@ -0,0 +1,436 @@
+//! Converts stories compiled by Ink (the json Inky exports) into adventures.
+//! Knots and stitches become rooms, choices become actions leading to where
+//! the choice diverts to. Ink is a scripting language, so much of it has no
+//! equivalent here: variables, conditions, tunnels and the like are reported
+//! instead of converted.
+
+use std::collections::BTreeSet;
+
+use anyhow::{anyhow, Result};
+use serde_json::Value as Json;
+use serde_yaml::{Mapping, Value};
+
+use crate::{loader::strip_bom, migration::CURRENT_VERSION};
+
+/// The room choices that end the story lead to.
+const END_ROOM: &str = "END";
+
+/// Control commands that only structure the flow, and are fine to skip.
+const IGNORED_COMMANDS: &[&str] = &["ev", "/ev", "str", "/str", "nop", "pop", "done", "end"];
+
+/// Converts a compiled Ink story into the raw document of an adventure. Also
+/// returns warnings about everything that couldn't be converted.
+pub fn convert(source: &str) -> Result<(Value, Vec<String>)> {
+  let story: Json = serde_json::from_str(strip_bom(source))?;
+  if story.get("inkVersion").is_none() {
+    return Err(anyhow!("This is not a compiled Ink story"));
+  }
+  let root = story
+    .get("root")
+    .and_then(|r| r.as_array())
+    .ok_or_else(|| anyhow!("The story has no root container"))?;
+
+  let mut converter = Converter {
+    root,
+    rooms: Mapping::new(),
+    warnings: Vec::new(),
+    ends: false,
+  };
+
+  // The content before the first knot is the intro, which usually diverts
+  // to the first knot
+  let main = root
+    .first()
+    .and_then(|m| m.as_array())
+    .map(Vec::as_slice)
+    .unwrap_or_default();
+  let intro = converter.read("the intro", main);
+
+  let knots = named(root);
+  for (name, knot) in knots.iter().flat_map(|k| k.iter()) {
+    if name == "global decl" {
+      converter
+        .warnings
+        .push("variables are not supported".to_string());
+      continue;
+    }
+    let Some(knot) = knot.as_array() else {
+      continue;
+    };
+    converter.room(name, knot);
+
+    // Stitches are rooms of their own
+    for (stitch, content) in named(knot).iter().flat_map(|s| s.iter()) {
+      if let (false, Some(content)) = (is_generated(stitch), content.as_array()) {
+        converter.room(&format!("{name}.{stitch}"), content);
+      }
+    }
+  }
+
+  let start = intro
+    .diverts
+    .first()
+    .cloned()
+    .or_else(|| {
+      converter
+        .rooms
+        .keys()
+        .next()
+        .and_then(|k| k.as_str())
+        .map(String::from)
+    })
+    .ok_or_else(|| anyhow!("The story has no knots"))?;
+
+  if converter.ends {
+    let mut end = Mapping::new();
+    end.insert("description".into(), "".into());
+    end.insert("ending".into(), true.into());
+    end.insert("actions".into(), Value::Sequence(Vec::new()));
+    converter.rooms.insert(END_ROOM.into(), Value::Mapping(end));
+  }
+
+  let mut adventure = Mapping::new();
+  adventure.insert("name".into(), "Ink story".into());
+  adventure.insert("start".into(), start.into());
+  adventure.insert("intro".into(), intro.text.trim().into());
+  adventure.insert("rooms".into(), Value::Mapping(converter.rooms));
+  adventure.insert("assets".into(), serde_yaml::from_str("music: {}")?);
+
+  let mut document = Mapping::new();
+  document.insert("version".into(), CURRENT_VERSION.into());
+  document.insert("adventure".into(), Value::Mapping(adventure));
+  Ok((Value::Mapping(document), converter.warnings))
+}
+
+struct Converter<'a> {
+  root: &'a [Json],
+  rooms: Mapping,
+  warnings: Vec<String>,
+  /// Whether a choice ends the story, which needs an ending room
+  ends: bool,
+}
+
+/// What was read from a container.
+#[derive(Default)]
+struct Content<'a> {
+  text: String,
+  /// The rooms the content diverts to, in order
+  diverts: Vec<String>,
+  /// The choice points, with their label and the container they lead to
+  choices: Vec<(String, Option<&'a [Json]>)>,
+  ends: bool,
+  unsupported: BTreeSet<String>,
+}
+
+impl<'a> Converter<'a> {
+  fn room(&mut self, name: &str, container: &'a [Json]) {
+    let content = self.read(&format!("knot {name}"), container);
+
+    let mut actions = Vec::new();
+    for (label, choice) in &content.choices {
+      let Some(choice) = choice else {
+        self
+          .warnings
+          .push(format!("knot {name}: the choice {label} leads nowhere"));
+        continue;
+      };
+      let outcome = self.read(&format!("knot {name}, choice {label}"), choice);
+
+      let mut action = Mapping::new();
+      action.insert("name".into(), label.trim().into());
+      action.insert("type".into(), "custom".into());
+      action.insert("text".into(), outcome.text.trim().into());
+      match (outcome.diverts.first(), outcome.ends) {
+        (Some(target), _) => {
+          action.insert("transition".into(), target.as_str().into());
+        }
+        (None, true) => {
+          self.ends = true;
+          action.insert("transition".into(), END_ROOM.into());
+        }
+        (None, false) => {}
+      }
+      actions.push(Value::Mapping(action));
+    }
+
+    // Flow that continues without a choice is offered as a single action
+    if content.choices.is_empty() {
+      if let Some(target) = content.diverts.first() {
+        let mut action = Mapping::new();
+        action.insert("name".into(), "Continue".into());
+        action.insert("type".into(), "navigate".into());
+        action.insert("transition".into(), target.as_str().into());
+        actions.push(Value::Mapping(action));
+      }
+    }
+
+    let mut room = Mapping::new();
+    room.insert("description".into(), content.text.trim().into());
+    if actions.is_empty() {
+      room.insert("ending".into(), true.into());
+    }
+    room.insert("actions".into(), Value::Sequence(actions));
+    self.rooms.insert(name.into(), Value::Mapping(room));
+  }
+
+  /// Reads the text, diverts and choices of a container and the containers
+  /// nested in it, except for the named ones, which are read when they are
+  /// reached.
+  fn read(&mut self, location: &str, container: &'a [Json]) -> Content<'a> {
+    let mut content = Content::default();
+    let mut walker = Walker {
+      root: self.root,
+      stack: Vec::new(),
+      label: None,
+      evaluated: None,
+    };
+    walker.walk(container, &mut content);
+    if !content.unsupported.is_empty() {
+      let list: Vec<&str> = content.unsupported.iter().map(|s| s.as_str()).collect();
+      self
+        .warnings
+        .push(format!("{location}: {} are not supported", list.join(", ")));
+    }
+    content
+  }
+}
+
+struct Walker<'a> {
+  root: &'a [Json],
+  /// The containers around the current one, for relative paths
+  stack: Vec<&'a [Json]>,
+  /// Collects the text of a choice while inside a string evaluation
+  label: Option<String>,
+  /// The last evaluated string, which the next choice uses as its label
+  evaluated: Option<String>,
+}
+
+impl<'a> Walker<'a> {
+  fn walk(&mut self, container: &'a [Json], content: &mut Content<'a>) {
+    self.stack.push(container);
+    for element in container {
+      match element {
+        Json::String(s) if s.starts_with('^') => match &mut self.label {
+          Some(label) => *label += &s[1..],
+          None => content.text += &s[1..],
+        },
+        Json::String(s) if s == "\n" => content.text += "\n",
+        Json::String(s) if s == "str" => self.label = Some(String::new()),
+        Json::String(s) if s == "/str" => self.evaluated = self.label.take(),
+        Json::String(s) if s == "end" || s == "done" => content.ends = true,
+        Json::String(s) if s == "<>" => {
+          content.unsupported.insert("glue".to_string());
+        }
+        Json::String(s) if !IGNORED_COMMANDS.contains(&s.as_str()) => {
+          content.unsupported.insert(format!("the command {s}"));
+        }
+        Json::String(_) => {}
+        Json::Array(nested) => self.walk(nested, content),
+        Json::Object(object) => self.object(object, content),
+        _ => {}
+      }
+    }
+    self.stack.pop();
+  }
+
+  fn object(&mut self, object: &'a serde_json::Map<String, Json>, content: &mut Content<'a>) {
+    if let Some(path) = object.get("*").and_then(|p| p.as_str()) {
+      let flags = object
+        .get("flg")
+        .and_then(|f| f.as_u64())
+        .unwrap_or_default();
+      if flags & 1 != 0 {
+        content
+          .unsupported
+          .insert("conditional choices".to_string());
+      }
+      let choice = self
+        .resolve(path)
+        .and_then(|c| c.as_array())
+        .map(Vec::as_slice);
+      content
+        .choices
+        .push((self.evaluated.take().unwrap_or_default(), choice));
+    } else if let Some(path) = object.get("->").and_then(|p| p.as_str()) {
+      if object.contains_key("var") {
+        // Returning from a label that was output is generated by Ink
+        if path != "$r" {
+          content.unsupported.insert("variable diverts".to_string());
+        }
+      } else if object.contains_key("c") {
+        content
+          .unsupported
+          .insert("conditional diverts".to_string());
+      } else if path.starts_with('.') {
+        // Choices refer to their label to output it again
+        if let Some(nested) = self.resolve(path).and_then(|n| n.as_array()) {
+          self.walk(nested, content);
+        }
+      } else if path == "END" || path == "DONE" {
+        content.ends = true;
+      } else {
+        content.diverts.push(room_of(path));
+      }
+    } else if object.contains_key("->t->") {
+      content.unsupported.insert("tunnels".to_string());
+    } else if object.contains_key("f()") || object.contains_key("x()") {
+      content.unsupported.insert("functions".to_string());
+    } else if object.contains_key("VAR=") || object.contains_key("VAR?") {
+      content.unsupported.insert("variables".to_string());
+    } else if object.get("temp=").is_some_and(|t| t != "$r") {
+      content
+        .unsupported
+        .insert("temporary variables".to_string());
+    } else if object.contains_key("#") {
+      content.unsupported.insert("tags".to_string());
+    }
+  }
+
+  /// Follows a path, either absolute like `knot.0.c-1` or relative to the
+  /// current container like `.^.c-1`, where every further `^` goes up a
+  /// level.
+  fn resolve(&self, path: &str) -> Option<&'a Json> {
+    let (mut container, segments): (&'a [Json], Vec<&str>) = match path.strip_prefix(".^") {
+      Some(relative) => {
+        let mut segments: Vec<&str> = relative.split('.').filter(|s| !s.is_empty()).collect();
+        let ups = segments.iter().take_while(|s| **s == "^").count();
+        segments.drain(..ups);
+        let level = self.stack.len().checked_sub(1 + ups)?;
+        (self.stack[level], segments)
+      }
+      None => (self.root, path.split('.').collect()),
+    };
+
+    let mut current = None;
+    for segment in segments {
+      let next = match segment.parse::<usize>() {
+        Ok(index) => container.get(index)?,
+        Err(_) => named(container)?.get(segment)?,
+      };
+      current = Some(next);
+      container = next.as_array().map(Vec::as_slice).unwrap_or_default();
+    }
+    current
+  }
+}
+
+/// The named containers of a container, which Ink keeps in a map at its end.
+fn named(container: &[Json]) -> Option<&serde_json::Map<String, Json>> {
+  container.last().and_then(|l| l.as_object())
+}
+
+/// Containers generated for choices, gathers and labels.
+fn is_generated(name: &str) -> bool {
+  name.starts_with("c-")
+    || name.starts_with("g-")
+    || name.starts_with('$')
+    || name.starts_with('#')
+    || name == "s"
+}
+
+/// The room a divert leads to, which is the knot or stitch it names.
+fn room_of(path: &str) -> String {
+  let mut segments = path.split('.');
+  let knot = segments.next().unwrap_or_default();
+  match segments.next() {
+    Some(stitch) if stitch.parse::<usize>().is_err() && !is_generated(stitch) => {
+      format!("{knot}.{stitch}")
+    }
+    _ => knot.to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A story with two knots, compiled from
+  ///
+  /// ```ink
+  /// -> hall
+  /// === hall ===
+  /// A dusty hall.
+  /// * [Open the door] -> yard
+  /// === yard ===
+  /// Sunlight.
+  /// -> END
+  /// ```
+  const STORY: &str = r##"{"inkVersion": 21, "root": [
+    [{"->": "hall"}, ["done", {"#f": 5, "#n": "g-0"}], null],
+    "done",
+    {
+      "hall": [["^A dusty hall.", "\n", "ev", "str", "^Open the door", "/str", "/ev",
+        {"*": ".^.c-0", "flg": 20}, {"c-0": ["\n", {"->": "yard"}, {"#f": 5}]}], null],
+      "yard": ["^Sunlight.", "\n", "end", null]
+    }
+  ], "listDefs": {}}"##;
+
+  #[test]
+  fn ignores_the_byte_order_mark() {
+    let (document, warnings) = convert(&format!("\u{feff}{STORY}")).unwrap();
+    assert!(warnings.is_empty(), "{warnings:?}");
+    assert_eq!(document["adventure"]["start"], "hall");
+  }
+
+  #[test]
+  fn converts_knots_into_rooms() {
+    let (document, _) = convert(STORY).unwrap();
+    let adventure = &document["adventure"];
+    assert_eq!(adventure["start"], "hall");
+
+    let rooms = adventure["rooms"].as_mapping().unwrap();
+    let names: Vec<&str> = rooms.keys().filter_map(|k| k.as_str()).collect();
+    assert_eq!(names, ["hall", "yard"]);
+    assert_eq!(rooms["hall"]["description"], "A dusty hall.");
+    assert_eq!(rooms["yard"]["description"], "Sunlight.");
+    assert_eq!(rooms["yard"]["ending"], true);
+  }
+
+  #[test]
+  fn converts_choices_into_actions() {
+    let (document, _) = convert(STORY).unwrap();
+    let actions = &document["adventure"]["rooms"]["hall"]["actions"];
+    assert_eq!(actions.as_sequence().unwrap().len(), 1);
+    assert_eq!(actions[0]["name"], "Open the door");
+    assert_eq!(actions[0]["transition"], "yard");
+  }
+
+  #[test]
+  fn follows_diverts() {
+    // `-> cellar.stairs` from a knot without choices, and a choice that ends
+    // the story
+    let story = r##"{"inkVersion": 21, "root": [
+      [{"->": "hall"}, ["done", {"#f": 5, "#n": "g-0"}], null],
+      "done",
+      {
+        "hall": ["^A trapdoor.", "\n", {"->": "cellar.stairs"}, null],
+        "cellar": [["^Dark.", "\n", "ev", "str", "^Give up", "/str", "/ev",
+          {"*": ".^.c-0", "flg": 20}, {"c-0": ["\n", "end", {"#f": 5}]}],
+          {"stairs": ["^Steep stairs.", "\n", {"->": "cellar"}, null]}]
+      }
+    ], "listDefs": {}}"##;
+    let (document, warnings) = convert(story).unwrap();
+    assert!(warnings.is_empty(), "{warnings:?}");
+    let rooms = &document["adventure"]["rooms"];
+
+    assert_eq!(rooms["hall"]["actions"][0]["type"], "navigate");
+    assert_eq!(rooms["hall"]["actions"][0]["transition"], "cellar.stairs");
+    assert_eq!(rooms["cellar.stairs"]["actions"][0]["transition"], "cellar");
+    assert_eq!(rooms["cellar"]["actions"][0]["transition"], END_ROOM);
+    assert_eq!(rooms[END_ROOM]["ending"], true);
+  }
+
+  #[test]
+  fn reports_what_is_not_supported() {
+    let story = r##"{"inkVersion": 21, "root": [
+      [{"->": "hall"}, null],
+      "done",
+      {"hall": ["^Hello", "<>", {"->t->": "tunnel"}, "end", null]}
+    ], "listDefs": {}}"##;
+    let (_, warnings) = convert(story).unwrap();
+    assert_eq!(warnings, ["knot hall: glue, tunnels are not supported"]);
+
+    let err = convert(r#"{"root": []}"#).unwrap_err();
+    assert_eq!(err.to_string(), "This is not a compiled Ink story");
+  }
+}
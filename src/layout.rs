@@ -0,0 +1,242 @@
+//! Prepares the page the game runs in. A page only needs an element with the
+//! `container` class, everything else is created here if it is missing.
+//!
+//! The ids of the elements default to the names of their parts, e.g.
+//! `maintext`. The container changes them with `data-<part>-id` attributes,
+//! and a script can set them before the game loads, in a
+//! `window.textadventureConfig` object like `{container: "#game", maintext:
+//! "story"}`.
+//!
+//! A `<text-adventure>` element instead plays in its own shadow root, see
+//! [`mount`]. Its parts keep their default ids, as they can't clash with the
+//! page.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use wasm_bindgen::JsCast;
+use web_sys::{Document, Element, HtmlElement, ShadowRoot, UrlSearchParams};
+
+use crate::{js_to_anyhow, loader::Manifest, markdown};
+
+const DEFAULT_STYLE: &str = include_str!("default_style.css");
+const STYLE_ID: &str = "textadventure-default-style";
+const CONFIG: &str = "textadventureConfig";
+
+/// The parts of the game and their tags, in the order they are laid out.
+const PARTS: [(&str, &str); 10] = [
+  ("status", "div"),
+  ("progress", "div"),
+  ("meters", "div"),
+  ("scene", "div"),
+  ("maintext", "div"),
+  ("actions", "div"),
+  ("inventory", "details"),
+  ("volume", "div"),
+  ("narration", "div"),
+  ("reset", "a"),
+];
+
+/// Where the parts of the game are on the page.
+pub struct Layout {
+  pub container: HtmlElement,
+  ids: HashMap<&'static str, String>,
+  /// Whether the container is in a shadow root, where the page's elements
+  /// don't belong to the game
+  isolated: bool,
+}
+
+impl Layout {
+  /// The element of `part`, looked up in the container first, as the page
+  /// might use the id for something else outside of it.
+  pub fn element(&self, part: &str) -> Option<HtmlElement> {
+    let id = self.id(part);
+    let selector = format!("[id=\"{}\"]", id.replace('\\', "\\\\").replace('"', "\\\""));
+    let inside = self.container.query_selector(&selector).ok().flatten();
+    let element = inside.or_else(|| {
+      let outside = self.container.owner_document()?.get_element_by_id(id);
+      outside.filter(|_| !self.isolated)
+    })?;
+    element.dyn_into().ok()
+  }
+
+  /// Like [`Layout::element`], for the parts the game can't run without.
+  pub fn required(&self, part: &str) -> Result<HtmlElement> {
+    self
+      .element(part)
+      .ok_or_else(|| anyhow!("Missing a #{} element in the dom", self.id(part)))
+  }
+
+  pub fn id<'a>(&'a self, part: &'a str) -> &'a str {
+    self.ids.get(part).map(String::as_str).unwrap_or(part)
+  }
+}
+
+/// Creates the elements the game needs and injects the default stylesheet,
+/// unless the container has `data-default-style="off"`.
+pub fn prepare(document: &Document) -> Result<Layout> {
+  let config = web_sys::window()
+    .and_then(|w| js_sys::Reflect::get(&w, &CONFIG.into()).ok())
+    .filter(|c| c.is_object());
+  let option = |name: &str| {
+    config
+      .as_ref()
+      .and_then(|c| js_sys::Reflect::get(c, &name.into()).ok())
+      .and_then(|v| v.as_string())
+  };
+
+  let selector = option("container").unwrap_or_else(|| ".container".to_string());
+  let container = match document.query_selector(&selector).map_err(js_to_anyhow)? {
+    Some(c) => c,
+    None => {
+      let body = document
+        .body()
+        .ok_or_else(|| anyhow!("The page has no body"))?;
+      let container = document.create_element("div").map_err(js_to_anyhow)?;
+      body.append_child(&container).map_err(js_to_anyhow)?;
+      container
+    }
+  };
+  // The default style and the themes rely on the class
+  container
+    .class_list()
+    .add_1("container")
+    .map_err(js_to_anyhow)?;
+  let container: HtmlElement = container
+    .dyn_into()
+    .map_err(|_| anyhow!("The container is not an html element"))?;
+
+  if container.get_attribute("data-default-style").as_deref() != Some("off") {
+    inject_style(document)?;
+  }
+
+  let ids = PARTS
+    .iter()
+    .filter_map(|(part, _)| {
+      let id = option(part).or_else(|| container.get_attribute(&format!("data-{part}-id")))?;
+      Some((*part, id))
+    })
+    .collect();
+  let layout = Layout {
+    container,
+    ids,
+    isolated: false,
+  };
+  create_parts(document, &layout)?;
+  Ok(layout)
+}
+
+/// Lays the game out in `root`, the shadow root of a `<text-adventure>`
+/// element. The default stylesheet goes into the root as well, unless the
+/// element has `data-default-style="off"`.
+pub fn mount(root: &ShadowRoot) -> Result<Layout> {
+  let document = root
+    .owner_document()
+    .ok_or_else(|| anyhow!("The shadow root has no document"))?;
+  let host = root.host();
+
+  if host.get_attribute("data-default-style").as_deref() != Some("off")
+    && root.get_element_by_id(STYLE_ID).is_none()
+  {
+    let style = create_style(&document)?;
+    root.append_child(&style).map_err(js_to_anyhow)?;
+  }
+
+  let container: HtmlElement = document
+    .create_element("div")
+    .map_err(js_to_anyhow)?
+    .dyn_into()
+    .map_err(|_| anyhow!("The container is not an html element"))?;
+  container.set_class_name("container");
+  root.append_child(&container).map_err(js_to_anyhow)?;
+
+  let layout = Layout {
+    container,
+    ids: HashMap::new(),
+    isolated: true,
+  };
+  create_parts(&document, &layout)?;
+  Ok(layout)
+}
+
+/// Adds the parts that are missing to the container.
+fn create_parts(document: &Document, layout: &Layout) -> Result<()> {
+  for (part, tag) in PARTS {
+    if layout.element(part).is_some() {
+      continue;
+    }
+
+    let element = document.create_element(tag).map_err(js_to_anyhow)?;
+    element.set_id(layout.id(part));
+    if tag != "a" {
+      element.set_class_name(part);
+    } else {
+      element.set_attribute("href", "#").map_err(js_to_anyhow)?;
+      element
+        .set_attribute("title", "Reset the game and start from the beginning.")
+        .map_err(js_to_anyhow)?;
+      element.set_text_content(Some("reset"));
+    }
+    layout
+      .container
+      .append_child(&element)
+      .map_err(js_to_anyhow)?;
+  }
+  Ok(())
+}
+
+/// Adds the default stylesheet before any other stylesheet, so the page's own
+/// styles take precedence.
+fn inject_style(document: &Document) -> Result<()> {
+  if document.get_element_by_id(STYLE_ID).is_some() {
+    return Ok(());
+  }
+
+  let head = document
+    .head()
+    .ok_or_else(|| anyhow!("The page has no head"))?;
+
+  let style = create_style(document)?;
+  head
+    .insert_before(&style, head.first_child().as_ref())
+    .map_err(js_to_anyhow)?;
+  Ok(())
+}
+
+fn create_style(document: &Document) -> Result<Element> {
+  let style = document.create_element("style").map_err(js_to_anyhow)?;
+  style.set_id(STYLE_ID);
+  style.set_text_content(Some(DEFAULT_STYLE));
+  Ok(style)
+}
+
+/// Lists the adventures of the manifest. Each links back to the page, with
+/// the adventure's url in the query.
+pub fn show_chooser(document: &Document, layout: &Layout, manifest: &Manifest) -> Result<()> {
+  let text = layout.required("maintext")?;
+  let actions = layout.required("actions")?;
+
+  // The manifest may come from anywhere, so none of it is trusted as html
+  let mut html = format!("<h2>{}</h2>", markdown::escape(&manifest.title));
+  for adventure in &manifest.adventures {
+    html += &format!(
+      "<h3>{}</h3><p>{}</p>",
+      markdown::escape(&adventure.title),
+      markdown::escape(&adventure.description)
+    );
+
+    let query = UrlSearchParams::new().map_err(js_to_anyhow)?;
+    query.set("adventure", &adventure.url);
+
+    let link = document.create_element("a").map_err(js_to_anyhow)?;
+    link
+      .set_attribute("href", &format!("?{}", String::from(query.to_string())))
+      .map_err(js_to_anyhow)?;
+    link.set_class_name("location_change");
+    link.set_text_content(Some(&adventure.title));
+    actions.append_child(&link).map_err(js_to_anyhow)?;
+  }
+  text.set_inner_html(&html);
+
+  Ok(())
+}
@@ -0,0 +1,41 @@
+pub mod adventure;
+pub mod analysis;
+pub mod audio;
+pub mod builder;
+pub mod bundle;
+pub mod component;
+pub mod game;
+pub mod generator;
+pub mod graph;
+pub mod includes;
+pub mod ink;
+pub mod layout;
+pub mod loader;
+pub mod localization;
+pub mod markdown;
+pub mod midi;
+pub mod migration;
+pub mod narration;
+pub mod notation;
+pub mod remote;
+pub mod rng;
+pub mod save;
+pub mod sheet;
+pub mod storage;
+pub mod twee;
+pub mod validation;
+
+/// Turns the errors of the browser's apis into errors that can be reported.
+pub(crate) fn js_to_anyhow(value: wasm_bindgen::JsValue) -> anyhow::Error {
+  anyhow::anyhow!("{value:?}")
+}
+
+/// Waits without blocking the page.
+pub async fn sleep(milliseconds: i32) {
+  let promise = js_sys::Promise::new(&mut |resolve, _| {
+    if let Some(window) = web_sys::window() {
+      let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, milliseconds);
+    }
+  });
+  let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
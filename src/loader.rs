@@ -0,0 +1,419 @@
+//! Loads adventures at runtime, so they can be swapped without recompiling
+//! the game.
+
+use std::{cell::Cell, collections::HashMap};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_yaml::Value;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Blob, BlobPropertyBag, RequestCache, RequestInit, Response, Url};
+
+use crate::{
+  adventure::{Adventure, Document},
+  bundle::{self, Bundle},
+  includes, ink, js_to_anyhow, localization, markdown, migration, twee,
+};
+
+/// Where the adventure is loaded from, unless the page says otherwise.
+pub const DEFAULT_URL: &str = "./adventure.yaml";
+/// Lists the adventures to choose from, if the site has more than one.
+pub const MANIFEST_URL: &str = "./adventures.yaml";
+
+thread_local! {
+  /// Whether the adventure is watched for changes, see [`watch`]
+  static WATCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Deserialize)]
+pub struct Manifest {
+  #[serde(default = "Manifest::default_title")]
+  pub title: String,
+  pub adventures: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+  fn default_title() -> String {
+    "Choose an adventure".to_string()
+  }
+}
+
+#[derive(Deserialize)]
+pub struct ManifestEntry {
+  pub title: String,
+  #[serde(default)]
+  pub description: String,
+  pub url: String,
+}
+
+async fn fetch(url: &str) -> Result<Response> {
+  let window = web_sys::window().ok_or_else(|| anyhow!("unable to get the window"))?;
+
+  // While watching, ask the server whether the file changed, so edits show up
+  let mut init = RequestInit::new();
+  if WATCHING.with(Cell::get) {
+    init.cache(RequestCache::NoCache);
+  }
+
+  let response: Response = JsFuture::from(window.fetch_with_str_and_init(url, &init))
+    .await
+    .map_err(js_to_anyhow)
+    .with_context(|| format!("unable to fetch {url}"))?
+    .dyn_into()
+    .map_err(|_| anyhow!("fetch didn't return a response"))?;
+  if !response.ok() {
+    return Err(anyhow!("fetching {url} failed with {}", response.status()));
+  }
+  Ok(response)
+}
+
+/// Makes every download after it bypass the browser's cache, so the
+/// adventure can be reloaded whenever its files change.
+pub fn watch() {
+  WATCHING.with(|w| w.set(true));
+}
+
+/// Downloads a text file.
+pub async fn fetch_text(url: &str) -> Result<String> {
+  let response = fetch(url).await?;
+  JsFuture::from(response.text().map_err(js_to_anyhow)?)
+    .await
+    .map_err(js_to_anyhow)?
+    .as_string()
+    .ok_or_else(|| anyhow!("{url} is not a text file"))
+}
+
+/// Downloads a binary file.
+pub async fn fetch_bytes(url: &str) -> Result<Vec<u8>> {
+  let response = fetch(url).await?;
+  let buffer = JsFuture::from(response.array_buffer().map_err(js_to_anyhow)?)
+    .await
+    .map_err(js_to_anyhow)?;
+  Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
+
+/// Where the files of an adventure come from.
+enum Files<'a> {
+  Web,
+  Bundle(&'a Bundle),
+}
+
+impl Files<'_> {
+  async fn text(&self, path: &str) -> Result<String> {
+    match self {
+      Self::Web => fetch_text(path).await,
+      Self::Bundle(bundle) => bundle.text(path),
+    }
+  }
+}
+
+/// The file formats adventures can be written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+  Yaml,
+  Json,
+  Toml,
+  /// Twine stories, see [`crate::twee`]
+  Twee,
+  /// Stories compiled by Ink, see [`crate::ink`]
+  Ink,
+}
+
+impl Format {
+  /// Picks the format by the extension of `url`, or by looking at the
+  /// content if the extension is unknown. Anything that isn't recognizably
+  /// JSON, TOML or Twee is read as YAML.
+  pub fn detect(url: &str, raw: &str) -> Self {
+    // Compiled Ink stories are json files, too
+    let content = strip_bom(raw).trim_start();
+    if content.starts_with('{') && content.contains("\"inkVersion\"") {
+      return Self::Ink;
+    }
+
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    match path
+      .rsplit_once('.')
+      .map(|(_, ext)| ext.to_ascii_lowercase())
+    {
+      Some(ext) if ext == "json" => return Self::Json,
+      Some(ext) if ext == "toml" => return Self::Toml,
+      Some(ext) if ext == "yaml" || ext == "yml" => return Self::Yaml,
+      Some(ext) if ext == "twee" || ext == "tw" => return Self::Twee,
+      _ => {}
+    }
+
+    if content.starts_with('{') {
+      Self::Json
+    } else if content.starts_with("[adventure") {
+      Self::Toml
+    } else if content.starts_with("::") {
+      Self::Twee
+    } else {
+      Self::Yaml
+    }
+  }
+}
+
+/// Parses an adventure, picking the translations for `language`. Includes
+/// are only resolved by [`load`], as they need to be downloaded.
+pub fn parse(raw: &str, format: Format, language: Option<&str>) -> Result<Document> {
+  finish(read(raw, format)?, language)
+}
+
+/// Removes the byte order mark some editors start files with, which none of
+/// the parsers expect.
+pub fn strip_bom(raw: &str) -> &str {
+  raw.strip_prefix('\u{feff}').unwrap_or(raw)
+}
+
+/// Reads a file into a yaml value, which can represent the other formats.
+pub fn read(raw: &str, format: Format) -> Result<Value> {
+  let raw = strip_bom(raw);
+  Ok(match format {
+    Format::Yaml => serde_yaml::from_str(raw)?,
+    Format::Json => serde_yaml::to_value(serde_json::from_str::<serde_json::Value>(raw)?)?,
+    Format::Toml => serde_yaml::to_value(toml::from_str::<toml::Value>(raw)?)?,
+    Format::Twee | Format::Ink => {
+      let (document, warnings) = match format {
+        Format::Twee => twee::convert(raw)?,
+        _ => ink::convert(raw)?,
+      };
+      for warning in warnings {
+        log::warn!("{warning}");
+      }
+      document
+    }
+  })
+}
+
+/// Turns the merged document into an adventure.
+pub fn finish(mut document: Value, language: Option<&str>) -> Result<Document> {
+  for warning in migration::migrate(&mut document)? {
+    log::warn!("{warning}");
+  }
+  let storage_name = localization::default_name(&document);
+  localization::localize(&mut document, language).context("unable to translate the adventure")?;
+  let mut document: Document = serde_yaml::from_value(document)?;
+  document.adventure.storage_name = storage_name;
+  markdown::render_adventure(&mut document.adventure);
+  Ok(document)
+}
+
+/// Downloads and parses the adventure at `url`, along with every file it
+/// includes.
+pub async fn load(url: &str, language: Option<&str>) -> Result<Document> {
+  Ok(load_with_sources(url, language).await?.0)
+}
+
+/// Like [`load`], but also returns the content of every downloaded file, so
+/// changes to any of them can be detected.
+pub async fn load_with_sources(
+  url: &str,
+  language: Option<&str>,
+) -> Result<(Document, Vec<String>)> {
+  if !Bundle::is_bundle(url) {
+    let (mut document, sources) = assemble(url, &Files::Web, language).await?;
+    load_midi(&mut document.adventure).await?;
+    return Ok((document, sources));
+  }
+
+  let bundle =
+    Bundle::unpack(&fetch_bytes(url).await?).with_context(|| format!("unable to unpack {url}"))?;
+  let (mut document, sources) = assemble(
+    &bundle.manifest.adventure,
+    &Files::Bundle(&bundle),
+    language,
+  )
+  .await
+  .with_context(|| format!("unable to load the adventure from {url}"))?;
+  for (path, content) in bundle.files() {
+    document.adventure.files.insert(
+      path.to_string(),
+      object_url(content, bundle::mime_type(path))?,
+    );
+  }
+  load_midi(&mut document.adventure).await?;
+  Ok((document, sources))
+}
+
+/// Downloads the MIDI files of the songs, from the bundle if they are in it.
+async fn load_midi(adventure: &mut Adventure) -> Result<()> {
+  for (name, song) in adventure.assets.music.iter_mut() {
+    let Some(path) = &song.midi else {
+      continue;
+    };
+    let url = adventure.files.get(path).unwrap_or(path);
+    let bytes = fetch_bytes(url)
+      .await
+      .with_context(|| format!("unable to load the MIDI file of {name}"))?;
+    song.midi_data = Some(bytes);
+  }
+  Ok(())
+}
+
+/// Reads the adventure at `url` and merges in every file it includes.
+async fn assemble(
+  url: &str,
+  files: &Files<'_>,
+  language: Option<&str>,
+) -> Result<(Document, Vec<String>)> {
+  let raw = files.text(url).await?;
+  let format = Format::detect(url, &raw);
+  let mut document =
+    read(&raw, format).with_context(|| format!("unable to parse {url} as {format:?}"))?;
+
+  let adventure = document
+    .get_mut("adventure")
+    .and_then(|a| a.as_mapping_mut())
+    .ok_or_else(|| anyhow!("{url} has no adventure section"))?;
+
+  // Included files can include further files, relative to themselves
+  let mut sources = vec![raw];
+  let mut pending = includes::Pending::new(adventure, url)?;
+  while let Some(file) = pending.next() {
+    let raw = files.text(&file).await?;
+    let format = Format::detect(&file, &raw);
+    let mut included =
+      match read(&raw, format).with_context(|| format!("unable to parse {file} as {format:?}"))? {
+        Value::Mapping(m) => m,
+        Value::Null => continue,
+        _ => return Err(anyhow!("{file} has to contain sections of the adventure")),
+      };
+    pending.add(&mut included, &file)?;
+    includes::merge(adventure, included, &file)?;
+    sources.push(raw);
+  }
+
+  let document = finish(document, language).with_context(|| format!("unable to parse {url}"))?;
+  Ok((document, sources))
+}
+
+/// Downloads the manifest, if the site has one.
+pub async fn load_manifest() -> Option<Manifest> {
+  let raw = match fetch_text(MANIFEST_URL).await {
+    Ok(r) => r,
+    Err(err) => {
+      log::debug!("No adventure manifest: {err:#}");
+      return None;
+    }
+  };
+
+  match serde_yaml::from_str::<Manifest>(&raw) {
+    Ok(m) if !m.adventures.is_empty() => Some(m),
+    Ok(_) => None,
+    Err(err) => {
+      log::error!("Unable to parse the adventure manifest: {err:#}");
+      None
+    }
+  }
+}
+
+/// Makes a file of a bundle loadable by the page, e.g. as the source of an
+/// image.
+pub(crate) fn object_url(content: &[u8], mime_type: &str) -> Result<String> {
+  let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(content));
+  let mut options = BlobPropertyBag::new();
+  options.type_(mime_type);
+  let blob =
+    Blob::new_with_u8_array_sequence_and_options(&parts, &options).map_err(js_to_anyhow)?;
+  Url::create_object_url_with_blob(&blob).map_err(js_to_anyhow)
+}
+
+/// Frees the urls of the files of a bundle, once nothing uses the adventure
+/// they were loaded for anymore.
+pub fn revoke_object_urls(files: &HashMap<String, String>) {
+  for url in files.values() {
+    let _ = Url::revoke_object_url(url);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const ADVENTURE: &str = "
+adventure:
+  name: The Cave
+  start: cave
+  intro: Welcome
+  rooms:
+    cave:
+      description: A cave
+      actions: []
+  assets:
+    music: {}
+";
+
+  #[test]
+  fn detects_the_format_by_extension() {
+    assert_eq!(Format::detect("cave.JSON", ""), Format::Json);
+    assert_eq!(Format::detect("cave.toml?v=2", ""), Format::Toml);
+    assert_eq!(
+      Format::detect("https://example.com/cave.yml#start", ""),
+      Format::Yaml
+    );
+    assert_eq!(Format::detect("cave.tw", ""), Format::Twee);
+    // Compiled Ink stories are json, too
+    assert_eq!(
+      Format::detect("cave.json", "{\"inkVersion\": 21}"),
+      Format::Ink
+    );
+  }
+
+  #[test]
+  fn detects_the_format_by_content() {
+    assert_eq!(
+      Format::detect("cave", "  {\"adventure\": {}}"),
+      Format::Json
+    );
+    assert_eq!(
+      Format::detect("cave", "[adventure]\nname = 'x'"),
+      Format::Toml
+    );
+    assert_eq!(Format::detect("cave", ":: Start\nHello"), Format::Twee);
+    // Anything else is read as yaml, and fails to parse if it isn't
+    assert_eq!(Format::detect("cave.txt", "<html>"), Format::Yaml);
+    assert!(parse("<html>", Format::Yaml, None).is_err());
+  }
+
+  #[test]
+  fn reads_every_format_into_the_same_adventure() {
+    let json = r#"{"adventure": {"name": "The Cave", "start": "cave", "intro": "Welcome",
+      "rooms": {"cave": {"description": "A cave", "actions": []}}, "assets": {"music": {}}}}"#;
+    let toml = "
+[adventure]
+name = 'The Cave'
+start = 'cave'
+intro = 'Welcome'
+assets = { music = {} }
+
+[adventure.rooms.cave]
+description = 'A cave'
+actions = []
+";
+    for (raw, format) in [
+      (ADVENTURE, Format::Yaml),
+      (json, Format::Json),
+      (toml, Format::Toml),
+    ] {
+      let document = parse(raw, format, None).unwrap();
+      assert_eq!(document.adventure.name, "The Cave", "{format:?}");
+      assert_eq!(document.adventure.rooms["cave"].description, "A cave");
+    }
+  }
+
+  #[test]
+  fn ignores_the_byte_order_mark() {
+    let raw = format!("\u{feff}{ADVENTURE}");
+    let document = parse(&raw, Format::detect("cave.yaml", &raw), None).unwrap();
+    assert_eq!(document.adventure.name, "The Cave");
+
+    let json = "\u{feff}{\"adventure\": {}}";
+    assert_eq!(Format::detect("cave", json), Format::Json);
+    assert_eq!(
+      Format::detect("cave", "\u{feff}:: Start\nHello"),
+      Format::Twee
+    );
+  }
+}
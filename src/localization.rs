@@ -0,0 +1,189 @@
+//! Translates adventures while they are loaded. Every text in the adventure
+//! can be a map from language code to translation, which is replaced with the
+//! translation for the chosen language before the adventure is parsed.
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_yaml::Value;
+
+pub(crate) const LANGUAGE_KEY: &str = "textadventure_language";
+
+/// Replaces every translated text in the raw adventure document with the
+/// translation for `language`, falling back to the adventure's default
+/// language. Unknown languages are ignored.
+pub fn localize(document: &mut Value, language: Option<&str>) -> Result<()> {
+  let adventure = match document
+    .get_mut("adventure")
+    .and_then(|a| a.as_mapping_mut())
+  {
+    Some(a) => a,
+    None => return Ok(()),
+  };
+
+  // The language section itself is keyed by language code, so it has to be
+  // kept out of the translation
+  let mut raw_languages = match adventure.remove("languages") {
+    Some(l) => l,
+    None => return Ok(()),
+  };
+  let LanguageCodes { available, default } = serde_yaml::from_value(raw_languages.clone())?;
+  if !default.is_empty() && !available.contains_key(&default) {
+    return Err(anyhow!("The default language {default} is not available"));
+  }
+
+  let language = language
+    .filter(|l| available.contains_key(*l))
+    .unwrap_or(&default);
+  let translator = Translator {
+    codes: available.keys().map(|k| k.as_str()).collect(),
+    language,
+    default: &default,
+  };
+
+  if let Some(languages) = raw_languages.as_mapping_mut() {
+    for (key, value) in languages.iter_mut() {
+      if key.as_str() != Some("available") {
+        translator.translate(value);
+      }
+    }
+  }
+  translator.translate(document);
+
+  if let Some(adventure) = document
+    .get_mut("adventure")
+    .and_then(|a| a.as_mapping_mut())
+  {
+    adventure.insert("languages".into(), raw_languages);
+  }
+  Ok(())
+}
+
+/// The name of the adventure in its default language, read before the
+/// document is translated.
+pub fn default_name(document: &Value) -> Option<String> {
+  let adventure = document.get("adventure")?;
+  match adventure.get("name")? {
+    Value::String(name) => Some(name.clone()),
+    Value::Mapping(names) => {
+      let default = adventure
+        .get("languages")
+        .and_then(|l| l.get("default"))
+        .and_then(|d| names.get(d));
+      default
+        .or_else(|| names.values().next())
+        .and_then(|n| n.as_str())
+        .map(str::to_string)
+    }
+    _ => None,
+  }
+}
+
+/// The parts of [`crate::adventure::Languages`] needed for translating, which
+/// can be read before the rest of the section is translated.
+#[derive(Deserialize)]
+struct LanguageCodes {
+  #[serde(default)]
+  default: String,
+  #[serde(default)]
+  available: BTreeMap<String, String>,
+}
+
+struct Translator<'a> {
+  codes: Vec<&'a str>,
+  language: &'a str,
+  default: &'a str,
+}
+
+impl Translator<'_> {
+  /// A map is a translated text if all of its keys are language codes and
+  /// all of its values are texts. Other maps, like rooms that happen to be
+  /// named after a language, are kept.
+  fn is_translation(&self, value: &Value) -> bool {
+    match value.as_mapping() {
+      Some(map) => {
+        !map.is_empty()
+          && map
+            .iter()
+            .all(|(k, v)| k.as_str().is_some_and(|k| self.codes.contains(&k)) && v.is_string())
+      }
+      None => false,
+    }
+  }
+
+  fn translate(&self, value: &mut Value) {
+    if self.is_translation(value) {
+      let map = value.as_mapping().expect("translations are maps");
+      let translation = map
+        .get(self.language)
+        .or_else(|| map.get(self.default))
+        .or_else(|| map.values().next())
+        .cloned()
+        .unwrap_or_default();
+      *value = translation;
+    }
+
+    match value {
+      Value::Mapping(map) => map.values_mut().for_each(|v| self.translate(v)),
+      Value::Sequence(seq) => seq.iter_mut().for_each(|v| self.translate(v)),
+      Value::Tagged(tagged) => self.translate(&mut tagged.value),
+      _ => {}
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn localized(raw: &str, language: Option<&str>) -> Value {
+    let mut document: Value = serde_yaml::from_str(raw).unwrap();
+    localize(&mut document, language).unwrap();
+    document
+  }
+
+  const ADVENTURE: &str = "
+adventure:
+  languages:
+    default: en
+    available: {en: English, de: Deutsch, fr: Français}
+  intro: {en: Welcome, de: Willkommen}
+  blurb: {fr: Bienvenue}
+  rooms:
+    en:
+      description: {en: The entrance, de: Der Eingang}
+    de:
+      description: The exit
+";
+
+  #[test]
+  fn falls_back_to_the_default_language() {
+    let german = localized(ADVENTURE, Some("de"));
+    assert_eq!(german["adventure"]["intro"], "Willkommen");
+
+    // Unknown languages and missing translations use the default language,
+    // and the first translation if even that is missing
+    let unknown = localized(ADVENTURE, Some("it"));
+    assert_eq!(unknown["adventure"]["intro"], "Welcome");
+    let french = localized(ADVENTURE, Some("fr"));
+    assert_eq!(french["adventure"]["intro"], "Welcome");
+    assert_eq!(french["adventure"]["blurb"], "Bienvenue");
+    assert_eq!(
+      localized(ADVENTURE, None)["adventure"]["blurb"],
+      "Bienvenue"
+    );
+  }
+
+  #[test]
+  fn keeps_maps_that_are_no_texts() {
+    let german = localized(ADVENTURE, Some("de"));
+    let rooms = &german["adventure"]["rooms"];
+    assert_eq!(rooms["en"]["description"], "Der Eingang");
+    assert_eq!(rooms["de"]["description"], "The exit");
+    assert_eq!(
+      german["adventure"]["languages"]["available"]["fr"],
+      "Français"
+    );
+  }
+}
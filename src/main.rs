@@ -1,34 +1,250 @@
-use game::Game;
+use std::collections::HashMap;
 
-pub mod adventure;
-pub mod audio;
-pub mod game;
+use textadventure::{
+  adventure::Adventure,
+  component,
+  game::Game,
+  layout::{self, Layout},
+  loader, sleep, storage,
+};
+use web_sys::{HtmlElement, ShadowRoot};
 
 fn main() {
-    console_log::init().expect("unable to initalize the logging");
-    console_error_panic_hook::set_once();
-
-    // Load the adventure
-    let raw_adventure = include_str!("../adventure.yaml");
-
-    // Parse the yaml
-    let adventure: adventure::Document = match serde_yaml::from_str(raw_adventure) {
-        Ok(a) => a,
-        Err(err) => {
-            log::error!("Unable to parse the adventure: {err:#}");
-            return;
-        }
-    };
+  console_log::init().expect("unable to initalize the logging");
+  console_error_panic_hook::set_once();
+
+  // Pages can embed any number of <text-adventure src="..."> elements
+  // instead of providing a container
+  component::define(|element, root| wasm_bindgen_futures::spawn_local(run_element(element, root)));
+  let document = web_sys::window()
+    .and_then(|w| w.document())
+    .expect("unable to get the document");
+  if document
+    .query_selector(component::TAG)
+    .ok()
+    .flatten()
+    .is_none()
+  {
+    wasm_bindgen_futures::spawn_local(run());
+  }
+}
+
+/// Plays the adventure at the element's `src` in its shadow root.
+async fn run_element(element: HtmlElement, root: ShadowRoot) {
+  let layout = match layout::mount(&root) {
+    Ok(l) => l,
+    Err(err) => {
+      log::error!(
+        "Unable to prepare the <{}> element: {err:#}",
+        component::TAG
+      );
+      return;
+    }
+  };
+  let url = element
+    .get_attribute("src")
+    .unwrap_or_else(|| loader::DEFAULT_URL.to_string());
+  let language = storage::stored_language();
+  let Some((adventure, _)) = load(&layout, &url, language.as_deref()).await else {
+    return;
+  };
+
+  storage::init().await;
+  let mut game = match Game::new(adventure, layout) {
+    Ok(g) => g,
+    Err(err) => {
+      log::error!("Unable to parse the adventure: {err:#}");
+      return;
+    }
+  };
+  game.sync().await;
+  if let Err(err) = game.start() {
+    log::error!("Unable to start the game: {err:#}");
+  }
+
+  // Each element has its own api, as there might be several games
+  if let Err(err) = game.expose_api(&element) {
+    log::error!("Unable to expose the JS api: {err:#}");
+  }
+}
 
-    let mut game = match Game::try_from(adventure.adventure) {
-        Ok(g) => g,
-        Err(err) => {
-            log::error!("Unable to parse the adventure: {err:#}");
-            return;
+async fn run() {
+  let document = web_sys::window()
+    .and_then(|w| w.document())
+    .expect("unable to get the document");
+  let layout = match layout::prepare(&document) {
+    Ok(l) => l,
+    Err(err) => {
+      log::error!("Unable to prepare the page: {err:#}");
+      return;
+    }
+  };
+
+  // Show something while the adventure is downloaded
+  let text = layout.element("maintext");
+  if let Some(text) = &text {
+    text.set_text_content(Some("Loading..."));
+  }
+
+  // Sites with several adventures let the player choose, the choice ends up
+  // in ?adventure=<url>
+  let url = match query_parameter("adventure") {
+    Some(url) => url,
+    None => match loader::load_manifest().await {
+      Some(manifest) => {
+        if let Err(err) = layout::show_chooser(&document, &layout, &manifest) {
+          log::error!("Unable to show the adventures: {err:#}");
         }
+        return;
+      }
+      None => loader::DEFAULT_URL.to_string(),
+    },
+  };
+  // Authors can edit the adventure while playing with ?dev
+  let dev = query_parameter("dev").is_some();
+  if dev {
+    loader::watch();
+  }
+  let language = storage::stored_language();
+  let Some((mut adventure, sources)) = load(&layout, &url, language.as_deref()).await else {
+    return;
+  };
+  let files = adventure.files.clone();
+
+  // Players can bring their own sync server with ?sync=<url>. Anyone can
+  // link to the page with one, so the player has to agree to send the saves
+  if let Some(endpoint) = query_parameter("sync") {
+    if adventure.sync.as_ref() == Some(&endpoint) || confirm_sync(&adventure, &endpoint) {
+      adventure.sync = Some(endpoint);
+    }
+  }
+
+  // The saves have to be read before the game can continue
+  storage::init().await;
+
+  let mut game = match Game::new(adventure, layout) {
+    Ok(g) => g,
+    Err(err) => {
+      log::error!("Unable to parse the adventure: {err:#}");
+      return;
+    }
+  };
+
+  // Authors can inspect a song's notation with ?sheet=<song>
+  if let Some(song) = query_parameter("sheet") {
+    if let Err(err) = game.show_sheet(&song) {
+      log::error!("Unable to show the sheet music: {err:#}");
+    }
+    return;
+  }
+
+  game.sync().await;
+
+  if let Err(err) = game.start() {
+    log::error!("Unable to start the game: {err:#}");
+  }
+
+  // Links to a shared game end in #save=<state>
+  if let Err(err) = game.offer_shared_save() {
+    log::error!("{err:#}");
+  }
+
+  let Some(window) = web_sys::window() else {
+    return;
+  };
+  if let Err(err) = game.expose_api(&window) {
+    log::error!("Unable to expose the JS api: {err:#}");
+  }
+
+  if dev {
+    watch(&mut game, &url, language.as_deref(), sources, files).await;
+  }
+}
+
+/// Downloads the adventure at `url`, and shows why if that fails.
+async fn load(
+  layout: &Layout,
+  url: &str,
+  language: Option<&str>,
+) -> Option<(Adventure, Vec<String>)> {
+  let text = layout.element("maintext");
+  if let Some(text) = &text {
+    text.set_text_content(Some("Loading..."));
+  }
+
+  match loader::load_with_sources(url, language).await {
+    Ok((mut adventure, sources)) => {
+      assign_id(&mut adventure.adventure, url);
+      Some((adventure.adventure, sources))
+    }
+    Err(err) => {
+      log::error!("Unable to load the adventure: {err:#}");
+      if let Some(text) = &text {
+        text.set_text_content(Some(&format!("Unable to load the adventure: {err:#}")));
+      }
+      None
+    }
+  }
+}
+
+fn confirm_sync(adventure: &Adventure, endpoint: &str) -> bool {
+  let question = adventure.strings.confirm_sync.replace("{url}", endpoint);
+  web_sys::window()
+    .and_then(|w| w.confirm_with_message(&question).ok())
+    .unwrap_or(false)
+}
+
+/// Keeps the saves of every adventure apart.
+fn assign_id(adventure: &mut Adventure, url: &str) {
+  if adventure.id.is_none() && url != loader::DEFAULT_URL {
+    adventure.id = Some(url.to_string());
+  }
+}
+
+/// Downloads the adventure every few seconds, and reloads it whenever one of
+/// its files changed. `files` are those of the adventure that is played.
+async fn watch(
+  game: &mut Game,
+  url: &str,
+  language: Option<&str>,
+  mut sources: Vec<String>,
+  mut files: HashMap<String, String>,
+) {
+  log::info!("Watching {url} for changes");
+  loop {
+    sleep(2000).await;
+
+    let (mut adventure, new_sources) = match loader::load_with_sources(url, language).await {
+      Ok(a) => a,
+      Err(err) => {
+        log::error!("Unable to reload the adventure: {err:#}");
+        continue;
+      }
     };
+    let new_files = adventure.adventure.files.clone();
+    if new_sources == sources {
+      loader::revoke_object_urls(&new_files);
+      continue;
+    }
+    sources = new_sources;
 
-    if let Err(err) = game.start() {
-      log::error!("Unable to start the game: {err:#}");
+    assign_id(&mut adventure.adventure, url);
+    match game.reload(adventure.adventure) {
+      Ok(()) => {
+        log::info!("Reloaded the adventure");
+        loader::revoke_object_urls(&std::mem::replace(&mut files, new_files));
+      }
+      Err(err) => {
+        log::error!("Unable to reload the adventure: {err:#}");
+        loader::revoke_object_urls(&new_files);
+      }
     }
+  }
+}
+
+fn query_parameter(name: &str) -> Option<String> {
+  let search = web_sys::window()?.location().search().ok()?;
+  web_sys::UrlSearchParams::new_with_str(&search)
+    .ok()?
+    .get(name)
 }
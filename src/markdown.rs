@@ -0,0 +1,268 @@
+//! Renders the Markdown authors write in their texts into the html shown by
+//! the game. Raw html in the texts is sanitized, so adventures can't inject
+//! scripts into the page, unless the adventure sets `raw_html`.
+//!
+//! Room descriptions can link to the room's actions, like
+//! `[lantern](take_lantern)`, which turns the word into a keyword that runs
+//! the action.
+
+use ammonia::Builder;
+use pulldown_cmark::{html, Event, Parser, Tag};
+
+use crate::adventure::{Action, ActionKind, Adventure, Outcome, Room};
+
+/// The only tags that are left in the texts. Everything else is removed, but
+/// the text inside it is kept.
+const TAGS: &[&str] = &[
+  "a",
+  "b",
+  "blockquote",
+  "br",
+  "code",
+  "del",
+  "em",
+  "h1",
+  "h2",
+  "h3",
+  "h4",
+  "h5",
+  "h6",
+  "hr",
+  "i",
+  "img",
+  "li",
+  "ol",
+  "p",
+  "pre",
+  "s",
+  "small",
+  "span",
+  "strong",
+  "sub",
+  "sup",
+  "table",
+  "tbody",
+  "td",
+  "th",
+  "thead",
+  "tr",
+  "u",
+  "ul",
+];
+
+/// Renders every text of the adventure that is shown as html, and the art of
+/// rooms and actions.
+pub fn render_adventure(adventure: &mut Adventure) {
+  let raw = adventure.raw_html;
+  let render = |text: &mut String| *text = render_text(text, raw);
+
+  render(&mut adventure.intro);
+  render(&mut adventure.blurb);
+  for room in adventure.rooms.values_mut() {
+    let keywords = action_ids(room);
+    let keywords: Vec<&str> = keywords.iter().map(String::as_str).collect();
+    room.description = render_keywords(&room.description, &keywords, raw);
+    room.art = render_art(&room.art);
+    render_actions(&mut room.actions, raw);
+    render_outcome(&mut room.timeout, raw);
+    for hook in room.on_enter.iter_mut().chain(&mut room.on_exit) {
+      render(&mut hook.text);
+    }
+    for target in &mut room.use_targets {
+      for outcome in target.outcomes.values_mut() {
+        render(&mut outcome.text);
+      }
+    }
+
+    for variant in [&mut room.day, &mut room.night].into_iter().flatten() {
+      if let Some(description) = &mut variant.description {
+        *description = render_keywords(description, &keywords, raw);
+      }
+      if let Some(actions) = &mut variant.actions {
+        render_actions(actions, raw);
+      }
+    }
+  }
+
+  for item in adventure.items.values_mut() {
+    for target in &mut item.use_targets {
+      for outcome in target.outcomes.values_mut() {
+        render(&mut outcome.text);
+      }
+    }
+  }
+  for code in adventure.codes.values_mut() {
+    render(&mut code.text);
+  }
+  for meter in adventure.meters.values_mut() {
+    for threshold in &mut meter.thresholds {
+      render(&mut threshold.text);
+    }
+  }
+  for line in &mut adventure.ambience.lines {
+    render(&mut line.text);
+  }
+  for entry in adventure.codex.entries.values_mut() {
+    render(&mut entry.text);
+  }
+  for enemy in adventure.bestiary.enemies.values_mut() {
+    render(&mut enemy.description);
+  }
+}
+
+/// The ids of all actions of `room`, including those of the time of day,
+/// which descriptions can link to.
+pub fn action_ids(room: &Room) -> Vec<String> {
+  let variants = [&room.day, &room.night].into_iter().flatten();
+  let variant_actions = variants.flat_map(|v| v.actions.iter().flatten());
+  room
+    .actions
+    .iter()
+    .chain(variant_actions)
+    .map(|action| action.id().to_string())
+    .collect()
+}
+
+fn render_actions(actions: &mut [Action], raw: bool) {
+  for action in actions {
+    action.text = render_text(&action.text, raw);
+    action.art = render_art(&action.art);
+    render_outcome(&mut action.timeout, raw);
+    render_actions(&mut action.choices, raw);
+    match &mut action.kind {
+      ActionKind::Dialogue { lines, .. } => {
+        for line in lines {
+          line.text = render_text(&line.text, raw);
+        }
+      }
+      ActionKind::Check {
+        success, failure, ..
+      } => {
+        render_outcome(success, raw);
+        render_outcome(failure, raw);
+      }
+      _ => {}
+    }
+  }
+}
+
+fn render_outcome(outcome: &mut Outcome, raw: bool) {
+  outcome.text = render_text(&outcome.text, raw);
+}
+
+/// Renders a single text. Line breaks are kept, as authors write texts with
+/// line breaks in mind. A text of a single paragraph isn't wrapped in one, so
+/// it can be combined with the texts around it.
+pub fn render(text: &str) -> String {
+  render_text(text, false)
+}
+
+fn render_text(text: &str, raw: bool) -> String {
+  render_keywords(text, &[], raw)
+}
+
+/// Like [`render_text`], but links to one of `actions` become keywords. The
+/// game runs the action when one is clicked, if it is offered.
+fn render_keywords(text: &str, actions: &[&str], raw: bool) -> String {
+  if text.is_empty() {
+    return String::new();
+  }
+
+  let parser = Parser::new(text).map(|event| match event {
+    Event::SoftBreak => Event::HardBreak,
+    Event::Start(Tag::Link { dest_url, .. }) if actions.contains(&dest_url.as_ref()) => {
+      Event::InlineHtml(
+        format!(
+          "<a class=\"keyword\" data-action=\"{}\" href=\"#\">",
+          escape(&dest_url)
+        )
+        .into(),
+      )
+    }
+    e => e,
+  });
+  let mut html = String::new();
+  html::push_html(&mut html, parser);
+
+  if !raw {
+    html = sanitize(&html);
+  }
+  let trimmed = html.trim_end();
+  match trimmed
+    .strip_prefix("<p>")
+    .and_then(|t| t.strip_suffix("</p>"))
+  {
+    Some(inner) if !inner.contains("<p>") => inner.to_string(),
+    _ => trimmed.to_string(),
+  }
+}
+
+/// Removes everything from `html` that isn't on the allowlist. The classes
+/// and speakers the game adds itself are kept, so texts of saves can be
+/// sanitized as well. Links and images only keep web and mail urls, and
+/// links don't give the pages they open access to the game.
+pub fn sanitize(html: &str) -> String {
+  Builder::empty()
+    .add_tags(TAGS)
+    .add_tag_attributes("a", &["class", "data-action", "href"])
+    .add_tag_attributes("img", &["src", "alt", "title"])
+    .add_tag_attributes("span", &["class", "data-speaker"])
+    .add_tag_attributes("pre", &["class"])
+    .add_tag_attributes("table", &["class"])
+    .url_schemes(["http", "https", "mailto"].into())
+    .link_rel(Some("noopener noreferrer"))
+    .clean(html)
+    .to_string()
+}
+
+/// Escapes `text` so it is shown as written, e.g. for names that are put
+/// into html.
+pub fn escape(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+/// Renders ASCII art into a preformatted block. Nothing in it is read as
+/// Markdown or html, so every character shows as written.
+pub fn render_art(art: &str) -> String {
+  let art = art.trim_end_matches('\n');
+  if art.is_empty() {
+    return String::new();
+  }
+  format!("<pre class=\"art\">{}</pre>", escape(art))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn keeps_keywords_focusable() {
+    let html = render_keywords("An old [lantern](take_lantern).", &["take_lantern"], false);
+    assert_eq!(
+      html,
+      "An old <a class=\"keyword\" data-action=\"take_lantern\" href=\"#\" \
+       rel=\"noopener noreferrer\">lantern</a>."
+    );
+  }
+
+  #[test]
+  fn sanitizes_links_and_images() {
+    assert_eq!(
+      render("[map](https://example.com/map) [x](javascript:alert(1))"),
+      "<a href=\"https://example.com/map\" rel=\"noopener noreferrer\">map</a> \
+       <a rel=\"noopener noreferrer\">x</a>"
+    );
+    assert_eq!(
+      render("![a map](map.png \"The map\")"),
+      "<img src=\"map.png\" alt=\"a map\" title=\"The map\">"
+    );
+    assert_eq!(
+      render("<img src=\"x.png\" onerror=\"alert(1)\">"),
+      "<img src=\"x.png\">"
+    );
+  }
+}
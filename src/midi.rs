@@ -0,0 +1,441 @@
+//! Reads Standard MIDI Files, so songs can be composed in other programs.
+//!
+//! Only the notes and tempo changes are used. Each track with notes becomes a
+//! voice, and notes starting together are played as a chord that lasts until
+//! the next note starts, as the voices of a song can't hold a note while
+//! another one starts.
+
+use anyhow::{anyhow, Context, Result};
+
+/// The tempo of a file until it sets one, in microseconds per quarter note
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// A track of a MIDI file, as the notes, chords and rests of a voice.
+pub struct Track {
+  pub events: Vec<Event>,
+}
+
+pub struct Event {
+  /// The MIDI key of every note of a chord, where 60 is middle c, and none
+  /// for rests
+  pub keys: Vec<u8>,
+  /// The length as a fraction of a whole note
+  pub value: f64,
+  /// Seconds, at the tempo of the file
+  pub duration: f64,
+}
+
+/// A note between its note on and note off events, in ticks.
+struct Sounding {
+  key: u8,
+  start: u64,
+  end: u64,
+}
+
+/// Parses a MIDI file into its tracks, leaving out tracks without notes.
+pub fn parse(bytes: &[u8]) -> Result<Vec<Track>> {
+  let mut reader = Reader { bytes, position: 0 };
+
+  if reader.take(4)? != b"MThd" {
+    return Err(anyhow!("not a MIDI file, it doesn't start with MThd"));
+  }
+  let header_length = reader.u32()? as usize;
+  let header = reader.take(header_length)?;
+  if header.len() < 6 {
+    return Err(anyhow!("the header of the MIDI file is too short"));
+  }
+  let track_count = u16::from_be_bytes([header[2], header[3]]);
+  let division = u16::from_be_bytes([header[4], header[5]]);
+  if division & 0x8000 != 0 || division == 0 {
+    return Err(anyhow!(
+      "only MIDI files that count in ticks per quarter note are supported"
+    ));
+  }
+  let ticks_per_whole = 4.0 * division as f64;
+
+  let mut tempos = Vec::new();
+  let mut notes = Vec::new();
+  while notes.len() < track_count as usize && reader.position < bytes.len() {
+    let kind = reader.take(4)?;
+    let length = reader.u32()? as usize;
+    let chunk = reader.take(length)?;
+    // Other chunks may be added by later versions of the format
+    if kind != b"MTrk" {
+      continue;
+    }
+    let track = read_track(chunk, &mut tempos)
+      .with_context(|| format!("unable to read track {}", notes.len() + 1))?;
+    notes.push(track);
+  }
+
+  tempos.sort_by_key(|(tick, _)| *tick);
+  let clock = Clock {
+    tempos,
+    division: division as f64,
+  };
+
+  Ok(
+    notes
+      .into_iter()
+      .filter(|n| !n.is_empty())
+      .map(|n| Track {
+        events: events(n, &clock, ticks_per_whole),
+      })
+      .collect(),
+  )
+}
+
+/// Reads the notes of a track, and adds its tempo changes to `tempos`.
+fn read_track(bytes: &[u8], tempos: &mut Vec<(u64, u32)>) -> Result<Vec<Sounding>> {
+  let mut reader = Reader { bytes, position: 0 };
+  let mut tick = 0;
+  let mut status = 0;
+  let mut notes = Vec::new();
+  // Notes that were started and not stopped yet, by channel and key
+  let mut open: Vec<(u8, u8, u64)> = Vec::new();
+
+  while reader.position < bytes.len() {
+    tick += reader.variable()? as u64;
+
+    // Running status: the status byte may be left out if it didn't change
+    let mut first = reader.byte()?;
+    if first & 0x80 != 0 {
+      status = first;
+      if status < 0xf0 {
+        first = reader.byte()?;
+      }
+    } else if status == 0 {
+      return Err(anyhow!("found data without a status byte"));
+    }
+
+    match status {
+      0xff => {
+        let kind = reader.byte()?;
+        let length = reader.variable()? as usize;
+        let data = reader.take(length)?;
+        match kind {
+          0x51 if data.len() == 3 => {
+            tempos.push((tick, u32::from_be_bytes([0, data[0], data[1], data[2]])))
+          }
+          0x2f => break,
+          _ => {}
+        }
+        // Meta events don't set the running status
+        status = 0;
+      }
+      0xf0 | 0xf7 => {
+        let length = reader.variable()? as usize;
+        reader.take(length)?;
+        status = 0;
+      }
+      _ => {
+        let channel = status & 0x0f;
+        match status & 0xf0 {
+          0x80 | 0x90 => {
+            let key = first;
+            let velocity = reader.byte()?;
+            // A note on with a velocity of 0 stops the note, too
+            if status & 0xf0 == 0x90 && velocity > 0 {
+              open.push((channel, key, tick));
+            } else if let Some(index) = open.iter().position(|(c, k, _)| *c == channel && *k == key)
+            {
+              let (_, _, start) = open.remove(index);
+              notes.push(Sounding {
+                key,
+                start,
+                end: tick,
+              });
+            }
+          }
+          0xa0 | 0xb0 | 0xe0 => {
+            reader.byte()?;
+          }
+          0xc0 | 0xd0 => {}
+          _ => return Err(anyhow!("unknown status byte {status:#x}")),
+        }
+      }
+    }
+  }
+
+  // Notes that are never stopped last until the end of the track
+  for (_, key, start) in open {
+    notes.push(Sounding {
+      key,
+      start,
+      end: tick,
+    });
+  }
+  notes.sort_by_key(|n| (n.start, n.key));
+  Ok(notes)
+}
+
+/// Turns the notes of a track into a sequence of chords and rests.
+fn events(notes: Vec<Sounding>, clock: &Clock, ticks_per_whole: f64) -> Vec<Event> {
+  let mut starts: Vec<u64> = notes.iter().map(|n| n.start).collect();
+  starts.dedup();
+
+  let mut events = Vec::new();
+  let mut push = |keys: Vec<u8>, from: u64, to: u64| {
+    if to > from {
+      events.push(Event {
+        keys,
+        value: (to - from) as f64 / ticks_per_whole,
+        duration: clock.seconds(to) - clock.seconds(from),
+      });
+    }
+  };
+
+  let mut position = 0;
+  for (index, start) in starts.iter().enumerate() {
+    push(Vec::new(), position, *start);
+
+    let chord: Vec<&Sounding> = notes.iter().filter(|n| n.start == *start).collect();
+    let mut end = chord.iter().map(|n| n.end).max().unwrap_or(*start);
+    if let Some(next) = starts.get(index + 1) {
+      end = end.min(*next);
+    }
+    let mut keys: Vec<u8> = chord.iter().map(|n| n.key).collect();
+    keys.dedup();
+    push(keys, *start, end);
+    position = end.max(*start);
+  }
+  events
+}
+
+/// Converts ticks into seconds, following the tempo changes.
+struct Clock {
+  /// The tick of every tempo change and the new tempo, in order
+  tempos: Vec<(u64, u32)>,
+  division: f64,
+}
+
+impl Clock {
+  fn seconds(&self, tick: u64) -> f64 {
+    let mut seconds = 0.0;
+    let mut last_tick = 0;
+    let mut tempo = DEFAULT_TEMPO;
+    for (change, new_tempo) in &self.tempos {
+      if *change >= tick {
+        break;
+      }
+      seconds += (change - last_tick) as f64 * tempo as f64 / self.division / 1e6;
+      last_tick = *change;
+      tempo = *new_tempo;
+    }
+    seconds + (tick - last_tick) as f64 * tempo as f64 / self.division / 1e6
+  }
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  position: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+    let end = self
+      .position
+      .checked_add(length)
+      .ok_or_else(|| anyhow!("the MIDI file ended unexpectedly"))?;
+    let taken = self
+      .bytes
+      .get(self.position..end)
+      .ok_or_else(|| anyhow!("the MIDI file ended unexpectedly"))?;
+    self.position = end;
+    Ok(taken)
+  }
+
+  fn byte(&mut self) -> Result<u8> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u32(&mut self) -> Result<u32> {
+    let bytes = self.take(4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+  }
+
+  /// Reads a variable length quantity, which stores seven bits in each byte
+  /// and sets the highest bit on all but the last.
+  fn variable(&mut self) -> Result<u32> {
+    let mut value = 0;
+    for _ in 0..4 {
+      let byte = self.byte()?;
+      value = (value << 7) | (byte & 0x7f) as u32;
+      if byte & 0x80 == 0 {
+        return Ok(value);
+      }
+    }
+    Err(anyhow!(
+      "a variable length number is longer than four bytes"
+    ))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A file with 96 ticks per quarter note and a chunk for every track.
+  fn file(tracks: &[&[u8]]) -> Vec<u8> {
+    let mut bytes = b"MThd".to_vec();
+    bytes.extend(6u32.to_be_bytes());
+    bytes.extend([0, 1]);
+    bytes.extend((tracks.len() as u16).to_be_bytes());
+    bytes.extend(96u16.to_be_bytes());
+    for track in tracks {
+      bytes.extend(b"MTrk");
+      bytes.extend((track.len() as u32).to_be_bytes());
+      bytes.extend(*track);
+    }
+    bytes
+  }
+
+  fn keys(track: &Track) -> Vec<Vec<u8>> {
+    track.events.iter().map(|e| e.keys.clone()).collect()
+  }
+
+  fn values(track: &Track) -> Vec<f64> {
+    track.events.iter().map(|e| e.value).collect()
+  }
+
+  #[test]
+  fn reads_notes_and_rests() {
+    let track = [
+      0x00, 0x90, 60, 100, // c on
+      0x60, 0x80, 60, 0, // c off after a quarter
+      0x30, 0x90, 62, 100, // d on after an eighth
+      0x81, 0x40, 0x80, 62, 0, // d off after a half
+      0x00, 0xff, 0x2f, 0x00,
+    ];
+    let tracks = parse(&file(&[&track])).unwrap();
+    assert_eq!(tracks.len(), 1);
+    assert_eq!(keys(&tracks[0]), [vec![60], vec![], vec![62]]);
+    assert_eq!(values(&tracks[0]), [0.25, 0.125, 0.5]);
+    // 120 beats per minute until the file sets a tempo
+    assert_eq!(tracks[0].events[0].duration, 0.5);
+  }
+
+  #[test]
+  fn follows_running_status() {
+    let track = [
+      0x00, 0x90, 60, 100, // c on
+      0x60, 60, 0, // c off, as a note on with the status left out
+      0x00, 64, 100, // e on
+      0x60, 64, 0, // e off
+    ];
+    let tracks = parse(&file(&[&track])).unwrap();
+    assert_eq!(keys(&tracks[0]), [vec![60], vec![64]]);
+    assert_eq!(values(&tracks[0]), [0.25, 0.25]);
+
+    // Data before any status byte
+    assert!(parse(&file(&[&[0x00, 60, 100]])).is_err());
+  }
+
+  #[test]
+  fn stops_notes_on_with_velocity_zero() {
+    let track = [
+      0x00, 0x90, 60, 100, // c on
+      0x81, 0x40, 0x90, 60, 0, // c off after a half
+      0x00, 0x90, 60, 100, // c on again
+      0x60, 0x90, 60, 0, // c off after a quarter
+    ];
+    let tracks = parse(&file(&[&track])).unwrap();
+    assert_eq!(keys(&tracks[0]), [vec![60], vec![60]]);
+    assert_eq!(values(&tracks[0]), [0.5, 0.25]);
+  }
+
+  #[test]
+  fn follows_tempo_changes() {
+    let clock = Clock {
+      tempos: vec![(96, 1_000_000), (192, 250_000)],
+      division: 96.0,
+    };
+    assert_eq!(clock.seconds(0), 0.0);
+    assert_eq!(clock.seconds(96), 0.5);
+    assert_eq!(clock.seconds(144), 1.0);
+    assert_eq!(clock.seconds(192), 1.5);
+    assert_eq!(clock.seconds(288), 1.75);
+
+    // Tempo changes apply to every track, wherever they are set
+    let tempo = [0x60, 0xff, 0x51, 0x03, 0x0f, 0x42, 0x40];
+    let notes = [
+      0x00, 0x90, 60, 100, // c on
+      0x60, 0x90, 62, 100, // d on at the tempo change
+      0x00, 0x80, 60, 0, // c off
+      0x60, 0x80, 62, 0,
+    ];
+    let tracks = parse(&file(&[&tempo, &notes])).unwrap();
+    assert_eq!(tracks.len(), 1);
+    let durations: Vec<f64> = tracks[0].events.iter().map(|e| e.duration).collect();
+    assert_eq!(durations, [0.5, 1.0]);
+  }
+
+  #[test]
+  fn splits_overlapping_notes_into_chords() {
+    let notes = vec![
+      Sounding {
+        key: 60,
+        start: 0,
+        end: 192,
+      },
+      Sounding {
+        key: 64,
+        start: 0,
+        end: 96,
+      },
+      Sounding {
+        key: 67,
+        start: 96,
+        end: 192,
+      },
+      Sounding {
+        key: 72,
+        start: 288,
+        end: 384,
+      },
+    ];
+    let clock = Clock {
+      tempos: Vec::new(),
+      division: 96.0,
+    };
+    let events = events(notes, &clock, 384.0);
+    let keys: Vec<Vec<u8>> = events.iter().map(|e| e.keys.clone()).collect();
+    let values: Vec<f64> = events.iter().map(|e| e.value).collect();
+    // The chord lasts until the next note starts, and its longest note until
+    // the rest
+    assert_eq!(keys, [vec![60, 64], vec![67], vec![], vec![72]]);
+    assert_eq!(values, [0.25, 0.25, 0.25, 0.25]);
+  }
+
+  #[test]
+  fn rejects_truncated_files() {
+    let track = [0x00, 0x90, 60, 100, 0x60, 0x80, 60, 0];
+    let bytes = file(&[&track]);
+    for length in [3, 10, 16, 20, bytes.len() - 1] {
+      let err = parse(&bytes[..length]).err().unwrap();
+      assert!(
+        format!("{err:#}").contains("ended unexpectedly"),
+        "{length}: {err:#}"
+      );
+    }
+
+    // A chunk claiming to be longer than the file
+    let mut bytes = bytes;
+    bytes[18..22].copy_from_slice(&u32::MAX.to_be_bytes());
+    assert!(parse(&bytes).is_err());
+    let mut reader = Reader {
+      bytes: &[0; 4],
+      position: 2,
+    };
+    assert!(reader.take(usize::MAX).is_err());
+  }
+
+  #[test]
+  fn rejects_other_files() {
+    assert!(parse(b"RIFF\0\0\0\0").is_err());
+    let mut bytes = file(&[]);
+    // Timecode based divisions
+    bytes[12] = 0xe7;
+    assert!(parse(&bytes).is_err());
+  }
+}
@@ -0,0 +1,146 @@
+//! Upgrades adventures written for older versions of the game. Every version
+//! that changed the structure of the file has a migration, which is applied to
+//! the raw document before it is parsed. Files without a version are version
+//! 1.
+
+use anyhow::{anyhow, Result};
+use serde_yaml::{Mapping, Value};
+
+/// The version of the file structure the game reads.
+pub const CURRENT_VERSION: u64 = 2;
+
+/// Migrations from the version at the same index plus one to the next.
+const MIGRATIONS: &[fn(&mut Mapping) -> Vec<String>] = &[type_actions];
+
+/// Upgrades the document to the current version. Returns a warning for every
+/// deprecated part of the file, which authors should update.
+pub fn migrate(document: &mut Value) -> Result<Vec<String>> {
+  let version = match document.get("version") {
+    None => 1,
+    Some(v) => v
+      .as_u64()
+      .filter(|v| *v >= 1)
+      .ok_or_else(|| anyhow!("The version has to be a positive number, not {v:?}"))?,
+  };
+  if version > CURRENT_VERSION {
+    return Err(anyhow!(
+      "The adventure was written for version {version}, but this game only supports up to \
+       version {CURRENT_VERSION}"
+    ));
+  }
+
+  let mut warnings = Vec::new();
+  if let Some(adventure) = document
+    .get_mut("adventure")
+    .and_then(|a| a.as_mapping_mut())
+  {
+    for migration in &MIGRATIONS[(version - 1) as usize..] {
+      warnings.extend(migration(adventure));
+    }
+  }
+  if version < CURRENT_VERSION {
+    warnings.push(format!(
+      "The adventure was upgraded from version {version}, set `version: {CURRENT_VERSION}` \
+       once the warnings are fixed"
+    ));
+  }
+
+  if let Some(document) = document.as_mapping_mut() {
+    document.insert("version".into(), CURRENT_VERSION.into());
+  }
+  Ok(warnings)
+}
+
+/// Version 2 introduced action types. Actions of version 1 only gave items
+/// and moved the player, which are now `gain_items` and `navigate` actions.
+fn type_actions(adventure: &mut Mapping) -> Vec<String> {
+  let mut warnings = Vec::new();
+  let Some(rooms) = adventure.get_mut("rooms").and_then(|r| r.as_mapping_mut()) else {
+    return warnings;
+  };
+
+  for (room_id, room) in rooms.iter_mut() {
+    let room_id = room_id.as_str().unwrap_or_default().to_string();
+    let mut lists: Vec<&mut Value> = Vec::new();
+    if let Some(room) = room.as_mapping_mut() {
+      for (key, value) in room.iter_mut() {
+        match key.as_str() {
+          Some("actions") => lists.push(value),
+          Some("day") | Some("night") => lists.extend(value.get_mut("actions")),
+          _ => {}
+        }
+      }
+    }
+
+    for action in lists
+      .into_iter()
+      .filter_map(|l| l.as_sequence_mut())
+      .flatten()
+      .filter_map(|a| a.as_mapping_mut())
+    {
+      if action.contains_key("type") {
+        continue;
+      }
+      let action_type = match (
+        action.contains_key("yields"),
+        action.contains_key("transition"),
+      ) {
+        (true, false) => "gain_items",
+        (false, true) => "navigate",
+        _ => "custom",
+      };
+      action.insert("type".into(), action_type.into());
+
+      let name = action.get("name").and_then(|n| n.as_str()).unwrap_or("?");
+      warnings.push(format!(
+        "room {room_id}, action {name}: actions had no type in version 1, this one became a \
+         {action_type} action"
+      ));
+    }
+  }
+  warnings
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn types_the_actions_of_version_1() {
+    let mut document: Value = serde_yaml::from_str(
+      "
+adventure:
+  rooms:
+    cave:
+      actions:
+        - {name: Take, yields: [lantern]}
+        - {name: Leave, transition: outside}
+        - {name: Wait, type: custom}
+",
+    )
+    .unwrap();
+    let warnings = migrate(&mut document).unwrap();
+    assert_eq!(document["version"], CURRENT_VERSION);
+
+    let actions = &document["adventure"]["rooms"]["cave"]["actions"];
+    assert_eq!(actions[0]["type"], "gain_items");
+    assert_eq!(actions[1]["type"], "navigate");
+    assert_eq!(actions[2]["type"], "custom");
+    // Two actions and the version itself
+    assert_eq!(warnings.len(), 3, "{warnings:?}");
+  }
+
+  #[test]
+  fn rejects_newer_versions() {
+    let mut document: Value = serde_yaml::from_str(&format!(
+      "version: {}\nadventure: {{}}",
+      CURRENT_VERSION + 1
+    ))
+    .unwrap();
+    let err = migrate(&mut document).unwrap_err();
+    assert!(err.to_string().contains("only supports up to"), "{err}");
+
+    let mut document: Value = serde_yaml::from_str("version: 0\nadventure: {}").unwrap();
+    assert!(migrate(&mut document).is_err());
+  }
+}
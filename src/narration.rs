@@ -0,0 +1,194 @@
+//! Reads the texts of the game aloud with the browser's speech synthesis. The
+//! adventure picks the voices, the player turns the narration on and sets its
+//! speed.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsCast;
+use web_sys::{Element, Node, SpeechSynthesis, SpeechSynthesisUtterance, SpeechSynthesisVoice};
+
+use crate::{
+  adventure::{Narration, SpeakingVoice},
+  js_to_anyhow,
+};
+
+/// Like the volume, the narration is the player's choice rather than part of
+/// a game.
+pub(crate) const NARRATION_KEY: &str = "textadventure_narration";
+
+/// What the player picked in the narration controls.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Listening {
+  pub enabled: bool,
+  /// Multiplies the rates of all voices
+  pub rate: f32,
+  /// The name of the voice that replaces the adventure's narrator
+  pub voice: Option<String>,
+}
+
+impl Default for Listening {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      rate: 1.0,
+      voice: None,
+    }
+  }
+}
+
+impl Listening {
+  /// The choices stored by [`Listening::store`], or the narration turned off.
+  pub fn stored() -> Self {
+    web_sys::window()
+      .and_then(|w| w.local_storage().ok().flatten())
+      .and_then(|s| s.get_item(NARRATION_KEY).ok().flatten())
+      .and_then(|raw| serde_json::from_str(&raw).ok())
+      .unwrap_or_default()
+  }
+
+  pub fn store(&self) -> Result<()> {
+    web_sys::window()
+      .ok_or_else(|| anyhow!("unable to get the window"))?
+      .local_storage()
+      .map_err(js_to_anyhow)?
+      .ok_or_else(|| anyhow!("local storage is not available"))?
+      .set_item(NARRATION_KEY, &serde_json::to_string(self)?)
+      .map_err(js_to_anyhow)
+  }
+}
+
+pub struct Narrator {
+  synthesis: SpeechSynthesis,
+  narration: Narration,
+  /// The language of the adventure, so the browser picks a fitting voice
+  language: Option<String>,
+  pub listening: Listening,
+}
+
+impl Narrator {
+  /// Fails if the browser can't speak.
+  pub fn new(narration: Narration, language: Option<String>) -> Result<Self> {
+    let synthesis = web_sys::window()
+      .ok_or_else(|| anyhow!("unable to get the window"))?
+      .speech_synthesis()
+      .map_err(js_to_anyhow)?;
+    // Browsers may keep reading the previous page
+    synthesis.cancel();
+    Ok(Self {
+      synthesis,
+      narration,
+      language,
+      listening: Listening::stored(),
+    })
+  }
+
+  /// The voices the browser offers. They might only be known once the
+  /// synthesis fired `voiceschanged`.
+  pub fn voices(&self) -> Vec<SpeechSynthesisVoice> {
+    self
+      .synthesis
+      .get_voices()
+      .iter()
+      .filter_map(|v| v.dyn_into().ok())
+      .collect()
+  }
+
+  pub fn synthesis(&self) -> &SpeechSynthesis {
+    &self.synthesis
+  }
+
+  /// Uses the voices of the reloaded adventure.
+  pub fn set_narration(&mut self, narration: Narration) {
+    self.narration = narration;
+  }
+
+  /// Stops reading, and forgets everything that was still to be read.
+  pub fn stop(&self) {
+    self.synthesis.cancel();
+  }
+
+  /// Reads the text below `node` instead of whatever was being read. Elements
+  /// with a `data-speaker` attribute are read by that speaker's voice, and
+  /// ASCII art isn't read at all.
+  pub fn read(&self, node: &Node) -> Result<()> {
+    self.stop();
+    if !self.listening.enabled {
+      return Ok(());
+    }
+
+    let mut parts = Vec::new();
+    spoken_parts(node, None, &mut parts);
+    for (speaker, text) in parts {
+      let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+      if !text.is_empty() {
+        self.speak(&text, speaker.as_deref())?;
+      }
+    }
+    Ok(())
+  }
+
+  fn speak(&self, text: &str, speaker: Option<&str>) -> Result<()> {
+    let utterance = SpeechSynthesisUtterance::new_with_text(text).map_err(js_to_anyhow)?;
+    if let Some(language) = &self.language {
+      utterance.set_lang(language);
+    }
+
+    // The player's voice only replaces the narrator
+    let own = speaker.and_then(|s| self.narration.speakers.get(s));
+    let voice: &SpeakingVoice = own.unwrap_or(&self.narration.narrator);
+    let name = match own {
+      Some(own) => own.voice.as_deref(),
+      None => self.listening.voice.as_deref().or(voice.voice.as_deref()),
+    };
+    if let Some(found) = name.and_then(|n| self.find_voice(n)) {
+      utterance.set_voice(Some(&found));
+    }
+    utterance.set_rate((voice.rate * self.listening.rate).clamp(0.1, 10.0));
+    utterance.set_pitch(voice.pitch.clamp(0.0, 2.0));
+
+    self.synthesis.speak(&utterance);
+    Ok(())
+  }
+
+  /// Finds a voice by its name, or else the first one for the language.
+  fn find_voice(&self, name: &str) -> Option<SpeechSynthesisVoice> {
+    let voices = self.voices();
+    let by_name = voices.iter().find(|v| v.name() == name).cloned();
+    by_name.or_else(|| {
+      voices
+        .into_iter()
+        .find(|v| v.lang().to_lowercase().starts_with(&name.to_lowercase()))
+    })
+  }
+}
+
+/// Collects the texts below `node` in the order they are read, in one part per
+/// change of speaker.
+fn spoken_parts(node: &Node, speaker: Option<&str>, parts: &mut Vec<(Option<String>, String)>) {
+  let children = node.child_nodes();
+  for index in 0..children.length() {
+    let Some(child) = children.get(index) else {
+      continue;
+    };
+    if child.node_type() == Node::TEXT_NODE {
+      let text = child.text_content().unwrap_or_default();
+      match parts.last_mut() {
+        Some((last, part)) if last.as_deref() == speaker => {
+          part.push(' ');
+          part.push_str(&text);
+        }
+        _ => parts.push((speaker.map(str::to_string), text)),
+      }
+      continue;
+    }
+
+    let Some(element) = child.dyn_ref::<Element>() else {
+      continue;
+    };
+    if element.class_list().contains("art") {
+      continue;
+    }
+    let own = element.get_attribute("data-speaker");
+    spoken_parts(&child, own.as_deref().or(speaker), parts);
+  }
+}
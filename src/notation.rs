@@ -0,0 +1,527 @@
+//! Reads the notes of a voice, which are written in a subset of LilyPond's
+//! relative mode:
+//!
+//! - A note is a letter from `c` to `b`. Every `is` after it raises it by a
+//!   semitone and every `es` lowers it, e.g. `fis`, `bes` or `cisis`. `as`
+//!   and `es` are short for `aes` and `ees`. `r` is a rest.
+//! - Each note is placed in the octave closest to the note before it, so the
+//!   two are at most a fourth apart. Only the letters count for this, not the
+//!   accidentals. Every `'` after the note moves it an octave up, and every
+//!   `,` an octave down. The first note is placed relative to the f in the
+//!   voice's starting octave, so it lands in that octave. Voices start in
+//!   octave 4, where a voice starting with `c` starts on middle c.
+//! - The duration follows the octave marks: `4` is a quarter note, `8.` a
+//!   dotted eighth and `2..` a double dotted half. Notes without a duration
+//!   are as long as the one before them, and the first defaults to a quarter.
+//!
+//! `c4~ c4` ties two notes into one, `c4( d e f)` slurs the notes between the
+//! parentheses, and `<c e g>2` plays a chord. In a chord, each note is placed
+//! relative to the one before it, and the note after the chord relative to its
+//! first note. `\tuplet 3/2 { c8 d e }` plays three notes in the time of two
+//! and `\tempo 2` plays the notes after it twice as fast.
+//!
+//! Dynamics follow the note they start on, from `\ppp` to `\fff`, e.g.
+//! `c4\p d e\f`. Notes before the first one are played `\mf`, as loud as the
+//! voice's volume. `\<` starts a crescendo and `\>` a decrescendo on the note
+//! before it, which swell or fade until the next dynamic, or until `\!` or the
+//! end of the voice, where they reach the next louder or softer dynamic.
+
+use std::{iter::Peekable, str::Chars};
+
+use anyhow::{anyhow, Context, Result};
+
+/// A note, chord or rest, with everything that surrounded it applied.
+pub struct Event {
+  /// Every pitch of a chord, a single one for notes and none for rests
+  pub pitches: Vec<Pitch>,
+  /// The note value as written, as a fraction of a whole note
+  pub value: f64,
+  /// Seconds, after tuplets and tempo changes
+  pub duration: f64,
+  /// Flows into the next note without being released, as it is tied or
+  /// slurred to it
+  pub legato: bool,
+  /// The loudness set by the dynamics at the start, where 1 is `\mf`
+  pub volume: f32,
+  /// The loudness a crescendo or decrescendo reaches by the end
+  pub ramp_to: Option<f32>,
+}
+
+pub struct Pitch {
+  /// The letters of the note, e.g. `cis`
+  pub name: String,
+  /// One up for every `'` and one down for every `,`
+  pub octave: i32,
+}
+
+/// The semitones of every letter above c
+const SEMITONES: [i64; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// The loudness of every dynamic, from soft to loud
+const DYNAMICS: [(&str, f32); 8] = [
+  ("ppp", 0.15),
+  ("pp", 0.25),
+  ("p", 0.4),
+  ("mp", 0.7),
+  ("mf", 1.0),
+  ("f", 1.4),
+  ("ff", 1.8),
+  ("fff", 2.2),
+];
+
+/// Places the pitches of a voice in their octave, as described in the
+/// [module documentation](self).
+#[derive(Clone, Copy)]
+pub struct Relative {
+  /// The diatonic step of the previous note, counted from middle c
+  step: i64,
+}
+
+impl Default for Relative {
+  fn default() -> Self {
+    Self::new(4)
+  }
+}
+
+impl Relative {
+  /// Places the first pitch in `octave`, where middle c starts octave 4.
+  pub fn new(octave: i64) -> Self {
+    // The f of the octave, as c to b are all within a fourth of it
+    Self {
+      step: (octave - 4) * 7 + 3,
+    }
+  }
+
+  /// Returns the semitones of the pitch relative to middle c, and places the
+  /// next pitch relative to it.
+  pub fn resolve(&mut self, pitch: &Pitch) -> Result<i64> {
+    let (letter, accidental) =
+      spell(&pitch.name).with_context(|| format!("unknown note {}", pitch.name))?;
+
+    let mut step = self.step.div_euclid(7) * 7 + letter;
+    if step - self.step > 3 {
+      step -= 7;
+    } else if self.step - step > 3 {
+      step += 7;
+    }
+    step += 7 * pitch.octave as i64;
+
+    self.step = step;
+    Ok(step.div_euclid(7) * 12 + SEMITONES[letter as usize] + accidental)
+  }
+
+  /// Resolves every pitch of a note or chord, each relative to the one before
+  /// it, and places the next pitch relative to the first.
+  pub fn resolve_all(&mut self, pitches: &[Pitch]) -> Result<Vec<i64>> {
+    let mut resolved = Vec::new();
+    let mut first = None;
+    for pitch in pitches {
+      resolved.push(self.resolve(pitch)?);
+      first = first.or(Some(*self));
+    }
+    if let Some(first) = first {
+      *self = first;
+    }
+    Ok(resolved)
+  }
+}
+
+/// Splits the name of a note into the index of its letter, counted from c, and
+/// the semitones its accidentals move it by.
+fn spell(name: &str) -> Option<(i64, i64)> {
+  let mut chars = name.chars();
+  let letter = chars.next()?;
+  let index = "cdefgab".find(letter)? as i64;
+
+  let mut rest = chars.as_str().to_string();
+  if matches!(letter, 'a' | 'e') && rest.starts_with('s') {
+    rest.insert(0, 'e');
+  }
+  let mut accidental = 0;
+  let mut rest = rest.as_str();
+  while !rest.is_empty() {
+    if let Some(r) = rest.strip_prefix("is") {
+      accidental += 1;
+      rest = r;
+    } else if let Some(r) = rest.strip_prefix("es") {
+      accidental -= 1;
+      rest = r;
+    } else {
+      return None;
+    }
+  }
+  Some((index, accidental))
+}
+
+/// The frequency of a pitch in semitones relative to middle c, tuned to a
+/// of 440 Hz.
+pub fn frequency(semitones: i64) -> f32 {
+  440.0 * 2f32.powf((semitones - 9) as f32 / 12.0)
+}
+
+/// Parses the notes of a voice. `unit_length` is the length of a whole note in
+/// seconds, before any tempo changes.
+pub fn parse(notes: &str, unit_length: f64) -> Result<Vec<Event>> {
+  let mut parser = Parser {
+    chars: notes.chars().peekable(),
+    unit_length,
+    tempo: unit_length,
+    tuplets: Vec::new(),
+    last_value: 0.25,
+    slur: false,
+    volume: 1.0,
+    hairpin: None,
+    events: Vec::new(),
+  };
+  parser.parse()?;
+  Ok(parser.events)
+}
+
+struct Parser<'a> {
+  chars: Peekable<Chars<'a>>,
+  unit_length: f64,
+  /// The length of a whole note at the current tempo
+  tempo: f64,
+  /// How much each open tuplet shortens its notes
+  tuplets: Vec<f64>,
+  last_value: f64,
+  /// Whether a slur was opened and not closed yet
+  slur: bool,
+  /// The loudness of the last dynamic
+  volume: f32,
+  /// The event a crescendo or decrescendo started on, and whether it gets
+  /// louder
+  hairpin: Option<(usize, bool)>,
+  events: Vec<Event>,
+}
+
+impl Parser<'_> {
+  fn parse(&mut self) -> Result<()> {
+    loop {
+      self.skip_whitespace();
+      match self.chars.peek() {
+        None => break,
+        Some('\\') => {
+          self.chars.next();
+          self.command()?;
+        }
+        Some('}') => {
+          self.chars.next();
+          self
+            .tuplets
+            .pop()
+            .ok_or_else(|| anyhow!("found a }} without a \\tuplet before it"))?;
+        }
+        Some('<') => {
+          self.chars.next();
+          self.chord()?;
+        }
+        Some(c) if c.is_ascii_lowercase() => self.note()?,
+        Some(c) => return Err(anyhow!("unexpected {c} in the notes")),
+      }
+    }
+
+    if !self.tuplets.is_empty() {
+      return Err(anyhow!("a \\tuplet was not closed with }}"));
+    }
+    // Hairpins that are still open last until the end
+    self.end_hairpin(self.events.len(), None);
+    Ok(())
+  }
+
+  fn command(&mut self) -> Result<()> {
+    if let Some(c) = self.chars.next_if(|c| matches!(c, '<' | '>' | '!')) {
+      let last = self.events.len().saturating_sub(1);
+      if c == '!' && self.hairpin.is_none() {
+        return Err(anyhow!("found a \\! without a \\< or \\> before it"));
+      }
+      self.end_hairpin(last, None);
+      if c != '!' {
+        self.hairpin = Some((last, c == '<'));
+      }
+      return Ok(());
+    }
+
+    let command = self.take_while(|c| c.is_ascii_alphabetic());
+    if let Some((_, volume)) = DYNAMICS.iter().find(|(name, _)| *name == command) {
+      let last = self.events.len().saturating_sub(1);
+      self.end_hairpin(last, Some(*volume));
+      if let Some(event) = self.events.last_mut() {
+        event.volume = *volume;
+      }
+      self.volume = *volume;
+      return Ok(());
+    }
+
+    self.skip_whitespace();
+    let argument = self.take_while(|c| !c.is_whitespace() && c != '{');
+
+    match command.as_str() {
+      "tempo" => {
+        self.tempo = argument
+          .parse::<f64>()
+          .ok()
+          .filter(|f| *f > 0.0 && f.is_finite())
+          .map(|f| self.unit_length / f)
+          .with_context(|| {
+            format!("the \\tempo factor has to be a positive number, got {argument:?}")
+          })?;
+      }
+      "tuplet" => {
+        let (notes, time) = argument
+          .split_once('/')
+          .and_then(|(n, t)| Some((n.parse::<u32>().ok()?, t.parse::<u32>().ok()?)))
+          .filter(|(n, t)| *n > 0 && *t > 0)
+          .with_context(|| format!("\\tuplet needs a ratio like 3/2 after it, got {argument:?}"))?;
+        self.skip_whitespace();
+        if self.chars.next() != Some('{') {
+          return Err(anyhow!("\\tuplet {argument} needs its notes in {{ }}"));
+        }
+        self.tuplets.push(time as f64 / notes as f64);
+      }
+      _ => return Err(anyhow!("unknown command \\{command}")),
+    }
+    Ok(())
+  }
+
+  fn note(&mut self) -> Result<()> {
+    let (pitch, raw) = self.pitch();
+    let pitches = if pitch.name == "r" {
+      Vec::new()
+    } else {
+      vec![pitch]
+    };
+    self.duration(pitches, raw)
+  }
+
+  fn chord(&mut self) -> Result<()> {
+    let mut pitches = Vec::new();
+    let mut raw = String::new();
+    loop {
+      self.skip_whitespace();
+      match self.chars.peek() {
+        Some('>') => {
+          self.chars.next();
+          break;
+        }
+        Some(c) if c.is_ascii_lowercase() => {
+          let (pitch, name) = self.pitch();
+          if pitch.name == "r" {
+            return Err(anyhow!("a chord can't contain rests"));
+          }
+          raw += &format!(" {name}");
+          pitches.push(pitch);
+        }
+        Some(c) => return Err(anyhow!("unexpected {c} in a chord")),
+        None => return Err(anyhow!("a chord was not closed with >")),
+      }
+    }
+    if pitches.is_empty() {
+      return Err(anyhow!("a chord needs at least one note"));
+    }
+    self.duration(pitches, format!("<{}>", raw.trim()))
+  }
+
+  /// Reads the letters and octave marks of a note, returning them as written
+  /// as well.
+  fn pitch(&mut self) -> (Pitch, String) {
+    let name = self.take_while(|c| c.is_ascii_lowercase());
+    let marks = self.take_while(|c| c == '\'' || c == ',');
+    let octave = marks.chars().map(|c| if c == '\'' { 1 } else { -1 }).sum();
+    let raw = format!("{name}{marks}");
+    (Pitch { name, octave }, raw)
+  }
+
+  /// Reads what follows the pitches of a note or chord, and adds it.
+  fn duration(&mut self, pitches: Vec<Pitch>, raw: String) -> Result<()> {
+    let digits = self.take_while(|c| c.is_ascii_digit());
+    let dots = self.take_while(|c| c == '.');
+    let suffix = self.take_while(|c| matches!(c, '~' | '(' | ')'));
+    let raw = format!("{raw}{digits}{dots}{suffix}");
+
+    if !digits.is_empty() {
+      let denominator: u32 = digits
+        .parse()
+        .ok()
+        .filter(|d| *d > 0)
+        .with_context(|| format!("the duration of {raw} has to be a positive number"))?;
+      // Every dot adds half of what the dot before it added
+      self.last_value =
+        (0..=dots.len()).map(|i| 0.5f64.powi(i as i32)).sum::<f64>() / denominator as f64;
+    }
+
+    if suffix.contains('(') {
+      self.slur = true;
+    }
+    let legato =
+      !pitches.is_empty() && (suffix.contains('~') || (self.slur && !suffix.contains(')')));
+    if suffix.contains(')') {
+      self.slur = false;
+    }
+
+    let tuplet: f64 = self.tuplets.iter().product();
+    self.events.push(Event {
+      pitches,
+      value: self.last_value,
+      duration: self.last_value * tuplet * self.tempo,
+      legato,
+      volume: self.volume,
+      ramp_to: None,
+    });
+    Ok(())
+  }
+
+  /// Ends the open crescendo or decrescendo where the event at `end` starts,
+  /// at `volume` or the next dynamic after the one it started on. The events
+  /// in between swell or fade evenly over time.
+  fn end_hairpin(&mut self, end: usize, volume: Option<f32>) {
+    let Some((start, louder)) = self.hairpin.take() else {
+      return;
+    };
+    let Some(from) = self.events.get(start).map(|e| e.volume) else {
+      return;
+    };
+    let to = volume.unwrap_or_else(|| {
+      let mut levels = DYNAMICS.iter().map(|(_, v)| *v);
+      if louder {
+        levels.find(|v| *v > from).unwrap_or(from)
+      } else {
+        levels.rev().find(|v| *v < from).unwrap_or(from)
+      }
+    });
+
+    let mut times = vec![0.0];
+    for event in &self.events[start..end.min(self.events.len())] {
+      times.push(times.last().unwrap_or(&0.0) + event.duration);
+    }
+    let length = *times.last().unwrap_or(&0.0);
+    if length > 0.0 {
+      let at = |time: f64| from + (to - from) * (time / length) as f32;
+      for (index, event) in self.events[start..end].iter_mut().enumerate() {
+        event.volume = at(times[index]);
+        event.ramp_to = Some(at(times[index + 1]));
+      }
+    }
+    if let Some(event) = self.events.get_mut(end) {
+      event.volume = to;
+    }
+    self.volume = to;
+  }
+
+  fn take_while(&mut self, condition: impl Fn(char) -> bool) -> String {
+    let mut taken = String::new();
+    while let Some(c) = self.chars.next_if(|c| condition(*c)) {
+      taken.push(c);
+    }
+    taken
+  }
+
+  fn skip_whitespace(&mut self) {
+    self.take_while(char::is_whitespace);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// The semitones of every event, relative to middle c.
+  fn pitches(notes: &str) -> Vec<Vec<i64>> {
+    let mut relative = Relative::default();
+    parse(notes, 1.0)
+      .unwrap()
+      .iter()
+      .map(|e| relative.resolve_all(&e.pitches).unwrap())
+      .collect()
+  }
+
+  fn values(notes: &str) -> Vec<f64> {
+    parse(notes, 1.0).unwrap().iter().map(|e| e.value).collect()
+  }
+
+  #[test]
+  fn places_notes_at_most_a_fourth_apart() {
+    assert_eq!(
+      pitches("c d e f g a b c"),
+      [[0], [2], [4], [5], [7], [9], [11], [12]]
+    );
+    assert_eq!(pitches("c f"), [[0], [5]]);
+    assert_eq!(pitches("c g"), [[0], [-5]]);
+    assert_eq!(pitches("e a d"), [[4], [9], [14]]);
+  }
+
+  #[test]
+  fn breaks_the_tritone_by_the_letters() {
+    assert_eq!(pitches("f b"), [[5], [11]]);
+    assert_eq!(pitches("b f"), [[11], [5]]);
+    // The accidentals don't change where the note is placed
+    assert_eq!(pitches("fis c"), [[6], [0]]);
+  }
+
+  #[test]
+  fn moves_notes_by_octave_marks() {
+    assert_eq!(pitches("c'"), [[12]]);
+    assert_eq!(pitches("c,"), [[-12]]);
+    assert_eq!(pitches("c c'' c,"), [[0], [24], [12]]);
+    assert_eq!(pitches("g, c"), [[-5], [0]]);
+  }
+
+  #[test]
+  fn spells_accidentals() {
+    assert_eq!(pitches("cis"), [[1]]);
+    assert_eq!(pitches("des"), [[1]]);
+    assert_eq!(pitches("es"), [[3]]);
+    assert_eq!(pitches("ees"), [[3]]);
+    assert_eq!(pitches("as"), [[8]]);
+    assert_eq!(pitches("bes"), [[10]]);
+    assert_eq!(pitches("cisis"), [[2]]);
+    assert_eq!(pitches("ceses"), [[-2]]);
+    assert_eq!(pitches("eis"), [[5]]);
+
+    let mut relative = Relative::default();
+    for name in ["cas", "h", "cxx"] {
+      let pitch = Pitch {
+        name: name.to_string(),
+        octave: 0,
+      };
+      assert!(relative.resolve(&pitch).is_err(), "{name}");
+    }
+  }
+
+  #[test]
+  fn reads_dotted_durations() {
+    assert_eq!(values("c1 d2 e4 f8 g16"), [1.0, 0.5, 0.25, 0.125, 0.0625]);
+    assert_eq!(values("c8."), [0.1875]);
+    assert_eq!(values("c2.."), [0.875]);
+    assert!(parse("c0", 1.0).is_err());
+  }
+
+  #[test]
+  fn inherits_durations() {
+    assert_eq!(values("c d"), [0.25, 0.25]);
+    assert_eq!(values("c8 d e2 f"), [0.125, 0.125, 0.5, 0.5]);
+    assert_eq!(values("c4. d"), [0.375, 0.375]);
+  }
+
+  #[test]
+  fn reads_rests() {
+    let events = parse("c4 r8 d", 2.0).unwrap();
+    assert!(events[1].pitches.is_empty());
+    assert_eq!(events[1].value, 0.125);
+    assert_eq!(events[1].duration, 0.25);
+    assert!(!events[1].legato);
+    // Rests don't move the next note
+    assert_eq!(pitches("c r a"), [vec![0], vec![], vec![-3]]);
+    assert!(parse("<c r>", 1.0).is_err());
+  }
+
+  #[test]
+  fn places_chords_relative_to_their_first_note() {
+    // Each note of the chord follows the one before it
+    assert_eq!(pitches("<c e g c'>2 d"), [vec![0, 4, 7, 24], vec![2]]);
+    assert_eq!(pitches("<c e g>2 b"), [vec![0, 4, 7], vec![-1]]);
+    assert_eq!(values("<c e g>2 d"), [0.5, 0.5]);
+    assert!(parse("<c e g", 1.0).is_err());
+    assert!(parse("<>", 1.0).is_err());
+  }
+}
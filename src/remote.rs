@@ -0,0 +1,131 @@
+//! Keeps a copy of the save on a server, so players can continue on another
+//! device. The server stores whatever is sent to `<endpoint>/<key>` with a
+//! POST and returns it on a GET, or answers 404 if it has no save yet.
+
+use std::{
+  cell::RefCell,
+  collections::{HashMap, HashSet},
+};
+
+use anyhow::{anyhow, Context, Result};
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Headers, RequestCache, RequestInit, Response};
+
+use crate::{
+  js_to_anyhow,
+  save::{self, SaveGame},
+  sleep,
+};
+
+/// How often a failed upload is tried again, waiting twice as long each time
+const RETRIES: u32 = 3;
+const RETRY_DELAY: i32 = 5000;
+
+thread_local! {
+  /// The newest save that wasn't sent yet, by the url it goes to, as every
+  /// game on the page syncs its own save
+  static PENDING: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+  /// The urls that saves are being sent to
+  static SENDING: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+fn url(endpoint: &str, key: &str) -> String {
+  format!(
+    "{}/{}",
+    endpoint.trim_end_matches('/'),
+    String::from(js_sys::encode_uri_component(key))
+  )
+}
+
+async fn request(url: &str, init: &RequestInit) -> Result<Response> {
+  let window = web_sys::window().ok_or_else(|| anyhow!("unable to get the window"))?;
+  JsFuture::from(window.fetch_with_str_and_init(url, init))
+    .await
+    .map_err(js_to_anyhow)
+    .with_context(|| format!("unable to reach {url}"))?
+    .dyn_into()
+    .map_err(|_| anyhow!("fetch didn't return a response"))
+}
+
+/// Downloads the save stored under `key`, if the server has one.
+pub async fn pull(endpoint: &str, key: &str) -> Result<Option<SaveGame>> {
+  let url = url(endpoint, key);
+  let mut init = RequestInit::new();
+  init.cache(RequestCache::NoStore);
+
+  let response = request(&url, &init).await?;
+  if response.status() == 404 {
+    return Ok(None);
+  }
+  if !response.ok() {
+    return Err(anyhow!("fetching {url} failed with {}", response.status()));
+  }
+
+  let text = JsFuture::from(response.text().map_err(js_to_anyhow)?)
+    .await
+    .map_err(js_to_anyhow)?
+    .as_string()
+    .ok_or_else(|| anyhow!("{url} didn't return text"))?;
+  save::parse(&text).map(Some)
+}
+
+/// Uploads the save in the background. Saves are sent one after another, and
+/// if several were made in the meantime only the newest one is sent. Failed
+/// uploads are tried again a few times, unless a newer save replaces them.
+pub fn push(endpoint: &str, key: &str, save: &SaveGame) {
+  let body = match serde_json::to_string(save) {
+    Ok(b) => b,
+    Err(err) => {
+      log::error!("Unable to serialize the save: {err:#}");
+      return;
+    }
+  };
+  let url = url(endpoint, key);
+  PENDING.with(|p| p.borrow_mut().insert(url.clone(), body));
+
+  if !SENDING.with(|s| s.borrow_mut().insert(url.clone())) {
+    return;
+  }
+  wasm_bindgen_futures::spawn_local(async move {
+    while let Some(body) = PENDING.with(|p| p.borrow_mut().remove(&url)) {
+      for attempt in 0..=RETRIES {
+        let Err(err) = send(&url, &body).await else {
+          break;
+        };
+        // Offline, the local save still has everything
+        if attempt == RETRIES {
+          log::warn!("Unable to upload the save: {err:#}");
+          break;
+        }
+        log::debug!("Unable to upload the save, trying again: {err:#}");
+        sleep(RETRY_DELAY << attempt).await;
+        if PENDING.with(|p| p.borrow().contains_key(&url)) {
+          break;
+        }
+      }
+    }
+    SENDING.with(|s| s.borrow_mut().remove(&url));
+  });
+}
+
+async fn send(url: &str, body: &str) -> Result<()> {
+  let headers = Headers::new().map_err(js_to_anyhow)?;
+  headers
+    .set("Content-Type", "application/json")
+    .map_err(js_to_anyhow)?;
+
+  let mut init = RequestInit::new();
+  init.method("POST");
+  init.headers(&headers);
+  init.body(Some(&body.into()));
+
+  let response = request(url, &init).await?;
+  if !response.ok() {
+    return Err(anyhow!(
+      "uploading to {url} failed with {}",
+      response.status()
+    ));
+  }
+  Ok(())
+}
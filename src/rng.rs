@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+/// A small xorshift generator. Its state is part of the save, so random events
+/// replay the same way after loading.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Rng(u64);
+
+impl Rng {
+  pub fn new(seed: u64) -> Self {
+    // The state must never be zero
+    Self(seed.max(1))
+  }
+
+  /// Seeds the generator from the browser's random number generator.
+  pub fn seeded() -> Self {
+    Self::new((js_sys::Math::random() * u64::MAX as f64) as u64)
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0
+  }
+
+  /// A random number in `[0, 1)`
+  pub fn next_f64(&mut self) -> f64 {
+    (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  /// A random index into a collection of length `len`
+  pub fn next_index(&mut self, len: usize) -> usize {
+    (self.next_u64() % len as u64) as usize
+  }
+}
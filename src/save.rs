@@ -0,0 +1,430 @@
+use std::{
+  collections::HashMap,
+  io::{Read, Write},
+};
+
+use anyhow::{anyhow, Context, Result};
+use base64::{
+  engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+  Engine,
+};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{rng::Rng, storage};
+
+/// The version of the save format the game writes. Saves without a version
+/// are version 1.
+pub const VERSION: u64 = 2;
+
+/// Migrations from the version at the same index plus one to the next.
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[track_progress];
+
+/// The largest save a link may unpack into. Links leave out the transcript,
+/// so real saves stay far below it.
+const MAX_SAVE_BYTES: u64 = 1024 * 1024;
+
+/// The complete state of a running game, everything else comes from the
+/// adventure. Every field has a default, so saves from older versions that
+/// lack some of them still load.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct SaveGame {
+  /// The save format, see [`VERSION`]
+  pub version: u64,
+  pub current_text: String,
+  pub inventory: Vec<String>,
+  pub visited: Vec<String>,
+  pub current_room: String,
+  pub time: Option<u64>,
+  /// How often each check failed, by the name of the check
+  pub failures: HashMap<String, i64>,
+  pub rng: Option<Rng>,
+  pub turn: u64,
+  pub encounter_turns: HashMap<String, u64>,
+  pub detour: Option<Detour>,
+  pub meters: HashMap<String, i64>,
+  pub active_character: String,
+  pub character_states: HashMap<String, CharacterState>,
+  pub score: i64,
+  pub items_found: Vec<String>,
+  /// Seconds played
+  pub playtime: f64,
+  pub ng_plus: bool,
+  pub difficulty: String,
+  pub flags: Vec<String>,
+  pub reputation: HashMap<String, i64>,
+  /// The topics each npc remembers
+  pub memory: HashMap<String, Vec<String>>,
+  /// Every secret code the player entered
+  pub codes: Vec<String>,
+  /// When the game was saved, in milliseconds since the epoch
+  pub saved_at: f64,
+  /// The name the player gave the slot the game was saved in, if any
+  pub slot_name: String,
+  pub transcript: Vec<TranscriptEntry>,
+}
+
+/// An action the player chose and the text it showed.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TranscriptEntry {
+  pub action: String,
+  pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CharacterState {
+  pub room: String,
+  pub inventory: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Detour {
+  pub destination: String,
+  pub label: String,
+}
+
+/// A save as it is stored. Every save is written twice, first to the journal
+/// key and then to the main key, so a failed write can never destroy the last
+/// good save.
+#[derive(Serialize, Deserialize)]
+struct JournalEntry {
+  /// Increases with every write, to find the most recent entry
+  sequence: u64,
+  checksum: u64,
+  payload: String,
+}
+
+/// The outcome of reading a save. `warning` describes any problems, like a
+/// corrupted save that had to be recovered from the journal.
+pub struct LoadReport {
+  pub save: Option<SaveGame>,
+  pub warning: Option<LoadWarning>,
+}
+
+/// Problems the player should know about, each with the underlying error.
+pub enum LoadWarning {
+  /// The storage can't be accessed
+  Unavailable(String),
+  /// The save was damaged, but the journal still held a copy
+  Restored(String),
+  /// The save couldn't be read, e.g. as it is damaged or from a newer
+  /// version, and a copy was kept at the key in the second field
+  SetAside(String, String),
+  /// The save couldn't be read, and not even a copy could be kept
+  Lost(String),
+}
+
+fn journal_key(key: &str) -> String {
+  format!("{key}_journal")
+}
+
+/// FNV-1a, which is plenty to detect truncated or garbled writes.
+pub(crate) fn checksum(payload: &str) -> u64 {
+  payload.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+    (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+  })
+}
+
+/// Parses a save written by any version of the game, upgrading it to the
+/// current format.
+pub fn parse(json: &str) -> Result<SaveGame> {
+  let mut save: Value = serde_json::from_str(json).context("the save is malformed")?;
+  let fields = save
+    .as_object_mut()
+    .ok_or_else(|| anyhow!("the save is malformed: expected an object"))?;
+
+  let version = match fields.get("version") {
+    None => 1,
+    Some(v) => v
+      .as_u64()
+      .filter(|v| *v >= 1)
+      .ok_or_else(|| anyhow!("the save version has to be a positive number, not {v}"))?,
+  };
+  if version > VERSION {
+    return Err(anyhow!(
+      "the save is from a newer version of the game (save format {version}, this game reads up \
+       to {VERSION})"
+    ));
+  }
+
+  for migration in &MIGRATIONS[(version - 1) as usize..] {
+    migration(fields);
+  }
+  fields.insert("version".to_string(), VERSION.into());
+
+  serde_json::from_value(save).context("the save is malformed")
+}
+
+/// Version 2 tracks found and visited items and rooms separately. Older saves
+/// at least found everything they carry and visited the room they are in.
+fn track_progress(save: &mut Map<String, Value>) {
+  for (list, from) in [("items_found", "inventory"), ("visited", "current_room")] {
+    let known: Vec<Value> = match save.get(from) {
+      Some(Value::Array(values)) => values.clone(),
+      Some(value @ Value::String(_)) => vec![value.clone()],
+      _ => continue,
+    };
+    if let Value::Array(values) = save.entry(list).or_insert_with(|| Value::Array(Vec::new())) {
+      for value in known {
+        if !values.contains(&value) {
+          values.push(value);
+        }
+      }
+    }
+  }
+}
+
+/// Parses an entry, accepting saves from before the journal was introduced.
+fn parse_entry(raw: &str) -> Result<(u64, SaveGame)> {
+  match serde_json::from_str::<JournalEntry>(raw) {
+    Ok(entry) => {
+      if checksum(&entry.payload) != entry.checksum {
+        return Err(anyhow!("the checksum does not match"));
+      }
+      Ok((entry.sequence, parse(&entry.payload)?))
+    }
+    Err(_) => Ok((0, parse(raw)?)),
+  }
+}
+
+fn read_entry(key: &str) -> Option<Result<(u64, SaveGame)>> {
+  match storage::get(key) {
+    Ok(Some(raw)) => Some(parse_entry(&raw)),
+    Ok(None) => None,
+    Err(err) => Some(Err(err)),
+  }
+}
+
+fn encode_entry(sequence: u64, save: &SaveGame) -> Result<String> {
+  let payload = serde_json::to_string(save)?;
+  Ok(serde_json::to_string(&JournalEntry {
+    sequence,
+    checksum: checksum(&payload),
+    payload,
+  })?)
+}
+
+pub fn write(key: &str, save: &SaveGame) -> Result<()> {
+  let sequence = read_entry(key)
+    .and_then(|entry| entry.ok())
+    .map(|(sequence, _)| sequence + 1)
+    .unwrap_or_default();
+
+  let entry = encode_entry(sequence, save)?;
+
+  // If this fails, the main key still holds the previous save
+  storage::set(&journal_key(key), &entry).context("unable to write the journal")?;
+
+  // If this fails, the journal holds the new save
+  storage::set(key, &entry).context("unable to write the save")?;
+
+  Ok(())
+}
+
+/// Reads the most recent valid save from either the main key or the journal.
+pub fn read(key: &str) -> LoadReport {
+  if let Err(err) = storage::get(key) {
+    return LoadReport {
+      save: None,
+      warning: Some(LoadWarning::Unavailable(format!("{err:#}"))),
+    };
+  }
+
+  let main = read_entry(key);
+  let journal = read_entry(&journal_key(key));
+
+  let main_error = match &main {
+    Some(Err(err)) => Some(format!("{err:#}")),
+    _ => None,
+  };
+  if let Some(err) = &main_error {
+    log::warn!("The save is corrupted: {err}");
+  }
+  if let Some(Err(err)) = &journal {
+    log::warn!("The save journal is corrupted: {err:#}");
+  }
+
+  let main = main.and_then(|e| e.ok());
+  let journal = journal.and_then(|e| e.ok());
+
+  match (main, journal) {
+    (Some(main), Some(journal)) if journal.0 > main.0 => {
+      log::warn!("The last save was interrupted, continuing from the journal");
+      LoadReport {
+        save: Some(journal.1),
+        warning: None,
+      }
+    }
+    (Some(main), _) => LoadReport {
+      save: Some(main.1),
+      warning: None,
+    },
+    (None, Some(journal)) => LoadReport {
+      save: Some(journal.1),
+      warning: main_error.map(LoadWarning::Restored),
+    },
+    (None, None) => LoadReport {
+      save: None,
+      warning: main_error.map(|err| match set_aside(key) {
+        Ok(backup) => LoadWarning::SetAside(err, backup),
+        Err(backup_err) => {
+          log::error!("Unable to keep a copy of the unreadable save: {backup_err:#}");
+          LoadWarning::Lost(err)
+        }
+      }),
+    },
+  }
+}
+
+/// Copies an unreadable save to `<key>.unreadable`, where the next save of
+/// the game doesn't overwrite it. Returns the key of the copy.
+fn set_aside(key: &str) -> Result<String> {
+  let raw = storage::get(key)?.ok_or_else(|| anyhow!("the save is gone"))?;
+  let backup = format!("{key}.unreadable");
+  storage::set(&backup, &raw)?;
+  Ok(backup)
+}
+
+/// Moves the save and its journal to another key, unless that key already
+/// holds a save.
+pub fn rename(from: &str, to: &str) -> Result<()> {
+  if storage::get(to)?.is_some() || storage::get(&journal_key(to))?.is_some() {
+    return Ok(());
+  }
+  storage::rename(&journal_key(from), &journal_key(to))?;
+  storage::rename(from, to)
+}
+
+pub fn remove(key: &str) {
+  for key in [key.to_string(), journal_key(key)] {
+    if let Err(err) = storage::remove(&key) {
+      log::error!("Unable to remove the save: {err:#}");
+    }
+  }
+}
+
+/// Turns a save into text the player can copy to another browser.
+pub fn export(save: &SaveGame) -> Result<String> {
+  Ok(STANDARD.encode(serde_json::to_string(save)?))
+}
+
+/// Reads an exported save, which is either the text of [`export`] or the save
+/// as plain JSON, e.g. from a downloaded file.
+pub fn import(text: &str) -> Result<SaveGame> {
+  let text = text.trim();
+  let json = if text.starts_with('{') {
+    text.to_string()
+  } else {
+    let bytes = STANDARD
+      .decode(text.split_whitespace().collect::<String>())
+      .context("the save is not valid base64")?;
+    String::from_utf8(bytes).context("the save is not valid text")?
+  };
+  parse(&json)
+}
+
+/// Packs a save small enough to be shared as part of a link.
+pub fn to_link(save: &SaveGame) -> Result<String> {
+  let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+  encoder.write_all(serde_json::to_string(save)?.as_bytes())?;
+  Ok(URL_SAFE_NO_PAD.encode(encoder.finish()?))
+}
+
+/// Unpacks a save created by [`to_link`].
+pub fn from_link(text: &str) -> Result<SaveGame> {
+  let compressed = URL_SAFE_NO_PAD
+    .decode(text)
+    .context("the link is damaged")?;
+  // A small link can unpack into a huge save, which would hang the page
+  let mut json = String::new();
+  DeflateDecoder::new(compressed.as_slice())
+    .take(MAX_SAVE_BYTES + 1)
+    .read_to_string(&mut json)
+    .context("the link is damaged")?;
+  if json.len() as u64 > MAX_SAVE_BYTES {
+    return Err(anyhow!("the save in the link is too large"));
+  }
+  parse(&json)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn save() -> SaveGame {
+    SaveGame {
+      version: VERSION,
+      current_room: "cave".to_string(),
+      inventory: vec!["lantern".to_string()],
+      turn: 7,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn journal_entries_round_trip() {
+    let entry = encode_entry(3, &save()).unwrap();
+    let (sequence, restored) = parse_entry(&entry).unwrap();
+    assert_eq!(sequence, 3);
+    assert_eq!(restored.current_room, "cave");
+    assert_eq!(restored.inventory, ["lantern"]);
+    assert_eq!(restored.turn, 7);
+  }
+
+  #[test]
+  fn exports_keep_the_failed_checks() {
+    let mut game = save();
+    game.failures.insert("lockpick".to_string(), 2);
+    let restored = import(&export(&game).unwrap()).unwrap();
+    assert_eq!(restored.failures.get("lockpick"), Some(&2));
+  }
+
+  #[test]
+  fn rejects_entries_whose_checksum_does_not_match() {
+    let entry = encode_entry(3, &save()).unwrap();
+    let garbled = entry.replace("lantern", "lanterm");
+    let err = parse_entry(&garbled).err().expect("the entry was garbled");
+    assert!(format!("{err:#}").contains("checksum"), "{err:#}");
+  }
+
+  #[test]
+  fn rejects_truncated_entries() {
+    let entry = encode_entry(3, &save()).unwrap();
+    assert!(parse_entry(&entry[..entry.len() / 2]).is_err());
+  }
+
+  #[test]
+  fn reads_saves_from_before_the_journal() {
+    let (sequence, restored) = parse_entry(&serde_json::to_string(&save()).unwrap()).unwrap();
+    assert_eq!(sequence, 0);
+    assert_eq!(restored.current_room, "cave");
+  }
+
+  #[test]
+  fn migrates_saves_of_version_1() {
+    // Version 1 had no version field
+    let restored = parse(r#"{"inventory": ["lantern"], "current_room": "cave"}"#).unwrap();
+    assert_eq!(restored.version, VERSION);
+    assert_eq!(restored.items_found, ["lantern"]);
+    assert_eq!(restored.visited, ["cave"]);
+  }
+
+  #[test]
+  fn migrates_saves_of_every_older_version() {
+    for version in 1..=VERSION {
+      let json = format!(r#"{{"version": {version}, "current_room": "cave"}}"#);
+      let restored = parse(&json).unwrap();
+      assert_eq!(restored.version, VERSION);
+      assert_eq!(restored.current_room, "cave");
+    }
+  }
+
+  #[test]
+  fn rejects_saves_of_newer_versions() {
+    let json = format!(r#"{{"version": {}}}"#, VERSION + 1);
+    let err = parse(&json).err().expect("the save is too new");
+    assert!(format!("{err:#}").contains("newer version"), "{err:#}");
+  }
+}
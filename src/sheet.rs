@@ -0,0 +1,264 @@
+use std::fmt::Write;
+
+use crate::audio::Note;
+
+/// Distance between two lines of a staff
+const LINE_SPACING: f64 = 8.0;
+const NOTE_SPACING: f64 = 32.0;
+const NOTES_PER_SYSTEM: usize = 16;
+const MARGIN: f64 = 40.0;
+/// The vertical space reserved for a single staff, including room for ledger
+/// lines above and below it.
+const SYSTEM_HEIGHT: f64 = 14.0 * LINE_SPACING;
+
+/// Maps a semitone within an octave to its diatonic step and whether it needs
+/// a sharp.
+const DIATONIC: [(i64, bool); 12] = [
+  (0, false),
+  (0, true),
+  (1, false),
+  (1, true),
+  (2, false),
+  (3, false),
+  (3, true),
+  (4, false),
+  (4, true),
+  (5, false),
+  (5, true),
+  (6, false),
+];
+
+/// Renders every voice on its own staff, wrapping long voices onto multiple
+/// systems.
+pub fn render(voices: &[&[Note]]) -> String {
+  let systems: usize = voices
+    .iter()
+    .map(|notes| notes.len().div_ceil(NOTES_PER_SYSTEM).max(1))
+    .sum();
+
+  let width = 2.0 * MARGIN + NOTE_SPACING * (NOTES_PER_SYSTEM + 1) as f64;
+  let height = SYSTEM_HEIGHT * systems as f64;
+
+  let mut svg = format!(
+    "<svg xmlns=\"http://www.w3.org/2000/svg\" class=\"sheet\" width=\"{width}\" \
+     height=\"{height}\" viewBox=\"0 0 {width} {height}\" \
+     stroke=\"currentColor\" fill=\"currentColor\">"
+  );
+
+  let mut top = 0.0;
+  for notes in voices {
+    // Pick the clef that fits the voice better
+    let steps: Vec<i64> = notes
+      .iter()
+      .flat_map(|n| n.pitches.iter().copied())
+      .map(step)
+      .collect();
+    let bass = !steps.is_empty() && steps.iter().sum::<i64>() / (steps.len() as i64) < 0;
+
+    let chunks: Vec<&[Note]> = if notes.is_empty() {
+      vec![&[]]
+    } else {
+      notes.chunks(NOTES_PER_SYSTEM).collect()
+    };
+
+    for chunk in chunks {
+      render_system(&mut svg, chunk, top, bass);
+      top += SYSTEM_HEIGHT;
+    }
+  }
+
+  svg += "</svg>";
+  svg
+}
+
+fn render_system(svg: &mut String, notes: &[Note], top: f64, bass: bool) {
+  // The step of the lowest line of the staff, relative to middle c
+  let bottom_step = if bass { -10 } else { 2 };
+  let bottom = top + 9.0 * LINE_SPACING;
+  let right = MARGIN + NOTE_SPACING * (NOTES_PER_SYSTEM + 1) as f64;
+
+  for line in 0..5 {
+    let y = bottom - line as f64 * LINE_SPACING;
+    let _ = write!(
+      svg,
+      "<line x1=\"{MARGIN}\" y1=\"{y}\" x2=\"{right}\" y2=\"{y}\" stroke-width=\"1\"/>"
+    );
+  }
+
+  let clef = if bass { "\u{1D122}" } else { "\u{1D11E}" };
+  let _ = write!(
+    svg,
+    "<text x=\"{}\" y=\"{}\" font-size=\"{}\" stroke=\"none\">{clef}</text>",
+    MARGIN + 2.0,
+    bottom - LINE_SPACING,
+    4.0 * LINE_SPACING
+  );
+
+  for (idx, note) in notes.iter().enumerate() {
+    let x = MARGIN + NOTE_SPACING * (idx + 1) as f64 + NOTE_SPACING / 2.0;
+    let relative = note.value;
+
+    // Dotted notes are one and a half times as long as a power of two
+    let dotted = !is_power_of_two(relative) && is_power_of_two(relative / 1.5);
+    let base = if dotted { relative / 1.5 } else { relative };
+
+    // The lowest and highest note of a chord, which are the same for single
+    // notes
+    let (Some(lowest), Some(highest)) = (
+      note.pitches.iter().map(|p| step(*p)).min(),
+      note.pitches.iter().map(|p| step(*p)).max(),
+    ) else {
+      render_rest(svg, x, bottom - 2.0 * LINE_SPACING, base);
+      continue;
+    };
+    let y_of = |note_step: i64| bottom - (note_step - bottom_step) as f64 * LINE_SPACING / 2.0;
+
+    // Ledger lines below and above the staff
+    let mut ledger = bottom_step - 2;
+    while ledger >= lowest {
+      render_ledger(
+        svg,
+        x,
+        bottom - (ledger - bottom_step) as f64 * LINE_SPACING / 2.0,
+      );
+      ledger -= 2;
+    }
+    let mut ledger = bottom_step + 10;
+    while ledger <= highest {
+      render_ledger(
+        svg,
+        x,
+        bottom - (ledger - bottom_step) as f64 * LINE_SPACING / 2.0,
+      );
+      ledger += 2;
+    }
+
+    for pitch in &note.pitches {
+      let y = y_of(step(*pitch));
+
+      if DIATONIC[pitch.rem_euclid(12) as usize].1 {
+        let _ = write!(
+          svg,
+          "<text x=\"{}\" y=\"{}\" font-size=\"{}\" stroke=\"none\">&#9839;</text>",
+          x - 2.2 * LINE_SPACING,
+          y + LINE_SPACING / 2.0,
+          1.5 * LINE_SPACING
+        );
+      }
+
+      let filled = base < 0.5;
+      let _ = write!(
+        svg,
+        "<ellipse cx=\"{x}\" cy=\"{y}\" rx=\"{}\" ry=\"{}\" fill=\"{}\" \
+         stroke-width=\"1.5\"/>",
+        LINE_SPACING * 0.65,
+        LINE_SPACING * 0.45,
+        if filled { "currentColor" } else { "none" }
+      );
+
+      if dotted {
+        let _ = write!(
+          svg,
+          "<circle cx=\"{}\" cy=\"{}\" r=\"1.5\" stroke=\"none\"/>",
+          x + LINE_SPACING,
+          y - LINE_SPACING / 4.0
+        );
+      }
+    }
+
+    // Whole notes have no stem
+    if base >= 1.0 {
+      continue;
+    }
+
+    // Stems point down for notes above the middle line, and run through
+    // every note of a chord
+    let up = lowest + highest < 2 * (bottom_step + 4);
+    let (stem_x, stem_start, stem_end) = if up {
+      (
+        x + LINE_SPACING * 0.6,
+        y_of(lowest),
+        y_of(highest) - 3.5 * LINE_SPACING,
+      )
+    } else {
+      (
+        x - LINE_SPACING * 0.6,
+        y_of(highest),
+        y_of(lowest) + 3.5 * LINE_SPACING,
+      )
+    };
+    let _ = write!(
+      svg,
+      "<line x1=\"{stem_x}\" y1=\"{stem_start}\" x2=\"{stem_x}\" y2=\"{stem_end}\" \
+       stroke-width=\"1.2\"/>"
+    );
+
+    // One flag per halving below a quarter note
+    let mut flag_length = 0.125;
+    let mut flag_offset = 0.0;
+    while base <= flag_length + f64::EPSILON {
+      let flag_y = if up {
+        stem_end + flag_offset
+      } else {
+        stem_end - flag_offset
+      };
+      let tip_y = if up {
+        flag_y + LINE_SPACING * 1.5
+      } else {
+        flag_y - LINE_SPACING * 1.5
+      };
+      let _ = write!(
+        svg,
+        "<line x1=\"{stem_x}\" y1=\"{flag_y}\" x2=\"{}\" y2=\"{tip_y}\" stroke-width=\"1.2\"/>",
+        stem_x + LINE_SPACING
+      );
+      flag_length /= 2.0;
+      flag_offset += LINE_SPACING * 0.75;
+    }
+  }
+}
+
+fn render_ledger(svg: &mut String, x: f64, y: f64) {
+  let _ = write!(
+    svg,
+    "<line x1=\"{}\" y1=\"{y}\" x2=\"{}\" y2=\"{y}\" stroke-width=\"1\"/>",
+    x - LINE_SPACING,
+    x + LINE_SPACING
+  );
+}
+
+fn render_rest(svg: &mut String, x: f64, middle: f64, length: f64) {
+  // Whole and half rests are blocks hanging from or sitting on a line, shorter
+  // rests are drawn as a simple bar.
+  let (y, height, width) = if length >= 1.0 {
+    (middle - LINE_SPACING, LINE_SPACING / 2.0, LINE_SPACING)
+  } else if length >= 0.5 {
+    (
+      middle - LINE_SPACING / 2.0,
+      LINE_SPACING / 2.0,
+      LINE_SPACING,
+    )
+  } else {
+    (
+      middle - 1.5 * LINE_SPACING,
+      3.0 * LINE_SPACING,
+      LINE_SPACING / 3.0,
+    )
+  };
+  let _ = write!(
+    svg,
+    "<rect x=\"{}\" y=\"{y}\" width=\"{width}\" height=\"{height}\" stroke=\"none\"/>",
+    x - width / 2.0
+  );
+}
+
+/// The diatonic step of a pitch relative to middle c, i.e. its vertical
+/// position in the staff.
+fn step(pitch: i64) -> i64 {
+  pitch.div_euclid(12) * 7 + DIATONIC[pitch.rem_euclid(12) as usize].0
+}
+
+fn is_power_of_two(value: f64) -> bool {
+  let exponent = value.log2();
+  (exponent - exponent.round()).abs() < 1e-6
+}
@@ -0,0 +1,267 @@
+//! Stores saves and profiles in the browser. IndexedDB is used where it is
+//! available, as local storage only holds a few megabytes, which long
+//! transcripts fill quickly. Otherwise everything stays in local storage.
+//!
+//! IndexedDB can only be accessed asynchronously, while the game changes its
+//! state synchronously. So [`init`] reads the whole database into memory, reads
+//! are answered from there and writes are persisted in the background.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use anyhow::{anyhow, Context, Result};
+use js_sys::{Array, Function, Promise};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{IdbDatabase, IdbObjectStore, IdbRequest, IdbTransactionMode};
+
+use crate::{audio, game, js_to_anyhow, localization, narration};
+
+const DATABASE: &str = "textadventure";
+const STORE: &str = "storage";
+/// Keys of local storage with this prefix are moved to IndexedDB
+const PREFIX: &str = "textadventure_";
+/// Stay in local storage, as they are needed before the database is opened
+/// or apply to every adventure
+const KEPT: &[&str] = &[
+  localization::LANGUAGE_KEY,
+  audio::VOLUME_KEY,
+  narration::NARRATION_KEY,
+  game::PROGRESS_KEY,
+];
+
+enum Backend {
+  IndexedDb {
+    database: IdbDatabase,
+    entries: HashMap<String, String>,
+  },
+  LocalStorage,
+}
+
+thread_local! {
+  static BACKEND: RefCell<Backend> = const { RefCell::new(Backend::LocalStorage) };
+}
+
+/// Opens the database and moves the entries of local storage into it. Until
+/// this finished, or if it fails, local storage is used. Every game on the
+/// page calls this, only the first one opens the database.
+pub async fn init() {
+  if is_open() {
+    return;
+  }
+  match open().await {
+    // Another game opened it in the meantime, and might have changed entries
+    Ok(_) if is_open() => {}
+    Ok((database, entries)) => {
+      BACKEND.with(|b| *b.borrow_mut() = Backend::IndexedDb { database, entries });
+      if let Err(err) = move_local_storage().await {
+        log::error!("Unable to move the saves out of local storage: {err:#}");
+      }
+    }
+    Err(err) => log::warn!("IndexedDB is not available, using local storage: {err:#}"),
+  }
+}
+
+fn is_open() -> bool {
+  BACKEND.with(|b| matches!(&*b.borrow(), Backend::IndexedDb { .. }))
+}
+
+pub fn get(key: &str) -> Result<Option<String>> {
+  BACKEND.with(|b| match &*b.borrow() {
+    Backend::IndexedDb { entries, .. } => Ok(entries.get(key).cloned()),
+    Backend::LocalStorage => local_storage()?.get_item(key).map_err(js_to_anyhow),
+  })
+}
+
+pub fn set(key: &str, value: &str) -> Result<()> {
+  if let Some(request) = put(key, value)? {
+    persist(request, format!("Unable to store {key}"));
+  }
+  Ok(())
+}
+
+/// Writes to the backend, returning the request to wait for if there is one.
+fn put(key: &str, value: &str) -> Result<Option<IdbRequest>> {
+  BACKEND.with(|b| match &mut *b.borrow_mut() {
+    Backend::IndexedDb { database, entries } => {
+      let request = object_store(database)?
+        .put_with_key(&value.into(), &key.into())
+        .map_err(js_to_anyhow)?;
+      entries.insert(key.to_string(), value.to_string());
+      Ok(Some(request))
+    }
+    Backend::LocalStorage => {
+      local_storage()?
+        .set_item(key, value)
+        .map_err(js_to_anyhow)?;
+      Ok(None)
+    }
+  })
+}
+
+pub fn remove(key: &str) -> Result<()> {
+  BACKEND.with(|b| match &mut *b.borrow_mut() {
+    Backend::IndexedDb { database, entries } => {
+      let request = object_store(database)?
+        .delete(&key.into())
+        .map_err(js_to_anyhow)?;
+      entries.remove(key);
+      persist(request, format!("Unable to remove {key}"));
+      Ok(())
+    }
+    Backend::LocalStorage => local_storage()?.remove_item(key).map_err(js_to_anyhow),
+  })
+}
+
+/// Moves an entry to another key, unless that key is already taken.
+pub fn rename(from: &str, to: &str) -> Result<()> {
+  let Some(value) = get(from)? else {
+    return Ok(());
+  };
+  if get(to)?.is_none() {
+    set(to, &value)?;
+  }
+  remove(from)
+}
+
+/// Waits until every write was persisted, e.g. before the page is reloaded.
+pub async fn flush() {
+  let request = BACKEND.with(|b| match &*b.borrow() {
+    // Writes are applied in order, so once a later request finished, all
+    // writes before it did
+    Backend::IndexedDb { database, .. } => {
+      Some(object_store(database).and_then(|s| s.count().map_err(js_to_anyhow)))
+    }
+    Backend::LocalStorage => None,
+  });
+  let result = match request {
+    Some(Ok(request)) => wait(&request).await.map(|_| ()),
+    Some(Err(err)) => Err(err),
+    None => Ok(()),
+  };
+  if let Err(err) = result {
+    log::error!("Unable to wait for the storage: {err:#}");
+  }
+}
+
+/// Opens the database, creating it on the first start, and reads all entries.
+async fn open() -> Result<(IdbDatabase, HashMap<String, String>)> {
+  let factory = web_sys::window()
+    .ok_or_else(|| anyhow!("unable to get the window"))?
+    .indexed_db()
+    .map_err(js_to_anyhow)?
+    .ok_or_else(|| anyhow!("the browser has no IndexedDB"))?;
+
+  let request = factory.open_with_u32(DATABASE, 1).map_err(js_to_anyhow)?;
+  let upgrade_request = request.clone();
+  let upgrade = Closure::<dyn FnMut()>::new(move || {
+    let created = upgrade_request.result().and_then(|db| {
+      db.unchecked_into::<IdbDatabase>()
+        .create_object_store(STORE)
+    });
+    if let Err(err) = created {
+      log::error!("Unable to create the database: {err:?}");
+    }
+  });
+  request.set_onupgradeneeded(Some(upgrade.as_ref().unchecked_ref()));
+  let database: IdbDatabase = wait(&request)
+    .await
+    .context("unable to open the database")?
+    .unchecked_into();
+
+  let store = database
+    .transaction_with_str(STORE)
+    .and_then(|t| t.object_store(STORE))
+    .map_err(js_to_anyhow)?;
+  let keys = store.get_all_keys().map_err(js_to_anyhow)?;
+  let values = store.get_all().map_err(js_to_anyhow)?;
+  let keys: Array = wait(&keys).await?.unchecked_into();
+  let values: Array = wait(&values).await?.unchecked_into();
+
+  let entries = keys
+    .iter()
+    .zip(values.iter())
+    .filter_map(|(key, value)| Some((key.as_string()?, value.as_string()?)))
+    .collect();
+  Ok((database, entries))
+}
+
+/// Copies the saves of older versions of the game to the database. They are
+/// removed from local storage once they were written.
+async fn move_local_storage() -> Result<()> {
+  let local = local_storage()?;
+  let keys: Vec<String> = (0..local.length().map_err(js_to_anyhow)?)
+    .filter_map(|i| local.key(i).ok().flatten())
+    .filter(|key| key.starts_with(PREFIX) && !KEPT.contains(&key.as_str()))
+    .collect();
+
+  for key in keys {
+    let Some(value) = local.get_item(&key).map_err(js_to_anyhow)? else {
+      continue;
+    };
+    // The database wins if both have the key
+    if get(&key)?.is_none() {
+      if let Some(request) = put(&key, &value)? {
+        wait(&request)
+          .await
+          .with_context(|| format!("unable to store {key}"))?;
+      }
+    }
+    local.remove_item(&key).map_err(js_to_anyhow)?;
+  }
+  Ok(())
+}
+
+fn object_store(database: &IdbDatabase) -> Result<IdbObjectStore> {
+  database
+    .transaction_with_str_and_mode(STORE, IdbTransactionMode::Readwrite)
+    .and_then(|t| t.object_store(STORE))
+    .map_err(js_to_anyhow)
+}
+
+/// Waits for a write in the background. Writes are applied in the order they
+/// were made, so a later write never gets overtaken by an earlier one.
+fn persist(request: IdbRequest, error: String) {
+  wasm_bindgen_futures::spawn_local(async move {
+    if let Err(err) = wait(&request).await {
+      log::error!("{error}: {err:#}");
+    }
+  });
+}
+
+/// Waits until the request finished and returns its result.
+async fn wait(request: &IdbRequest) -> Result<JsValue> {
+  let promise = Promise::new(&mut |resolve: Function, reject: Function| {
+    request.set_onsuccess(Some(&resolve));
+    request.set_onerror(Some(&reject));
+  });
+  JsFuture::from(promise).await.map_err(|_| {
+    let error = request.error().ok().flatten().map(|e| e.message());
+    anyhow!(
+      "{}",
+      error.unwrap_or_else(|| "the request failed".to_string())
+    )
+  })?;
+  request.result().map_err(js_to_anyhow)
+}
+
+/// The language the player picked, if any.
+pub fn stored_language() -> Option<String> {
+  local_storage()
+    .ok()?
+    .get_item(localization::LANGUAGE_KEY)
+    .ok()?
+}
+
+pub fn store_language(language: &str) -> Result<()> {
+  local_storage()?
+    .set_item(localization::LANGUAGE_KEY, language)
+    .map_err(js_to_anyhow)
+}
+
+fn local_storage() -> Result<web_sys::Storage> {
+  web_sys::window()
+    .ok_or_else(|| anyhow!("unable to get the window"))?
+    .local_storage()
+    .map_err(js_to_anyhow)?
+    .ok_or_else(|| anyhow!("local storage is not available"))
+}
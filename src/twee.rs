@@ -0,0 +1,277 @@
+//! Converts Twine stories in the Twee 3 format into adventures. Every passage
+//! becomes a room, and every link in it an action leading to the linked
+//! passage. Story formats like Harlowe add macros on top of that, which have
+//! no equivalent here and are reported instead.
+
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+
+use crate::{loader::strip_bom, migration::CURRENT_VERSION};
+
+/// Passages that hold data about the story instead of content.
+const SPECIAL_PASSAGES: &[&str] = &["StoryTitle", "StoryData", "StoryInit"];
+/// Passages with these tags hold code or styles.
+const SPECIAL_TAGS: &[&str] = &["script", "stylesheet", "widget"];
+
+#[derive(Deserialize, Default)]
+struct StoryData {
+  #[serde(default)]
+  start: Option<String>,
+}
+
+struct Passage {
+  name: String,
+  tags: Vec<String>,
+  text: String,
+}
+
+/// Converts a Twee story into the raw document of an adventure. Also returns
+/// warnings about everything that couldn't be converted.
+pub fn convert(source: &str) -> Result<(Value, Vec<String>)> {
+  let passages = passages(strip_bom(source));
+  let mut warnings = Vec::new();
+
+  let title = passages
+    .iter()
+    .find(|p| p.name == "StoryTitle")
+    .map(|p| p.text.trim().to_string())
+    .unwrap_or_else(|| "Untitled story".to_string());
+  let data: StoryData = match passages.iter().find(|p| p.name == "StoryData") {
+    Some(p) => serde_json::from_str(&p.text).map_err(|e| anyhow!("Invalid StoryData: {e}"))?,
+    None => StoryData::default(),
+  };
+
+  let story: Vec<&Passage> = passages
+    .iter()
+    .filter(|p| !SPECIAL_PASSAGES.contains(&p.name.as_str()))
+    .filter(|p| !p.tags.iter().any(|t| SPECIAL_TAGS.contains(&t.as_str())))
+    .collect();
+  for passage in passages
+    .iter()
+    .filter(|p| !story.iter().any(|s| s.name == p.name))
+  {
+    if passage.name != "StoryTitle" && passage.name != "StoryData" {
+      warnings.push(format!(
+        "passage {}: scripts and styles are not supported",
+        passage.name
+      ));
+    }
+  }
+
+  let start = data
+    .start
+    .or_else(|| {
+      story
+        .iter()
+        .find(|p| p.name == "Start")
+        .map(|p| p.name.clone())
+    })
+    .or_else(|| story.first().map(|p| p.name.clone()))
+    .ok_or_else(|| anyhow!("The story has no passages"))?;
+
+  let link = Regex::new(r"\[\[(.+?)\]\]").expect("the link pattern is valid");
+  let makro = Regex::new(r"\(([a-z][a-z0-9-]*):").expect("the macro pattern is valid");
+
+  let mut rooms = Mapping::new();
+  for passage in &story {
+    let mut actions = Vec::new();
+    for captures in link.captures_iter(&passage.text) {
+      let (name, target) = parse_link(&captures[1]);
+      if !story.iter().any(|p| p.name == target) {
+        warnings.push(format!(
+          "passage {}: links to the unknown passage {target}",
+          passage.name
+        ));
+        continue;
+      }
+
+      let mut action = Mapping::new();
+      action.insert("name".into(), name.into());
+      action.insert("type".into(), "navigate".into());
+      action.insert("transition".into(), target.into());
+      actions.push(Value::Mapping(action));
+    }
+
+    for captures in makro.captures_iter(&passage.text) {
+      warnings.push(format!(
+        "passage {}: the macro ({}:) is not supported and was kept as text",
+        passage.name, &captures[1]
+      ));
+    }
+
+    // The links are offered as actions, their text stays in the description
+    let description = link
+      .replace_all(&passage.text, |c: &regex::Captures| {
+        parse_link(&c[1]).0.to_string()
+      })
+      .trim()
+      .to_string();
+
+    let mut room = Mapping::new();
+    room.insert("description".into(), description.into());
+    // Stories end in passages without links
+    if actions.is_empty() {
+      room.insert("ending".into(), true.into());
+    }
+    room.insert("actions".into(), Value::Sequence(actions));
+    if !passage.tags.is_empty() {
+      room.insert("tags".into(), passage.tags.clone().into());
+    }
+    rooms.insert(passage.name.clone().into(), Value::Mapping(room));
+  }
+
+  let mut adventure = Mapping::new();
+  adventure.insert("name".into(), title.clone().into());
+  adventure.insert("start".into(), start.into());
+  adventure.insert("intro".into(), title.into());
+  adventure.insert("rooms".into(), Value::Mapping(rooms));
+  adventure.insert("assets".into(), serde_yaml::from_str("music: {}")?);
+
+  let mut document = Mapping::new();
+  document.insert("version".into(), CURRENT_VERSION.into());
+  document.insert("adventure".into(), Value::Mapping(adventure));
+  Ok((Value::Mapping(document), warnings))
+}
+
+/// Splits the source into passages, which start with a `::` header.
+fn passages(source: &str) -> Vec<Passage> {
+  let mut passages: Vec<Passage> = Vec::new();
+  for line in source.lines() {
+    match line.strip_prefix("::") {
+      Some(header) => passages.push(parse_header(header)),
+      None => {
+        if let Some(passage) = passages.last_mut() {
+          passage.text += line;
+          passage.text.push('\n');
+        }
+      }
+    }
+  }
+  passages
+}
+
+/// Reads a header like `Name [tag1 tag2] {"position":"100,100"}`.
+fn parse_header(header: &str) -> Passage {
+  // The metadata is only used by the Twine editor
+  let header = match header.find(" {") {
+    Some(i) => &header[..i],
+    None => header,
+  };
+  let (name, tags) = match header.split_once('[') {
+    Some((name, tags)) => (
+      name,
+      tags
+        .trim_end()
+        .trim_end_matches(']')
+        .split_whitespace()
+        .map(|t| t.to_string())
+        .collect(),
+    ),
+    None => (header, Vec::new()),
+  };
+  Passage {
+    name: name.trim().to_string(),
+    tags,
+    text: String::new(),
+  }
+}
+
+/// Splits a link into the text shown and the passage it leads to. Twine
+/// supports `Target`, `Text|Target`, `Text->Target` and `Target<-Text`.
+fn parse_link(link: &str) -> (&str, &str) {
+  if let Some((text, target)) = link.split_once("->") {
+    (text.trim(), target.trim())
+  } else if let Some((target, text)) = link.split_once("<-") {
+    (text.trim(), target.trim())
+  } else if let Some((text, target)) = link.split_once('|') {
+    (text.trim(), target.trim())
+  } else {
+    (link.trim(), link.trim())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const STORY: &str = r#":: StoryTitle
+The Cave
+
+:: StoryData
+{"start": "Entrance"}
+
+:: Entrance [dark cold] {"position":"100,100"}
+A dark cave. [[Go deeper->Depths]] or [[Outside<-leave]].
+
+:: Depths
+You found the [[way out|Outside]]. (set: $lit to true)
+
+:: Outside
+Daylight.
+
+:: Style [stylesheet]
+body { color: red; }
+"#;
+
+  #[test]
+  fn converts_passages() {
+    let (document, warnings) = convert(STORY).unwrap();
+    let adventure = &document["adventure"];
+    assert_eq!(adventure["name"], "The Cave");
+    assert_eq!(adventure["start"], "Entrance");
+
+    let rooms = adventure["rooms"].as_mapping().unwrap();
+    let names: Vec<&str> = rooms.keys().filter_map(|k| k.as_str()).collect();
+    assert_eq!(names, ["Entrance", "Depths", "Outside"]);
+    assert_eq!(rooms["Outside"]["description"], "Daylight.");
+    assert_eq!(rooms["Outside"]["ending"], true);
+    assert!(warnings.contains(&"passage Style: scripts and styles are not supported".to_string()));
+  }
+
+  #[test]
+  fn converts_links_into_actions() {
+    let (document, warnings) = convert(STORY).unwrap();
+    let rooms = &document["adventure"]["rooms"];
+    assert_eq!(
+      rooms["Entrance"]["description"],
+      "A dark cave. Go deeper or leave."
+    );
+
+    let actions = rooms["Entrance"]["actions"].as_sequence().unwrap();
+    let links: Vec<(&str, &str)> = actions
+      .iter()
+      .map(|a| {
+        (
+          a["name"].as_str().unwrap(),
+          a["transition"].as_str().unwrap(),
+        )
+      })
+      .collect();
+    assert_eq!(links, [("Go deeper", "Depths"), ("leave", "Outside")]);
+    assert_eq!(rooms["Depths"]["actions"][0]["name"], "way out");
+    assert!(warnings
+      .iter()
+      .any(|w| w.contains("the macro (set:) is not supported")));
+  }
+
+  #[test]
+  fn reports_links_to_unknown_passages() {
+    let (document, warnings) = convert(":: Start\n[[Nowhere]]\n").unwrap();
+    assert_eq!(document["adventure"]["rooms"]["Start"]["ending"], true);
+    assert_eq!(
+      warnings,
+      ["passage Start: links to the unknown passage Nowhere"]
+    );
+  }
+
+  #[test]
+  fn keeps_the_tags_of_passages() {
+    let (document, _) = convert(STORY).unwrap();
+    let rooms = &document["adventure"]["rooms"];
+    assert_eq!(rooms["Entrance"]["tags"][0], "dark");
+    assert_eq!(rooms["Entrance"]["tags"][1], "cold");
+    assert!(rooms["Depths"].get("tags").is_none());
+  }
+}
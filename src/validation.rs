@@ -0,0 +1,542 @@
+//! Checks that everything an adventure refers to exists, before the game
+//! starts. Parsing only checks the structure of the file, so a typo in a room
+//! name would otherwise only show up when the player takes that transition.
+
+use std::{collections::HashMap, fmt};
+
+use crate::adventure::{
+  Action, ActionKind, Adventure, Outcome, Room, SoundEffect, SpeakingVoice, StatusField, Theme,
+  UseTarget, Waveform,
+};
+
+/// Something that is wrong with the adventure, and where.
+pub struct Problem {
+  pub location: String,
+  pub message: String,
+}
+
+impl fmt::Display for Problem {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}: {}", self.location, self.message)
+  }
+}
+
+/// Returns every problem found in the adventure, in a stable order.
+pub fn validate(adventure: &Adventure) -> Vec<Problem> {
+  let mut validator = Validator {
+    adventure,
+    problems: Vec::new(),
+  };
+  validator.run();
+  validator.problems
+}
+
+struct Validator<'a> {
+  adventure: &'a Adventure,
+  problems: Vec<Problem>,
+}
+
+impl Validator<'_> {
+  fn run(&mut self) {
+    let adventure = self.adventure;
+    self.room("adventure", "start", &adventure.start);
+
+    let characters = &adventure.characters;
+    if let Some(initial) = &characters.initial {
+      if !characters.playable.contains_key(initial) {
+        self.report(
+          "characters",
+          format!("the initial character {initial} is not playable"),
+        );
+      }
+    }
+    for (id, character) in &characters.playable {
+      if let Some(start) = &character.start {
+        self.room(&format!("character {id}"), "start", start);
+      }
+    }
+
+    for (id, room) in sorted(&adventure.rooms) {
+      self.check_room(id, room);
+    }
+
+    self.theme("theme", &adventure.theme);
+    if let Some(typewriter) = &adventure.typewriter {
+      if !(typewriter.speed > 0.0 && typewriter.speed.is_finite()) {
+        self.report(
+          "typewriter",
+          format!(
+            "the speed has to be a positive number of letters per second, got {}",
+            typewriter.speed
+          ),
+        );
+      }
+    }
+
+    if let Some(narration) = &adventure.narration {
+      self.voice("narration", &narration.narrator);
+      for (speaker, voice) in sorted(&narration.speakers) {
+        self.voice(&format!("narration, speaker {speaker}"), voice);
+      }
+    }
+
+    for field in &adventure.status {
+      match field {
+        StatusField::Time if adventure.clock.is_none() => self.report(
+          "status",
+          "shows the time, but the adventure has no clock".to_string(),
+        ),
+        StatusField::Meter(meter) if !adventure.meters.contains_key(meter) => {
+          self.report("status", format!("shows the unknown meter {meter}"))
+        }
+        StatusField::Reputation(faction) if !adventure.factions.contains_key(faction) => self
+          .report(
+            "status",
+            format!("shows the reputation with the unknown faction {faction}"),
+          ),
+        _ => {}
+      }
+    }
+    if adventure.scrollback.as_ref().is_some_and(|s| s.limit == 0) {
+      self.report(
+        "scrollback",
+        "the limit has to keep at least one text".to_string(),
+      );
+    }
+
+    if let Some(progress) = &adventure.progress {
+      for room in &progress.exclude {
+        if !adventure.rooms.contains_key(room) {
+          self.report("progress", format!("excludes the unknown room {room}"));
+        }
+      }
+    }
+
+    for (name, code) in sorted(&adventure.codes) {
+      if let Some(transition) = &code.transition {
+        self.room(&format!("code {name}"), "transition", transition);
+      }
+    }
+    for (name, table) in sorted(&adventure.encounters) {
+      for room in &table.rooms {
+        self.room(&format!("encounter table {name}"), "encounter", room);
+      }
+    }
+    if adventure.assets.crossfade < 0.0 {
+      self.report(
+        "assets",
+        format!(
+          "the crossfade can't be negative, got {}",
+          adventure.assets.crossfade
+        ),
+      );
+    }
+    for (name, effect) in sorted(&adventure.assets.sfx) {
+      let location = format!("sound effect {name}");
+      match effect {
+        SoundEffect::Noise { noise, volume, .. } => {
+          if *noise <= 0.0 {
+            self.report(
+              &location,
+              format!("the noise has to last longer than 0 seconds, got {noise}"),
+            );
+          }
+          if *volume < 0.0 {
+            self.report(
+              &location,
+              format!("the volume can't be negative, got {volume}"),
+            );
+          }
+        }
+        SoundEffect::Notes { voice, .. } => {
+          if let Err(error) = adventure.assets.waveform(&voice.instrument) {
+            self.report(&location, error.to_string());
+          }
+          if let Err(message) = voice.envelope.check() {
+            self.report(&location, message);
+          }
+          if let Err(message) = voice.check_modulation() {
+            self.report(&location, message);
+          }
+        }
+      }
+    }
+    for (name, song) in sorted(&adventure.assets.music) {
+      if song.midi.is_none() && song.unit_length <= 0.0 {
+        self.report(
+          &format!("song {name}"),
+          format!(
+            "the unit_length has to be longer than 0 seconds, got {}",
+            song.unit_length
+          ),
+        );
+      }
+      for (fade, seconds) in [("fade_in", song.fade_in), ("fade_out", song.fade_out)] {
+        if seconds.is_some_and(|s| s < 0.0) {
+          self.report(
+            &format!("song {name}"),
+            format!("the {fade} can't be negative"),
+          );
+        }
+      }
+      if song.volume < 0.0 {
+        self.report(
+          &format!("song {name}"),
+          format!("the volume can't be negative, got {}", song.volume),
+        );
+      }
+      if song.prerender && !song.layers.is_empty() {
+        self.report(
+          &format!("song {name}"),
+          "has layers, which can't change once the song is pre-rendered".to_string(),
+        );
+      }
+      for (layer_name, layer) in sorted(&song.layers) {
+        if !(layer.fade >= 0.0 && layer.fade.is_finite()) {
+          self.report(
+            &format!("song {name}, layer {layer_name}"),
+            format!("the fade can't be negative, got {}", layer.fade),
+          );
+        }
+      }
+      for (index, voice) in song.voices.iter().enumerate() {
+        let location = format!("song {name}, voice {}", index + 1);
+        if song.midi.is_some() && !voice.notes.trim().is_empty() {
+          self.report(
+            &location,
+            "has notes, which are ignored as the song plays a MIDI file".to_string(),
+          );
+        }
+        if voice.volume < 0.0 {
+          self.report(
+            &location,
+            format!("the volume can't be negative, got {}", voice.volume),
+          );
+        }
+        if let Some(layer) = &voice.layer {
+          if !song.layers.contains_key(layer) {
+            self.report(
+              &location,
+              format!("belongs to the layer {layer}, which the song doesn't have"),
+            );
+          }
+        }
+        match adventure.assets.waveform(&voice.instrument) {
+          Ok(Waveform::Drums) if voice.transpose != 0 => self.report(
+            &location,
+            "transposes drums, which have no pitch".to_string(),
+          ),
+          Ok(Waveform::Drums) if voice.arpeggio.is_some() => {
+            self.report(&location, "has an arpeggio, which drums ignore".to_string())
+          }
+          Ok(_) => {}
+          Err(error) => self.report(&location, error.to_string()),
+        }
+        if let Err(message) = voice.envelope.check() {
+          self.report(&location, message);
+        }
+        if let Err(message) = voice.check_modulation() {
+          self.report(&location, message);
+        }
+        for (effect, level, present) in [
+          ("reverb", voice.sends.reverb, song.effects.reverb.is_some()),
+          ("delay", voice.sends.delay, song.effects.delay.is_some()),
+        ] {
+          if level < 0.0 {
+            self.report(
+              &location,
+              format!("the {effect} send can't be negative, got {level}"),
+            );
+          } else if level > 0.0 && !present {
+            self.report(
+              &location,
+              format!("sends to the {effect}, but the song has no {effect} in its effects"),
+            );
+          }
+        }
+      }
+      if let Some(reverb) = &song.effects.reverb {
+        if reverb.seconds <= 0.0 || reverb.decay < 0.0 {
+          self.report(
+            &format!("song {name}"),
+            "the reverb needs more than 0 seconds and a decay that isn't negative".to_string(),
+          );
+        }
+      }
+      if let Some(delay) = &song.effects.delay {
+        // Browsers don't delay by more than three minutes
+        if delay.seconds <= 0.0 || delay.seconds >= 180.0 {
+          self.report(
+            &format!("song {name}"),
+            format!(
+              "the delay has to be between 0 and 180 seconds, got {}",
+              delay.seconds
+            ),
+          );
+        }
+        if !(0.0..1.0).contains(&delay.feedback) {
+          self.report(
+            &format!("song {name}"),
+            format!(
+              "the delay's feedback has to be at least 0 and less than 1, got {}",
+              delay.feedback
+            ),
+          );
+        }
+      }
+    }
+    for (name, item) in sorted(&adventure.items) {
+      let location = format!("item {name}");
+      if let Some(image) = &item.image {
+        self.image(&location, image);
+      }
+      self.use_targets(&location, &item.use_targets);
+    }
+    for (name, file) in sorted(&adventure.assets.audio_files) {
+      let location = format!("audio file {name}");
+      if adventure.assets.music.contains_key(name) {
+        self.report(
+          &location,
+          "has the same name as a song, so only one of them can be played".to_string(),
+        );
+      }
+      if file.volume < 0.0 {
+        self.report(
+          &location,
+          format!("the volume can't be negative, got {}", file.volume),
+        );
+      }
+    }
+    for (name, meter) in &adventure.meters {
+      for threshold in &meter.thresholds {
+        if let Some(transition) = &threshold.transition {
+          self.room(&format!("meter {name}"), "threshold transition", transition);
+        }
+      }
+    }
+  }
+
+  fn check_room(&mut self, id: &str, room: &Room) {
+    let location = format!("room {id}");
+    self.check_actions(&location, &room.actions);
+    for (variant, name) in [(&room.day, "day"), (&room.night, "night")] {
+      if let Some(actions) = variant.as_ref().and_then(|v| v.actions.as_ref()) {
+        self.check_actions(&format!("{location} ({name})"), actions);
+      }
+    }
+
+    self.outcome(&location, "timeout", &room.timeout);
+    if let Some(music) = &room.music {
+      self.music(&location, music);
+    }
+    let variant_images = [&room.day, &room.night]
+      .into_iter()
+      .filter_map(|v| v.as_ref().and_then(|v| v.image.as_ref()));
+    for image in room.image.iter().chain(variant_images) {
+      self.image(&location, image);
+    }
+    if let Some(theme) = &room.theme {
+      self.theme(&location, theme);
+    }
+    self.use_targets(&location, &room.use_targets);
+    for hook in room.on_enter.iter().chain(&room.on_exit) {
+      if let Some(music) = &hook.music {
+        self.music(&location, music);
+      }
+    }
+  }
+
+  fn use_targets(&mut self, location: &str, targets: &[UseTarget]) {
+    for target in targets {
+      for (item, outcome) in sorted(&target.outcomes) {
+        if let Some(transition) = &outcome.transition {
+          self.room(
+            &format!("{location}, using {item} on {}", target.name),
+            "transition",
+            transition,
+          );
+        }
+      }
+    }
+  }
+
+  fn check_actions(&mut self, location: &str, actions: &[Action]) {
+    for action in actions {
+      let location = format!("{location}, action {}", action.name);
+      if let Some(transition) = action.transition() {
+        self.room(&location, "transition", transition);
+      }
+      if let ActionKind::Check {
+        success, failure, ..
+      } = &action.kind
+      {
+        self.outcome(&location, "success", success);
+        self.outcome(&location, "failure", failure);
+      }
+      self.outcome(&location, "timeout", &action.timeout);
+      self.check_actions(&location, &action.choices);
+
+      if let Some(music) = &action.music {
+        self.music(&location, &music.song);
+        for (fade, seconds) in [("fade_in", music.fade_in), ("fade_out", music.fade_out)] {
+          if seconds.is_some_and(|s| s < 0.0) {
+            self.report(
+              &location,
+              format!("the {fade} of the music can't be negative"),
+            );
+          }
+        }
+      }
+      if let Some(sfx) = &action.sfx {
+        if !self.adventure.assets.sfx.contains_key(sfx) {
+          self.report(
+            &location,
+            format!("plays the sound effect {sfx}, which is not in the assets"),
+          );
+        }
+      }
+      for (meter, _) in sorted(&action.meters) {
+        if !self.adventure.meters.contains_key(meter) {
+          self.report(&location, format!("changes the unknown meter {meter}"));
+        }
+      }
+      for (faction, _) in sorted(&action.reputation) {
+        if !self.adventure.factions.contains_key(faction) {
+          self.report(
+            &location,
+            format!("changes the reputation with the unknown faction {faction}"),
+          );
+        }
+      }
+    }
+  }
+
+  fn outcome(&mut self, location: &str, what: &str, outcome: &Outcome) {
+    if let Some(transition) = &outcome.transition {
+      self.room(location, &format!("{what} transition"), transition);
+    }
+  }
+
+  fn room(&mut self, location: &str, what: &str, room: &str) {
+    if !self.adventure.rooms.contains_key(room) {
+      self.report(
+        location,
+        format!("the {what} leads to the unknown room {room}"),
+      );
+    }
+  }
+
+  fn theme(&mut self, location: &str, theme: &Theme) {
+    if let Some(class) = &theme.class {
+      if class.is_empty() || class.contains(char::is_whitespace) {
+        self.report(
+          location,
+          format!("the theme's class has to be a single class name, got {class:?}"),
+        );
+      }
+    }
+  }
+
+  fn voice(&mut self, location: &str, voice: &SpeakingVoice) {
+    if !(0.1..=10.0).contains(&voice.rate) {
+      self.report(
+        location,
+        format!("the rate has to be between 0.1 and 10, got {}", voice.rate),
+      );
+    }
+    if !(0.0..=2.0).contains(&voice.pitch) {
+      self.report(
+        location,
+        format!("the pitch has to be between 0 and 2, got {}", voice.pitch),
+      );
+    }
+  }
+
+  fn image(&mut self, location: &str, image: &str) {
+    if !self.adventure.assets.images.contains_key(image) {
+      self.report(
+        location,
+        format!("shows the image {image}, which is not in the assets"),
+      );
+    }
+  }
+
+  fn music(&mut self, location: &str, music: &str) {
+    let assets = &self.adventure.assets;
+    if !assets.music.contains_key(music) && !assets.audio_files.contains_key(music) {
+      self.report(
+        location,
+        format!("plays {music}, which is not in the assets"),
+      );
+    }
+  }
+
+  fn report(&mut self, location: &str, message: String) {
+    self.problems.push(Problem {
+      location: location.to_string(),
+      message,
+    });
+  }
+}
+
+/// Hash maps are iterated in a random order, which would shuffle the report.
+fn sorted<V>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+  let mut entries: Vec<_> = map.iter().collect();
+  entries.sort_by_key(|(key, _)| key.as_str());
+  entries
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::loader::{parse, Format};
+
+  fn problems(rooms: &str) -> Vec<String> {
+    let raw = format!(
+      "
+adventure:
+  name: The Cave
+  start: cave
+  intro: Welcome
+  rooms:
+{rooms}
+  assets:
+    music: {{}}
+"
+    );
+    let document = parse(&raw, Format::Yaml, None).unwrap();
+    validate(&document.adventure)
+      .iter()
+      .map(|p| p.to_string())
+      .collect()
+  }
+
+  #[test]
+  fn accepts_a_valid_adventure() {
+    let rooms = "
+    cave:
+      description: A cave
+      actions:
+        - {name: Leave, type: navigate, transition: outside}
+    outside:
+      description: Daylight
+      actions: []";
+    assert_eq!(problems(rooms), Vec::<String>::new());
+  }
+
+  #[test]
+  fn reports_transitions_to_unknown_rooms() {
+    let rooms = "
+    cave:
+      description: A cave
+      actions:
+        - {name: Leave, type: navigate, transition: outside}";
+    let problems = problems(rooms);
+    assert_eq!(problems.len(), 1, "{problems:?}");
+    assert!(
+      problems[0].starts_with("room cave, action Leave: ")
+        && problems[0].ends_with("leads to the unknown room outside"),
+      "{problems:?}"
+    );
+  }
+}